@@ -31,6 +31,7 @@ use libafl::{
 };
 use libafl_bolts::{
     core_affinity::{CoreId, Cores},
+    noise::PreSharedKey,
     rands::StdRand,
     shmem::{ShMemProvider, StdShMemProvider},
     tuples::{tuple_list, Merge},
@@ -131,6 +132,14 @@ struct Opt {
         default_value = None
     )]
     node_listening_port: Option<u16>,
+
+    #[arg(
+        long,
+        help = "A file with the key shared by all nodes, to authenticate and encrypt the links between them",
+        name = "PSK_FILE",
+        default_value = None
+    )]
+    psk_file: Option<PathBuf>,
 }
 
 /// The main fn, `no_mangle` as it is a C symbol
@@ -288,6 +297,11 @@ pub extern "C" fn libafl_main() {
         node_description.node_listening_port = opt.node_listening_port;
     }
 
+    if let Some(psk_file) = &opt.psk_file {
+        node_description.psk =
+            Some(PreSharedKey::from_file(psk_file).expect("Failed to load the pre-shared key"));
+    }
+
     match CentralizedLauncher::builder()
         .shmem_provider(shmem_provider)
         .configuration(EventConfig::from_name("default"))
@@ -12,12 +12,14 @@ use libafl_bolts::{
 #[cfg(feature = "llmp_compression")]
 use crate::events::COMPRESS_THRESHOLD;
 use crate::{
-    events::{BrokerEventResult, Event, _LLMP_TAG_TO_MAIN},
+    events::{BrokerEventResult, Event, TestcaseDeduplicator, _LLMP_TAG_TO_MAIN},
     inputs::Input,
 };
 
 /// An LLMP-backed event manager for scalable multi-processed fuzzing
 pub struct CentralizedLlmpHook<I> {
+    /// Drops the testcases the main node already got
+    deduplicator: Option<TestcaseDeduplicator>,
     #[cfg(feature = "llmp_compression")]
     compressor: GzipCompressor,
     phantom: PhantomData<I>,
@@ -52,6 +54,12 @@ where
                 &*msg
             };
             let event: Event<I> = postcard::from_bytes(event_bytes)?;
+            if let Some(deduplicator) = &mut self.deduplicator {
+                if deduplicator.is_duplicate(&event)? {
+                    log::debug!("Dropping a duplicate testcase from {client_id:?}");
+                    return Ok(LlmpMsgHookResult::Handled);
+                }
+            }
             match Self::handle_in_broker(client_id, &event)? {
                 BrokerEventResult::Forward => Ok(LlmpMsgHookResult::ForwardToClients),
                 BrokerEventResult::Handled => Ok(LlmpMsgHookResult::Handled),
@@ -70,6 +78,7 @@ impl<I> Debug for CentralizedLlmpHook<I> {
         let debug_struct = debug_struct.field("compressor", &self.compressor);
 
         debug_struct
+            .field("deduplicator", &self.deduplicator)
             .field("phantom", &self.phantom)
             .finish_non_exhaustive()
     }
@@ -81,7 +90,14 @@ where
 {
     /// Create an event broker from a raw broker.
     pub fn new() -> Result<Self, Error> {
+        Self::with_deduplicator(None)
+    }
+
+    /// Create an event broker dropping the duplicate testcases before they reach the main node,
+    /// see [`TestcaseDeduplicator`]
+    pub fn with_deduplicator(deduplicator: Option<TestcaseDeduplicator>) -> Result<Self, Error> {
         Ok(Self {
+            deduplicator,
             #[cfg(feature = "llmp_compression")]
             compressor: GzipCompressor::with_threshold(COMPRESS_THRESHOLD),
             phantom: PhantomData,
@@ -7,7 +7,7 @@ use std::{
 };
 
 #[cfg(feature = "llmp_compression")]
-use libafl_bolts::llmp::LLMP_FLAG_COMPRESSED;
+use libafl_bolts::{compress::GzipCompressor, llmp::LLMP_FLAG_COMPRESSED};
 use libafl_bolts::{
     llmp::{Flags, LlmpBrokerInner, LlmpHook, LlmpMsgHookResult, Tag, LLMP_FLAG_FROM_MM},
     ownedref::OwnedRef,
@@ -180,30 +180,28 @@ where
         // Here, we suppose msg will *never* be written again and will always be available.
         // Thus, it is safe to handle this in a separate thread.
         let msg_lock = unsafe { NullLock::new((msg.as_ptr(), msg.len())) };
-        // let flags = msg_flags.clone();
+        #[cfg(feature = "llmp_compression")]
+        let compressed = *_msg_flags & LLMP_FLAG_COMPRESSED == LLMP_FLAG_COMPRESSED;
 
         let _handle: JoinHandle<Result<(), Error>> = self.rt.spawn(async move {
-            let mut state_wr_lock = shared_state.write().await;
             let (msg_ptr, msg_len) = msg_lock.into_innter();
             let msg: &[u8] = unsafe { slice::from_raw_parts(msg_ptr, msg_len) }; // most likely crash here
 
-            // #[cfg(not(feature = "llmp_compression"))]
-            // let event_bytes = msg;
-            // #[cfg(feature = "llmp_compression")]
-            // let compressed;
-            // #[cfg(feature = "llmp_compression")]
-            // let event_bytes = if flags & LLMP_FLAG_COMPRESSED == LLMP_FLAG_COMPRESSED {
-            //     compressed = state_wr_lock.compressor().decompress(msg)?;
-            //     &compressed
-            // } else {
-            //     &*msg
-            // };
-            // let event: Event<I> = postcard::from_bytes(event_bytes)?;
-
-            let mm_msg: MultiMachineMsg<I> = MultiMachineMsg::llmp_msg(OwnedRef::Ref(msg));
+            // The other nodes get the events uncompressed, the way the receiver hook forwards them
+            #[cfg(feature = "llmp_compression")]
+            let msg: OwnedRef<[u8]> = if compressed {
+                OwnedRef::Owned(GzipCompressor::new().decompress(msg)?.into_boxed_slice())
+            } else {
+                OwnedRef::Ref(msg)
+            };
+            #[cfg(not(feature = "llmp_compression"))]
+            let msg: OwnedRef<[u8]> = OwnedRef::Ref(msg);
+            let mm_msg: MultiMachineMsg<I> = MultiMachineMsg::llmp_msg(msg);
+
+            let mut state_wr_lock = shared_state.write().await;
 
             // TODO: do not copy here
-            state_wr_lock.add_past_msg(msg);
+            state_wr_lock.add_past_msg(mm_msg.serialize_as_ref());
 
             log::debug!("Sending msg...");
 
@@ -0,0 +1,113 @@
+//! A broker hook handling typed [`Event::Custom`] events
+
+use alloc::{borrow::Cow, vec::Vec};
+use core::{any::type_name, fmt, marker::PhantomData};
+
+#[cfg(feature = "llmp_compression")]
+use libafl_bolts::{compress::GzipCompressor, llmp::LLMP_FLAG_COMPRESSED};
+use libafl_bolts::{
+    llmp::{Flags, LlmpBrokerInner, LlmpHook, LlmpMsgHookResult, Tag},
+    serdeany::SerdeAny,
+    shmem::ShMemProvider,
+    ClientId,
+};
+
+#[cfg(feature = "llmp_compression")]
+use crate::events::llmp::COMPRESS_THRESHOLD;
+use crate::{
+    events::{llmp::LLMP_TAG_EVENT_TO_BOTH, BrokerEventResult, Event},
+    inputs::Input,
+    Error,
+};
+
+/// An [`LlmpHook`] handling the [`Event::Custom`] events carrying a payload of type `T` in the broker.
+///
+/// The handler gets the sender and the deserialized payload, and decides if the event is forwarded to the
+/// clients. All other messages are passed on to the next hooks.
+/// Put it before the [`super::StdLlmpEventHook`], which forwards all custom events.
+pub struct CustomEventBrokerHook<F, I, T> {
+    handler: F,
+    /// The serialized start of the [`Event::Custom`] events carrying a `T`: the variant and the payload name
+    event_prefix: Vec<u8>,
+    #[cfg(feature = "llmp_compression")]
+    compressor: GzipCompressor,
+    phantom: PhantomData<fn() -> (I, T)>,
+}
+
+impl<F, I, T> CustomEventBrokerHook<F, I, T>
+where
+    F: FnMut(ClientId, &T) -> Result<BrokerEventResult, Error>,
+    I: Input,
+{
+    /// Creates a new [`CustomEventBrokerHook`], calling `handler` for each payload of type `T`
+    pub fn new(handler: F) -> Result<Self, Error> {
+        let mut event_prefix = postcard::to_allocvec(&Event::<I>::Custom {
+            name: Cow::Borrowed(type_name::<T>()),
+            payload: Vec::new(),
+        })?;
+        // Drop the length of the empty payload
+        event_prefix.pop();
+        Ok(Self {
+            handler,
+            event_prefix,
+            #[cfg(feature = "llmp_compression")]
+            compressor: GzipCompressor::with_threshold(COMPRESS_THRESHOLD),
+            phantom: PhantomData,
+        })
+    }
+}
+
+impl<F, I, T> fmt::Debug for CustomEventBrokerHook<F, I, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CustomEventBrokerHook")
+            .field("payload", &type_name::<T>())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F, I, SP, T> LlmpHook<SP> for CustomEventBrokerHook<F, I, T>
+where
+    F: FnMut(ClientId, &T) -> Result<BrokerEventResult, Error>,
+    I: Input,
+    SP: ShMemProvider,
+    T: SerdeAny,
+{
+    fn on_new_message(
+        &mut self,
+        _broker_inner: &mut LlmpBrokerInner<SP>,
+        client_id: ClientId,
+        msg_tag: &mut Tag,
+        #[cfg(feature = "llmp_compression")] msg_flags: &mut Flags,
+        #[cfg(not(feature = "llmp_compression"))] _msg_flags: &mut Flags,
+        msg: &mut [u8],
+        _new_msgs: &mut Vec<(Tag, Flags, Vec<u8>)>,
+    ) -> Result<LlmpMsgHookResult, Error> {
+        if *msg_tag != LLMP_TAG_EVENT_TO_BOTH {
+            return Ok(LlmpMsgHookResult::ForwardToClients);
+        }
+
+        #[cfg(not(feature = "llmp_compression"))]
+        let event_bytes = msg;
+        #[cfg(feature = "llmp_compression")]
+        let compressed;
+        #[cfg(feature = "llmp_compression")]
+        let event_bytes = if *msg_flags & LLMP_FLAG_COMPRESSED == LLMP_FLAG_COMPRESSED {
+            compressed = self.compressor.decompress(msg)?;
+            &compressed
+        } else {
+            &*msg
+        };
+        // Only deserialize the events for this hook
+        if !event_bytes.starts_with(&self.event_prefix) {
+            return Ok(LlmpMsgHookResult::ForwardToClients);
+        }
+        let event: Event<I> = postcard::from_bytes(event_bytes)?;
+        match event.custom_payload::<T>()? {
+            Some(payload) => match (self.handler)(client_id, &payload)? {
+                BrokerEventResult::Forward => Ok(LlmpMsgHookResult::ForwardToClients),
+                BrokerEventResult::Handled => Ok(LlmpMsgHookResult::Handled),
+            },
+            None => Ok(LlmpMsgHookResult::ForwardToClients),
+        }
+    }
+}
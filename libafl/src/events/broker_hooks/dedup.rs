@@ -0,0 +1,132 @@
+//! Deduplication of the new testcases in the broker
+
+use hashbrown::HashSet;
+
+use crate::{
+    events::{
+        delta::{content_hash, input_hash},
+        Event,
+    },
+    inputs::Input,
+    Error,
+};
+
+/// Recognizes the [`Event::NewTestcase`] events the broker already forwarded, so that each client
+/// evaluates each testcase once, however many clients found it.
+/// Used by the [`super::StdLlmpEventHook`], the [`super::CentralizedLlmpHook`] and the `tcp_manager`.
+///
+/// A testcase is a duplicate if its input was seen before. Optionally, it is a duplicate as well if the
+/// serialized observers sent along (its coverage signature) were seen before. This only works if the
+/// clients send their observers (same [`crate::events::EventConfig`]) and the observers are
+/// deterministic: a [`crate::observers::TimeObserver`] makes every signature unique.
+#[derive(Debug, Clone, Default)]
+pub struct TestcaseDeduplicator {
+    /// The content hashes of the inputs seen
+    inputs: HashSet<u64>,
+    /// The hashes of the coverage signatures seen, if deduplicating by coverage
+    signatures: Option<HashSet<u64>>,
+    /// The number of testcases dropped
+    dropped: u64,
+}
+
+impl TestcaseDeduplicator {
+    /// Creates a new [`TestcaseDeduplicator`], dropping the testcases with an input seen before
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new [`TestcaseDeduplicator`], dropping the testcases with an input or a coverage
+    /// signature seen before
+    #[must_use]
+    pub fn with_coverage_signature() -> Self {
+        Self {
+            signatures: Some(HashSet::new()),
+            ..Self::default()
+        }
+    }
+
+    /// Checks if `event` is a testcase seen before, and remembers it otherwise.
+    /// All other events are never duplicates.
+    pub fn is_duplicate<I>(&mut self, event: &Event<I>) -> Result<bool, Error>
+    where
+        I: Input,
+    {
+        let Event::NewTestcase {
+            input,
+            observers_buf,
+            ..
+        } = event
+        else {
+            return Ok(false);
+        };
+
+        let mut duplicate = !self.inputs.insert(input_hash(input)?);
+        if !duplicate {
+            if let (Some(signatures), Some(observers_buf)) = (&mut self.signatures, observers_buf) {
+                duplicate = !signatures.insert(content_hash(observers_buf));
+            }
+        }
+        if duplicate {
+            self.dropped += 1;
+        }
+        Ok(duplicate)
+    }
+
+    /// The number of testcases dropped so far
+    #[must_use]
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use core::time::Duration;
+
+    use super::TestcaseDeduplicator;
+    use crate::{
+        events::{Event, EventConfig},
+        executors::ExitKind,
+        inputs::BytesInput,
+    };
+
+    fn new_testcase(input: &[u8], observers_buf: &[u8]) -> Event<BytesInput> {
+        Event::NewTestcase {
+            input: BytesInput::new(input.to_vec()),
+            observers_buf: Some(observers_buf.to_vec()),
+            exit_kind: ExitKind::Ok,
+            corpus_size: 1,
+            client_config: EventConfig::AlwaysUnique,
+            time: Duration::ZERO,
+            forward_id: None,
+            parent_hash: None,
+            #[cfg(all(unix, feature = "std", feature = "multi_machine"))]
+            node_id: None,
+        }
+    }
+
+    #[test]
+    fn test_testcase_dedup() {
+        let mut by_input = TestcaseDeduplicator::new();
+        let mut by_coverage = TestcaseDeduplicator::with_coverage_signature();
+        let events: Vec<_> = [("a", "1"), ("a", "2"), ("b", "1"), ("c", "3")]
+            .iter()
+            .map(|(input, signature)| new_testcase(input.as_bytes(), signature.as_bytes()))
+            .collect();
+
+        let dropped: Vec<bool> = events
+            .iter()
+            .map(|event| by_input.is_duplicate(event).unwrap())
+            .collect();
+        assert_eq!(dropped, [false, true, false, false]);
+
+        let dropped: Vec<bool> = events
+            .iter()
+            .map(|event| by_coverage.is_duplicate(event).unwrap())
+            .collect();
+        assert_eq!(dropped, [false, true, true, false]);
+        assert_eq!(by_coverage.dropped(), 2);
+    }
+}
@@ -19,6 +19,20 @@ use crate::{
     Error,
 };
 
+/// Hook for typed custom events
+pub mod custom;
+pub use custom::*;
+
+/// Deduplication of the testcases forwarded by the broker hooks
+pub mod dedup;
+pub use dedup::*;
+
+/// Hook recording the events to disk
+#[cfg(feature = "std")]
+pub mod recorder;
+#[cfg(feature = "std")]
+pub use recorder::*;
+
 /// centralized hook
 #[cfg(all(unix, feature = "std"))]
 pub mod centralized;
@@ -35,6 +49,7 @@ pub use centralized_multi_machine::*;
 #[derive(Debug)]
 pub struct StdLlmpEventHook<I, MT> {
     monitor: MT,
+    deduplicator: Option<TestcaseDeduplicator>,
     #[cfg(feature = "llmp_compression")]
     compressor: GzipCompressor,
     phantom: PhantomData<I>,
@@ -57,6 +72,7 @@ where
         _new_msgs: &mut Vec<(Tag, Flags, Vec<u8>)>,
     ) -> Result<LlmpMsgHookResult, Error> {
         let monitor = &mut self.monitor;
+        let deduplicator = &mut self.deduplicator;
         #[cfg(feature = "llmp_compression")]
         let compressor = &self.compressor;
 
@@ -74,7 +90,16 @@ where
             };
             let event: Event<I> = postcard::from_bytes(event_bytes)?;
             match Self::handle_in_broker(monitor, client_id, &event)? {
-                BrokerEventResult::Forward => Ok(LlmpMsgHookResult::ForwardToClients),
+                BrokerEventResult::Forward => {
+                    // The monitor saw the corpus size of the client, the other clients need no duplicate
+                    if let Some(deduplicator) = deduplicator {
+                        if deduplicator.is_duplicate(&event)? {
+                            log::debug!("Dropping a duplicate testcase from {client_id:?}");
+                            return Ok(LlmpMsgHookResult::Handled);
+                        }
+                    }
+                    Ok(LlmpMsgHookResult::ForwardToClients)
+                }
                 BrokerEventResult::Handled => Ok(LlmpMsgHookResult::Handled),
             }
         } else {
@@ -95,8 +120,18 @@ where
 {
     /// Create an event broker from a raw broker.
     pub fn new(monitor: MT) -> Result<Self, Error> {
+        Self::with_deduplicator(monitor, None)
+    }
+
+    /// Create an event broker dropping the duplicate testcases instead of forwarding them,
+    /// see [`TestcaseDeduplicator`]
+    pub fn with_deduplicator(
+        monitor: MT,
+        deduplicator: Option<TestcaseDeduplicator>,
+    ) -> Result<Self, Error> {
         Ok(Self {
             monitor,
+            deduplicator,
             #[cfg(feature = "llmp_compression")]
             compressor: GzipCompressor::with_threshold(COMPRESS_THRESHOLD),
             phantom: PhantomData,
@@ -105,7 +140,7 @@ where
 
     /// Handle arriving events in the broker
     #[allow(clippy::unnecessary_wraps)]
-    fn handle_in_broker(
+    pub(crate) fn handle_in_broker(
         monitor: &mut MT,
         client_id: ClientId,
         event: &Event<I>,
@@ -195,6 +230,7 @@ where
                 Ok(BrokerEventResult::Handled)
             }
             Event::CustomBuf { .. } => Ok(BrokerEventResult::Forward),
+            Event::Custom { .. } => Ok(BrokerEventResult::Forward),
             Event::Stop => Ok(BrokerEventResult::Forward),
             //_ => Ok(BrokerEventResult::Forward),
         }
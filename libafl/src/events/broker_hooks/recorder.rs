@@ -0,0 +1,82 @@
+//! A broker hook recording all events to an event log, see [`crate::events::recorder`]
+
+use alloc::vec::Vec;
+use std::path::Path;
+
+#[cfg(feature = "llmp_compression")]
+use libafl_bolts::llmp::LLMP_FLAG_COMPRESSED;
+use libafl_bolts::{
+    llmp::{Flags, LlmpBrokerInner, LlmpHook, LlmpMsgHookResult, Tag},
+    shmem::ShMemProvider,
+    ClientId,
+};
+
+use crate::{
+    events::{llmp::LLMP_TAG_EVENT_TO_BOTH, recorder::EventRecorder},
+    Error,
+};
+
+/// An [`LlmpHook`] recording every event passing the broker with an [`EventRecorder`].
+///
+/// It never consumes a message, put it first, so it sees the events the next hooks handle.
+/// Without a recorder, it does nothing.
+/// It flushes the recorder on the timeout of the broker, so the log keeps up with an idle campaign.
+#[derive(Debug)]
+pub struct EventRecorderHook {
+    recorder: Option<EventRecorder>,
+}
+
+impl EventRecorderHook {
+    /// Creates a new [`EventRecorderHook`]
+    #[must_use]
+    pub fn new(recorder: Option<EventRecorder>) -> Self {
+        Self { recorder }
+    }
+
+    /// Creates a new [`EventRecorderHook`], recording to the event log at `path`, if any
+    pub fn with_log_path<P>(path: Option<P>) -> Result<Self, Error>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(Self::new(path.map(EventRecorder::new).transpose()?))
+    }
+}
+
+impl<SP> LlmpHook<SP> for EventRecorderHook
+where
+    SP: ShMemProvider,
+{
+    fn on_new_message(
+        &mut self,
+        _broker_inner: &mut LlmpBrokerInner<SP>,
+        client_id: ClientId,
+        msg_tag: &mut Tag,
+        #[cfg(feature = "llmp_compression")] msg_flags: &mut Flags,
+        #[cfg(not(feature = "llmp_compression"))] _msg_flags: &mut Flags,
+        msg: &mut [u8],
+        _new_msgs: &mut Vec<(Tag, Flags, Vec<u8>)>,
+    ) -> Result<LlmpMsgHookResult, Error> {
+        let Some(recorder) = &mut self.recorder else {
+            return Ok(LlmpMsgHookResult::ForwardToClients);
+        };
+        if *msg_tag != LLMP_TAG_EVENT_TO_BOTH {
+            return Ok(LlmpMsgHookResult::ForwardToClients);
+        }
+
+        // The log compresses the events like LLMP does, keep the compressed messages as they are
+        #[cfg(feature = "llmp_compression")]
+        if *msg_flags & LLMP_FLAG_COMPRESSED == LLMP_FLAG_COMPRESSED {
+            recorder.record_compressed(client_id, msg)?;
+            return Ok(LlmpMsgHookResult::ForwardToClients);
+        }
+        recorder.record(client_id, msg)?;
+        Ok(LlmpMsgHookResult::ForwardToClients)
+    }
+
+    fn on_timeout(&mut self) -> Result<(), Error> {
+        match &mut self.recorder {
+            Some(recorder) => recorder.flush(),
+            None => Ok(()),
+        }
+    }
+}
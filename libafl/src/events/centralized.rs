@@ -338,6 +338,11 @@ where
     fn configuration(&self) -> EventConfig {
         self.inner.configuration()
     }
+
+    /// The main node forwards the testcases to the other machines as deltas, with the `multi_machine` feature
+    fn sends_deltas(&self) -> bool {
+        cfg!(feature = "multi_machine") || self.inner.sends_deltas()
+    }
 }
 
 impl<EM, EMH, S, SP> EventRestarter for CentralizedEventManager<EM, EMH, S, SP>
@@ -601,6 +606,7 @@ where
                 observers_buf,
                 time,
                 forward_id,
+                parent_hash,
                 #[cfg(feature = "multi_machine")]
                 node_id,
             } => {
@@ -658,6 +664,7 @@ where
                         observers_buf,
                         time,
                         forward_id,
+                        parent_hash,
                         #[cfg(feature = "multi_machine")]
                         node_id,
                     };
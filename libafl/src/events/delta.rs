@@ -0,0 +1,543 @@
+//! Delta encoding of [`Event::NewTestcase`] events, to cut the bandwidth between machines.
+//!
+//! Most new testcases are mutations of a testcase the other side already has: the parent, which the
+//! fuzzer names by its content hash in [`Event::NewTestcase`]. Instead of the full event, the sender
+//! transmits the few ops rebuilding the serialized event from the serialized parent input.
+//! Used by the `tcp_manager` and by the `multi_machine` nodes.
+
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    vec::Vec,
+};
+
+use hashbrown::{HashMap, HashSet};
+use libafl_bolts::hash_std;
+use serde::{Deserialize, Serialize};
+
+use crate::{events::Event, inputs::Input, Error};
+
+/// The number of bytes looked up in the base at once. Shorter matches are sent as literals.
+const WINDOW: usize = 8;
+
+/// The content hash of some serialized input
+pub(crate) fn content_hash(bytes: &[u8]) -> u64 {
+    hash_std(bytes)
+}
+
+/// The id of a delta made by [`DeltaCodec::encode`]: its content hash.
+/// A receiver missing the base of a delta asks the sender to resend it in full by this id, see [`DeltaCodec::take_sent`].
+#[must_use]
+pub fn delta_id(delta: &[u8]) -> u64 {
+    content_hash(delta)
+}
+
+/// The content hash of an input, as sent in [`Event::NewTestcase`] to name the parent of a testcase
+pub fn input_hash<I>(input: &I) -> Result<u64, Error>
+where
+    I: Serialize,
+{
+    Ok(content_hash(&postcard::to_allocvec(input)?))
+}
+
+/// The serialized input of an [`Event::NewTestcase`], as a [`DeltaCodec`] remembers it
+pub fn serialized_input<I>(event: &Event<I>) -> Result<Option<Vec<u8>>, Error>
+where
+    I: Input,
+{
+    match event {
+        Event::NewTestcase { input, .. } => Ok(Some(postcard::to_allocvec(input)?)),
+        _ => Ok(None),
+    }
+}
+
+/// An operation rebuilding the target from the base
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum DeltaOp {
+    /// Copy `len` bytes of the base, starting at `offset`
+    Copy {
+        /// The start in the base
+        offset: usize,
+        /// The number of bytes
+        len: usize,
+    },
+    /// Insert some new bytes
+    Insert(Vec<u8>),
+}
+
+/// Computes the ops turning `base` into `target`, for [`patch`].
+///
+/// A greedy match finder: it indexes the base every [`WINDOW`] bytes, so matches of twice this length are
+/// always found, at a cost linear in the size of the inputs.
+#[must_use]
+pub fn diff(base: &[u8], target: &[u8]) -> Vec<DeltaOp> {
+    let window_at =
+        |bytes: &[u8], pos: usize| u64::from_le_bytes(bytes[pos..pos + WINDOW].try_into().unwrap());
+
+    let mut index: HashMap<u64, usize> = HashMap::new();
+    for offset in (0..base.len().saturating_sub(WINDOW - 1)).step_by(WINDOW) {
+        index.entry(window_at(base, offset)).or_insert(offset);
+    }
+
+    let mut ops = Vec::new();
+    let mut literal_start = 0;
+    let mut pos = 0;
+    while pos + WINDOW <= target.len() {
+        let Some(&offset) = index.get(&window_at(target, pos)) else {
+            pos += 1;
+            continue;
+        };
+
+        // Grow the match backwards into the pending literal, then forwards
+        let (mut start, mut base_start) = (pos, offset);
+        while start > literal_start && base_start > 0 && target[start - 1] == base[base_start - 1] {
+            start -= 1;
+            base_start -= 1;
+        }
+        let (mut end, mut base_end) = (pos + WINDOW, offset + WINDOW);
+        while end < target.len() && base_end < base.len() && target[end] == base[base_end] {
+            end += 1;
+            base_end += 1;
+        }
+
+        if start > literal_start {
+            ops.push(DeltaOp::Insert(target[literal_start..start].to_vec()));
+        }
+        ops.push(DeltaOp::Copy {
+            offset: base_start,
+            len: end - start,
+        });
+        pos = end;
+        literal_start = end;
+    }
+    if literal_start < target.len() {
+        ops.push(DeltaOp::Insert(target[literal_start..].to_vec()));
+    }
+    ops
+}
+
+/// Rebuilds the target from `base` and the ops computed by [`diff`]
+pub fn patch(base: &[u8], ops: &[DeltaOp]) -> Result<Vec<u8>, Error> {
+    let mut target = Vec::new();
+    for op in ops {
+        match op {
+            DeltaOp::Copy { offset, len } => {
+                let bytes = offset
+                    .checked_add(*len)
+                    .and_then(|end| base.get(*offset..end))
+                    .ok_or_else(|| Error::illegal_argument("The delta copies past the base"))?;
+                target.extend_from_slice(bytes);
+            }
+            DeltaOp::Insert(bytes) => target.extend_from_slice(bytes),
+        }
+    }
+    Ok(target)
+}
+
+/// A serialized [`Event`] encoded against a base input
+#[derive(Serialize, Deserialize, Debug)]
+struct EventDelta {
+    /// The content hash of the base input
+    base: u64,
+    /// The ops rebuilding the serialized event
+    ops: Vec<DeltaOp>,
+}
+
+/// Remembers the inputs of the testcases exchanged with other fuzzers, to send new testcases as deltas
+/// against them, and to rebuild the deltas received.
+///
+/// Both sides must [`remember`](Self::remember) every testcase they send or receive.
+/// Use one codec per link: the sender only picks bases it exchanged on the link, see [`KnownInputs`],
+/// which a codec shared with other links may have evicted for their testcases.
+/// Small inputs are not worth a delta, they are always sent in full and not remembered.
+/// Only the most recently used inputs are kept, the others are evicted.
+///
+/// The peer may have evicted a base the sender still believes it has. The codec keeps the events of the last
+/// deltas sent, so the peer can ask for them in full instead, see [`Self::take_sent`].
+#[derive(Debug, Clone)]
+pub struct DeltaCodec {
+    /// The serialized inputs, by content hash, with the time they were last used
+    inputs: HashMap<u64, (Vec<u8>, u64)>,
+    /// The content hashes of the inputs, by the time they were last used
+    recently_used: BTreeMap<u64, u64>,
+    /// The time of the next use of an input
+    clock: u64,
+    /// The full events of the last deltas sent, by [`delta_id`], with the content hash of their base
+    sent: VecDeque<(u64, u64, Vec<u8>)>,
+    /// The number of inputs kept
+    capacity: usize,
+    /// Inputs shorter than this (serialized) are sent in full
+    min_input_len: usize,
+}
+
+impl Default for DeltaCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DeltaCodec {
+    /// The default size under which inputs are sent in full
+    pub const DEFAULT_MIN_INPUT_LEN: usize = 512;
+    /// The default number of inputs kept
+    pub const DEFAULT_CAPACITY: usize = 1024;
+
+    /// Creates a new [`DeltaCodec`]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::with_min_input_len(Self::DEFAULT_MIN_INPUT_LEN)
+    }
+
+    /// Creates a new [`DeltaCodec`], sending inputs shorter than `min_input_len` bytes in full
+    #[must_use]
+    pub fn with_min_input_len(min_input_len: usize) -> Self {
+        Self::with_limits(min_input_len, Self::DEFAULT_CAPACITY)
+    }
+
+    /// Creates a new [`DeltaCodec`], sending inputs shorter than `min_input_len` bytes in full, and
+    /// keeping at most `capacity` inputs
+    #[must_use]
+    pub fn with_limits(min_input_len: usize, capacity: usize) -> Self {
+        Self {
+            inputs: HashMap::new(),
+            recently_used: BTreeMap::new(),
+            clock: 0,
+            sent: VecDeque::new(),
+            capacity: capacity.max(1),
+            min_input_len,
+        }
+    }
+
+    /// The number of inputs kept
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Marks the input with the given content hash as just used, and returns it
+    fn touch(&mut self, hash: u64) -> Option<&Vec<u8>> {
+        let (bytes, last_used) = self.inputs.get_mut(&hash)?;
+        self.recently_used.remove(last_used);
+        *last_used = self.clock;
+        self.recently_used.insert(self.clock, hash);
+        self.clock += 1;
+        Some(bytes)
+    }
+
+    /// Remembers the input of an [`Event::NewTestcase`] sent or received.
+    /// Returns its content hash, if it is large enough to be remembered.
+    pub fn remember<I>(&mut self, event: &Event<I>) -> Result<Option<u64>, Error>
+    where
+        I: Input,
+    {
+        Ok(serialized_input(event)?.and_then(|bytes| self.remember_serialized(bytes)))
+    }
+
+    /// Remembers a serialized input, see [`serialized_input`], sent or received.
+    /// Returns its content hash, if it is large enough to be remembered.
+    pub fn remember_serialized(&mut self, bytes: Vec<u8>) -> Option<u64> {
+        if bytes.len() < self.min_input_len {
+            return None;
+        }
+        let hash = content_hash(&bytes);
+        if self.touch(hash).is_none() {
+            self.inputs.insert(hash, (bytes, self.clock));
+            self.recently_used.insert(self.clock, hash);
+            self.clock += 1;
+            if self.inputs.len() > self.capacity {
+                if let Some((_, evicted)) = self.recently_used.pop_first() {
+                    self.inputs.remove(&evicted);
+                }
+            }
+        }
+        Some(hash)
+    }
+
+    /// If the input with the given content hash is remembered
+    #[must_use]
+    pub fn contains(&self, hash: u64) -> bool {
+        self.inputs.contains_key(&hash)
+    }
+
+    /// The number of inputs remembered
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.inputs.len()
+    }
+
+    /// If no input is remembered
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+
+    /// Encodes `event`, serialized as `serialized`, as a delta against its parent input.
+    ///
+    /// Returns `None` if the event should be sent in full: it is no testcase, its parent is unknown here
+    /// or, according to `peer_has`, on the receiving side, or the delta would not be smaller.
+    pub fn encode<I, F>(
+        &mut self,
+        event: &Event<I>,
+        serialized: &[u8],
+        peer_has: F,
+    ) -> Result<Option<Vec<u8>>, Error>
+    where
+        I: Input,
+        F: Fn(u64) -> bool,
+    {
+        let Event::NewTestcase {
+            parent_hash: Some(parent_hash),
+            ..
+        } = event
+        else {
+            return Ok(None);
+        };
+        if !peer_has(*parent_hash) {
+            return Ok(None);
+        }
+        let Some(base) = self.touch(*parent_hash) else {
+            return Ok(None);
+        };
+
+        let delta = postcard::to_allocvec(&EventDelta {
+            base: *parent_hash,
+            ops: diff(base, serialized),
+        })?;
+        if delta.len() >= serialized.len() {
+            return Ok(None);
+        }
+        self.keep_sent(delta_id(&delta), *parent_hash, serialized.to_vec());
+        Ok(Some(delta))
+    }
+
+    /// Keeps `msg`, the full form of the delta with the given [`delta_id`] against `base`, to resend it
+    /// if the peer misses the base. [`Self::encode`] keeps the deltas it makes, only forwarded deltas need this.
+    pub fn keep_sent(&mut self, delta_id: u64, base: u64, msg: Vec<u8>) {
+        self.sent.push_back((delta_id, base, msg));
+        if self.sent.len() > self.capacity {
+            self.sent.pop_front();
+        }
+    }
+
+    /// Takes the full form of the delta with the given [`delta_id`], which the peer could not rebuild,
+    /// and the content hash of its base, which the peer does not have.
+    /// Returns `None` if the delta is too old, or was never sent.
+    pub fn take_sent(&mut self, delta_id: u64) -> Option<(u64, Vec<u8>)> {
+        let idx = self.sent.iter().position(|(id, _, _)| *id == delta_id)?;
+        self.sent.remove(idx).map(|(_, base, msg)| (base, msg))
+    }
+
+    /// Rebuilds the serialized event from a delta made by [`Self::encode`].
+    /// Fails if the base input is unknown, e.g., if this side attached after it was sent.
+    pub fn decode(&mut self, delta: &[u8]) -> Result<Vec<u8>, Error> {
+        let delta: EventDelta = postcard::from_bytes(delta)?;
+        let base = self.touch(delta.base).ok_or_else(|| {
+            Error::key_not_found("The base input of the delta is unknown, it was never received")
+        })?;
+        patch(base, &delta.ops)
+    }
+}
+
+/// The content hashes of the inputs a peer sent or received on a link, which it can rebuild deltas against.
+///
+/// Keeps half as many inputs as a [`DeltaCodec`], so the peer still has them, even if it used some more
+/// inputs than seen on the link in between.
+#[derive(Debug, Clone)]
+pub struct KnownInputs {
+    /// The content hashes
+    hashes: HashSet<u64>,
+    /// The content hashes, oldest first
+    order: VecDeque<u64>,
+    /// The number of content hashes kept
+    capacity: usize,
+}
+
+impl Default for KnownInputs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KnownInputs {
+    /// Creates a new [`KnownInputs`], for a peer with a [`DeltaCodec`] of the default capacity
+    #[must_use]
+    pub fn new() -> Self {
+        Self::with_capacity(DeltaCodec::DEFAULT_CAPACITY / 2)
+    }
+
+    /// Creates a new [`KnownInputs`], keeping at most `capacity` content hashes
+    #[must_use]
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            hashes: HashSet::new(),
+            order: VecDeque::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Records that the peer has the input with the given content hash
+    pub fn insert(&mut self, hash: u64) {
+        if self.hashes.insert(hash) {
+            self.order.push_back(hash);
+            if self.order.len() > self.capacity {
+                if let Some(evicted) = self.order.pop_front() {
+                    self.hashes.remove(&evicted);
+                }
+            }
+        }
+    }
+
+    /// Records that the peer does not have the input with the given content hash (anymore)
+    pub fn remove(&mut self, hash: u64) {
+        if self.hashes.remove(&hash) {
+            self.order.retain(|known| *known != hash);
+        }
+    }
+
+    /// If the peer has the input with the given content hash
+    #[must_use]
+    pub fn contains(&self, hash: u64) -> bool {
+        self.hashes.contains(&hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use core::time::Duration;
+
+    use super::{delta_id, diff, input_hash, patch, DeltaCodec, DeltaOp, KnownInputs};
+    use crate::{
+        events::{Event, EventConfig},
+        executors::ExitKind,
+        inputs::BytesInput,
+    };
+
+    fn new_testcase(input: BytesInput, parent_hash: Option<u64>) -> Event<BytesInput> {
+        Event::NewTestcase {
+            input,
+            observers_buf: None,
+            exit_kind: ExitKind::Ok,
+            corpus_size: 1,
+            client_config: EventConfig::AlwaysUnique,
+            time: Duration::from_secs(1),
+            forward_id: None,
+            parent_hash,
+            #[cfg(all(unix, feature = "std", feature = "multi_machine"))]
+            node_id: None,
+        }
+    }
+
+    #[test]
+    fn test_diff_patch() {
+        let base: Vec<u8> = (0..4096_u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut target = base.clone();
+        target[100] ^= 0xff;
+        target.splice(2000..2010, *b"inserted bytes");
+        target.truncate(4000);
+
+        let ops = diff(&base, &target);
+        assert_eq!(patch(&base, &ops).unwrap(), target);
+        assert!(ops.len() <= 6);
+        assert_eq!(diff(&[], b"abc"), [DeltaOp::Insert(b"abc".to_vec())]);
+        assert!(patch(b"abc", &[DeltaOp::Copy { offset: 2, len: 2 }]).is_err());
+    }
+
+    #[test]
+    fn test_delta_codec() {
+        let parent = BytesInput::new(vec![0x41; 4096]);
+        let mut child_bytes = vec![0x41; 4096];
+        child_bytes[1234] = 0x42;
+        let child = new_testcase(
+            BytesInput::new(child_bytes),
+            Some(input_hash(&parent).unwrap()),
+        );
+        let serialized = postcard::to_allocvec(&child).unwrap();
+
+        let mut sender = DeltaCodec::new();
+        let mut receiver = DeltaCodec::new();
+        // Without the parent, the testcase goes in full
+        assert!(sender
+            .encode(&child, &serialized, |_| true)
+            .unwrap()
+            .is_none());
+
+        let parent = new_testcase(parent, None);
+        sender.remember(&parent).unwrap();
+        assert!(sender
+            .encode(&child, &serialized, |_| false)
+            .unwrap()
+            .is_none());
+        let delta = sender
+            .encode(&child, &serialized, |_| true)
+            .unwrap()
+            .unwrap();
+        assert!(delta.len() < 64);
+
+        assert!(receiver.decode(&delta).is_err());
+        receiver.remember(&parent).unwrap();
+        assert_eq!(receiver.decode(&delta).unwrap(), serialized);
+    }
+
+    #[test]
+    fn test_delta_codec_resend() {
+        let parent = BytesInput::new(vec![0x41; 4096]);
+        let parent_hash = input_hash(&parent).unwrap();
+        let mut child_bytes = vec![0x41; 4096];
+        child_bytes[42] = 0x42;
+        let child = new_testcase(BytesInput::new(child_bytes), Some(parent_hash));
+        let serialized = postcard::to_allocvec(&child).unwrap();
+
+        let mut sender = DeltaCodec::new();
+        sender.remember(&new_testcase(parent, None)).unwrap();
+        let delta = sender
+            .encode(&child, &serialized, |_| true)
+            .unwrap()
+            .unwrap();
+
+        // The receiver evicted the base, and asks for the full event
+        let mut receiver = DeltaCodec::new();
+        assert!(receiver.decode(&delta).is_err());
+        assert_eq!(
+            sender.take_sent(delta_id(&delta)),
+            Some((parent_hash, serialized))
+        );
+        assert!(sender.take_sent(delta_id(&delta)).is_none());
+
+        let mut known = KnownInputs::new();
+        known.insert(parent_hash);
+        known.remove(parent_hash);
+        assert!(!known.contains(parent_hash));
+    }
+
+    #[test]
+    fn test_delta_codec_eviction() {
+        let mut codec = DeltaCodec::with_limits(1, 2);
+        let hashes: Vec<u64> = (0..3_u8)
+            .map(|i| {
+                codec
+                    .remember(&new_testcase(BytesInput::new(vec![i; 4]), None))
+                    .unwrap()
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(codec.len(), 2);
+        assert!(!codec.contains(hashes[0]));
+
+        // Using an input keeps it
+        let child = new_testcase(BytesInput::new(vec![1; 4]), Some(hashes[1]));
+        let serialized = postcard::to_allocvec(&child).unwrap();
+        codec.encode(&child, &serialized, |_| true).unwrap();
+        codec
+            .remember(&new_testcase(BytesInput::new(vec![3; 4]), None))
+            .unwrap();
+        assert!(codec.contains(hashes[1]));
+        assert!(!codec.contains(hashes[2]));
+
+        let mut known = KnownInputs::with_capacity(2);
+        for hash in &hashes {
+            known.insert(*hash);
+        }
+        assert!(!known.contains(hashes[0]));
+        assert!(known.contains(hashes[1]) && known.contains(hashes[2]));
+    }
+}
@@ -2,7 +2,9 @@
 //!
 //! This will allow user to define pre/post-processing code when the event manager receives any message from
 //! other clients
-use libafl_bolts::ClientId;
+use core::{fmt, marker::PhantomData};
+
+use libafl_bolts::{serdeany::SerdeAny, ClientId};
 
 use crate::{events::Event, state::State, Error};
 
@@ -126,3 +128,52 @@ where
         Ok(first & second)
     }
 }
+
+/// An [`EventManagerHook`] handling the [`Event::Custom`] events carrying a payload of type `T`.
+///
+/// The handler gets the deserialized payload, and returns if the event manager should continue to
+/// handle the event, like [`EventManagerHook::pre_exec`].
+pub struct CustomEventHook<F, S, T> {
+    handler: F,
+    phantom: PhantomData<fn() -> (S, T)>,
+}
+
+impl<F, S, T> CustomEventHook<F, S, T>
+where
+    F: FnMut(&mut S, ClientId, &T) -> Result<bool, Error>,
+{
+    /// Creates a new [`CustomEventHook`], calling `handler` for each payload of type `T`
+    pub fn new(handler: F) -> Self {
+        Self {
+            handler,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<F, S, T> fmt::Debug for CustomEventHook<F, S, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CustomEventHook")
+            .field("payload", &core::any::type_name::<T>())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F, S, T> EventManagerHook<S> for CustomEventHook<F, S, T>
+where
+    F: FnMut(&mut S, ClientId, &T) -> Result<bool, Error>,
+    S: State,
+    T: SerdeAny,
+{
+    fn pre_exec(
+        &mut self,
+        state: &mut S,
+        client_id: ClientId,
+        event: &Event<S::Input>,
+    ) -> Result<bool, Error> {
+        match event.custom_payload::<T>()? {
+            Some(payload) => (self.handler)(state, client_id, &payload),
+            None => Ok(true),
+        }
+    }
+}
@@ -5,6 +5,7 @@
 //!
 //! To use multiple [`Launcher`]`s` for individual configurations,
 //! we can set `spawn_broker` to `false` on all but one.
+//! Alternatively, a single [`Launcher`] runs distinct client configurations on distinct cores, see [`ClientConfigs`].
 //!
 //! To connect multiple nodes together via TCP, we can use the `remote_broker_addr`.
 //! (this requires the `llmp_bind_public` compile-time feature for `LibAFL`).
@@ -12,7 +13,10 @@
 //! On `Unix` systems, the [`Launcher`] will use `fork` if the `fork` feature is used for `LibAFL`.
 //! Else, it will start subsequent nodes with the same commandline, and will set special `env` variables accordingly.
 
-use alloc::string::ToString;
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
 #[cfg(feature = "std")]
 use core::time::Duration;
 use core::{
@@ -21,12 +25,14 @@ use core::{
 };
 #[cfg(all(unix, feature = "std", feature = "fork"))]
 use std::boxed::Box;
-#[cfg(feature = "std")]
-use std::net::SocketAddr;
+#[cfg(all(unix, feature = "std", not(feature = "fork")))]
+use std::os::unix::process::CommandExt;
 #[cfg(all(feature = "std", any(windows, not(feature = "fork"))))]
 use std::process::Stdio;
 #[cfg(all(unix, feature = "std"))]
 use std::{fs::File, os::unix::io::AsRawFd};
+#[cfg(feature = "std")]
+use std::{net::SocketAddr, path::PathBuf};
 
 #[cfg(all(unix, feature = "std", feature = "fork"))]
 use libafl_bolts::llmp::Broker;
@@ -34,6 +40,8 @@ use libafl_bolts::llmp::Broker;
 use libafl_bolts::llmp::Brokers;
 #[cfg(all(unix, feature = "std", feature = "fork"))]
 use libafl_bolts::llmp::LlmpBroker;
+#[cfg(feature = "noise")]
+use libafl_bolts::noise::PreSharedKey;
 #[cfg(all(unix, feature = "std"))]
 use libafl_bolts::os::dup2;
 #[cfg(all(feature = "std", any(windows, not(feature = "fork"))))]
@@ -58,8 +66,12 @@ use super::StdLlmpEventHook;
 use crate::events::multi_machine::NodeDescriptor;
 #[cfg(all(unix, feature = "std", feature = "fork", feature = "multi_machine"))]
 use crate::events::multi_machine::TcpMultiMachineHooks;
+#[cfg(all(target_os = "linux", feature = "std"))]
+use crate::events::supervisor::ClientCgroups;
 #[cfg(all(unix, feature = "std", feature = "fork"))]
-use crate::events::{centralized::CentralizedEventManager, CentralizedLlmpHook};
+use crate::events::{centralized::CentralizedEventManager, CentralizedLlmpHook, EventRecorderHook};
+#[cfg(feature = "std")]
+use crate::events::{ClientLimits, RestartPolicy, TestcaseDeduplicator};
 #[cfg(all(unix, feature = "std", feature = "fork"))]
 use crate::inputs::UsesInput;
 use crate::observers::TimeObserver;
@@ -72,6 +84,9 @@ use crate::{
         EventConfig,
     },
     monitors::Monitor,
+    stages::{
+        checkpoint::LIBAFL_CHECKPOINT_PATH, client_checkpoint_path, launcher_checkpoint_path,
+    },
     state::{HasExecutions, State},
     Error,
 };
@@ -83,6 +98,73 @@ const _AFL_LAUNCHER_CLIENT: &str = "AFL_LAUNCHER_CLIENT";
 #[cfg(all(feature = "fork", unix))]
 const LIBAFL_DEBUG_OUTPUT: &str = "LIBAFL_DEBUG_OUTPUT";
 
+/// The name of the user stats with the label of the client configuration, see [`ClientConfigs`]
+pub const CLIENT_CONFIG_STATS: &str = "config";
+
+/// Maps the cores of a [`Launcher`] to distinct client configurations, to build ensemble fuzzers from one
+/// binary: e.g., some clients with cmplog, some with grimoire, some with `MOpt`, and one concolic.
+///
+/// Launch on [`Self::cores`]. The `run_client` closure picks the configuration of its core with
+/// [`Self::label`], and the [`Launcher`] reports the label to the monitors, as the [`CLIENT_CONFIG_STATS`]
+/// user stats of the client.
+/// The [`CentralizedLauncher`] does not take the configurations, its clients can still look up their label,
+/// but it is not reported.
+#[derive(Debug, Clone, Default)]
+pub struct ClientConfigs {
+    /// The label of each configuration, and its cores
+    configs: Vec<(String, Cores)>,
+}
+
+impl ClientConfigs {
+    /// Creates a new, empty [`ClientConfigs`]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs the configuration `label` on the `cores`.
+    /// Fails if one of the cores already has a configuration.
+    pub fn add<L>(mut self, label: L, cores: Cores) -> Result<Self, Error>
+    where
+        L: Into<String>,
+    {
+        let label = label.into();
+        if let Some(core_id) = cores
+            .ids
+            .iter()
+            .find(|&&core_id| self.label(core_id).is_some())
+        {
+            return Err(Error::illegal_argument(format!(
+                "Core {core_id:?} has the configurations {} and {label}",
+                self.label(*core_id).unwrap_or_default()
+            )));
+        }
+        self.configs.push((label, cores));
+        Ok(self)
+    }
+
+    /// The label of the configuration on `core_id`, if any
+    #[must_use]
+    pub fn label(&self, core_id: CoreId) -> Option<&str> {
+        self.configs
+            .iter()
+            .find(|(_, cores)| cores.contains(core_id))
+            .map(|(label, _)| label.as_str())
+    }
+
+    /// All cores with a configuration, to pass to the [`Launcher`]
+    #[must_use]
+    pub fn cores(&self) -> Cores {
+        let mut ids: Vec<usize> = self
+            .configs
+            .iter()
+            .flat_map(|(_, cores)| cores.ids.iter().map(|core_id| core_id.0))
+            .collect();
+        ids.sort_unstable();
+        Cores::from(ids)
+    }
+}
+
 /// Provides a [`Launcher`], which can be used to launch a fuzzing run on a specified list of cores
 ///
 /// Will hide child output, unless the settings indicate otherwise, or the `LIBAFL_DEBUG_OUTPUT` env variable is set.
@@ -108,6 +190,9 @@ pub struct Launcher<'a, CF, MT, SP> {
     broker_port: u16,
     /// The list of cores to run on
     cores: &'a Cores,
+    /// The client configuration of each core, reported to the monitors
+    #[builder(default = None)]
+    client_configs: Option<&'a ClientConfigs>,
     /// The number of clients to spawn on each core
     #[builder(default = 1)]
     overcommit: usize,
@@ -135,6 +220,29 @@ pub struct Launcher<'a, CF, MT, SP> {
     /// clusters.
     #[builder(default = None)]
     remote_broker_addr: Option<SocketAddr>,
+    /// The key authenticating and encrypting the connections to and from remote brokers.
+    /// Brokers without the same key are rejected.
+    #[cfg(feature = "noise")]
+    #[builder(default = None)]
+    b2b_psk: Option<PreSharedKey>,
+    /// Record all events the broker receives to the event log at this path, to replay the campaign
+    /// offline with the [`crate::events::recorder::CampaignReplay`]
+    #[builder(default = None)]
+    event_log: Option<PathBuf>,
+    /// Drop the testcases other clients already got in the broker, instead of forwarding them
+    #[builder(default = None)]
+    testcase_dedup: Option<TestcaseDeduplicator>,
+    /// The memory and cpu limits of each client
+    #[builder(default = None)]
+    client_limits: Option<ClientLimits>,
+    /// How to restart the clients, with a watchdog and a backoff after failures
+    #[builder(default = None)]
+    restart_policy: Option<RestartPolicy>,
+    /// Resume each client from its checkpoint in this directory, at its [`client_checkpoint_path`],
+    /// if there is no state to restore from its last run. The client finds the path with
+    /// [`launcher_checkpoint_path`]. See [`crate::stages::CheckpointStage`].
+    #[builder(default = None)]
+    checkpoint_dir: Option<PathBuf>,
     /// The time observer for addaptive serialization
     #[builder(default = None)]
     time_ref: Option<Handle<TimeObserver>>,
@@ -156,6 +264,7 @@ impl<CF, MT, SP> Debug for Launcher<'_, CF, MT, SP> {
             .field("configuration", &self.configuration)
             .field("broker_port", &self.broker_port)
             .field("core", &self.cores)
+            .field("client_configs", &self.client_configs)
             .field("spawn_broker", &self.spawn_broker)
             .field("remote_broker_addr", &self.remote_broker_addr);
         #[cfg(all(unix, feature = "std"))]
@@ -230,6 +339,14 @@ where
 
         let debug_output = std::env::var(LIBAFL_DEBUG_OUTPUT).is_ok();
 
+        // Removed again when the launcher returns
+        #[cfg(target_os = "linux")]
+        let cgroups = ClientCgroups::for_limits(self.client_limits);
+        #[cfg(target_os = "linux")]
+        let in_cgroup = cgroups.is_some();
+        #[cfg(not(target_os = "linux"))]
+        let in_cgroup = false;
+
         // Spawn clients
         let mut index = 0_u64;
         for (id, bind_to) in core_ids.iter().enumerate() {
@@ -264,6 +381,21 @@ where
                                 }
                             }
 
+                            #[cfg(target_os = "linux")]
+                            if let Some(cgroups) = &cgroups {
+                                cgroups.attach(index, std::process::id())?;
+                            }
+                            if let Some(limits) = self.client_limits {
+                                limits.apply_rlimits(in_cgroup)?;
+                            }
+
+                            if let Some(dir) = &self.checkpoint_dir {
+                                std::env::set_var(
+                                    LIBAFL_CHECKPOINT_PATH,
+                                    client_checkpoint_path(dir, index),
+                                );
+                            }
+
                             // Fuzzer client. keeps retrying the connection to broker till the broker starts
                             let builder = RestartingMgr::<EMH, MT, S, SP>::builder()
                                 .shmem_provider(self.shmem_provider.clone())
@@ -273,9 +405,18 @@ where
                                 })
                                 .configuration(self.configuration)
                                 .serialize_state(self.serialize_state)
+                                .restart_policy(self.restart_policy)
+                                .client_limits(self.client_limits)
+                                .checkpoint(launcher_checkpoint_path())
                                 .hooks(hooks);
                             let builder = builder.time_ref(self.time_ref.clone());
-                            let (state, mgr) = builder.build().launch()?;
+                            let (state, mut mgr) = builder.build().launch()?;
+                            if let Some(label) = self
+                                .client_configs
+                                .and_then(|configs| configs.label(*bind_to))
+                            {
+                                mgr.send_client_config(label)?;
+                            }
 
                             return (self.run_client.take().unwrap())(state, mgr, *bind_to);
                         }
@@ -300,6 +441,11 @@ where
                 .hooks(hooks);
 
             let builder = builder.time_ref(self.time_ref.clone());
+            #[cfg(feature = "noise")]
+            let builder = builder.b2b_psk(self.b2b_psk.clone());
+            let builder = builder
+                .event_log(self.event_log.clone())
+                .testcase_dedup(self.testcase_dedup.clone());
 
             builder.build().launch()?;
 
@@ -343,7 +489,7 @@ where
         let mut handles = match is_client {
             Ok(core_conf) => {
                 let core_id = core_conf.parse()?;
-                // the actual client. do the fuzzing
+                // the actual client. do the fuzzing, the launcher put it in its cgroup
 
                 let builder = RestartingMgr::<EMH, MT, S, SP>::builder()
                     .shmem_provider(self.shmem_provider.clone())
@@ -353,11 +499,20 @@ where
                     })
                     .configuration(self.configuration)
                     .serialize_state(self.serialize_state)
+                    .restart_policy(self.restart_policy)
+                    .client_limits(self.client_limits)
+                    .checkpoint(launcher_checkpoint_path())
                     .hooks(hooks);
 
                 let builder = builder.time_ref(self.time_ref.clone());
 
-                let (state, mgr) = builder.build().launch()?;
+                let (state, mut mgr) = builder.build().launch()?;
+                if let Some(label) = self
+                    .client_configs
+                    .and_then(|configs| configs.label(CoreId(core_id)))
+                {
+                    mgr.send_client_config(label)?;
+                }
 
                 return (self.run_client.take().unwrap())(state, mgr, CoreId(core_id));
             }
@@ -371,6 +526,15 @@ where
 
                 log::info!("spawning on cores: {:?}", self.cores);
 
+                // Removed again when the launcher returns
+                #[cfg(target_os = "linux")]
+                let cgroups = ClientCgroups::for_limits(self.client_limits);
+                #[cfg(target_os = "linux")]
+                let in_cgroup = cgroups.is_some();
+                #[cfg(all(unix, not(target_os = "linux")))]
+                let in_cgroup = false;
+                let mut index = 0_u64;
+
                 let debug_output = std::env::var("LIBAFL_DEBUG_OUTPUT").is_ok();
                 #[cfg(unix)]
                 {
@@ -396,6 +560,7 @@ where
                 for (id, _) in core_ids.iter().enumerate().take(num_cores) {
                     if self.cores.ids.iter().any(|&x| x == id.into()) {
                         for _ in 0..self.overcommit {
+                            index += 1;
                             // Forward own stdio to child processes, if requested by user
                             let (mut stdout, mut stderr) = (Stdio::null(), Stdio::null());
                             #[cfg(unix)]
@@ -411,7 +576,25 @@ where
                             ));
 
                             std::env::set_var(_AFL_LAUNCHER_CLIENT, id.to_string());
+                            if let Some(dir) = &self.checkpoint_dir {
+                                std::env::set_var(
+                                    LIBAFL_CHECKPOINT_PATH,
+                                    client_checkpoint_path(dir, index),
+                                );
+                            }
                             let mut child = startable_self()?;
+                            #[cfg(unix)]
+                            if let Some(limits) = self.client_limits {
+                                // # Safety
+                                // Only calls `setrlimit` in the child, before it runs.
+                                unsafe {
+                                    child.pre_exec(move || {
+                                        limits
+                                            .apply_rlimits(in_cgroup)
+                                            .map_err(|_| std::io::Error::last_os_error())
+                                    });
+                                }
+                            }
                             let child = (if debug_output {
                                 &mut child
                             } else {
@@ -419,6 +602,10 @@ where
                                 child.stderr(stderr)
                             })
                             .spawn()?;
+                            #[cfg(target_os = "linux")]
+                            if let Some(cgroups) = &cgroups {
+                                cgroups.attach(index, child.id())?;
+                            }
                             handles.push(child);
                         }
                     }
@@ -451,6 +638,11 @@ where
                 .hooks(hooks);
 
             let builder = builder.time_ref(self.time_ref.clone());
+            #[cfg(feature = "noise")]
+            let builder = builder.b2b_psk(self.b2b_psk.clone());
+            let builder = builder
+                .event_log(self.event_log.clone())
+                .testcase_dedup(self.testcase_dedup.clone());
 
             builder.build().launch()?;
 
@@ -476,6 +668,7 @@ where
 ///
 /// Provides a Launcher, which can be used to launch a fuzzing run on a specified list of cores with a single main and multiple secondary nodes
 /// This is for centralized, the 4th argument of the closure should mean if this is the main node.
+/// It does not report the labels of [`ClientConfigs`] to the monitors.
 #[cfg(all(unix, feature = "std", feature = "fork"))]
 #[derive(TypedBuilder)]
 #[allow(clippy::type_complexity, missing_debug_implementations)]
@@ -528,6 +721,29 @@ pub struct CentralizedLauncher<'a, CF, MF, MT, SP> {
     /// clusters.
     #[builder(default = None)]
     remote_broker_addr: Option<SocketAddr>,
+    /// The key authenticating and encrypting the connections to and from remote brokers.
+    /// Brokers without the same key are rejected.
+    #[cfg(feature = "noise")]
+    #[builder(default = None)]
+    b2b_psk: Option<PreSharedKey>,
+    /// Record all events the broker receives to the event log at this path, to replay the campaign
+    /// offline with the [`crate::events::recorder::CampaignReplay`]
+    #[builder(default = None)]
+    event_log: Option<PathBuf>,
+    /// Drop the testcases the main node already got in the centralized broker, instead of passing them on
+    #[builder(default = None)]
+    testcase_dedup: Option<TestcaseDeduplicator>,
+    /// The memory and cpu limits of each client
+    #[builder(default = None)]
+    client_limits: Option<ClientLimits>,
+    /// How to restart the clients, with a watchdog and a backoff after failures
+    #[builder(default = None)]
+    restart_policy: Option<RestartPolicy>,
+    /// Resume each client from its checkpoint in this directory, at its [`client_checkpoint_path`],
+    /// if there is no state to restore from its last run. The client finds the path with
+    /// [`launcher_checkpoint_path`]. See [`crate::stages::CheckpointStage`].
+    #[builder(default = None)]
+    checkpoint_dir: Option<PathBuf>,
     #[cfg(feature = "multi_machine")]
     multi_machine_node_descriptor: NodeDescriptor<SocketAddr>,
     /// If this launcher should spawn a new `broker` on `[Self::broker_port]` (default).
@@ -592,6 +808,9 @@ where
                 })
                 .configuration(centralized_launcher.configuration)
                 .serialize_state(centralized_launcher.serialize_state)
+                .restart_policy(centralized_launcher.restart_policy)
+                .client_limits(centralized_launcher.client_limits)
+                .checkpoint(launcher_checkpoint_path())
                 .hooks(tuple_list!());
 
             let builder = builder.time_ref(centralized_launcher.time_obs.clone());
@@ -661,6 +880,14 @@ where
 
         let debug_output = std::env::var(LIBAFL_DEBUG_OUTPUT).is_ok();
 
+        // Removed again when the launcher returns
+        #[cfg(target_os = "linux")]
+        let cgroups = ClientCgroups::for_limits(self.client_limits);
+        #[cfg(target_os = "linux")]
+        let in_cgroup = cgroups.is_some();
+        #[cfg(not(target_os = "linux"))]
+        let in_cgroup = false;
+
         // Spawn clients
         let mut index = 0_u64;
         for (id, bind_to) in core_ids.iter().enumerate().take(num_cores) {
@@ -691,6 +918,21 @@ where
                             }
                         }
 
+                        #[cfg(target_os = "linux")]
+                        if let Some(cgroups) = &cgroups {
+                            cgroups.attach(index, std::process::id())?;
+                        }
+                        if let Some(limits) = self.client_limits {
+                            limits.apply_rlimits(in_cgroup)?;
+                        }
+
+                        if let Some(dir) = &self.checkpoint_dir {
+                            std::env::set_var(
+                                LIBAFL_CHECKPOINT_PATH,
+                                client_checkpoint_path(dir, index),
+                            );
+                        }
+
                         if index == 1 {
                             // Main client
                             log::debug!("Running main client on PID {}", std::process::id());
@@ -759,12 +1001,14 @@ where
         brokers.add(Box::new({
             #[cfg(feature = "multi_machine")]
             let centralized_hooks = tuple_list!(
-                CentralizedLlmpHook::<S::Input>::new()?,
+                CentralizedLlmpHook::<S::Input>::with_deduplicator(self.testcase_dedup.clone())?,
                 multi_machine_receiver_hook,
             );
 
             #[cfg(not(feature = "multi_machine"))]
-            let centralized_hooks = tuple_list!(CentralizedLlmpHook::<S::Input>::new()?);
+            let centralized_hooks = tuple_list!(
+                CentralizedLlmpHook::<S::Input>::with_deduplicator(self.testcase_dedup.clone())?
+            );
 
             // TODO switch to false after solving the bug
             let mut broker = LlmpBroker::with_keep_pages_attach_to_tcp(
@@ -788,11 +1032,14 @@ where
             log::info!("I am broker!!.");
 
             #[cfg(not(feature = "multi_machine"))]
-            let llmp_hook =
-                tuple_list!(StdLlmpEventHook::<S::Input, MT>::new(self.monitor.clone())?);
+            let llmp_hook = tuple_list!(
+                EventRecorderHook::with_log_path(self.event_log.as_ref())?,
+                StdLlmpEventHook::<S::Input, MT>::new(self.monitor.clone())?
+            );
 
             #[cfg(feature = "multi_machine")]
             let llmp_hook = tuple_list!(
+                EventRecorderHook::with_log_path(self.event_log.as_ref())?,
                 StdLlmpEventHook::<S::Input, MT>::new(self.monitor.clone())?,
                 multi_machine_sender_hook,
             );
@@ -803,6 +1050,11 @@ where
                 self.broker_port,
             )?;
 
+            #[cfg(feature = "noise")]
+            if let Some(psk) = &self.b2b_psk {
+                broker.inner_mut().set_b2b_psk(psk.clone());
+            }
+
             if let Some(remote_broker_addr) = self.remote_broker_addr {
                 log::info!("B2b: Connecting to {:?}", &remote_broker_addr);
                 broker.inner_mut().connect_b2b(remote_broker_addr)?;
@@ -830,3 +1082,33 @@ where
         Err(Error::shutting_down())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use libafl_bolts::core_affinity::{CoreId, Cores};
+
+    use super::ClientConfigs;
+
+    #[test]
+    fn test_client_configs() {
+        let configs = ClientConfigs::new()
+            .add("cmplog", Cores::from(vec![0, 2]))
+            .unwrap()
+            .add("grimoire", Cores::from_cmdline("3-4").unwrap())
+            .unwrap()
+            .add("concolic", Cores::from(vec![1]))
+            .unwrap();
+
+        assert_eq!(configs.label(CoreId(0)), Some("cmplog"));
+        assert_eq!(configs.label(CoreId(1)), Some("concolic"));
+        assert_eq!(configs.label(CoreId(4)), Some("grimoire"));
+        assert_eq!(configs.label(CoreId(5)), None);
+        assert_eq!(configs.cores(), Cores::from(vec![0, 1, 2, 3, 4]));
+
+        assert!(configs
+            .clone()
+            .add("mopt", Cores::from(vec![5, 2]))
+            .is_err());
+        assert!(configs.add("mopt", Cores::from(vec![5])).is_ok());
+    }
+}
@@ -2,7 +2,7 @@
 //! using low-level message passing, [`libafl_bolts::llmp`].
 
 #[cfg(feature = "std")]
-use alloc::string::ToString;
+use alloc::{borrow::Cow, string::ToString};
 use alloc::{boxed::Box, vec::Vec};
 use core::{marker::PhantomData, time::Duration};
 #[cfg(feature = "std")]
@@ -29,9 +29,14 @@ use serde::{Deserialize, Serialize};
 
 #[cfg(feature = "llmp_compression")]
 use crate::events::llmp::COMPRESS_THRESHOLD;
+#[cfg(feature = "std")]
+use crate::{
+    events::CLIENT_CONFIG_STATS,
+    monitors::{AggregatorOps, UserStats, UserStatsValue},
+};
 use crate::{
     events::{
-        llmp::{LLMP_TAG_EVENT_TO_BOTH, _LLMP_TAG_EVENT_TO_BROKER},
+        llmp::{_LLMP_TAG_EVENT_TO_BROKER, LLMP_TAG_EVENT_TO_BOTH},
         AdaptiveSerializer, CustomBufEventResult, CustomBufHandlerFn, Event, EventConfig,
         EventFirer, EventManager, EventManagerHooksTuple, EventManagerId, EventProcessor,
         EventRestarter, HasCustomBufHandlers, HasEventManagerId, ProgressReporter,
@@ -468,6 +473,8 @@ where
                     }
                 }
             }
+            // Handled by the `CustomEventHook`s
+            Event::Custom { .. } => {}
             Event::Stop => {
                 state.request_stop();
             }
@@ -491,6 +498,23 @@ impl<EMH, S: State, SP: ShMemProvider> LlmpEventManager<EMH, S, SP> {
     pub fn send_exiting(&mut self) -> Result<(), Error> {
         self.llmp.sender_mut().send_exiting()
     }
+
+    /// Reports the label of the configuration of this client to the monitors, as the
+    /// [`crate::events::CLIENT_CONFIG_STATS`] user stats.
+    /// Other than [`EventFirer::fire`], it needs no state, so that it works before the client built one.
+    #[cfg(feature = "std")]
+    pub fn send_client_config(&mut self, label: &str) -> Result<(), Error> {
+        let event: Event<S::Input> = Event::UpdateUserStats {
+            name: Cow::Borrowed(CLIENT_CONFIG_STATS),
+            value: UserStats::new(
+                UserStatsValue::String(Cow::Owned(label.to_string())),
+                AggregatorOps::None,
+            ),
+            phantom: PhantomData,
+        };
+        self.llmp
+            .send_buf(LLMP_TAG_EVENT_TO_BOTH, &postcard::to_allocvec(&event)?)
+    }
 }
 
 impl<EMH, S, SP> UsesState for LlmpEventManager<EMH, S, SP>
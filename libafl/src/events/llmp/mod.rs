@@ -329,7 +329,7 @@ where
                 }
                 Ok(())
             }
-            Event::Stop => Ok(()),
+            Event::Custom { .. } | Event::Stop => Ok(()),
             _ => Err(Error::unknown(format!(
                 "Received illegal message that message should not have arrived: {:?}.",
                 event.name()
@@ -434,6 +434,7 @@ where
                 forward_id,
                 #[cfg(all(unix, feature = "std", feature = "multi_machine"))]
                 node_id,
+                ..
             } => Event::NewTestcase {
                 input: self.converter.as_mut().unwrap().convert(input)?,
                 client_config,
@@ -442,10 +443,13 @@ where
                 observers_buf,
                 time,
                 forward_id,
+                // The converted input has another hash
+                parent_hash: None,
                 #[cfg(all(unix, feature = "std", feature = "multi_machine"))]
                 node_id,
             },
             Event::CustomBuf { buf, tag } => Event::CustomBuf { buf, tag },
+            Event::Custom { name, payload } => Event::Custom { name, payload },
             _ => {
                 return Ok(());
             }
@@ -491,6 +495,7 @@ where
                 forward_id,
                 #[cfg(all(unix, feature = "std", feature = "multi_machine"))]
                 node_id,
+                ..
             } => Event::NewTestcase {
                 input: self.converter.as_mut().unwrap().convert(input)?,
                 client_config,
@@ -499,10 +504,13 @@ where
                 observers_buf,
                 time,
                 forward_id,
+                // The converted input has another hash
+                parent_hash: None,
                 #[cfg(all(unix, feature = "std", feature = "multi_machine"))]
                 node_id,
             },
             Event::CustomBuf { buf, tag } => Event::CustomBuf { buf, tag },
+            Event::Custom { name, payload } => Event::Custom { name, payload },
             _ => {
                 return Ok(());
             }
@@ -10,10 +10,12 @@ use core::sync::atomic::{compiler_fence, Ordering};
 use core::time::Duration;
 use core::{marker::PhantomData, num::NonZeroUsize};
 #[cfg(feature = "std")]
-use std::net::SocketAddr;
+use std::{net::SocketAddr, path::PathBuf, time::Instant};
 
 #[cfg(feature = "std")]
 use libafl_bolts::core_affinity::CoreId;
+#[cfg(feature = "noise")]
+use libafl_bolts::noise::PreSharedKey;
 #[cfg(all(feature = "std", any(windows, not(feature = "fork"))))]
 use libafl_bolts::os::startable_self;
 #[cfg(all(unix, feature = "std", not(miri)))]
@@ -33,10 +35,17 @@ use serde::{Deserialize, Serialize};
 #[cfg(feature = "std")]
 use typed_builder::TypedBuilder;
 
+#[cfg(all(feature = "std", feature = "fork", unix))]
+use crate::events::supervisor::{wait_for_client, ClientKill, Watchdog};
 #[cfg(all(unix, feature = "std", not(miri)))]
 use crate::events::EVENTMGR_SIGHANDLER_STATE;
 #[cfg(feature = "std")]
-use crate::events::{AdaptiveSerializer, CustomBufEventResult, HasCustomBufHandlers};
+use crate::events::{
+    supervisor::Heartbeat, AdaptiveSerializer, ClientLimits, CustomBufEventResult,
+    EventRecorderHook, HasCustomBufHandlers, RestartPolicy, TestcaseDeduplicator,
+};
+#[cfg(feature = "std")]
+use crate::stages::load_checkpoint;
 use crate::{
     events::{
         Event, EventConfig, EventFirer, EventManager, EventManagerHooksTuple, EventManagerId,
@@ -67,6 +76,8 @@ where
     staterestorer: StateRestorer<SP>,
     /// Decide if the state restorer must save the serialized state
     save_state: LlmpShouldSaveState,
+    /// Tells the watchdog of the respawner that this client is alive, see [`RestartPolicy`]
+    heartbeat: Option<Heartbeat<SP::ShMem>>,
 }
 
 #[cfg(feature = "std")]
@@ -139,6 +150,9 @@ where
         state: &mut Self::State,
         event: Event<<Self::State as UsesInput>::Input>,
     ) -> Result<(), Error> {
+        if let Some(heartbeat) = &self.heartbeat {
+            heartbeat.beat();
+        }
         // Check if we are going to crash in the event, in which case we store our current state for the next runner
         self.llmp_mgr.fire(state, event)?;
         self.intermediate_save()?;
@@ -155,6 +169,10 @@ where
     fn configuration(&self) -> EventConfig {
         self.llmp_mgr.configuration()
     }
+
+    fn sends_deltas(&self) -> bool {
+        self.llmp_mgr.sends_deltas()
+    }
 }
 
 #[cfg(feature = "std")]
@@ -282,6 +300,7 @@ where
             llmp_mgr,
             staterestorer,
             save_state: LlmpShouldSaveState::OnRestart,
+            heartbeat: None,
         }
     }
 
@@ -295,9 +314,15 @@ where
             llmp_mgr,
             staterestorer,
             save_state,
+            heartbeat: None,
         }
     }
 
+    /// Beat the given heartbeat on every event, for the watchdog of the respawner
+    pub(crate) fn set_heartbeat(&mut self, heartbeat: Option<Heartbeat<SP::ShMem>>) {
+        self.heartbeat = heartbeat;
+    }
+
     /// Get the staterestorer
     pub fn staterestorer(&self) -> &StateRestorer<SP> {
         &self.staterestorer
@@ -308,6 +333,12 @@ where
         &mut self.staterestorer
     }
 
+    /// Reports the label of the configuration of this client to the monitors, see
+    /// [`LlmpEventManager::send_client_config`]
+    pub fn send_client_config(&mut self, label: &str) -> Result<(), Error> {
+        self.llmp_mgr.send_client_config(label)
+    }
+
     /// Save LLMP state and empty state in staterestorer
     pub fn intermediate_save(&mut self) -> Result<(), Error> {
         // First, reset the page to 0 so the next iteration can read read from the beginning of this page
@@ -426,6 +457,10 @@ pub struct RestartingMgr<EMH, MT, S, SP> {
     /// The address to connect to
     #[builder(default = None)]
     remote_broker_addr: Option<SocketAddr>,
+    /// The key authenticating and encrypting the connections to and from remote brokers
+    #[cfg(feature = "noise")]
+    #[builder(default = None)]
+    b2b_psk: Option<PreSharedKey>,
     /// The type of manager to build
     #[builder(default = ManagerKind::Any)]
     kind: ManagerKind,
@@ -437,6 +472,23 @@ pub struct RestartingMgr<EMH, MT, S, SP> {
     /// but it will quit after client 2 connected and disconnected.
     #[builder(default = None)]
     exit_cleanly_after: Option<NonZeroUsize>,
+    /// Record all events the broker receives to the event log at this path, see [`crate::events::recorder`]
+    #[builder(default = None)]
+    event_log: Option<PathBuf>,
+    /// Drop the testcases other clients already got in the broker, instead of forwarding them
+    #[builder(default = None)]
+    testcase_dedup: Option<TestcaseDeduplicator>,
+    /// How to restart the client, with a watchdog and a backoff after failures
+    #[builder(default = None)]
+    restart_policy: Option<RestartPolicy>,
+    /// Kill the client once its resident memory exceeds the memory limit, checked by the respawner.
+    /// The cpu limit needs the cgroups of the [`crate::events::Launcher`].
+    #[builder(default = None)]
+    client_limits: Option<ClientLimits>,
+    /// Resume from the checkpoint at this path, written by a [`crate::stages::CheckpointStage`],
+    /// if there is no state to restore from the last run of the client
+    #[builder(default = None)]
+    checkpoint: Option<PathBuf>,
     /// Tell the manager to serialize or not the state on restart
     #[builder(default = LlmpShouldSaveState::OnRestart)]
     serialize_state: LlmpShouldSaveState,
@@ -460,10 +512,17 @@ where
     /// Launch the broker and the clients and fuzz
     pub fn launch(&mut self) -> Result<(Option<S>, LlmpRestartingEventManager<EMH, S, SP>), Error> {
         // We start ourselves as child process to actually fuzz
-        let (staterestorer, new_shmem_provider, core_id) = if std::env::var(_ENV_FUZZER_SENDER)
-            .is_err()
+        let (staterestorer, new_shmem_provider, core_id, heartbeat) = if std::env::var(
+            _ENV_FUZZER_SENDER,
+        )
+        .is_err()
         {
             let broker_things = |mut broker: LlmpBroker<_, SP>, remote_broker_addr| {
+                #[cfg(feature = "noise")]
+                if let Some(psk) = &self.b2b_psk {
+                    broker.inner_mut().set_b2b_psk(psk.clone());
+                }
+
                 if let Some(remote_broker_addr) = remote_broker_addr {
                     log::info!("B2b: Connecting to {:?}", &remote_broker_addr);
                     broker.inner_mut().connect_b2b(remote_broker_addr)?;
@@ -487,8 +546,11 @@ where
                         LlmpConnection::on_port(self.shmem_provider.clone(), self.broker_port)?;
                     match connection {
                         LlmpConnection::IsBroker { broker } => {
-                            let llmp_hook = StdLlmpEventHook::<S::Input, MT>::new(
+                            let recorder_hook =
+                                EventRecorderHook::with_log_path(self.event_log.as_ref())?;
+                            let llmp_hook = StdLlmpEventHook::<S::Input, MT>::with_deduplicator(
                                 self.monitor.take().unwrap(),
+                                self.testcase_dedup.clone(),
                             )?;
 
                             // Yep, broker. Just loop here.
@@ -497,7 +559,7 @@ where
                             );
 
                             broker_things(
-                                broker.add_hooks(tuple_list!(llmp_hook)),
+                                broker.add_hooks(tuple_list!(recorder_hook, llmp_hook)),
                                 self.remote_broker_addr,
                             )?;
 
@@ -517,11 +579,15 @@ where
                     }
                 }
                 ManagerKind::Broker => {
-                    let llmp_hook = StdLlmpEventHook::new(self.monitor.take().unwrap())?;
+                    let recorder_hook = EventRecorderHook::with_log_path(self.event_log.as_ref())?;
+                    let llmp_hook = StdLlmpEventHook::with_deduplicator(
+                        self.monitor.take().unwrap(),
+                        self.testcase_dedup.clone(),
+                    )?;
 
                     let broker = LlmpBroker::create_attach_to_tcp(
                         self.shmem_provider.clone(),
-                        tuple_list!(llmp_hook),
+                        tuple_list!(recorder_hook, llmp_hook),
                         self.broker_port,
                     )?;
 
@@ -564,14 +630,40 @@ where
             // Store the information to a map.
             staterestorer.write_to_env(_ENV_FUZZER_SENDER)?;
 
+            let policy = self.restart_policy.unwrap_or_default();
+            // The watchdog needs to wait on a forked child
+            #[cfg(all(unix, feature = "fork"))]
+            let heartbeat = match policy.heartbeat_timeout() {
+                Some(_) => Some(Heartbeat::new(
+                    self.shmem_provider.new_shmem(size_of::<u64>())?,
+                )?),
+                None => None,
+            };
+            #[cfg(not(all(unix, feature = "fork")))]
+            if policy.heartbeat_timeout().is_some()
+                || self
+                    .client_limits
+                    .and_then(|limits| limits.memory())
+                    .is_some()
+            {
+                log::warn!("The watchdog needs fork, not enforcing the heartbeat timeout and the memory limit");
+            }
+            let mut failures: u32 = 0;
+
             let mut ctr: u64 = 0;
             // Client->parent loop
             loop {
                 log::info!("Spawning next client (id {ctr})");
+                let started = Instant::now();
+                #[cfg(not(all(unix, feature = "fork")))]
+                let kill: Option<()> = None;
 
                 // On Unix, we fork (when fork feature is enabled)
                 #[cfg(all(unix, feature = "fork"))]
-                let child_status = {
+                let (child_status, kill) = {
+                    if let Some(heartbeat) = &heartbeat {
+                        heartbeat.beat();
+                    }
                     self.shmem_provider.pre_fork()?;
                     match unsafe { fork() }? {
                         ForkResult::Parent(handle) => {
@@ -579,7 +671,11 @@ where
                                 libc::signal(libc::SIGINT, libc::SIG_IGN);
                             }
                             self.shmem_provider.post_fork(false)?;
-                            handle.status()
+                            let watchdog = Watchdog {
+                                heartbeat: heartbeat.as_ref().zip(policy.heartbeat_timeout()),
+                                rss_limit: self.client_limits.and_then(|limits| limits.memory()),
+                            };
+                            wait_for_client(handle.pid, &watchdog)?
                         }
                         ForkResult::Child => {
                             log::debug!(
@@ -588,7 +684,12 @@ where
                                 std::process::id()
                             );
                             self.shmem_provider.post_fork(true)?;
-                            break (staterestorer, self.shmem_provider.clone(), core_id);
+                            break (
+                                staterestorer,
+                                self.shmem_provider.clone(),
+                                core_id,
+                                heartbeat,
+                            );
                         }
                     }
                 };
@@ -627,6 +728,12 @@ where
                     if let Err(err) = mgr.detach_from_broker(self.broker_port) {
                         log::error!("Failed to detach from broker: {err}");
                     }
+                    #[cfg(all(unix, feature = "fork"))]
+                    match kill {
+                        Some(ClientKill::Hang) => panic!("The watchdog killed a hanging client, but it could not save its state to restart. Use an OOM-safe LlmpShouldSaveState to restart hanging clients"),
+                        Some(ClientKill::OutOfMemory) => panic!("The watchdog killed a client above its memory limit, but it could not save its state to restart. Use an OOM-safe LlmpShouldSaveState to restart such clients"),
+                        None => {}
+                    }
                     #[cfg(unix)]
                     if child_status == 9 {
                         panic!("Target received SIGKILL!. This could indicate the target crashed due to OOM, user sent SIGKILL, or the target was in an unrecoverable situation and could not save state to restart");
@@ -635,6 +742,17 @@ where
                     panic!("Fuzzer-respawner: Storing state in crashed fuzzer instance did not work, no point to spawn the next client! This can happen if the child calls `exit()`, in that case make sure it uses `abort()`, if it got killed unrecoverable (OOM), or if there is a bug in the fuzzer itself. (Child exited with: {child_status})");
                 }
 
+                if policy.failed(started.elapsed(), kill.is_some()) {
+                    failures = failures.saturating_add(1);
+                    let backoff = policy.backoff_after(failures);
+                    if !backoff.is_zero() {
+                        log::warn!("Client failed {failures} times in a row, waiting {backoff:?} before restarting it");
+                        std::thread::sleep(backoff);
+                    }
+                } else {
+                    failures = 0;
+                }
+
                 ctr = ctr.wrapping_add(1);
             }
         } else {
@@ -645,6 +763,7 @@ where
                 StateRestorer::from_env(&mut self.shmem_provider, _ENV_FUZZER_SENDER)?,
                 self.shmem_provider.clone(),
                 None,
+                None,
             )
         };
 
@@ -701,6 +820,14 @@ where
                     ),
                 )
             };
+        mgr.set_heartbeat(heartbeat);
+
+        // After a reboot, or a restart without the state, the checkpoint is all that is left
+        let state = match (state, &self.checkpoint) {
+            (None, Some(checkpoint)) => load_checkpoint(checkpoint)?,
+            (state, _) => state,
+        };
+
         // We reset the staterestorer, the next staterestorer and receiver (after crash) will reuse the page from the initial message.
         if self.serialize_state.oom_safe() {
             mgr.intermediate_save()?;
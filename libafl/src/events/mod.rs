@@ -21,8 +21,14 @@ pub use llmp::*;
 pub mod tcp;
 
 pub mod broker_hooks;
+pub mod delta;
+#[cfg(feature = "std")]
+pub mod recorder;
+#[cfg(feature = "std")]
+pub mod supervisor;
 use alloc::{borrow::Cow, boxed::Box, string::String, vec::Vec};
 use core::{
+    any::type_name,
     fmt,
     hash::{BuildHasher, Hasher},
     marker::PhantomData,
@@ -39,10 +45,15 @@ use libafl_bolts::os::unix_signals::{siginfo_t, ucontext_t, Signal, SignalHandle
 use libafl_bolts::os::CTRL_C_EXIT;
 use libafl_bolts::{
     current_time,
+    serdeany::SerdeAny,
     tuples::{Handle, MatchNameRef},
     ClientId,
 };
 use serde::{Deserialize, Serialize};
+#[cfg(all(feature = "std", target_os = "linux"))]
+pub use supervisor::ClientCgroups;
+#[cfg(feature = "std")]
+pub use supervisor::{ClientLimits, RestartPolicy};
 #[cfg(feature = "std")]
 use uuid::Uuid;
 
@@ -248,21 +259,6 @@ impl From<String> for EventConfig {
     }
 }
 
-/*
-/// A custom event, for own messages, with own handler.
-pub trait CustomEvent<I>: SerdeAny
-where
-    I: Input,
-{
-    /// Returns the name of this event
-    fn name(&self) -> &str;
-    /// This method will be called in the broker
-    fn handle_in_broker(&self) -> Result<BrokerEventResult, Error>;
-    /// This method will be called in the clients after handle_in_broker (unless BrokerEventResult::Handled) was returned in handle_in_broker
-    fn handle_in_client(&self) -> Result<(), Error>;
-}
-*/
-
 // TODO remove forward_id as not anymore needed for centralized
 /// Events sent around in the library
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -289,6 +285,10 @@ where
         time: Duration,
         /// The original sender if, if forwarded
         forward_id: Option<ClientId>,
+        /// The content hash of the input this testcase was mutated from, see [`delta::input_hash`].
+        /// Lets the managers send the testcase as a delta against it. Only set for the managers which
+        /// [send deltas](EventFirer::sends_deltas).
+        parent_hash: Option<u64>,
         /// The (multi-machine) node from which the tc is from, if any
         #[cfg(all(unix, feature = "std", feature = "multi_machine"))]
         node_id: Option<NodeId>,
@@ -349,11 +349,15 @@ where
     },
     /// Exit gracefully
     Stop,
-    /*/// A custom type
+    /// Sends a typed payload to other clients, see [`Event::custom`].
+    /// Handle it with a [`CustomEventHook`] in the clients, or a [`CustomEventBrokerHook`] in the broker.
     Custom {
-        // TODO: Allow custom events
-        // custom_event: Box<dyn CustomEvent<I, OT>>,
-    },*/
+        /// The [`core::any::type_name`] of the payload, the key of its type in the [`SerdeAny`] registry,
+        /// like in a [`libafl_bolts::serdeany::SerdeAnyMap`] with `stable_anymap`
+        name: Cow<'static, str>,
+        /// The payload, serialized as [`SerdeAny`], so that it deserializes through the registry
+        payload: Vec<u8>,
+    },
 }
 
 impl<I> Event<I>
@@ -371,9 +375,7 @@ where
             Event::Objective { .. } => "Objective",
             Event::Log { .. } => "Log",
             Event::CustomBuf { .. } => "CustomBuf",
-            /*Event::Custom {
-                sender_id: _, /*custom_event} => custom_event.name()*/
-            } => "todo",*/
+            Event::Custom { .. } => "Custom",
             Event::Stop => "Stop",
         }
     }
@@ -391,13 +393,55 @@ where
             Event::Objective { .. } => Cow::Borrowed("Objective"),
             Event::Log { .. } => Cow::Borrowed("Log"),
             Event::CustomBuf { .. } => Cow::Borrowed("CustomBuf"),
+            Event::Custom { name, .. } => Cow::Owned(format!("Custom {name}")),
             Event::Stop => Cow::Borrowed("Stop"),
-            /*Event::Custom {
-                sender_id: _, /*custom_event} => custom_event.name()*/
-            } => "todo",*/
         }
     }
 
+    /// Creates an [`Event::Custom`] carrying `payload`.
+    ///
+    /// The payload type must be registered with [`libafl_bolts::impl_serdeany`], to be deserialized on the
+    /// other side. The registry rejects two payload types with the same name, and a payload of another
+    /// type than its name says fails to deserialize, so the names of different crates can't collide.
+    pub fn custom<T>(payload: &T) -> Result<Self, Error>
+    where
+        T: SerdeAny,
+    {
+        Ok(Event::Custom {
+            name: Cow::Borrowed(type_name::<T>()),
+            payload: postcard::to_allocvec(payload as &dyn SerdeAny)?,
+        })
+    }
+
+    /// If this is an [`Event::Custom`] with a payload of type `T`
+    pub fn is_custom<T>(&self) -> bool
+    where
+        T: SerdeAny,
+    {
+        matches!(self, Event::Custom { name, .. } if name == type_name::<T>())
+    }
+
+    /// Deserializes the payload of an [`Event::Custom`], if it is of type `T`
+    pub fn custom_payload<T>(&self) -> Result<Option<Box<T>>, Error>
+    where
+        T: SerdeAny,
+    {
+        let Event::Custom { payload, .. } = self else {
+            return Ok(None);
+        };
+        if !self.is_custom::<T>() {
+            return Ok(None);
+        }
+        let payload: Box<dyn SerdeAny> = postcard::from_bytes(payload)?;
+        payload
+            .as_any_boxed()
+            .downcast::<T>()
+            .map(Some)
+            .map_err(|_| {
+                Error::illegal_state(format!("Mismatching payload of {}", self.name_detailed()))
+            })
+    }
+
     /// Returns true if self is a new testcase, false otherwise.
     pub fn is_new_testcase(&self) -> bool {
         matches!(self, Event::NewTestcase { .. })
@@ -453,6 +497,12 @@ pub trait EventFirer: UsesState {
 
     /// Return if we really send this event or not
     fn should_send(&self) -> bool;
+
+    /// If this manager sends new testcases as deltas against their parent, see [`delta`].
+    /// Only then, the fuzzer fills in the `parent_hash` of [`Event::NewTestcase`].
+    fn sends_deltas(&self) -> bool {
+        false
+    }
 }
 
 /// [`ProgressReporter`] report progress to the broker.
@@ -770,6 +820,11 @@ where
     fn configuration(&self) -> EventConfig {
         self.inner.configuration()
     }
+
+    #[inline]
+    fn sends_deltas(&self) -> bool {
+        self.inner.sends_deltas()
+    }
 }
 
 impl<EM, M> EventRestarter for MonitorTypedEventManager<EM, M>
@@ -942,7 +997,10 @@ pub trait AdaptiveSerializer {
 #[cfg(test)]
 mod tests {
 
-    use libafl_bolts::{current_time, tuples::tuple_list, Named};
+    use alloc::{borrow::Cow, vec::Vec};
+    use core::any::type_name;
+
+    use libafl_bolts::{current_time, serdeany::SerdeAny, tuples::tuple_list, Named};
     use tuple_list::tuple_list_type;
 
     use crate::{
@@ -973,6 +1031,7 @@ mod tests {
             client_config: EventConfig::AlwaysUnique,
             time: current_time(),
             forward_id: None,
+            parent_hash: None,
             #[cfg(all(unix, feature = "std", feature = "multi_machine"))]
             node_id: None,
         };
@@ -989,4 +1048,43 @@ mod tests {
             _ => panic!("mistmatch"),
         };
     }
+
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct Tokens {
+        tokens: Vec<Vec<u8>>,
+    }
+
+    libafl_bolts::impl_serdeany!(Tokens);
+
+    #[test]
+    fn test_custom_event_serde() {
+        #[cfg(any(not(feature = "serdeany_autoreg"), miri))]
+        unsafe {
+            Tokens::register();
+            ExitKind::register();
+        }
+
+        let tokens = Tokens {
+            tokens: vec![b"GET".to_vec(), b"POST".to_vec()],
+        };
+        let e = Event::<BytesInput>::custom(&tokens).unwrap();
+        let serialized = postcard::to_allocvec(&e).unwrap();
+        let d = postcard::from_bytes::<Event<BytesInput>>(&serialized).unwrap();
+
+        assert!(d.is_custom::<Tokens>());
+        assert_eq!(*d.custom_payload::<Tokens>().unwrap().unwrap(), tokens);
+        // Other payloads and events are left alone
+        assert!(d.custom_payload::<ExitKind>().unwrap().is_none());
+        assert!(Event::<BytesInput>::Stop
+            .custom_payload::<Tokens>()
+            .unwrap()
+            .is_none());
+
+        // A payload of another type than the name says is rejected
+        let mismatched = Event::<BytesInput>::Custom {
+            name: Cow::Borrowed(type_name::<Tokens>()),
+            payload: postcard::to_allocvec(&ExitKind::Ok as &dyn SerdeAny).unwrap(),
+        };
+        assert!(mismatched.custom_payload::<Tokens>().is_err());
+    }
 }
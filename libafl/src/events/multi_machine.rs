@@ -1,9 +1,10 @@
-use core::fmt::Display;
+use core::{fmt::Display, future::Future, iter};
 use std::{
     boxed::Box,
-    collections::HashMap,
-    io::ErrorKind,
+    collections::{HashMap, HashSet, VecDeque},
+    io::{self, ErrorKind},
     process,
+    string::{String, ToString},
     sync::{
         atomic::{AtomicU64, Ordering},
         Arc, OnceLock,
@@ -15,7 +16,12 @@ use std::{
 use enumflags2::{bitflags, BitFlags};
 #[cfg(feature = "llmp_compression")]
 use libafl_bolts::compress::GzipCompressor;
-use libafl_bolts::{current_time, ownedref::OwnedRef, Error};
+#[cfg(feature = "noise")]
+use libafl_bolts::noise::{NoiseChannel, NoiseHandshake, PreSharedKey};
+use libafl_bolts::{
+    current_time, hash_std, llmp::LLMP_MAX_REMOTE_MSG_LEN, ownedref::OwnedRef, rands::random_seed,
+    Error,
+};
 use serde::{Deserialize, Serialize};
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
@@ -28,7 +34,10 @@ use tokio::{
 use typed_builder::TypedBuilder;
 
 use crate::{
-    events::{Event, TcpMultiMachineLlmpReceiverHook, TcpMultiMachineLlmpSenderHook},
+    events::{
+        delta::{delta_id, DeltaCodec, KnownInputs},
+        Event, TcpMultiMachineLlmpReceiverHook, TcpMultiMachineLlmpSenderHook,
+    },
     inputs::{Input, NopInput},
 };
 
@@ -45,7 +54,29 @@ pub enum NodePolicy {
     SendToChildren,
 }
 
+/// Starts a frame carrying an llmp message
 const DUMMY_BYTE: u8 = 0x14;
+/// Starts a frame carrying a [`NodeControlMsg`]
+const CONTROL_BYTE: u8 = 0x15;
+/// Starts a frame carrying an llmp message, encoded as delta by a [`DeltaCodec`]
+const DELTA_BYTE: u8 = 0x16;
+/// Starts a frame carrying an old llmp message, replayed to a new link. Its testcase is no base for deltas:
+/// the sender does not remember the replayed testcases.
+const REPLAY_BYTE: u8 = 0x17;
+/// Starts a frame asking the other node to resend a delta in full, as its base is unknown here.
+/// The body is the [`delta_id`] of the delta.
+const NACK_BYTE: u8 = 0x18;
+
+/// The largest frame body read from a link, with room for the kind byte and the authentication tags
+#[cfg(feature = "noise")]
+const MAX_FRAME_LEN: usize = NoiseChannel::ciphertext_len(LLMP_MAX_REMOTE_MSG_LEN + 1);
+/// The largest frame body read from a link
+#[cfg(not(feature = "noise"))]
+const MAX_FRAME_LEN: usize = LLMP_MAX_REMOTE_MSG_LEN;
+
+/// Time a node waits for the other side of a new link during the `Noise` handshake
+#[cfg(feature = "noise")]
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
 
 /// Use `OwnedRef` as much as possible here to avoid useless copies.
 /// An owned TCP message for multi machine
@@ -120,16 +151,184 @@ impl NodeId {
     }
 }
 
+/// A link to another node, encrypted if the nodes share a `PreSharedKey`
+#[derive(Debug)]
+struct NodeLink {
+    stream: TcpStream,
+    #[cfg(feature = "noise")]
+    channel: Option<NoiseChannel>,
+    /// The content hashes of the inputs sent or received on this link, the bases for deltas
+    known_inputs: KnownInputs,
+    /// The inputs of the testcases sent or received on this link, to send and receive testcases as deltas
+    delta_codec: DeltaCodec,
+}
+
+impl NodeLink {
+    /// Sets up a new link. With a key in the [`NodeDescriptor`], runs the `Noise` handshake first,
+    /// which fails for nodes without the key.
+    #[cfg_attr(not(feature = "noise"), allow(clippy::unused_async))]
+    async fn new<A>(
+        stream: TcpStream,
+        node_descriptor: &NodeDescriptor<A>,
+        initiator: bool,
+    ) -> Result<Self, Error> {
+        #[cfg(feature = "noise")]
+        if let Some(psk) = &node_descriptor.psk {
+            return Self::handshake(stream, psk, initiator).await;
+        }
+        #[cfg(not(feature = "noise"))]
+        let _ = (node_descriptor, initiator);
+        Ok(Self {
+            stream,
+            #[cfg(feature = "noise")]
+            channel: None,
+            known_inputs: KnownInputs::new(),
+            delta_codec: DeltaCodec::new(),
+        })
+    }
+
+    #[cfg(feature = "noise")]
+    async fn handshake(
+        mut stream: TcpStream,
+        psk: &PreSharedKey,
+        initiator: bool,
+    ) -> Result<Self, Error> {
+        let mut handshake = if initiator {
+            NoiseHandshake::initiator(psk)?
+        } else {
+            NoiseHandshake::responder(psk)?
+        };
+        // Same framing as `NoiseChannel::connect`: each handshake message is prefixed by its u16 be length
+        time::timeout(HANDSHAKE_TIMEOUT, async {
+            while !handshake.is_finished() {
+                if handshake.is_my_turn() {
+                    let msg = handshake.write_message()?;
+                    stream.write_all(&(msg.len() as u16).to_be_bytes()).await?;
+                    stream.write_all(&msg).await?;
+                } else {
+                    let mut len = [0; 2];
+                    stream.read_exact(&mut len).await?;
+                    let mut msg = vec![0; u16::from_be_bytes(len).into()];
+                    stream.read_exact(&mut msg).await?;
+                    handshake.read_message(&msg)?;
+                }
+            }
+            Ok::<(), Error>(())
+        })
+        .await
+        .map_err(|_| Error::illegal_state("Timeout during the Noise handshake"))??;
+
+        Ok(Self {
+            stream,
+            channel: Some(handshake.into_channel()?),
+            known_inputs: KnownInputs::new(),
+            delta_codec: DeltaCodec::new(),
+        })
+    }
+}
+
+/// A node above this one in the tree
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct NodeAncestor {
+    /// The random identifier of the node
+    node_uid: u64,
+    /// The address the node was reached at
+    addr: String,
+}
+
+/// Messages a node sends to its children to maintain the tree
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum NodeControlMsg {
+    /// The first message on a new link: who the parent is, and the ancestors of the parent, closest first
+    Welcome {
+        /// The random identifier of the parent
+        node_uid: u64,
+        /// The ancestors of the parent
+        ancestors: Vec<NodeAncestor>,
+    },
+    /// The ancestors of the parent changed, after it re-parented
+    Ancestors(Vec<NodeAncestor>),
+}
+
+/// A testcase seen by this node, to bootstrap nodes joining later
+#[derive(Debug)]
+struct PastMsg {
+    msg: Arc<[u8]>,
+    hash: u64,
+    /// If it came from a parent, and must not be sent back up
+    from_parent: bool,
+}
+
+/// The testcases seen by this node, oldest first.
+/// A testcase seen twice is only kept once, and once full, the oldest ones are forgotten.
+#[derive(Debug)]
+struct PastMsgs {
+    msgs: VecDeque<PastMsg>,
+    hashes: HashSet<u64>,
+    /// The number of testcases ever kept, the sequence number of the next one
+    next_seq: u64,
+    capacity: usize,
+}
+
+impl PastMsgs {
+    fn new(capacity: usize) -> Self {
+        Self {
+            msgs: VecDeque::new(),
+            hashes: HashSet::new(),
+            next_seq: 0,
+            capacity,
+        }
+    }
+
+    /// Keeps a testcase, unless it is already known
+    fn push(&mut self, msg: &[u8], from_parent: bool) {
+        let hash = hash_std(msg);
+        if self.capacity == 0 || !self.hashes.insert(hash) {
+            return;
+        }
+        if self.msgs.len() == self.capacity {
+            if let Some(oldest) = self.msgs.pop_front() {
+                self.hashes.remove(&oldest.hash);
+            }
+        }
+        self.msgs.push_back(PastMsg {
+            msg: msg.into(),
+            hash,
+            from_parent,
+        });
+        self.next_seq += 1;
+    }
+
+    /// The testcases kept since the sequence number `seq` (or the oldest ones still around), and the next sequence number.
+    /// The testcases which came from a parent are left out, unless `from_parent` is set.
+    fn since(&self, seq: u64, from_parent: bool) -> (Vec<Arc<[u8]>>, u64) {
+        let first_seq = self.next_seq - self.msgs.len() as u64;
+        let skip = seq.saturating_sub(first_seq) as usize;
+        let msgs = self
+            .msgs
+            .iter()
+            .skip(skip)
+            .filter(|past_msg| from_parent || !past_msg.from_parent)
+            .map(|past_msg| past_msg.msg.clone())
+            .collect();
+        (msgs, self.next_seq)
+    }
+}
+
 /// The state of the hook shared between the background threads and the main thread.
 #[derive(Debug)]
 #[allow(dead_code)]
 pub struct TcpMultiMachineState<A> {
     node_descriptor: NodeDescriptor<A>,
+    /// A random identifier of this node, to detect cycles when re-parenting
+    node_uid: u64,
     /// the parent to which the testcases should be forwarded when deemed interesting
-    parent: Option<TcpStream>,
+    parent: Option<NodeLink>,
+    /// The current (or last) parent, followed by its ancestors. The candidates to re-parent to if the parent dies.
+    ancestors: Vec<NodeAncestor>,
     /// The children who connected during the fuzzing session.
-    children: HashMap<NodeId, TcpStream>, // The children who connected during the fuzzing session.
-    old_msgs: Vec<Vec<u8>>,
+    children: HashMap<NodeId, NodeLink>, // The children who connected during the fuzzing session.
+    old_msgs: PastMsgs,
     #[cfg(feature = "llmp_compression")]
     compressor: GzipCompressor,
 }
@@ -148,9 +347,36 @@ pub struct NodeDescriptor<A> {
     /// The timeout for connecting to parent
     pub timeout: Duration,
 
+    /// Other nodes to connect to, in order, if the parent can not be reached.
+    /// Once connected, the ancestors of the parent are tried as well.
+    #[builder(default = Vec::new())]
+    pub fallback_parent_addrs: Vec<A>,
+
+    /// The delay before trying to reconnect after losing the parent. Doubles after each failed round.
+    #[builder(default = Duration::from_secs(1))]
+    pub reconnect_backoff: Duration,
+
+    /// The maximum delay between two reconnection rounds
+    #[builder(default = Duration::from_secs(60))]
+    pub max_reconnect_backoff: Duration,
+
+    /// How long to wait for a stalled node before dropping the link to it
+    #[builder(default = Duration::from_secs(10))]
+    pub io_timeout: Duration,
+
+    /// How many testcases to keep, to bootstrap nodes joining later. The oldest ones are forgotten first.
+    #[builder(default = 100_000)]
+    pub max_old_msgs: usize,
+
     /// Node flags
     #[builder(default_code = "BitFlags::default()")]
     pub flags: BitFlags<NodePolicy>, // The policy for shared messages between nodes.
+
+    /// The key authenticating and encrypting the links to the parent and the children.
+    /// Nodes without the same key are rejected. If `None`, the links are in cleartext.
+    #[cfg(feature = "noise")]
+    #[builder(default)]
+    pub psk: Option<PreSharedKey>,
 }
 
 /// A set of multi-machine `broker_hooks`.
@@ -234,20 +460,13 @@ where
 
         // Create the state of the hook. This will be shared with the background server, so we wrap
         // it with concurrent-safe objects
-        let state = Arc::new(RwLock::new(TcpMultiMachineState {
-            node_descriptor,
-            parent: None,
-            children: HashMap::default(),
-            old_msgs: Vec::new(),
-            #[cfg(feature = "llmp_compression")]
-            compressor: GzipCompressor::new(),
-        }));
+        let state = Arc::new(RwLock::new(TcpMultiMachineState::new(node_descriptor)));
 
         let rt =
             Arc::new(Runtime::new().map_err(|_| Error::unknown("Tokio runtime spawning failed"))?);
 
         unsafe {
-            TcpMultiMachineState::init::<I>(&state.clone(), &rt.clone())?;
+            TcpMultiMachineState::init(&state.clone(), &rt.clone())?;
         }
 
         Ok(TcpMultiMachineHooks {
@@ -261,49 +480,62 @@ impl<A> TcpMultiMachineState<A>
 where
     A: Clone + Display + ToSocketAddrs + Send + Sync + 'static,
 {
+    /// A new, unconnected node
+    fn new(node_descriptor: NodeDescriptor<A>) -> Self {
+        let old_msgs = PastMsgs::new(node_descriptor.max_old_msgs);
+        Self {
+            node_descriptor,
+            node_uid: random_seed(),
+            parent: None,
+            ancestors: Vec::new(),
+            children: HashMap::default(),
+            old_msgs,
+            #[cfg(feature = "llmp_compression")]
+            compressor: GzipCompressor::new(),
+        }
+    }
+
     /// Initializes the Multi-Machine state.
     ///
     /// # Safety
     ///
     /// This should be run **only once**, in the same process as the llmp hooks, and before the hooks
     /// are effectively used.
-    unsafe fn init<I: Input>(
-        self_mutex: &Arc<RwLock<Self>>,
-        rt: &Arc<Runtime>,
-    ) -> Result<(), Error> {
+    unsafe fn init(self_mutex: &Arc<RwLock<Self>>, rt: &Arc<Runtime>) -> Result<(), Error> {
         let node_descriptor =
             rt.block_on(async { self_mutex.read().await.node_descriptor.clone() });
 
         // Try to connect to the parent if we should
-        rt.block_on(async {
-            let parent_mutex = self_mutex.clone();
-            let mut parent_lock = parent_mutex.write().await;
-
-            if let Some(parent_addr) = &parent_lock.node_descriptor.parent_addr {
-                let timeout = current_time() + parent_lock.node_descriptor.timeout;
+        let has_parent = rt.block_on(async {
+            let candidates = self_mutex.read().await.parent_candidates();
+            if candidates.is_empty() {
+                return Ok(false);
+            }
 
-                parent_lock.parent = loop {
+            let timeout = current_time() + node_descriptor.timeout;
+            loop {
+                for parent_addr in &candidates {
                     log::debug!("Trying to connect to parent @ {}..", parent_addr);
-                    match TcpStream::connect(parent_addr).await {
-                        Ok(stream) => {
-                            log::debug!("Connected to parent @ {}", parent_addr);
-
-                            break Some(stream);
-                        }
-                        Err(e) => {
-                            if current_time() > timeout {
-                                return Err(Error::os_error(e, "Unable to connect to parent"));
-                            }
-                        }
+                    match Self::connect_to_parent(self_mutex, parent_addr).await {
+                        Ok(()) => return Ok(true),
+                        Err(e) => log::debug!("Unable to connect to parent @ {parent_addr}: {e:?}"),
                     }
+                }
 
-                    time::sleep(Duration::from_secs(1)).await;
-                };
+                if current_time() > timeout {
+                    return Err(Error::illegal_state(format!(
+                        "Unable to connect to any parent of {candidates:?}"
+                    )));
+                }
+                time::sleep(Duration::from_secs(1)).await;
             }
-
-            Ok(())
         })?;
 
+        // Reconnect in the background if the parent goes away
+        if has_parent {
+            let _handle: JoinHandle<()> = rt.spawn(Self::supervise_parent(self_mutex.clone()));
+        }
+
         // Now, setup the background tasks for the children to connect to
         if let Some(listening_port) = node_descriptor.node_listening_port {
             let bg_state = self_mutex.clone();
@@ -316,28 +548,28 @@ where
                 let state = bg_state;
 
                 // The main listening loop. Should never fail.
-                'listening: loop {
+                loop {
                     log::debug!("listening for children on {:?}...", listener);
                     match listener.accept().await {
-                        Ok((mut stream, addr)) => {
-                            log::debug!("{} joined the children.", addr);
-                            let mut state_guard = state.write().await;
-
-                            if let Err(e) = state_guard
-                                .send_old_events_to_stream::<I>(&mut stream)
-                                .await
-                            {
-                                log::error!("Error while send old messages: {e:?}.");
-                                log::error!("The loop will resume");
-                                continue 'listening;
-                            }
-
-                            state_guard.children.insert(NodeId::new(), stream);
-                            log::debug!(
-                                "[pid {}]{addr} added the child. nb children: {}",
-                                process::id(),
-                                state_guard.children.len()
-                            );
+                        Ok((stream, addr)) => {
+                            // The handshake waits for the child, don't keep other children from joining meanwhile
+                            let state = state.clone();
+                            let node_descriptor = node_descriptor.clone();
+                            let _handle: JoinHandle<()> = tokio::spawn(async move {
+                                let link =
+                                    match NodeLink::new(stream, &node_descriptor, false).await {
+                                        Ok(link) => link,
+                                        Err(e) => {
+                                            log::warn!("Rejecting child {addr}: {e:?}");
+                                            return;
+                                        }
+                                    };
+                                log::debug!("{} joined the children.", addr);
+
+                                if let Err(e) = Self::welcome_child(&state, link).await {
+                                    log::error!("Error while welcoming the child {addr}: {e:?}.");
+                                }
+                            });
                         }
                         Err(e) => {
                             log::error!("Error while accepting child {e:?}.");
@@ -350,121 +582,480 @@ where
         Ok(())
     }
 
-    /// Add an event as past event.
-    pub fn add_past_msg(&mut self, msg: &[u8]) {
-        self.old_msgs.push(msg.to_vec());
+    /// The addresses to try, in order, to (re)connect to a parent.
+    /// The configured parent and fallbacks first, then the known ancestors.
+    fn parent_candidates(&self) -> Vec<String> {
+        let configured = self
+            .node_descriptor
+            .parent_addr
+            .iter()
+            .chain(&self.node_descriptor.fallback_parent_addrs)
+            .map(ToString::to_string);
+        let ancestors = self.ancestors.iter().map(|ancestor| ancestor.addr.clone());
+
+        let mut candidates: Vec<String> = Vec::new();
+        for addr in configured.chain(ancestors) {
+            if !candidates.contains(&addr) {
+                candidates.push(addr);
+            }
+        }
+        candidates
     }
 
-    /// The compressor
-    #[cfg(feature = "llmp_compression")]
-    pub fn compressor(&mut self) -> &GzipCompressor {
-        &self.compressor
+    /// If this node would be its own ancestor in the given chain
+    fn creates_cycle(&self, ancestors: &[NodeAncestor]) -> bool {
+        ancestors
+            .iter()
+            .any(|ancestor| ancestor.node_uid == self.node_uid)
+    }
+
+    /// Connects to the node at `parent_addr` and makes it the parent.
+    /// Fails if the node is unreachable, not authenticated, or a descendant of this node.
+    async fn connect_to_parent(
+        self_mutex: &Arc<RwLock<Self>>,
+        parent_addr: &str,
+    ) -> Result<(), Error> {
+        let node_descriptor = self_mutex.read().await.node_descriptor.clone();
+        let io_timeout = node_descriptor.io_timeout;
+
+        let stream = with_io_timeout(io_timeout, TcpStream::connect(parent_addr)).await?;
+        let addr = stream.peer_addr()?.to_string();
+        let mut link = NodeLink::new(stream, &node_descriptor, true).await?;
+
+        // The parent introduces itself first
+        let (kind, msg) = Self::recv_frame(&mut link, io_timeout).await?;
+        let welcome = (kind == CONTROL_BYTE)
+            .then(|| postcard::from_bytes(&msg))
+            .transpose()?;
+        let Some(NodeControlMsg::Welcome {
+            node_uid,
+            ancestors,
+        }) = welcome
+        else {
+            return Err(Error::illegal_state(format!(
+                "The node @ {parent_addr} did not welcome us"
+            )));
+        };
+
+        let parent = NodeAncestor { node_uid, addr };
+        Self::attach_parent(self_mutex, link, parent, ancestors).await
     }
 
-    /// Read a [`TcpMultiMachineMsg`] from a stream.
-    /// Expects a message written by [`TcpMultiMachineState::write_msg`].
-    /// If there is nothing to read from the stream, return asap with Ok(None).
-    #[allow(clippy::uninit_vec)]
-    async fn read_msg<'a, I: Input + 'a>(
-        stream: &mut TcpStream,
-    ) -> Result<Option<MultiMachineMsg<'a, I>>, Error> {
-        // 0. Check if we should try to fetch something from the stream
-        let mut dummy_byte: [u8; 1] = [0u8];
-        log::debug!("Starting read msg...");
-
-        let n_read = match stream.try_read(&mut dummy_byte) {
-            Ok(n) => n,
-            Err(e) if e.kind() == ErrorKind::WouldBlock => {
-                return Ok(None);
+    /// Makes `link` the parent.
+    /// Sends it the testcases of this subtree, and tells the children about their new ancestors.
+    /// Like in [`Self::welcome_child`], the testcases are replayed without holding the lock.
+    async fn attach_parent(
+        self_mutex: &Arc<RwLock<Self>>,
+        mut link: NodeLink,
+        parent: NodeAncestor,
+        ancestors: Vec<NodeAncestor>,
+    ) -> Result<(), Error> {
+        let (send_to_parent, io_timeout) = {
+            let state = self_mutex.read().await;
+            // Refuse descendants before replaying anything to them
+            if parent.node_uid == state.node_uid || state.creates_cycle(&ancestors) {
+                return Err(Error::illegal_state(format!(
+                    "The node @ {} is this node or one of its descendants",
+                    parent.addr
+                )));
             }
-            Err(e) => return Err(Error::os_error(e, "try read failed")),
+            (
+                state
+                    .node_descriptor
+                    .flags
+                    .intersects(NodePolicy::SendToParent),
+                state.node_descriptor.io_timeout,
+            )
         };
 
-        log::debug!("msg read.");
+        // Replay the history without holding the lock, until nothing new came in meanwhile.
+        // From then on, the parent gets everything as a parent.
+        let mut next_seq = 0;
+        let mut state = loop {
+            if send_to_parent {
+                let (old_msgs, seq) = self_mutex.read().await.old_msgs.since(next_seq, false);
+                for old_msg in &old_msgs {
+                    Self::write_frame(&mut link, REPLAY_BYTE, old_msg, io_timeout).await?;
+                }
+                next_seq = seq;
+            }
+
+            let state = self_mutex.write().await;
+            if !send_to_parent || state.old_msgs.since(next_seq, false).0.is_empty() {
+                break state;
+            }
+        };
+
+        log::info!("Attached to parent @ {}", parent.addr);
+        state.ancestors = iter::once(parent).chain(ancestors).collect();
+        state.parent = Some(link);
+
+        let ancestors = NodeControlMsg::Ancestors(state.ancestors.clone());
+        state.send_control_to_children(&ancestors).await;
+        Ok(())
+    }
+
+    /// Watches the link to the parent. Once it is lost, tries to reconnect to it, or to the next
+    /// [candidate](Self::parent_candidates), with an exponential backoff.
+    async fn supervise_parent(self_mutex: Arc<RwLock<Self>>) {
+        let (min_backoff, max_backoff) = {
+            let state = self_mutex.read().await;
+            (
+                state.node_descriptor.reconnect_backoff,
+                state.node_descriptor.max_reconnect_backoff,
+            )
+        };
+        let mut backoff = min_backoff;
 
-        if n_read == 0 {
-            log::debug!("No dummy byte received...");
-            return Ok(None); // Nothing to read from this stream
+        loop {
+            if self_mutex.read().await.parent.is_some() {
+                backoff = min_backoff;
+                time::sleep(min_backoff).await;
+                continue;
+            }
+
+            let candidates = self_mutex.read().await.parent_candidates();
+            let mut connected = false;
+            for parent_addr in &candidates {
+                log::debug!("Trying to reconnect to parent @ {}..", parent_addr);
+                match Self::connect_to_parent(&self_mutex, parent_addr).await {
+                    Ok(()) => {
+                        connected = true;
+                        break;
+                    }
+                    Err(e) => log::debug!("Unable to connect to parent @ {parent_addr}: {e:?}"),
+                }
+            }
+
+            if !connected {
+                log::warn!("No parent reachable, retrying in {backoff:?}");
+                time::sleep(backoff).await;
+                backoff = (backoff * 2).min(max_backoff);
+            }
         }
+    }
 
-        log::debug!("Received dummy byte!");
+    /// Introduces this node to a new child, bootstraps it with all the testcases seen so far,
+    /// and adds it to the children.
+    /// The testcases are replayed without holding the lock, so a slow child does not stall the node meanwhile.
+    async fn welcome_child(
+        self_mutex: &Arc<RwLock<Self>>,
+        mut link: NodeLink,
+    ) -> Result<(), Error> {
+        let (node_uid, ancestors, io_timeout) = {
+            let state = self_mutex.read().await;
+            (
+                state.node_uid,
+                state.ancestors.clone(),
+                state.node_descriptor.io_timeout,
+            )
+        };
+        let welcome = NodeControlMsg::Welcome {
+            node_uid,
+            ancestors: ancestors.clone(),
+        };
+        Self::write_frame(
+            &mut link,
+            CONTROL_BYTE,
+            &postcard::to_allocvec(&welcome)?,
+            io_timeout,
+        )
+        .await?;
+
+        log::debug!("Send old events to new child...");
+        // Send without holding the lock, until nothing changed meanwhile.
+        // From then on, the child gets everything as a child.
+        let mut next_seq = 0;
+        let mut nb_sent = 0;
+        let mut ancestors = ancestors;
+        let mut state = loop {
+            let (old_msgs, seq) = self_mutex.read().await.old_msgs.since(next_seq, true);
+            for old_msg in &old_msgs {
+                Self::write_frame(&mut link, REPLAY_BYTE, old_msg, io_timeout).await?;
+            }
+            nb_sent += old_msgs.len();
+            next_seq = seq;
+
+            let state = self_mutex.write().await;
+            if !state.old_msgs.since(next_seq, true).0.is_empty() {
+                continue;
+            }
+            if state.ancestors == ancestors {
+                break state;
+            }
+            ancestors.clone_from(&state.ancestors);
+            drop(state);
+            let msg = postcard::to_allocvec(&NodeControlMsg::Ancestors(ancestors.clone()))?;
+            Self::write_frame(&mut link, CONTROL_BYTE, &msg, io_timeout).await?;
+        };
+        log::debug!("Sent {nb_sent} old messages.");
 
-        // we should always read the dummy byte at this point.
-        assert_eq!(u8::from_le_bytes(dummy_byte), DUMMY_BYTE);
+        state.children.insert(NodeId::new(), link);
+        log::debug!(
+            "[pid {}] added the child. nb children: {}",
+            process::id(),
+            state.children.len()
+        );
+        Ok(())
+    }
 
-        // 1. Read msg size
-        let mut node_msg_len: [u8; 4] = [0; 4];
-        log::debug!("Receiving msg len...");
-        stream.read_exact(&mut node_msg_len).await?;
-        log::debug!("msg len received.");
-        let node_msg_len = u32::from_le_bytes(node_msg_len) as usize;
+    /// Sends a control message to all children, dropping the ones that disconnected
+    async fn send_control_to_children(&mut self, msg: &NodeControlMsg) {
+        let msg = match postcard::to_allocvec(msg) {
+            Ok(msg) => msg,
+            Err(e) => {
+                log::error!("Could not serialize control message: {e:?}");
+                return;
+            }
+        };
 
-        // 2. Read msg
-        // do not store msg on the stack to avoid overflow issues
-        // TODO: optimize with less allocations...
-        let mut node_msg: Vec<u8> = Vec::with_capacity(node_msg_len);
-        unsafe {
-            node_msg.set_len(node_msg_len);
+        let mut ids_to_remove: Vec<NodeId> = Vec::new();
+        for (child_id, child_link) in &mut self.children {
+            if let Err(e) = Self::write_frame(
+                child_link,
+                CONTROL_BYTE,
+                &msg,
+                self.node_descriptor.io_timeout,
+            )
+            .await
+            {
+                log::debug!("The child {child_id:?} disconnected. Error: {e:?}");
+                ids_to_remove.push(*child_id);
+            }
+        }
+        for id_to_remove in &ids_to_remove {
+            self.children.remove(id_to_remove);
         }
-        log::debug!("Receiving msg...");
-        stream.read_exact(node_msg.as_mut_slice()).await?;
-        log::debug!("msg received.");
-        let node_msg = node_msg.into_boxed_slice();
+    }
 
-        Ok(Some(MultiMachineMsg::from_llmp_msg(node_msg)))
+    /// Add an event as past event.
+    pub fn add_past_msg(&mut self, msg: &[u8]) {
+        self.old_msgs.push(msg, false);
     }
 
-    /// Write an [`OwnedTcpMultiMachineMsg`] to a stream.
-    /// Can be read back using [`TcpMultiMachineState::read_msg`].
-    async fn write_msg<'a, I: Input>(
-        stream: &mut TcpStream,
-        msg: &MultiMachineMsg<'a, I>,
-    ) -> Result<(), Error> {
-        let serialized_msg = msg.serialize_as_ref();
-        let msg_len = u32::to_le_bytes(serialized_msg.len() as u32);
+    /// The compressor
+    #[cfg(feature = "llmp_compression")]
+    pub fn compressor(&mut self) -> &GzipCompressor {
+        &self.compressor
+    }
 
-        // 0. Write the dummy byte
-        log::debug!("Sending dummy byte...");
-        stream.write_all(&[DUMMY_BYTE]).await?;
-        log::debug!("dummy byte sent.");
+    /// Reads a frame from a link, if one is available. Expects a frame written by [`Self::write_frame`].
+    /// If there is nothing to read from the link, return asap with Ok(None).
+    /// A closed, stalled or tampered link is reported as [`Error::OsError`].
+    async fn poll_frame(
+        link: &mut NodeLink,
+        io_timeout: Duration,
+    ) -> Result<Option<(u8, Vec<u8>)>, Error> {
+        let mut kind = [0_u8; 1];
+        match link.stream.try_read(&mut kind) {
+            Ok(0) => Err(Error::os_error(
+                io::Error::from(ErrorKind::UnexpectedEof),
+                "The node closed the link",
+            )),
+            Ok(_) => Self::read_frame_body(link, kind[0], io_timeout)
+                .await
+                .map(Some),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(Error::os_error(e, "try read failed")),
+        }
+    }
 
-        // 1. Write msg size
-        log::debug!("Sending msg len...");
-        stream.write_all(&msg_len).await?;
-        log::debug!("msg len sent.");
+    /// Waits for the next frame on a link
+    async fn recv_frame(link: &mut NodeLink, io_timeout: Duration) -> Result<(u8, Vec<u8>), Error> {
+        let mut kind = [0_u8; 1];
+        with_io_timeout(io_timeout, link.stream.read_exact(&mut kind)).await?;
+        Self::read_frame_body(link, kind[0], io_timeout).await
+    }
 
-        // 2. Write msg
-        log::debug!("Sending msg...");
-        stream.write_all(serialized_msg).await?;
-        log::debug!("msg sent.");
+    /// Reads the length and the body of a frame of the given kind, and decrypts it
+    async fn read_frame_body(
+        link: &mut NodeLink,
+        kind: u8,
+        io_timeout: Duration,
+    ) -> Result<(u8, Vec<u8>), Error> {
+        if ![DUMMY_BYTE, CONTROL_BYTE, DELTA_BYTE, REPLAY_BYTE, NACK_BYTE].contains(&kind) {
+            return Err(Error::os_error(
+                io::Error::new(ErrorKind::InvalidData, format!("Unknown frame {kind:#x}")),
+                "The link is out of sync",
+            ));
+        }
 
-        Ok(())
+        let stream = &mut link.stream;
+        let msg = with_io_timeout(io_timeout, async {
+            let mut msg_len = [0_u8; 4];
+            stream.read_exact(&mut msg_len).await?;
+            // The length is not authenticated yet, don't let the peer pick the size of the allocation
+            let msg_len = u32::from_le_bytes(msg_len) as usize;
+            if msg_len > MAX_FRAME_LEN {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Frame of {msg_len} bytes, the limit is {MAX_FRAME_LEN} bytes"),
+                ));
+            }
+            // do not store msg on the stack to avoid overflow issues
+            let mut msg = vec![0; msg_len];
+            stream.read_exact(&mut msg).await?;
+            Ok::<_, io::Error>(msg)
+        })
+        .await?;
+
+        #[cfg(feature = "noise")]
+        let msg = match &mut link.channel {
+            Some(channel) => {
+                let mut msg = channel.decrypt(&msg).map_err(|e| {
+                    Error::os_error(
+                        io::Error::new(ErrorKind::InvalidData, e.to_string()),
+                        "Received a message failing authentication",
+                    )
+                })?;
+                // The kind is sent in cleartext too, but only the authenticated copy counts
+                if msg.first() != Some(&kind) {
+                    return Err(Error::os_error(
+                        io::Error::new(ErrorKind::InvalidData, format!("Forged frame {kind:#x}")),
+                        "Received a message failing authentication",
+                    ));
+                }
+                msg.remove(0);
+                msg
+            }
+            None => msg,
+        };
+        Ok((kind, msg))
     }
 
-    pub(crate) async fn send_old_events_to_stream<I: Input>(
-        &mut self,
-        stream: &mut TcpStream,
+    /// Writes a frame to a link: the kind byte, the `u32` length, and the (encrypted) body.
+    /// Encrypted bodies start with the kind byte, to authenticate it.
+    /// Can be read back using [`Self::poll_frame`].
+    async fn write_frame(
+        link: &mut NodeLink,
+        kind: u8,
+        msg: &[u8],
+        io_timeout: Duration,
     ) -> Result<(), Error> {
-        log::debug!("Send old events to new child...");
-
-        for old_msg in &self.old_msgs {
-            let event_ref: MultiMachineMsg<I> =
-                MultiMachineMsg::llmp_msg(OwnedRef::Ref(old_msg.as_slice()));
-            log::debug!("Sending an old message...");
-            Self::write_msg(stream, &event_ref).await?;
-            log::debug!("Old message sent.");
+        #[cfg(feature = "noise")]
+        let encrypted_msg;
+        #[cfg(feature = "noise")]
+        let msg = match &mut link.channel {
+            Some(channel) => {
+                let mut framed_msg = Vec::with_capacity(1 + msg.len());
+                framed_msg.push(kind);
+                framed_msg.extend_from_slice(msg);
+                encrypted_msg = channel.encrypt(&framed_msg)?;
+                encrypted_msg.as_slice()
+            }
+            None => msg,
+        };
+        if msg.len() > MAX_FRAME_LEN {
+            return Err(Error::illegal_argument(format!(
+                "Trying to send a frame of {} bytes, the limit is {MAX_FRAME_LEN} bytes",
+                msg.len()
+            )));
         }
+        let msg_len = u32::to_le_bytes(msg.len() as u32);
+
+        let stream = &mut link.stream;
+        with_io_timeout(io_timeout, async {
+            stream.write_all(&[kind]).await?;
+            stream.write_all(&msg_len).await?;
+            stream.write_all(msg).await
+        })
+        .await
+    }
 
-        log::debug!("Sent {} old messages.", self.old_msgs.len());
+    /// Writes an llmp message to a link. A new testcase goes as a delta against its parent input, if the
+    /// other node has it.
+    async fn write_event<I: Input>(
+        link: &mut NodeLink,
+        event: Option<&Event<I>>,
+        msg: &[u8],
+        io_timeout: Duration,
+    ) -> Result<(), Error> {
+        let Some(event) = event else {
+            return Self::write_frame(link, DUMMY_BYTE, msg, io_timeout).await;
+        };
 
+        let known_inputs = &link.known_inputs;
+        match link
+            .delta_codec
+            .encode(event, msg, |hash| known_inputs.contains(hash))?
+        {
+            Some(delta) => Self::write_frame(link, DELTA_BYTE, &delta, io_timeout).await?,
+            None => Self::write_frame(link, DUMMY_BYTE, msg, io_timeout).await?,
+        }
+        if let Some(input_hash) = link.delta_codec.remember(event)? {
+            link.known_inputs.insert(input_hash);
+        }
         Ok(())
     }
 
+    /// Rebuilds an llmp message received on a link, and remembers its input as known to the other node.
+    /// Returns `None` for a delta against an input this node does not have (anymore), after asking the
+    /// other node to resend it in full.
+    async fn accept_event<I: Input>(
+        link: &mut NodeLink,
+        kind: u8,
+        msg: Vec<u8>,
+        io_timeout: Duration,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        // The other node did not remember the testcases it replayed
+        if kind == REPLAY_BYTE {
+            return Ok(Some(msg));
+        }
+        let msg = if kind == DELTA_BYTE {
+            match link.delta_codec.decode(&msg) {
+                Ok(msg) => msg,
+                Err(Error::KeyNotFound(e, _)) => {
+                    log::debug!("Asking for a testcase in full: {e}");
+                    Self::write_frame(link, NACK_BYTE, &delta_id(&msg).to_le_bytes(), io_timeout)
+                        .await?;
+                    return Ok(None);
+                }
+                Err(e) => return Err(e),
+            }
+        } else {
+            msg
+        };
+
+        if let Ok(event) = postcard::from_bytes::<Event<I>>(&msg) {
+            if let Some(input_hash) = link.delta_codec.remember(&event)? {
+                link.known_inputs.insert(input_hash);
+            }
+        }
+        Ok(Some(msg))
+    }
+
+    /// Resends the delta the other node could not rebuild, named by the body of a [`NACK_BYTE`] frame, in full.
+    /// The other node gets no more deltas against the missing base.
+    async fn resend_in_full(
+        link: &mut NodeLink,
+        nack: &[u8],
+        io_timeout: Duration,
+    ) -> Result<(), Error> {
+        let delta_id = u64::from_le_bytes(
+            nack.try_into()
+                .map_err(|_| Error::illegal_argument("Invalid resend request"))?,
+        );
+        match link.delta_codec.take_sent(delta_id) {
+            Some((base, msg)) => {
+                link.known_inputs.remove(base);
+                Self::write_frame(link, DUMMY_BYTE, &msg, io_timeout).await
+            }
+            None => {
+                log::warn!("Can not resend a testcase, it is too old. Dropping it.");
+                Ok(())
+            }
+        }
+    }
+
     pub(crate) async fn send_interesting_event_to_nodes<'a, I: Input>(
         &mut self,
         msg: &MultiMachineMsg<'a, I>,
     ) -> Result<(), Error> {
         log::debug!("Sending interesting events to nodes...");
+        let io_timeout = self.node_descriptor.io_timeout;
+
+        // Messages which are no events are forwarded as they are
+        let event = postcard::from_bytes::<Event<I>>(msg.serialize_as_ref()).ok();
 
         if self
             .node_descriptor
@@ -473,10 +1064,11 @@ where
         {
             if let Some(parent) = &mut self.parent {
                 log::debug!("Sending to parent...");
-                if let Err(e) = Self::write_msg(parent, msg).await {
-                    log::error!(
-                        "The parent disconnected. We won't try to communicate with it again."
-                    );
+                if let Err(e) =
+                    Self::write_event(parent, event.as_ref(), msg.serialize_as_ref(), io_timeout)
+                        .await
+                {
+                    log::error!("The parent disconnected. We will try to reconnect.");
                     log::error!("Error: {e:?}");
                     self.parent.take();
                 }
@@ -489,9 +1081,16 @@ where
             .intersects(NodePolicy::SendToChildren)
         {
             let mut ids_to_remove: Vec<NodeId> = Vec::new();
-            for (child_id, child_stream) in &mut self.children {
+            for (child_id, child_link) in &mut self.children {
                 log::debug!("Sending to child {child_id:?}...");
-                if let Err(err) = Self::write_msg(child_stream, msg).await {
+                if let Err(err) = Self::write_event(
+                    child_link,
+                    event.as_ref(),
+                    msg.serialize_as_ref(),
+                    io_timeout,
+                )
+                .await
+                {
                     // most likely the child disconnected. drop the connection later on and continue.
                     log::debug!(
                         "The child disconnected. We won't try to communicate with it again. Error: {err:?}"
@@ -512,44 +1111,71 @@ where
 
     /// Flush the message queue from other nodes and add incoming events to the
     /// centralized event manager queue.
+    /// The incoming testcases are kept, to bootstrap nodes joining later.
     pub(crate) async fn receive_new_messages_from_nodes<'a, I: Input>(
         &mut self,
         msgs: &mut Vec<MultiMachineMsg<'a, I>>,
     ) -> Result<(), Error> {
         log::debug!("Checking for new events from other nodes...");
-        // let mut nb_received = 0usize;
+        let io_timeout = self.node_descriptor.io_timeout;
 
         // Our (potential) parent could have something for us
+        let mut parent_lost = false;
+        let mut new_ancestors = None;
         if let Some(parent) = &mut self.parent {
             loop {
-                // Exit if received a lot of inputs at once.
-                // TODO: this causes problems in some cases, it could freeze all fuzzer instances.
-                // if nb_received > MAX_NB_RECEIVED_AT_ONCE {
-                //     log::debug!("hitting MAX_NB_RECEIVED_AT_ONCE limit...");
-                //     return Ok(());
-                // }
-
                 log::debug!("Receiving from parent...");
-                match Self::read_msg(parent).await {
-                    Ok(Some(msg)) => {
+                match Self::poll_frame(parent, io_timeout).await {
+                    Ok(Some((kind @ (DUMMY_BYTE | DELTA_BYTE | REPLAY_BYTE), msg))) => {
                         log::debug!("Received event from parent");
+                        let msg = match Self::accept_event::<I>(parent, kind, msg, io_timeout).await
+                        {
+                            Ok(Some(msg)) => msg,
+                            Ok(None) => continue,
+                            Err(e) => {
+                                // The link is out of sync, start over with a new one
+                                log::warn!(
+                                    "Invalid event from parent, we will reconnect. Error: {e:?}"
+                                );
+                                parent_lost = true;
+                                break;
+                            }
+                        };
                         // The parent has something for us, we store it
-                        msgs.push(msg);
-                        // nb_received += 1;
+                        self.old_msgs.push(&msg, true);
+                        msgs.push(MultiMachineMsg::from_llmp_msg(msg.into_boxed_slice()));
+                    }
+
+                    Ok(Some((NACK_BYTE, msg))) => {
+                        if let Err(e) = Self::resend_in_full(parent, &msg, io_timeout).await {
+                            log::warn!(
+                                "Could not resend to parent, we will reconnect. Error: {e:?}"
+                            );
+                            parent_lost = true;
+                            break;
+                        }
                     }
 
+                    Ok(Some((_, msg))) => match postcard::from_bytes(&msg) {
+                        Ok(NodeControlMsg::Ancestors(ancestors)) => {
+                            new_ancestors = Some(ancestors);
+                        }
+                        Ok(msg) => log::debug!("Ignoring unexpected control message {msg:?}"),
+                        Err(e) => log::error!("Invalid control message from parent: {e:?}"),
+                    },
+
                     Ok(None) => {
                         // nothing from the parent, we continue
                         log::debug!("Nothing from parent");
                         break;
                     }
 
-                    Err(Error::OsError(_, _, _)) => {
+                    Err(Error::OsError(e, _, _)) => {
                         // most likely the parent disconnected. drop the connection
-                        log::debug!(
-                            "The parent disconnected. We won't try to communicate with it again."
+                        log::warn!(
+                            "The parent disconnected, we will try to reconnect. Error: {e:?}"
                         );
-                        self.parent.take();
+                        parent_lost = true;
                         break;
                     }
 
@@ -561,6 +1187,24 @@ where
             }
         }
 
+        if let Some(ancestors) = new_ancestors {
+            if self.creates_cycle(&ancestors) {
+                // Another node re-parented below us at the same time, break the cycle
+                log::warn!(
+                    "The parent became a descendant of this node, looking for a new parent."
+                );
+                parent_lost = true;
+            } else {
+                self.ancestors.truncate(1);
+                self.ancestors.extend(ancestors);
+                self.send_control_to_children(&NodeControlMsg::Ancestors(self.ancestors.clone()))
+                    .await;
+            }
+        }
+        if parent_lost {
+            self.parent.take();
+        }
+
         // What about the (potential) children?
         let mut ids_to_remove: Vec<NodeId> = Vec::new();
         log::debug!(
@@ -568,34 +1212,55 @@ where
             process::id(),
             self.children.len()
         );
-        for (child_id, child_stream) in &mut self.children {
+        for (child_id, child_link) in &mut self.children {
             loop {
-                // Exit if received a lot of inputs at once.
-                // if nb_received > MAX_NB_RECEIVED_AT_ONCE {
-                //    return Ok(());
-                //}
-
                 log::debug!("Receiving from child {child_id:?}...");
-                match Self::read_msg(child_stream).await {
-                    Ok(Some(msg)) => {
-                        // The parent has something for us, we store it
+                match Self::poll_frame(child_link, io_timeout).await {
+                    Ok(Some((kind @ (DUMMY_BYTE | DELTA_BYTE | REPLAY_BYTE), msg))) => {
+                        // The child has something for us, we store it
                         log::debug!("Received event from child!");
-                        msgs.push(msg);
-                        // nb_received += 1;
+                        let msg = match Self::accept_event::<I>(child_link, kind, msg, io_timeout)
+                            .await
+                        {
+                            Ok(Some(msg)) => msg,
+                            Ok(None) => continue,
+                            Err(e) => {
+                                // The link is out of sync, the child reconnects with a new one
+                                log::warn!("Invalid event from child {child_id:?}, dropping it. Error: {e:?}");
+                                ids_to_remove.push(*child_id);
+                                break;
+                            }
+                        };
+                        self.old_msgs.push(&msg, false);
+                        msgs.push(MultiMachineMsg::from_llmp_msg(msg.into_boxed_slice()));
+                    }
+
+                    Ok(Some((NACK_BYTE, msg))) => {
+                        if let Err(e) = Self::resend_in_full(child_link, &msg, io_timeout).await {
+                            log::warn!(
+                                "Could not resend to child {child_id:?}, dropping it. Error: {e:?}"
+                            );
+                            ids_to_remove.push(*child_id);
+                            break;
+                        }
+                    }
+
+                    Ok(Some(_)) => {
+                        log::debug!("Ignoring control message from child {child_id:?}");
                     }
 
                     Ok(None) => {
-                        // nothing from the parent, we continue
+                        // nothing from the child, we continue
                         log::debug!("Nothing from child");
                         break;
                     }
 
                     Err(Error::OsError(e, _, _)) => {
-                        // most likely the parent disconnected. drop the connection
-                        log::error!(
+                        // most likely the child disconnected (or left). drop the connection
+                        log::info!(
                             "The child disconnected. We won't try to communicate with it again."
                         );
-                        log::error!("Error: {e:?}");
+                        log::info!("Error: {e:?}");
                         ids_to_remove.push(*child_id);
                         break;
                     }
@@ -618,3 +1283,178 @@ where
         Ok(())
     }
 }
+
+/// Runs an io operation on a link. Fails with [`ErrorKind::TimedOut`] if the other node stalls
+/// for longer than `timeout`, so a single stuck node can not freeze the others.
+async fn with_io_timeout<F, T>(timeout: Duration, operation: F) -> Result<T, Error>
+where
+    F: Future<Output = io::Result<T>>,
+{
+    match time::timeout(timeout, operation).await {
+        Ok(res) => Ok(res?),
+        Err(_) => Err(Error::os_error(
+            io::Error::from(ErrorKind::TimedOut),
+            "The other node stalled",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        net::TcpListener,
+        string::{String, ToString},
+        sync::Arc,
+        thread,
+        time::{Duration, Instant},
+        vec::Vec,
+    };
+
+    #[cfg(feature = "llmp_compression")]
+    use libafl_bolts::compress::GzipCompressor;
+    use tokio::{runtime::Runtime, sync::RwLock};
+
+    use super::{NodeAncestor, NodeDescriptor, PastMsgs, TcpMultiMachineState};
+    use crate::inputs::BytesInput;
+
+    type SharedState = Arc<RwLock<TcpMultiMachineState<String>>>;
+
+    /// A free port on localhost
+    fn free_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    /// Starts a node listening on `port`, attached to the node listening on `parent_port`
+    fn start_node(parent_port: Option<u16>, port: Option<u16>) -> (SharedState, Runtime) {
+        let node_descriptor = NodeDescriptor::builder()
+            .parent_addr(parent_port.map(|port| format!("127.0.0.1:{port}")))
+            .node_listening_port(port)
+            .timeout(Duration::from_secs(10))
+            .reconnect_backoff(Duration::from_millis(50))
+            .build();
+        let state = Arc::new(RwLock::new(TcpMultiMachineState::new(node_descriptor)));
+        let rt = Arc::new(Runtime::new().unwrap());
+        unsafe {
+            TcpMultiMachineState::init(&state, &rt).unwrap();
+        }
+        (state, Arc::into_inner(rt).unwrap())
+    }
+
+    #[test]
+    fn test_parent_candidates() {
+        let node_descriptor = NodeDescriptor::builder()
+            .parent_addr(Some("10.0.0.1:50000".to_string()))
+            .fallback_parent_addrs(vec!["10.0.0.2:50000".to_string()])
+            .build();
+        let state = TcpMultiMachineState {
+            node_descriptor,
+            node_uid: 1,
+            parent: None,
+            ancestors: vec![
+                NodeAncestor {
+                    node_uid: 2,
+                    addr: "10.0.0.1:50000".to_string(),
+                },
+                NodeAncestor {
+                    node_uid: 3,
+                    addr: "10.0.0.3:50000".to_string(),
+                },
+            ],
+            children: HashMap::new(),
+            old_msgs: PastMsgs::new(16),
+            #[cfg(feature = "llmp_compression")]
+            compressor: GzipCompressor::new(),
+        };
+
+        // The configured parents first, then the ancestors, without duplicates
+        assert_eq!(
+            state.parent_candidates(),
+            ["10.0.0.1:50000", "10.0.0.2:50000", "10.0.0.3:50000"]
+        );
+        assert!(!state.creates_cycle(&state.ancestors));
+        assert!(state.creates_cycle(&[NodeAncestor {
+            node_uid: 1,
+            addr: "10.0.0.4:50000".to_string(),
+        }]));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_reparent_after_parent_disconnects() {
+        let (root_port, parent_port) = (free_port(), free_port());
+        let (root, root_rt) = start_node(None, Some(root_port));
+        let (parent, parent_rt) = start_node(Some(root_port), Some(parent_port));
+        let (child, child_rt) = start_node(Some(parent_port), None);
+
+        let root_uid = root_rt.block_on(async { root.read().await.node_uid });
+        let parent_uid = parent_rt.block_on(async { parent.read().await.node_uid });
+        let ancestors: Vec<u64> = child_rt.block_on(async {
+            let state = child.read().await;
+            state
+                .ancestors
+                .iter()
+                .map(|ancestor| ancestor.node_uid)
+                .collect()
+        });
+        assert_eq!(ancestors, [parent_uid, root_uid]);
+
+        // The parent goes away: its listener and its links are closed
+        parent_rt.shutdown_timeout(Duration::from_secs(1));
+        drop(parent);
+
+        // The child notices, and falls back to its grand-parent
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let ancestors: Vec<u64> = child_rt.block_on(async {
+                let mut state = child.write().await;
+                state
+                    .receive_new_messages_from_nodes::<BytesInput>(&mut Vec::new())
+                    .await
+                    .unwrap();
+                if state.parent.is_none() {
+                    return Vec::new();
+                }
+                state
+                    .ancestors
+                    .iter()
+                    .map(|ancestor| ancestor.node_uid)
+                    .collect()
+            });
+            if ancestors == [root_uid] {
+                break;
+            }
+            assert!(Instant::now() < deadline, "The child did not re-parent");
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    #[test]
+    fn test_past_msgs() {
+        let mut old_msgs = PastMsgs::new(2);
+        old_msgs.push(b"a", false);
+        old_msgs.push(b"a", true);
+        assert_eq!(old_msgs.msgs.len(), 1);
+
+        old_msgs.push(b"b", false);
+        let (msgs, seq) = old_msgs.since(0, true);
+        assert_eq!(msgs, [b"a".as_slice().into(), b"b".as_slice().into()]);
+
+        // The oldest testcase is forgotten, and can come back later
+        old_msgs.push(b"c", true);
+        let (msgs, _) = old_msgs.since(seq, true);
+        assert_eq!(msgs, [b"c".as_slice().into()]);
+        let (msgs, _) = old_msgs.since(0, true);
+        assert_eq!(msgs, [b"b".as_slice().into(), b"c".as_slice().into()]);
+        // The testcases from the parent are not sent back up
+        let (msgs, next_seq) = old_msgs.since(0, false);
+        assert_eq!(msgs, [b"b".as_slice().into()]);
+        assert_eq!(next_seq, 3);
+        old_msgs.push(b"a", false);
+        assert_eq!(old_msgs.msgs.len(), 2);
+    }
+}
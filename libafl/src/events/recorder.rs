@@ -0,0 +1,572 @@
+//! Records the events of a campaign to disk, and replays them offline.
+//!
+//! The [`EventRecorder`] appends every event a broker sees to a compact log: the
+//! [`super::EventRecorderHook`] records the events of an LLMP broker, the `TcpEventBroker` takes a
+//! recorder as well. With the `llmp_compression` feature, the large events (like the testcases) are
+//! compressed, the way LLMP compresses its messages. After the campaign, the [`CampaignReplay`] feeds the log to a [`Monitor`], the way
+//! the broker did, and rebuilds the coverage over time and the evolution of the corpus.
+
+use alloc::{string::String, vec::Vec};
+use core::{fmt::Write as _, marker::PhantomData, time::Duration};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufReader, BufWriter, ErrorKind, Read, Seek, Write},
+    path::Path,
+};
+
+#[cfg(feature = "llmp_compression")]
+use libafl_bolts::compress::GzipCompressor;
+use libafl_bolts::{current_time, ClientId};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "llmp_compression")]
+use crate::events::llmp::COMPRESS_THRESHOLD;
+use crate::{
+    events::{Event, StdLlmpEventHook},
+    inputs::Input,
+    monitors::{Aggregator, Monitor, UserStatsValue},
+    Error,
+};
+
+/// The first bytes of an event log
+const EVENT_LOG_MAGIC: &[u8; 8] = b"LAFLEVT2";
+
+/// A record of the log, as written to disk
+#[derive(Serialize, Deserialize, Debug)]
+struct EventRecord {
+    time: Duration,
+    client_id: ClientId,
+    /// If `event` is gzip-compressed, see the `llmp_compression` feature
+    compressed: bool,
+    event: Vec<u8>,
+}
+
+/// An event of the log
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LoggedEvent {
+    /// The time the broker got the event
+    pub time: Duration,
+    /// The client which sent the event
+    pub client_id: ClientId,
+    /// The serialized [`Event`]
+    pub event: Vec<u8>,
+}
+
+impl LoggedEvent {
+    /// Deserializes the event
+    pub fn event<I>(&self) -> Result<Event<I>, Error>
+    where
+        I: Input,
+    {
+        Ok(postcard::from_bytes(&self.event)?)
+    }
+}
+
+/// Appends the events seen by a broker to a log, read back by the [`EventLogReader`].
+///
+/// The records are buffered. They are flushed by the next record once [`Self::FLUSH_INTERVAL`] passed
+/// since the last flush, and when the recorder is dropped. An idle broker flushes them as well: the
+/// [`super::EventRecorderHook`] on the timeout of the LLMP broker (every 30 seconds), the
+/// `TcpEventBroker` after [`Self::FLUSH_INTERVAL`] without events.
+/// A campaign killed in between loses its last events, but the log stays readable.
+#[derive(Debug)]
+pub struct EventRecorder {
+    writer: BufWriter<File>,
+    /// The time of the last flush
+    last_flush: Duration,
+    #[cfg(feature = "llmp_compression")]
+    compressor: GzipCompressor,
+}
+
+impl EventRecorder {
+    /// The time a record waits in the buffer, before the next record flushes it
+    pub const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+    /// Creates a new log at `path`, or appends to the existing one, e.g., after the broker restarted.
+    /// A record cut short, because the campaign was killed while writing it, is cut off first.
+    pub fn new<P>(path: P) -> Result<Self, Error>
+    where
+        P: AsRef<Path>,
+    {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+        if file.metadata()?.len() < EVENT_LOG_MAGIC.len() as u64 {
+            file.set_len(0)?;
+            file.write_all(EVENT_LOG_MAGIC)?;
+        } else {
+            // Appending after a partial record would shift all the records to come
+            let mut reader = EventLogReader::new(file.try_clone()?)?;
+            let mut end = reader.reader.stream_position()?;
+            while let Ok(Some(_)) = reader.next_record() {
+                end = reader.reader.stream_position()?;
+            }
+            file.set_len(end)?;
+        }
+        Ok(Self {
+            writer: BufWriter::new(file),
+            last_flush: current_time(),
+            #[cfg(feature = "llmp_compression")]
+            compressor: GzipCompressor::with_threshold(COMPRESS_THRESHOLD),
+        })
+    }
+
+    /// Writes the buffered records to the log
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+        self.last_flush = current_time();
+        Ok(())
+    }
+
+    /// Records a serialized (uncompressed) [`Event`] sent by `client_id`
+    pub fn record(&mut self, client_id: ClientId, event: &[u8]) -> Result<(), Error> {
+        #[cfg(feature = "llmp_compression")]
+        if let Some(compressed) = self.compressor.maybe_compress(event) {
+            return self.write_record(client_id, true, compressed);
+        }
+        self.write_record(client_id, false, event.to_vec())
+    }
+
+    /// Records a serialized [`Event`] sent by `client_id`, compressed by a [`GzipCompressor`], e.g., an
+    /// LLMP message with the `LLMP_FLAG_COMPRESSED` flag
+    #[cfg(feature = "llmp_compression")]
+    pub fn record_compressed(&mut self, client_id: ClientId, event: &[u8]) -> Result<(), Error> {
+        self.write_record(client_id, true, event.to_vec())
+    }
+
+    fn write_record(
+        &mut self,
+        client_id: ClientId,
+        compressed: bool,
+        event: Vec<u8>,
+    ) -> Result<(), Error> {
+        let record = postcard::to_allocvec(&EventRecord {
+            time: current_time(),
+            client_id,
+            compressed,
+            event,
+        })?;
+        self.writer
+            .write_all(&u32::try_from(record.len())?.to_le_bytes())?;
+        self.writer.write_all(&record)?;
+        if current_time().saturating_sub(self.last_flush) >= Self::FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Records an [`Event`] sent by `client_id`
+    pub fn record_event<I>(&mut self, client_id: ClientId, event: &Event<I>) -> Result<(), Error>
+    where
+        I: Input,
+    {
+        self.record(client_id, &postcard::to_allocvec(event)?)
+    }
+}
+
+impl Drop for EventRecorder {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            log::error!("Could not flush the event log: {e}");
+        }
+    }
+}
+
+/// Reads the events of a log written by the [`EventRecorder`], in the order they were recorded
+#[derive(Debug)]
+pub struct EventLogReader {
+    reader: BufReader<File>,
+}
+
+impl EventLogReader {
+    /// Opens the log at `path`
+    pub fn open<P>(path: P) -> Result<Self, Error>
+    where
+        P: AsRef<Path>,
+    {
+        Self::new(File::open(path)?)
+    }
+
+    /// Reads the log in `file`, from its start
+    fn new(file: File) -> Result<Self, Error> {
+        let mut reader = BufReader::new(file);
+        reader.rewind()?;
+        let mut magic = [0; EVENT_LOG_MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if &magic != EVENT_LOG_MAGIC {
+            return Err(Error::illegal_argument("Not an event log"));
+        }
+        Ok(Self { reader })
+    }
+
+    /// The next event of the log, `None` at its end.
+    /// A record cut short, because the campaign was killed while writing it, ends the log.
+    pub fn next_event(&mut self) -> Result<Option<LoggedEvent>, Error> {
+        let Some(record) = self.next_record()? else {
+            return Ok(None);
+        };
+        let event = if record.compressed {
+            #[cfg(feature = "llmp_compression")]
+            {
+                GzipCompressor::new().decompress(&record.event)?
+            }
+            #[cfg(not(feature = "llmp_compression"))]
+            return Err(Error::unsupported(
+                "The event log is compressed, enable the `llmp_compression` feature to read it",
+            ));
+        } else {
+            record.event
+        };
+        Ok(Some(LoggedEvent {
+            time: record.time,
+            client_id: record.client_id,
+            event,
+        }))
+    }
+
+    /// The next record of the log, as written to disk
+    fn next_record(&mut self) -> Result<Option<EventRecord>, Error> {
+        let mut len = [0; 4];
+        if !self.read_record_part(&mut len)? {
+            return Ok(None);
+        }
+        let mut record = vec![0; u32::from_le_bytes(len) as usize];
+        if !self.read_record_part(&mut record)? {
+            log::warn!("The last event of the log is truncated");
+            return Ok(None);
+        }
+        Ok(Some(postcard::from_bytes(&record)?))
+    }
+
+    /// Fills `buf`, returns `false` at the end of the log
+    fn read_record_part(&mut self, buf: &mut [u8]) -> Result<bool, Error> {
+        match self.reader.read_exact(buf) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Iterator for EventLogReader {
+    type Item = Result<LoggedEvent, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_event().transpose()
+    }
+}
+
+/// An event replayed by the [`CampaignReplay`]
+#[derive(Debug, Clone)]
+pub struct ReplayedEvent<I>
+where
+    I: Input,
+{
+    /// The time of the event, since the first event of the log
+    pub time: Duration,
+    /// The client which sent the event
+    pub client_id: ClientId,
+    /// The event
+    pub event: Event<I>,
+}
+
+/// The state of the campaign after an event, see [`CampaignReplay::timeline`]
+#[derive(Debug, Clone)]
+pub struct CampaignSample {
+    /// The time of the event, since the first event of the log
+    pub time: Duration,
+    /// The size of the corpus, over all clients
+    pub corpus_size: u64,
+    /// The number of objectives, over all clients
+    pub objective_size: u64,
+    /// The executions, over all clients
+    pub executions: u64,
+    /// The requested user stats (e.g., the coverage), aggregated over the clients
+    pub user_stats: Vec<Option<UserStatsValue>>,
+}
+
+/// Replays an event log into a [`Monitor`], the way the broker handled the events.
+///
+/// The monitor shows the state of the campaign at each event. Only the rates, like the executions per
+/// second, are off: the monitors measure them against the current time.
+#[derive(Debug)]
+pub struct CampaignReplay<I, MT> {
+    reader: EventLogReader,
+    monitor: MT,
+    /// The time of the first event
+    start_time: Option<Duration>,
+    /// The time of the last event replayed, since the first one
+    elapsed: Duration,
+    phantom: PhantomData<I>,
+}
+
+impl<I, MT> CampaignReplay<I, MT>
+where
+    I: Input,
+    MT: Monitor,
+{
+    /// Opens the log at `path`, to replay it into `monitor`.
+    /// Use a [`crate::monitors::NopMonitor`] to replay silently.
+    pub fn new<P>(path: P, monitor: MT) -> Result<Self, Error>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(Self {
+            reader: EventLogReader::open(path)?,
+            monitor,
+            start_time: None,
+            elapsed: Duration::ZERO,
+            phantom: PhantomData,
+        })
+    }
+
+    /// The monitor
+    pub fn monitor(&self) -> &MT {
+        &self.monitor
+    }
+
+    /// The monitor (mutable)
+    pub fn monitor_mut(&mut self) -> &mut MT {
+        &mut self.monitor
+    }
+
+    /// The time of the last event replayed, since the first event of the log
+    #[must_use]
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// Replays the next event of the log, `None` at its end
+    pub fn replay_next(&mut self) -> Result<Option<ReplayedEvent<I>>, Error> {
+        let Some(logged) = self.reader.next_event()? else {
+            return Ok(None);
+        };
+        let start_time = *self.start_time.get_or_insert(logged.time);
+        self.elapsed = logged.time.saturating_sub(start_time);
+
+        let event = logged.event::<I>()?;
+        StdLlmpEventHook::<I, MT>::handle_in_broker(&mut self.monitor, logged.client_id, &event)?;
+        Ok(Some(ReplayedEvent {
+            time: self.elapsed,
+            client_id: logged.client_id,
+            event,
+        }))
+    }
+
+    /// The user stats `name` (e.g., the coverage), aggregated over the clients like the monitors show it
+    #[must_use]
+    pub fn user_stats(&self, name: &str) -> Option<UserStatsValue> {
+        let mut aggregator = Aggregator::new();
+        aggregator.aggregate(name, self.monitor.client_stats());
+        aggregator.get(name).cloned()
+    }
+
+    /// Replays the rest of the log, and returns the state of the campaign after each event, with the
+    /// given user stats, e.g., to plot the coverage over time
+    pub fn timeline(&mut self, user_stats: &[&str]) -> Result<Vec<CampaignSample>, Error> {
+        let mut samples = Vec::new();
+        while let Some(replayed) = self.replay_next()? {
+            samples.push(CampaignSample {
+                time: replayed.time,
+                corpus_size: self.monitor.corpus_size(),
+                objective_size: self.monitor.objective_size(),
+                executions: self.monitor.total_execs(),
+                user_stats: user_stats
+                    .iter()
+                    .map(|name| self.user_stats(name))
+                    .collect(),
+            });
+        }
+        Ok(samples)
+    }
+
+    /// Replays the rest of the log, and writes the testcases to `dir`, in the order they were found.
+    /// Returns the number of testcases written.
+    pub fn dump_corpus<P>(&mut self, dir: P) -> Result<usize, Error>
+    where
+        P: AsRef<Path>,
+    {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let mut count = 0;
+        while let Some(replayed) = self.replay_next()? {
+            let Event::NewTestcase {
+                input, forward_id, ..
+            } = replayed.event
+            else {
+                continue;
+            };
+            let client_id = forward_id.unwrap_or(replayed.client_id);
+            input.to_file(dir.join(format!(
+                "{count:06}-{}s-client{}",
+                replayed.time.as_secs(),
+                client_id.0
+            )))?;
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+/// Formats a [`CampaignReplay::timeline`] as csv, with one column per user stats
+#[must_use]
+pub fn timeline_csv(samples: &[CampaignSample], user_stats: &[&str]) -> String {
+    let mut csv = String::from("time,corpus,objectives,executions");
+    for name in user_stats {
+        let _ = write!(csv, ",{name}");
+    }
+    csv.push('\n');
+
+    for sample in samples {
+        let _ = write!(
+            csv,
+            "{},{},{},{}",
+            sample.time.as_secs_f64(),
+            sample.corpus_size,
+            sample.objective_size,
+            sample.executions
+        );
+        for value in &sample.user_stats {
+            match value {
+                Some(value) => {
+                    let _ = write!(csv, ",{value}");
+                }
+                None => csv.push(','),
+            }
+        }
+        csv.push('\n');
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use core::{marker::PhantomData, time::Duration};
+    use std::{borrow::Cow, env, fs, fs::OpenOptions};
+
+    use libafl_bolts::ClientId;
+
+    use super::{CampaignReplay, EventLogReader, EventRecorder};
+    use crate::{
+        events::{Event, EventConfig},
+        executors::ExitKind,
+        inputs::BytesInput,
+        monitors::{AggregatorOps, NopMonitor, UserStats, UserStatsValue},
+    };
+
+    #[test]
+    fn test_record_replay() {
+        let log = env::temp_dir().join(format!("libafl_event_log_{}", std::process::id()));
+        let _ = fs::remove_file(&log);
+
+        let mut recorder = EventRecorder::new(&log).unwrap();
+        for (client, edges) in [(0, 10), (1, 20)] {
+            let testcase: Event<BytesInput> = Event::NewTestcase {
+                input: BytesInput::new(vec![client; 4]),
+                observers_buf: None,
+                exit_kind: ExitKind::Ok,
+                corpus_size: 1,
+                client_config: EventConfig::AlwaysUnique,
+                time: Duration::ZERO,
+                forward_id: None,
+                parent_hash: None,
+                #[cfg(all(unix, feature = "std", feature = "multi_machine"))]
+                node_id: None,
+            };
+            recorder
+                .record_event(ClientId(client.into()), &testcase)
+                .unwrap();
+            let coverage: Event<BytesInput> = Event::UpdateUserStats {
+                name: Cow::Borrowed("edges"),
+                value: UserStats::new(UserStatsValue::Number(edges), AggregatorOps::Max),
+                phantom: PhantomData,
+            };
+            recorder
+                .record_event(ClientId(client.into()), &coverage)
+                .unwrap();
+        }
+        drop(recorder);
+
+        let mut replay = CampaignReplay::<BytesInput, _>::new(&log, NopMonitor::new()).unwrap();
+        let timeline = replay.timeline(&["edges"]).unwrap();
+        assert_eq!(timeline.len(), 4);
+        assert_eq!(timeline[2].corpus_size, 2);
+        assert!(matches!(
+            timeline[1].user_stats[0],
+            Some(UserStatsValue::Number(10))
+        ));
+        assert!(matches!(
+            timeline[3].user_stats[0],
+            Some(UserStatsValue::Number(20))
+        ));
+
+        let _ = fs::remove_file(&log);
+    }
+
+    #[test]
+    fn test_record_after_truncated_record() {
+        let log =
+            env::temp_dir().join(format!("libafl_event_log_truncated_{}", std::process::id()));
+        let _ = fs::remove_file(&log);
+
+        let mut recorder = EventRecorder::new(&log).unwrap();
+        recorder.record(ClientId(0), b"first").unwrap();
+        recorder.record(ClientId(0), b"second").unwrap();
+        drop(recorder);
+        // The campaign was killed in the middle of the second record
+        let len = fs::metadata(&log).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&log)
+            .unwrap()
+            .set_len(len - 3)
+            .unwrap();
+
+        let mut recorder = EventRecorder::new(&log).unwrap();
+        recorder.record(ClientId(1), b"third").unwrap();
+        drop(recorder);
+
+        let events: Vec<_> = EventLogReader::open(&log)
+            .unwrap()
+            .map(|event| event.unwrap())
+            .map(|event| (event.client_id, event.event))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                (ClientId(0), b"first".to_vec()),
+                (ClientId(1), b"third".to_vec())
+            ]
+        );
+
+        let _ = fs::remove_file(&log);
+    }
+
+    #[test]
+    #[cfg(feature = "llmp_compression")]
+    fn test_record_compressed() {
+        let log = env::temp_dir().join(format!(
+            "libafl_event_log_compressed_{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&log);
+
+        let large = vec![b'a'; 64 * 1024];
+        let mut recorder = EventRecorder::new(&log).unwrap();
+        recorder.record(ClientId(0), &large).unwrap();
+        recorder.record(ClientId(1), b"small").unwrap();
+        drop(recorder);
+        assert!(fs::metadata(&log).unwrap().len() < 4096);
+
+        let events: Vec<_> = EventLogReader::open(&log)
+            .unwrap()
+            .map(|event| event.unwrap().event)
+            .collect();
+        assert_eq!(events, [large, b"small".to_vec()]);
+
+        let _ = fs::remove_file(&log);
+    }
+}
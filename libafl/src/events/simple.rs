@@ -268,6 +268,8 @@ where
                 Ok(BrokerEventResult::Handled)
             }
             Event::CustomBuf { .. } => Ok(BrokerEventResult::Forward),
+            // There are no other clients to send it to
+            Event::Custom { .. } => Ok(BrokerEventResult::Handled),
             Event::Stop => Ok(BrokerEventResult::Forward),
         }
     }
@@ -0,0 +1,689 @@
+//! Keeps the clients of a campaign in check: resource limits per client, a watchdog restarting clients that
+//! hang, and a backoff after repeated failures of the fuzzer itself.
+//!
+//! Set them on the [`crate::events::Launcher`], or on the [`crate::events::RestartingMgr`] directly.
+
+#[cfg(target_os = "linux")]
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+#[cfg(target_os = "linux")]
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+#[cfg(not(unix))]
+use libafl_bolts::current_time;
+use libafl_bolts::shmem::ShMem;
+
+use crate::Error;
+
+/// The mount point of the cgroup v2 hierarchy
+#[cfg(target_os = "linux")]
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// The period of the cgroup cpu quota, in microseconds
+#[cfg(target_os = "linux")]
+const CGROUP_CPU_PERIOD: u64 = 100_000;
+
+/// How often the respawner checks the heartbeat and the memory of its client
+#[cfg(unix)]
+const WATCHDOG_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Memory and cpu limits for each client.
+///
+/// With cgroups v2, the [`crate::events::Launcher`] puts each client in its own cgroup, see
+/// [`ClientCgroups`]. In any case, the respawner of each client kills it once its resident memory exceeds
+/// the limit, like libFuzzer's `-rss_limit_mb`. The cpu limit needs cgroups, else every client is still bound
+/// to its core.
+///
+/// Without cgroups, the launcher also sets the limits as `rlimit`s of each client, see
+/// [`Self::apply_rlimits`]: the memory as `RLIMIT_DATA`, which targets built with `ASan` exceed, as they
+/// reserve terabytes of shadow memory at startup. With cgroups, the memory limit is no such limit, so it
+/// works for them. The total cpu time, as `RLIMIT_CPU`, is always set as `rlimit`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClientLimits {
+    /// The maximum memory of a client, in bytes
+    memory: Option<u64>,
+    /// The maximum cpu time of a client, in percent of a core
+    cpu_percent: Option<u64>,
+    /// The maximum total cpu time of a client process
+    cpu_time: Option<Duration>,
+}
+
+impl ClientLimits {
+    /// Creates new [`ClientLimits`], without any limit
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits the memory of each client to `bytes`
+    #[must_use]
+    pub fn with_memory(mut self, bytes: u64) -> Self {
+        self.memory = Some(bytes);
+        self
+    }
+
+    /// Limits the cpu time of each client to `percent` of a core
+    #[must_use]
+    pub fn with_cpu_percent(mut self, percent: u64) -> Self {
+        self.cpu_percent = Some(percent);
+        self
+    }
+
+    /// Limits the total cpu time of each client process to `cpu_time`. A client using it up is killed with
+    /// `SIGXCPU`, and restarted by its respawner.
+    #[must_use]
+    pub fn with_cpu_time(mut self, cpu_time: Duration) -> Self {
+        self.cpu_time = Some(cpu_time);
+        self
+    }
+
+    /// The maximum memory of a client, in bytes, if any
+    #[must_use]
+    pub fn memory(&self) -> Option<u64> {
+        self.memory
+    }
+
+    /// The maximum cpu time of a client, in percent of a core, if any
+    #[must_use]
+    pub fn cpu_percent(&self) -> Option<u64> {
+        self.cpu_percent
+    }
+
+    /// The maximum total cpu time of a client process, if any
+    #[must_use]
+    pub fn cpu_time(&self) -> Option<Duration> {
+        self.cpu_time
+    }
+
+    /// If there is no limit at all
+    #[must_use]
+    pub fn is_unlimited(&self) -> bool {
+        self.memory.is_none() && self.cpu_percent.is_none() && self.cpu_time.is_none()
+    }
+
+    /// If some limit is enforced with cgroups, if available
+    #[must_use]
+    pub fn needs_cgroups(&self) -> bool {
+        self.memory.is_some() || self.cpu_percent.is_some()
+    }
+
+    /// Sets the limits as `rlimit`s of the current process, meant to be called in a freshly forked client:
+    /// the total cpu time as `RLIMIT_CPU` and, if the client is not in a cgroup, the memory as `RLIMIT_DATA`.
+    #[cfg(unix)]
+    #[allow(trivial_numeric_casts)]
+    pub fn apply_rlimits(&self, in_cgroup: bool) -> Result<(), Error> {
+        if let Some(cpu_time) = self.cpu_time {
+            // Whole seconds, at least one, zero would kill the client right away
+            let limit = cpu_time.as_secs().max(1) as libc::rlim_t;
+            let r = libc::rlimit {
+                rlim_cur: limit,
+                rlim_max: limit,
+            };
+            if unsafe { libc::setrlimit(libc::RLIMIT_CPU, &raw const r) } < 0 {
+                return Err(Error::last_os_error("Failed to set the cpu time limit"));
+            }
+        }
+        if let Some(memory) = self.memory.filter(|_| !in_cgroup) {
+            let r = libc::rlimit {
+                rlim_cur: memory as libc::rlim_t,
+                rlim_max: memory as libc::rlim_t,
+            };
+            if unsafe { libc::setrlimit(libc::RLIMIT_DATA, &raw const r) } < 0 {
+                return Err(Error::last_os_error("Failed to set the memory limit"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The cgroups of the clients of a launcher, next to a leaf cgroup for the launcher itself.
+///
+/// cgroups v2 only hand controllers to the children of a cgroup without processes of its own. So the launcher
+/// first moves itself from its cgroup (which must be delegated to the user, e.g., with
+/// `systemd-run --user --scope -p Delegate=yes`) to the leaf `libafl-<pid>-launcher`, then enables the
+/// `memory` and `cpu` controllers for the children, and puts each client in its own sibling
+/// `libafl-<pid>-client-<index>`.
+///
+/// Dropping it in the launcher kills the processes left in the client cgroups, removes them, and moves the
+/// launcher back.
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub struct ClientCgroups {
+    /// The delegated cgroup of the launcher
+    parent: PathBuf,
+    limits: ClientLimits,
+    /// The pid of the launcher, the forked clients must not tear down the cgroups
+    owner: u32,
+}
+
+#[cfg(target_os = "linux")]
+impl ClientCgroups {
+    /// Moves the launcher to its leaf cgroup and enables the controllers for the clients
+    pub fn new(limits: ClientLimits) -> Result<Self, Error> {
+        let own_cgroups = fs::read_to_string("/proc/self/cgroup")?;
+        let own_cgroup = own_cgroups
+            .lines()
+            .find_map(|line| line.strip_prefix("0::"))
+            .ok_or_else(|| Error::unsupported("Not in a cgroup v2 hierarchy"))?;
+        let cgroups = Self {
+            parent: Path::new(CGROUP_ROOT).join(own_cgroup.trim_start_matches('/')),
+            limits,
+            owner: std::process::id(),
+        };
+
+        let launcher = cgroups.launcher_cgroup();
+        fs::create_dir(&launcher)?;
+        if let Err(e) = cgroups.enter_leaf(&launcher) {
+            cgroups.leave_leaf(&launcher);
+            return Err(e);
+        }
+        Ok(cgroups)
+    }
+
+    /// The cgroups for clients with `limits`, if any, or `None` if the cgroups are not delegated to us
+    pub(crate) fn for_limits(limits: Option<ClientLimits>) -> Option<Self> {
+        let limits = limits.filter(ClientLimits::needs_cgroups)?;
+        match Self::new(limits) {
+            Ok(cgroups) => Some(cgroups),
+            Err(e) => {
+                log::warn!(
+                    "No cgroups for the clients ({e}), only enforcing the memory limit, by polling and with an rlimit"
+                );
+                None
+            }
+        }
+    }
+
+    fn launcher_cgroup(&self) -> PathBuf {
+        self.parent.join(format!("libafl-{}-launcher", self.owner))
+    }
+
+    fn client_cgroup(&self, index: u64) -> PathBuf {
+        self.parent
+            .join(format!("libafl-{}-client-{index}", self.owner))
+    }
+
+    /// The controllers the limits need, as written to `cgroup.subtree_control`
+    fn controllers(&self, enable: bool) -> String {
+        let sign = if enable { '+' } else { '-' };
+        let mut controllers = Vec::new();
+        if self.limits.memory.is_some() {
+            controllers.push(format!("{sign}memory"));
+        }
+        if self.limits.cpu_percent.is_some() {
+            controllers.push(format!("{sign}cpu"));
+        }
+        controllers.join(" ")
+    }
+
+    fn enter_leaf(&self, launcher: &Path) -> Result<(), Error> {
+        fs::write(launcher.join("cgroup.procs"), self.owner.to_string())?;
+        fs::write(
+            self.parent.join("cgroup.subtree_control"),
+            self.controllers(true),
+        )
+        .map_err(|e| {
+            Error::illegal_state(format!(
+                "Could not enable the controllers below {}, other processes are in it or it is not delegated: {e}",
+                self.parent.display()
+            ))
+        })
+    }
+
+    /// Moves the launcher back to its cgroup and removes the leaf, best effort
+    fn leave_leaf(&self, launcher: &Path) {
+        drop(fs::write(
+            self.parent.join("cgroup.subtree_control"),
+            self.controllers(false),
+        ));
+        drop(fs::write(
+            self.parent.join("cgroup.procs"),
+            self.owner.to_string(),
+        ));
+        if let Err(e) = fs::remove_dir(launcher) {
+            log::warn!("Could not remove the cgroup {}: {e}", launcher.display());
+        }
+    }
+
+    /// Moves the process `pid`, the client number `index` of this launcher, to its own cgroup with the limits
+    pub fn attach(&self, index: u64, pid: u32) -> Result<(), Error> {
+        let cgroup = self.client_cgroup(index);
+        if let Err(e) = fs::create_dir(&cgroup) {
+            if e.kind() != std::io::ErrorKind::AlreadyExists {
+                return Err(e.into());
+            }
+        }
+        if let Some(memory) = self.limits.memory {
+            fs::write(cgroup.join("memory.max"), memory.to_string())?;
+        }
+        if let Some(cpu_percent) = self.limits.cpu_percent {
+            let quota = cpu_percent * CGROUP_CPU_PERIOD / 100;
+            fs::write(
+                cgroup.join("cpu.max"),
+                format!("{quota} {CGROUP_CPU_PERIOD}"),
+            )?;
+        }
+        fs::write(cgroup.join("cgroup.procs"), pid.to_string())?;
+        log::info!(
+            "Limiting client {pid} to {:?} in {}",
+            self.limits,
+            cgroup.display()
+        );
+        Ok(())
+    }
+
+    /// Kills the processes left in the client cgroups and removes them
+    fn remove_client_cgroups(&self) -> Result<(), Error> {
+        let prefix = format!("libafl-{}-client-", self.owner);
+        for entry in fs::read_dir(&self.parent)? {
+            let cgroup = entry?.path();
+            if !cgroup
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(&prefix))
+            {
+                continue;
+            }
+            // `cgroup.kill` needs Linux 5.14, older kernels keep the cgroups of clients still running
+            drop(fs::write(cgroup.join("cgroup.kill"), "1"));
+            let mut removed = fs::remove_dir(&cgroup);
+            for _ in 0..50 {
+                if removed.is_ok() {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+                removed = fs::remove_dir(&cgroup);
+            }
+            if let Err(e) = removed {
+                log::warn!("Could not remove the cgroup {}: {e}", cgroup.display());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for ClientCgroups {
+    fn drop(&mut self) {
+        if std::process::id() != self.owner {
+            return;
+        }
+        if let Err(e) = self.remove_client_cgroups() {
+            log::warn!("Could not remove the client cgroups: {e}");
+        }
+        self.leave_leaf(&self.launcher_cgroup());
+    }
+}
+
+/// How the respawner of a client restarts it.
+///
+/// The watchdog kills a client that stopped firing events, e.g., the periodic
+/// [`crate::events::Event::UpdateExecStats`], for longer than the heartbeat timeout. Pick a timeout longer
+/// than the slowest step of the client between two events.
+///
+/// A run of the client fails if the watchdog killed it or if it exited before `min_uptime`. After
+/// repeated failures, the respawner waits exponentially longer before the next restart.
+///
+/// Only a client with a saved state can restart: with the default
+/// [`crate::events::LlmpShouldSaveState::OnRestart`], a killed client cannot save its state and ends the
+/// respawner. Use an OOM-safe mode, like [`crate::events::LlmpShouldSaveState::OOMSafeOnRestart`], to
+/// restart hanging clients. The watchdog needs `fork`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestartPolicy {
+    /// Kill the client if it fired no event for this long
+    heartbeat_timeout: Option<Duration>,
+    /// A run of the client shorter than this counts as failure
+    min_uptime: Duration,
+    /// The wait after the second failure in a row
+    initial_backoff: Duration,
+    /// The longest wait
+    max_backoff: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            heartbeat_timeout: None,
+            min_uptime: Duration::from_secs(1),
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(300),
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// Creates a new [`RestartPolicy`], without watchdog
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Kills and restarts the clients which fired no event for `timeout`
+    #[must_use]
+    pub fn with_heartbeat_timeout(mut self, timeout: Duration) -> Self {
+        self.heartbeat_timeout = Some(timeout);
+        self
+    }
+
+    /// Counts runs of the client shorter than `min_uptime` as failure
+    #[must_use]
+    pub fn with_min_uptime(mut self, min_uptime: Duration) -> Self {
+        self.min_uptime = min_uptime;
+        self
+    }
+
+    /// Waits `initial` after the second failure in a row, doubling up to `max` for each further one
+    #[must_use]
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// The timeout of the watchdog, if any
+    #[must_use]
+    pub fn heartbeat_timeout(&self) -> Option<Duration> {
+        self.heartbeat_timeout
+    }
+
+    /// If a run of the client, killed by the watchdog or not, failed
+    #[must_use]
+    pub fn failed(&self, uptime: Duration, killed: bool) -> bool {
+        killed || uptime < self.min_uptime
+    }
+
+    /// The wait before restarting a client after `failures` failures in a row
+    #[must_use]
+    pub fn backoff_after(&self, failures: u32) -> Duration {
+        if failures < 2 {
+            return Duration::ZERO;
+        }
+        let factor = 1_u32.checked_shl(failures - 2).unwrap_or(u32::MAX);
+        self.initial_backoff
+            .checked_mul(factor)
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff))
+    }
+}
+
+/// The time since some fixed point, the same in all processes and unaffected by changes of the wall clock
+#[cfg(unix)]
+fn monotonic_time() -> Duration {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // # Safety
+    // A plain libc call on a valid struct, `CLOCK_MONOTONIC` is always available
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &raw mut now);
+    }
+    Duration::new(
+        u64::try_from(now.tv_sec).unwrap_or_default(),
+        u32::try_from(now.tv_nsec).unwrap_or_default(),
+    )
+}
+
+/// The time since some fixed point; the heartbeat is only watched on unix
+#[cfg(not(unix))]
+fn monotonic_time() -> Duration {
+    current_time()
+}
+
+/// The time of the last event of a client, in shared memory, watched by its respawner
+#[derive(Debug)]
+pub(crate) struct Heartbeat<SHM> {
+    shmem: SHM,
+}
+
+impl<SHM> Heartbeat<SHM>
+where
+    SHM: ShMem,
+{
+    /// Creates a new [`Heartbeat`] in the given map, beating right away
+    pub(crate) fn new(shmem: SHM) -> Result<Self, Error> {
+        if shmem.as_ptr_of::<AtomicU64>().is_none() {
+            return Err(Error::illegal_argument("The heartbeat map is too small"));
+        }
+        let heartbeat = Self { shmem };
+        heartbeat.beat();
+        Ok(heartbeat)
+    }
+
+    fn millis(&self) -> &AtomicU64 {
+        // # Safety
+        // The map is large enough, checked in `new`, and page aligned
+        unsafe { &*self.shmem.as_ptr_of::<AtomicU64>().unwrap() }
+    }
+
+    /// Marks the client alive
+    pub(crate) fn beat(&self) {
+        let now = u64::try_from(monotonic_time().as_millis()).unwrap_or(u64::MAX);
+        self.millis().store(now, Ordering::Relaxed);
+    }
+
+    /// The time since the last beat
+    pub(crate) fn elapsed(&self) -> Duration {
+        monotonic_time()
+            .saturating_sub(Duration::from_millis(self.millis().load(Ordering::Relaxed)))
+    }
+}
+
+/// Why the respawner killed its client
+#[cfg(unix)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ClientKill {
+    /// The client fired no event for longer than the heartbeat timeout
+    Hang,
+    /// The resident memory of the client exceeded the limit
+    OutOfMemory,
+}
+
+/// What the respawner watches while its client runs
+#[cfg(unix)]
+#[derive(Debug)]
+pub(crate) struct Watchdog<'a, SHM> {
+    /// The heartbeat of the client, and the longest time without beat
+    pub(crate) heartbeat: Option<(&'a Heartbeat<SHM>, Duration)>,
+    /// The maximum resident memory of the client, in bytes
+    pub(crate) rss_limit: Option<u64>,
+}
+
+/// The resident memory of the running process `pid`, in bytes
+#[cfg(target_os = "linux")]
+fn rss_of(pid: libc::pid_t) -> Option<u64> {
+    let statm = fs::read_to_string(format!("/proc/{pid}/statm")).ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    // # Safety
+    // A plain libc call
+    let page_size = u64::try_from(unsafe { libc::sysconf(libc::_SC_PAGESIZE) }).ok()?;
+    Some(pages * page_size)
+}
+
+/// The resident memory of a running process, unknown outside of Linux
+#[cfg(all(unix, not(target_os = "linux")))]
+fn rss_of(_pid: libc::pid_t) -> Option<u64> {
+    None
+}
+
+/// Waits for the client `pid` to exit, and returns its exit status.
+/// Kills the client once it stopped beating or grew too large, and tells why.
+#[cfg(unix)]
+pub(crate) fn wait_for_client<SHM>(
+    pid: libc::pid_t,
+    watchdog: &Watchdog<'_, SHM>,
+) -> Result<(i32, Option<ClientKill>), Error>
+where
+    SHM: ShMem,
+{
+    let watched = watchdog.heartbeat.is_some() || watchdog.rss_limit.is_some();
+    let flags = if watched { libc::WNOHANG } else { 0 };
+    let mut status = 0;
+    let mut kill = None;
+    loop {
+        // # Safety
+        // A plain libc call
+        let waited = unsafe { libc::waitpid(pid, &raw mut status, flags) };
+        if waited == pid {
+            return Ok((libc::WEXITSTATUS(status), kill));
+        }
+        if waited < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(Error::os_error(
+                err,
+                format!("Could not wait for client {pid}"),
+            ));
+        }
+
+        if kill.is_none() {
+            if let Some((heartbeat, timeout)) = watchdog.heartbeat {
+                if heartbeat.elapsed() > timeout {
+                    log::warn!("Client {pid} fired no event for {timeout:?}, killing it");
+                    kill = Some(ClientKill::Hang);
+                }
+            }
+            if let Some(rss_limit) = watchdog.rss_limit {
+                if let Some(rss) = rss_of(pid).filter(|rss| *rss > rss_limit) {
+                    log::warn!(
+                        "Client {pid} uses {rss} bytes of memory, more than the limit of {rss_limit}, killing it"
+                    );
+                    kill = Some(ClientKill::OutOfMemory);
+                }
+            }
+            if kill.is_some() {
+                // # Safety
+                // A plain libc call, on our own child
+                unsafe {
+                    libc::kill(pid, libc::SIGKILL);
+                }
+            }
+        }
+        std::thread::sleep(WATCHDOG_POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use core::time::Duration;
+
+    #[cfg(unix)]
+    use libafl_bolts::shmem::{ShMemProvider, StdShMemProvider};
+
+    #[cfg(unix)]
+    use super::{wait_for_client, ClientKill, Heartbeat, Watchdog};
+    use super::{ClientLimits, RestartPolicy};
+
+    #[test]
+    fn test_restart_backoff() {
+        let policy = RestartPolicy::new()
+            .with_min_uptime(Duration::from_secs(10))
+            .with_backoff(Duration::from_secs(1), Duration::from_secs(60));
+
+        assert!(policy.failed(Duration::from_secs(3), false));
+        assert!(policy.failed(Duration::from_secs(30), true));
+        assert!(!policy.failed(Duration::from_secs(30), false));
+
+        let backoffs: Vec<u64> = (0..10)
+            .map(|failures| policy.backoff_after(failures).as_secs())
+            .collect();
+        assert_eq!(backoffs, [0, 0, 1, 2, 4, 8, 16, 32, 60, 60]);
+        assert_eq!(policy.backoff_after(u32::MAX), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_client_limits() {
+        assert!(ClientLimits::new().is_unlimited());
+        let limits = ClientLimits::new()
+            .with_memory(1 << 30)
+            .with_cpu_percent(50);
+        assert!(!limits.is_unlimited());
+        assert_eq!(limits.memory(), Some(1 << 30));
+        assert_eq!(limits.cpu_percent(), Some(50));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    #[cfg_attr(miri, ignore)]
+    fn test_client_rlimits() {
+        let limits = ClientLimits::new()
+            .with_memory(64 << 20)
+            .with_cpu_time(Duration::from_secs(5));
+        assert!(limits.needs_cgroups());
+        assert!(!ClientLimits::new()
+            .with_cpu_time(Duration::from_secs(5))
+            .needs_cgroups());
+
+        // # Safety
+        // The child only sets and checks its limits, then exits
+        let pid = unsafe { libc::fork() };
+        if pid == 0 {
+            let ok = limits.apply_rlimits(false).is_ok()
+                && unsafe {
+                    let mut cpu = core::mem::zeroed::<libc::rlimit>();
+                    libc::getrlimit(libc::RLIMIT_CPU, &raw mut cpu);
+                    // Allocations beyond the memory limit fail
+                    let huge = libc::malloc(256 << 20);
+                    cpu.rlim_cur == 5 && huge.is_null()
+                };
+            unsafe { libc::_exit(i32::from(!ok)) };
+        }
+        let mut status = 0;
+        unsafe { libc::waitpid(pid, &raw mut status, 0) };
+        assert!(libc::WIFEXITED(status));
+        assert_eq!(libc::WEXITSTATUS(status), 0);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    #[cfg_attr(miri, ignore)]
+    fn test_heartbeat_watchdog() {
+        let mut provider = StdShMemProvider::new().unwrap();
+        let heartbeat = Heartbeat::new(provider.new_shmem(size_of::<u64>()).unwrap()).unwrap();
+        assert!(heartbeat.elapsed() < Duration::from_secs(1));
+
+        // A client exiting on its own
+        let watchdog = Watchdog {
+            heartbeat: Some((&heartbeat, Duration::from_secs(10))),
+            rss_limit: None,
+        };
+        // # Safety
+        // The child only exits
+        let pid = unsafe { libc::fork() };
+        if pid == 0 {
+            unsafe { libc::_exit(3) };
+        }
+        assert_eq!(wait_for_client(pid, &watchdog).unwrap(), (3, None));
+
+        // A client hanging without beat
+        let watchdog = Watchdog {
+            heartbeat: Some((&heartbeat, Duration::from_millis(200))),
+            rss_limit: None,
+        };
+        heartbeat.beat();
+        // # Safety
+        // The child only sleeps
+        let pid = unsafe { libc::fork() };
+        if pid == 0 {
+            std::thread::sleep(Duration::from_secs(30));
+            unsafe { libc::_exit(0) };
+        }
+        let (_, kill) = wait_for_client(pid, &watchdog).unwrap();
+        assert_eq!(kill, Some(ClientKill::Hang));
+        assert!(heartbeat.elapsed() >= Duration::from_millis(200));
+
+        // Not our child
+        assert!(wait_for_client(pid, &watchdog).is_err());
+    }
+}
@@ -11,9 +11,11 @@ use std::{
     env,
     io::{ErrorKind, Read, Write},
     net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
-    sync::Arc,
+    path::PathBuf,
+    sync::{Arc, Mutex},
 };
 
+use hashbrown::HashMap;
 #[cfg(feature = "tcp_compression")]
 use libafl_bolts::compress::GzipCompressor;
 #[cfg(feature = "std")]
@@ -43,9 +45,11 @@ use super::{CustomBufEventResult, CustomBufHandlerFn};
 use crate::events::EVENTMGR_SIGHANDLER_STATE;
 use crate::{
     events::{
+        delta::{delta_id, serialized_input, DeltaCodec, KnownInputs},
+        recorder::EventRecorder,
         BrokerEventResult, Event, EventConfig, EventFirer, EventManager, EventManagerHooksTuple,
         EventManagerId, EventProcessor, EventRestarter, HasCustomBufHandlers, HasEventManagerId,
-        ProgressReporter,
+        ProgressReporter, TestcaseDeduplicator,
     },
     executors::{Executor, HasObservers},
     fuzzer::{EvaluatorObservers, ExecutionProcessor},
@@ -56,6 +60,143 @@ use crate::{
     Error, HasMetadata,
 };
 
+/// The message carries a full [`Event`]
+const TCP_FULL_EVENT: u8 = 0;
+/// The message carries a [`Event::NewTestcase`] encoded as delta by a [`DeltaCodec`]
+const TCP_DELTA_EVENT: u8 = 1;
+/// The message asks the other side to resend a delta in full, as its base is unknown here.
+/// The (uncompressed) body is the [`delta_id`] of the delta.
+const TCP_NACK_EVENT: u8 = 2;
+
+/// A message rebuilt by [`decode_event`]
+enum DecodedMsg<I>
+where
+    I: Input,
+{
+    /// An event
+    Event {
+        /// The event
+        event: Event<I>,
+        /// The content hash of the input of a new testcase
+        input_hash: Option<u64>,
+        /// The [`delta_id`], if the event came as delta
+        delta_id: Option<u64>,
+    },
+    /// A delta against an input this side does not have (anymore), by [`delta_id`].
+    /// Ask the other side to resend it in full.
+    MissingBase(u64),
+    /// The other side asks to resend the delta with the given [`delta_id`] in full
+    Nack(u64),
+}
+
+/// Rebuilds a message written by [`TcpEventManager::fire`]: the kind byte, then the (compressed) event or
+/// delta. Remembers the input of new testcases, as base for the deltas to come.
+fn decode_event<I>(delta_codec: &mut DeltaCodec, buf: &[u8]) -> Result<DecodedMsg<I>, Error>
+where
+    I: Input,
+{
+    let (&kind, body) = buf
+        .split_first()
+        .ok_or_else(|| Error::illegal_state("Received an empty message"))?;
+
+    if kind == TCP_NACK_EVENT {
+        let delta_id = <[u8; 8]>::try_from(body)
+            .map_err(|_| Error::illegal_state("Received an invalid resend request"))?;
+        return Ok(DecodedMsg::Nack(u64::from_le_bytes(delta_id)));
+    }
+
+    #[cfg(feature = "tcp_compression")]
+    let body = GzipCompressor::new().decompress(body)?;
+
+    #[allow(clippy::needless_borrow)] // make decompressed vec and slice compatible
+    let (event, delta_id): (Event<I>, _) = match kind {
+        TCP_FULL_EVENT => (postcard::from_bytes(&body)?, None),
+        TCP_DELTA_EVENT => match delta_codec.decode(&body) {
+            Ok(serialized) => (postcard::from_bytes(&serialized)?, Some(delta_id(&body))),
+            Err(Error::KeyNotFound(..)) => return Ok(DecodedMsg::MissingBase(delta_id(&body))),
+            Err(e) => return Err(e),
+        },
+        _ => {
+            return Err(Error::illegal_state(format!(
+                "Received a message of unknown kind {kind}"
+            )))
+        }
+    };
+    let input_hash = delta_codec.remember(&event)?;
+    Ok(DecodedMsg::Event {
+        event,
+        input_hash,
+        delta_id,
+    })
+}
+
+/// Serializes a full message, as the broker forwards it, for `event` sent by the client `client_id`
+fn full_event_msg<I>(client_id: ClientId, event: &Event<I>) -> Result<Vec<u8>, Error>
+where
+    I: Input,
+{
+    let serialized = postcard::to_allocvec(event)?;
+    #[cfg(feature = "tcp_compression")]
+    let serialized = GzipCompressor::new().compress(&serialized);
+
+    let mut msg = Vec::with_capacity(serialized.len() + 5);
+    msg.extend_from_slice(&client_id.0.to_le_bytes());
+    msg.push(TCP_FULL_EVENT);
+    msg.extend_from_slice(&serialized);
+    Ok(msg)
+}
+
+/// Serializes a message asking a client to resend the delta with the given [`delta_id`] in full, as the broker
+/// forwards it
+fn nack_msg(delta_id: u64) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(13);
+    msg.extend_from_slice(&UNDEFINED_CLIENT_ID.0.to_le_bytes());
+    msg.push(TCP_NACK_EVENT);
+    msg.extend_from_slice(&delta_id.to_le_bytes());
+    msg
+}
+
+/// A message from a client, as the broker forwards it to the other clients
+#[derive(Debug, Clone)]
+struct ForwardedMsg {
+    /// The full message, with the id of the sending client
+    full: Vec<u8>,
+    /// The message as received, if it is a delta, with the content hash of its base input and its [`delta_id`]
+    delta: Option<(u64, u64, Vec<u8>)>,
+    /// The content hash of the input of a new testcase
+    input_hash: Option<u64>,
+    /// The serialized input of a new testcase, for the codecs of the clients it is forwarded to
+    input: Option<Arc<Vec<u8>>>,
+    /// Only forward to this client, e.g., a delta it could not rebuild, resent in full
+    to: Option<ClientId>,
+    /// The content hash of an input the client does not have, it gets no more deltas against it
+    missing_base: Option<u64>,
+}
+
+impl ForwardedMsg {
+    /// A message for the given client only
+    fn to_client(client_id: ClientId, full: Vec<u8>, missing_base: Option<u64>) -> Self {
+        Self {
+            full,
+            delta: None,
+            input_hash: None,
+            input: None,
+            to: Some(client_id),
+            missing_base,
+        }
+    }
+}
+
+/// What the tasks serving a client pass on to the main loop of the broker
+#[derive(Debug)]
+enum ClientMsg {
+    /// The client (re)connected. Its deltas are rebuilt with this codec, which remembers the testcases
+    /// exchanged with this very client, just like the codec of the client does.
+    Connected(ClientId, Arc<Mutex<DeltaCodec>>),
+    /// A message of the client, prefixed by its id
+    Msg(Vec<u8>),
+}
+
 /// Tries to create (synchronously) a [`TcpListener`] that is `nonblocking` (for later use in tokio).
 /// Will error if the port is already in use (or other errors occur)
 fn create_nonblocking_listener<A: ToSocketAddrs>(addr: A) -> Result<TcpListener, Error> {
@@ -77,6 +218,12 @@ where
     listener: Option<TcpListener>,
     /// Amount of all clients ever, after which (when all are disconnected) this broker should quit.
     exit_cleanly_after: Option<NonZeroUsize>,
+    /// Rebuilds the testcases sent as deltas, one codec per client
+    delta_codecs: HashMap<ClientId, Arc<Mutex<DeltaCodec>>>,
+    /// Records the events to an event log, if set
+    event_recorder: Option<EventRecorder>,
+    /// Drops the duplicate testcases, if set
+    deduplicator: Option<TestcaseDeduplicator>,
     phantom: PhantomData<I>,
 }
 
@@ -102,9 +249,22 @@ where
             monitor,
             phantom: PhantomData,
             exit_cleanly_after: None,
+            delta_codecs: HashMap::new(),
+            event_recorder: None,
+            deduplicator: None,
         }
     }
 
+    /// Drop the testcases other clients already got, instead of forwarding them, see [`TestcaseDeduplicator`]
+    pub fn set_deduplicator(&mut self, deduplicator: TestcaseDeduplicator) {
+        self.deduplicator = Some(deduplicator);
+    }
+
+    /// Record all events the broker receives to an event log, see [`crate::events::recorder`]
+    pub fn set_event_recorder(&mut self, recorder: EventRecorder) {
+        self.event_recorder = Some(recorder);
+    }
+
     /// Exit the broker process cleanly after at least `n` clients attached and all of them disconnected again
     pub fn set_exit_cleanly_after(&mut self, n_clients: NonZeroUsize) {
         self.exit_cleanly_after = Some(n_clients);
@@ -170,6 +330,12 @@ where
                     continue;
                 }
 
+                // A (re)connected client starts without any inputs to build deltas against
+                let delta_codec = Arc::new(Mutex::new(DeltaCodec::new()));
+                tx.send(ClientMsg::Connected(this_client_id, delta_codec.clone()))
+                    .await
+                    .expect("Could not send");
+
                 let tx_inner = tx.clone();
 
                 let handle = async move {
@@ -203,7 +369,10 @@ where
                         }
 
                         log::debug!("TCP Manager - len: {len:?} - {buf:?}");
-                        tx_inner.send(buf).await.expect("Could not send");
+                        tx_inner
+                            .send(ClientMsg::Msg(buf))
+                            .await
+                            .expect("Could not send");
                     }
                 };
 
@@ -224,10 +393,12 @@ where
 
                 // The forwarding end. No need to keep a handle to this (TODO: unless they don't quit/get stuck?)
                 spawn(async move {
+                    // The inputs this client got since it (re)connected, the bases it can rebuild deltas against
+                    let mut known_inputs = KnownInputs::new();
                     // In a loop, read data from the socket and write the data back.
                     loop {
-                        let buf: Vec<u8> = match rx_inner.lock().await.recv().await {
-                            Ok(buf) => buf,
+                        let msg: ForwardedMsg = match rx_inner.lock().await.recv().await {
+                            Ok(msg) => msg,
                             Err(RecvError::Lagged(num)) => {
                                 log::error!("Receiver lagged, skipping {num} messages");
                                 continue;
@@ -235,19 +406,51 @@ where
                             _ => panic!("Could not receive"),
                         };
 
-                        log::debug!("TCP Manager - {buf:?}");
+                        log::debug!("TCP Manager - {msg:?}");
 
-                        if buf.len() <= 4 {
+                        if msg.to.is_some_and(|to| to != this_client_id) {
+                            continue;
+                        }
+                        if let Some(base) = msg.missing_base {
+                            known_inputs.remove(base);
+                        }
+
+                        if msg.full.len() <= 4 {
                             log::warn!("We got no contents (or only the length) in a broadcast");
                             continue;
                         }
 
-                        if buf[..4] == this_client_id_bytes {
+                        if msg.full[..4] == this_client_id_bytes {
                             log::debug!("TCP Manager - Not forwarding message from this very client ({this_client_id:?})."
                         );
+                            if let Some(input_hash) = msg.input_hash {
+                                known_inputs.insert(input_hash);
+                            }
                             continue;
                         }
 
+                        // A delta only goes to clients which got its base, the others get the full event
+                        let buf = match &msg.delta {
+                            Some((base, delta_id, delta)) if known_inputs.contains(*base) => {
+                                // In case the client evicted the base meanwhile, and asks for the full event
+                                delta_codec.lock().unwrap().keep_sent(
+                                    *delta_id,
+                                    *base,
+                                    msg.full.clone(),
+                                );
+                                delta
+                            }
+                            _ => &msg.full,
+                        };
+                        if let (Some(input_hash), Some(input)) = (msg.input_hash, &msg.input) {
+                            known_inputs.insert(input_hash);
+                            // The client may send deltas against it from now on
+                            delta_codec
+                                .lock()
+                                .unwrap()
+                                .remember_serialized(input.to_vec());
+                        }
+
                         // subtract 4 since the client_id isn't part of the actual message.
                         let len = u32::try_from(buf.len() - 4).unwrap();
                         let len_buf: [u8; 4] = len.to_le_bytes();
@@ -259,7 +462,7 @@ where
                             return;
                         }
                         // Write the rest
-                        if write.write_all(&buf).await.is_err() {
+                        if write.write_all(buf).await.is_err() {
                             // The socket is closed, the client is restarting
                             log::info!("Socket closed, client restarting");
                             return;
@@ -276,7 +479,25 @@ where
         });
 
         loop {
-            let buf = rx_mpsc.recv().await.expect("Could not receive");
+            let msg = if let Some(recorder) = &mut self.event_recorder {
+                // Flush the event log while the clients are idle
+                match tokio::time::timeout(EventRecorder::FLUSH_INTERVAL, rx_mpsc.recv()).await {
+                    Ok(msg) => msg,
+                    Err(_) => {
+                        recorder.flush()?;
+                        continue;
+                    }
+                }
+            } else {
+                rx_mpsc.recv().await
+            };
+            let buf = match msg.expect("Could not receive") {
+                ClientMsg::Connected(client_id, delta_codec) => {
+                    self.delta_codecs.insert(client_id, delta_codec);
+                    continue;
+                }
+                ClientMsg::Msg(buf) => buf,
+            };
 
             // read client ID.
             let mut client_id_buf = [0_u8; 4];
@@ -284,19 +505,78 @@ where
 
             let client_id = ClientId(u32::from_le_bytes(client_id_buf));
 
+            let delta_codec = self.delta_codecs.entry(client_id).or_default().clone();
             // cut off the ID.
-            let event_bytes = &buf[4..];
-
-            #[cfg(feature = "tcp_compression")]
-            let event_bytes = GzipCompressor::new().decompress(event_bytes)?;
-
-            #[allow(clippy::needless_borrow)] // make decompressed vec and slice compatible
-            let event: Event<I> = postcard::from_bytes(&event_bytes)?;
-            match Self::handle_in_broker(&mut self.monitor, client_id, &event)? {
-                BrokerEventResult::Forward => {
-                    tx_bc.send(buf).expect("Could not send");
+            let decoded = decode_event::<I>(&mut delta_codec.lock().unwrap(), &buf[4..]);
+            match decoded {
+                Ok(DecodedMsg::Event {
+                    event,
+                    input_hash,
+                    delta_id,
+                }) => {
+                    if let Some(recorder) = &mut self.event_recorder {
+                        recorder.record_event(client_id, &event)?;
+                    }
+                    match Self::handle_in_broker(&mut self.monitor, client_id, &event)? {
+                        BrokerEventResult::Forward => {
+                            if self.should_forward(client_id, &event)? {
+                                let input = match input_hash {
+                                    Some(_) => serialized_input(&event)?.map(Arc::new),
+                                    None => None,
+                                };
+                                let msg = match (delta_id, &event) {
+                                    (
+                                        Some(delta_id),
+                                        Event::NewTestcase {
+                                            parent_hash: Some(base),
+                                            ..
+                                        },
+                                    ) => ForwardedMsg {
+                                        full: full_event_msg(client_id, &event)?,
+                                        delta: Some((*base, delta_id, buf)),
+                                        input_hash,
+                                        input,
+                                        to: None,
+                                        missing_base: None,
+                                    },
+                                    _ => ForwardedMsg {
+                                        full: buf,
+                                        delta: None,
+                                        input_hash,
+                                        input,
+                                        to: None,
+                                        missing_base: None,
+                                    },
+                                };
+                                tx_bc.send(msg).expect("Could not send");
+                            }
+                        }
+                        BrokerEventResult::Handled => (),
+                    }
+                }
+                Ok(DecodedMsg::MissingBase(delta_id)) => {
+                    log::debug!("Asking {client_id:?} to resend a testcase in full");
+                    tx_bc
+                        .send(ForwardedMsg::to_client(client_id, nack_msg(delta_id), None))
+                        .expect("Could not send");
+                }
+                Ok(DecodedMsg::Nack(delta_id)) => {
+                    let resent = delta_codec.lock().unwrap().take_sent(delta_id);
+                    match resent {
+                        Some((base, full)) => {
+                            tx_bc
+                                .send(ForwardedMsg::to_client(client_id, full, Some(base)))
+                                .expect("Could not send");
+                        }
+                        None => log::warn!(
+                            "Can not resend a testcase to {client_id:?}, it is too old. Dropping it."
+                        ),
+                    }
+                }
+                Err(e) => {
+                    // A single malformed message must not take down the broker
+                    log::warn!("Dropping an invalid message from {client_id:?}: {e}");
                 }
-                BrokerEventResult::Handled => (),
             }
 
             if tokio_broker.is_finished() {
@@ -309,6 +589,18 @@ where
         Err(Error::shutting_down())
     }
 
+    /// If `event` is to be forwarded to the other clients: it is no duplicate testcase
+    fn should_forward(&mut self, client_id: ClientId, event: &Event<I>) -> Result<bool, Error> {
+        let Some(deduplicator) = &mut self.deduplicator else {
+            return Ok(true);
+        };
+        if deduplicator.is_duplicate(event)? {
+            log::debug!("Dropping a duplicate testcase from {client_id:?}");
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
     /// Handle arriving events in the broker
     #[allow(clippy::unnecessary_wraps)]
     fn handle_in_broker(
@@ -399,8 +691,9 @@ where
                 log::log!((*severity_level).into(), "{message}");
                 Ok(BrokerEventResult::Handled)
             }
-            Event::CustomBuf { .. } | Event::Stop => Ok(BrokerEventResult::Forward),
-            //_ => Ok(BrokerEventResult::Forward),
+            Event::CustomBuf { .. } | Event::Custom { .. } | Event::Stop => {
+                Ok(BrokerEventResult::Forward)
+            }
         }
     }
 }
@@ -424,6 +717,10 @@ where
     custom_buf_handlers: Vec<Box<CustomBufHandlerFn<S>>>,
     #[cfg(feature = "tcp_compression")]
     compressor: GzipCompressor,
+    /// Sends new testcases as deltas against the inputs exchanged with the broker
+    delta_codec: DeltaCodec,
+    /// The inputs exchanged with the broker, the bases it can rebuild deltas against
+    broker_inputs: KnownInputs,
     /// The configuration defines this specific fuzzer.
     /// A node will not re-use the observer values sent over TCP
     /// from nodes with other configurations.
@@ -517,6 +814,8 @@ where
             client_id,
             #[cfg(feature = "tcp_compression")]
             compressor: GzipCompressor::new(),
+            delta_codec: DeltaCodec::new(),
+            broker_inputs: KnownInputs::new(),
             configuration,
             phantom: PhantomData,
             custom_buf_handlers: vec![],
@@ -651,6 +950,8 @@ where
                     }
                 }
             }
+            // Handled by the `CustomEventHook`s
+            Event::Custom { .. } => {}
             Event::Stop => {
                 state.request_stop();
             }
@@ -671,6 +972,30 @@ where
     EMH: EventManagerHooksTuple<S>,
     S: State,
 {
+    /// Writes a message to the broker: the kind byte, then the body. The caller compresses the body, if needed.
+    fn send_msg(&mut self, kind: u8, body: &[u8]) -> Result<(), Error> {
+        let size = u32::try_from(body.len() + 1)?;
+        self.tcp.write_all(&size.to_le_bytes())?;
+        self.tcp.write_all(&self.client_id.0.to_le_bytes())?;
+        self.tcp.write_all(&[kind])?;
+        self.tcp.write_all(body)?;
+        Ok(())
+    }
+
+    /// Resends the delta with the given [`delta_id`] in full, as the broker misses its base
+    fn resend_in_full(&mut self, delta_id: u64) -> Result<(), Error> {
+        let Some((base, serialized)) = self.delta_codec.take_sent(delta_id) else {
+            log::warn!("Can not resend a testcase to the broker, it is too old. Dropping it.");
+            return Ok(());
+        };
+        self.broker_inputs.remove(base);
+
+        #[cfg(feature = "tcp_compression")]
+        let serialized = self.compressor.compress(&serialized);
+
+        self.send_msg(TCP_FULL_EVENT, &serialized)
+    }
+
     /// Send information that this client is exiting.
     /// The other side may free up all allocated memory.
     /// We are no longer allowed to send anything afterwards.
@@ -709,13 +1034,23 @@ where
     ) -> Result<(), Error> {
         let serialized = postcard::to_allocvec(&event)?;
 
+        // The broker forwards the delta to the clients which have the base, and the full event to the others
+        let broker_inputs = &self.broker_inputs;
+        let (kind, serialized) = match self
+            .delta_codec
+            .encode(&event, &serialized, |hash| broker_inputs.contains(hash))?
+        {
+            Some(delta) => (TCP_DELTA_EVENT, delta),
+            None => (TCP_FULL_EVENT, serialized),
+        };
+        if let Some(input_hash) = self.delta_codec.remember(&event)? {
+            self.broker_inputs.insert(input_hash);
+        }
+
         #[cfg(feature = "tcp_compression")]
         let serialized = self.compressor.compress(&serialized);
 
-        let size = u32::try_from(serialized.len())?;
-        self.tcp.write_all(&size.to_le_bytes())?;
-        self.tcp.write_all(&self.client_id.0.to_le_bytes())?;
-        self.tcp.write_all(&serialized)?;
+        self.send_msg(kind, &serialized)?;
 
         self.last_sent = libafl_bolts::current_time();
         Ok(())
@@ -724,6 +1059,10 @@ where
     fn configuration(&self) -> EventConfig {
         self.configuration
     }
+
+    fn sends_deltas(&self) -> bool {
+        true
+    }
 }
 
 impl<EMH, S> EventRestarter for TcpEventManager<EMH, S>
@@ -782,13 +1121,29 @@ where
                     } else {
                         log::info!("{self_id:?} (from {other_client_id:?}) Received: {buf:?}");
 
-                        let buf = &buf[4..];
-                        #[cfg(feature = "tcp_compression")]
-                        let buf = self.compressor.decompress(buf)?;
-
-                        // make decompressed vec and slice compatible
-                        #[allow(clippy::needless_borrow)]
-                        let event = postcard::from_bytes(&buf)?;
+                        let event = match decode_event(&mut self.delta_codec, &buf[4..])? {
+                            DecodedMsg::Event {
+                                event, input_hash, ..
+                            } => {
+                                if let Some(input_hash) = input_hash {
+                                    self.broker_inputs.insert(input_hash);
+                                }
+                                event
+                            }
+                            DecodedMsg::MissingBase(delta_id) => {
+                                log::debug!("Asking the broker to resend a testcase in full");
+                                self.tcp.set_nonblocking(false).expect("set to blocking");
+                                self.send_msg(TCP_NACK_EVENT, &delta_id.to_le_bytes())?;
+                                self.tcp.set_nonblocking(true).expect("set to non-blocking");
+                                continue;
+                            }
+                            DecodedMsg::Nack(delta_id) => {
+                                self.tcp.set_nonblocking(false).expect("set to blocking");
+                                self.resend_in_full(delta_id)?;
+                                self.tcp.set_nonblocking(true).expect("set to non-blocking");
+                                continue;
+                            }
+                        };
 
                         self.handle_in_client(fuzzer, executor, state, other_client_id, event)?;
                         count += 1;
@@ -918,6 +1273,10 @@ where
     fn configuration(&self) -> EventConfig {
         self.tcp_mgr.configuration()
     }
+
+    fn sends_deltas(&self) -> bool {
+        self.tcp_mgr.sends_deltas()
+    }
 }
 
 #[cfg(feature = "std")]
@@ -1139,6 +1498,12 @@ where
     /// but it will quit after client 2 connected and disconnected.
     #[builder(default = None)]
     exit_cleanly_after: Option<NonZeroUsize>,
+    /// Record all events the broker receives to the event log at this path, see [`crate::events::recorder`]
+    #[builder(default = None)]
+    event_log: Option<PathBuf>,
+    /// Drop the testcases other clients already got in the broker, instead of forwarding them
+    #[builder(default = None)]
+    testcase_dedup: Option<TestcaseDeduplicator>,
     /// Tell the manager to serialize or not the state on restart
     #[builder(default = true)]
     serialize_state: bool,
@@ -1166,6 +1531,12 @@ where
                 if let Some(exit_cleanly_after) = self.exit_cleanly_after {
                     broker.set_exit_cleanly_after(exit_cleanly_after);
                 }
+                if let Some(event_log) = &self.event_log {
+                    broker.set_event_recorder(EventRecorder::new(event_log)?);
+                }
+                if let Some(deduplicator) = &self.testcase_dedup {
+                    broker.set_deduplicator(deduplicator.clone());
+                }
 
                 broker.broker_loop()
             };
@@ -16,6 +16,7 @@ use std::{
     },
     path::Path,
     process::{Child, Command, Stdio},
+    time::Instant,
 };
 
 use libafl_bolts::{
@@ -40,10 +41,13 @@ use nix::{
 use super::HasTimeout;
 #[cfg(feature = "regex")]
 use crate::observers::{
-    get_asan_runtime_flags, get_asan_runtime_flags_with_log_path, AsanBacktraceObserver,
+    get_asan_runtime_flags, get_asan_runtime_flags_with_log_path,
+    get_sanitizer_runtime_flags_with_log_path, AsanBacktraceObserver,
 };
 use crate::{
-    executors::{Executor, ExitKind, HasObservers},
+    executors::{
+        resources::rss_of, Executor, ExitKind, HasObservers, ResourceLimits, ResourceMonitor,
+    },
     inputs::{
         BytesInput, HasTargetBytes, Input, NopTargetBytesConverter, TargetBytesConverter, UsesInput,
     },
@@ -423,6 +427,13 @@ impl Forkserver {
                 get_asan_runtime_flags()
             };
             command.env("ASAN_OPTIONS", asan_options);
+            if dump_asan_logs {
+                for options in ["UBSAN_OPTIONS", "MSAN_OPTIONS", "TSAN_OPTIONS"] {
+                    if env::var_os(options).is_none() {
+                        command.env(options, get_sanitizer_runtime_flags_with_log_path());
+                    }
+                }
+            }
         }
 
         let fsrv_handle = match command
@@ -498,6 +509,11 @@ impl Forkserver {
         self.status = status;
     }
 
+    /// The pid of the forkserver process
+    pub fn pid(&self) -> Result<Pid, Error> {
+        Ok(Pid::from_raw(self.fsrv_handle.id().try_into()?))
+    }
+
     /// The child pid
     #[must_use]
     pub fn child_pid(&self) -> Pid {
@@ -628,6 +644,7 @@ where
     asan_obs: Handle<AsanBacktraceObserver>,
     timeout: TimeSpec,
     crash_exitcode: Option<i8>,
+    resources: Option<ResourceMonitor>,
 }
 
 impl<TC, OT, S, SP> Debug for ForkserverExecutor<TC, OT, S, SP>
@@ -646,6 +663,7 @@ where
             .field("forkserver", &self.forkserver)
             .field("observers", &self.observers)
             .field("map", &self.map)
+            .field("resources", &self.resources)
             .finish_non_exhaustive()
     }
 }
@@ -697,6 +715,58 @@ where
         self.map_size
     }
 
+    /// The [`ResourceMonitor`] enforcing the memory limits, if limits were set
+    pub fn resources(&self) -> Option<&ResourceMonitor> {
+        self.resources.as_ref()
+    }
+
+    /// The peak RSS the last execution added to the child, in bytes.
+    ///
+    /// The peak is sampled while the child runs, and read once more after a persistent child stopped.
+    /// A new child exiting before the first sample is not measured, a cgroup limit still catches it.
+    pub fn last_peak_rss(&self) -> Option<u64> {
+        self.resources
+            .as_ref()
+            .and_then(ResourceMonitor::last_peak_rss)
+    }
+
+    /// Wait for the status of `child`, sampling its memory use while it runs.
+    ///
+    /// Returns `None` on timeout. If the child exceeds the RSS limit, it is killed and its status is returned.
+    fn read_st_sampled(&mut self, child: Pid) -> Result<Option<i32>, Error> {
+        let Some(resources) = self.resources.as_mut() else {
+            return self.forkserver.read_st_timed(&self.timeout);
+        };
+        if !resources.needs_sampling() {
+            return self.forkserver.read_st_timed(&self.timeout);
+        }
+
+        let timeout: Duration = self.timeout.into();
+        let interval = resources.limits().rss_sample_interval();
+        let start = Instant::now();
+        loop {
+            let elapsed = start.elapsed();
+            if elapsed >= timeout {
+                return Ok(None);
+            }
+            let slice = TimeSpec::from_duration(interval.min(timeout - elapsed));
+            if let Some(status) = self.forkserver.read_st_timed(&slice)? {
+                return Ok(Some(status));
+            }
+            if resources.sample(child) {
+                log::debug!(
+                    "Child {child} exceeded the RSS limit ({:?} bytes), killing it",
+                    resources.last_peak_rss()
+                );
+                let _ = kill(child, Signal::SIGKILL);
+                let status = self.forkserver.read_st().map_err(|err| {
+                    Error::unknown(format!("Could not kill out-of-memory child: {err:?}"))
+                })?;
+                return Ok(Some(status));
+            }
+        }
+    }
+
     /// Execute input and increase the execution counter.
     #[inline]
     fn execute_input(&mut self, state: &mut S, input: &TC::Input) -> Result<ExitKind, Error>
@@ -748,6 +818,17 @@ where
                 .write_buf(&input_bytes.as_slice()[..input_size])?;
         }
 
+        if let Some(resources) = self.resources.as_mut() {
+            // A persistent child resumes with the memory of its last execution,
+            // a new one starts out with the memory of the forkserver.
+            let baseline_rss = match self.forkserver.child_pid {
+                Some(child_pid) => rss_of(child_pid),
+                None => rss_of(self.forkserver.pid()?),
+            }
+            .unwrap_or(0);
+            resources.pre_exec(baseline_rss, self.forkserver.child_pid)?;
+        }
+
         self.forkserver.set_last_run_timed_out(false);
         if let Err(err) = self.forkserver.write_ctl(last_run_timed_out) {
             return Err(Error::unknown(format!(
@@ -769,8 +850,14 @@ where
 
         self.forkserver.set_child_pid(Pid::from_raw(pid));
 
-        if let Some(status) = self.forkserver.read_st_timed(&self.timeout)? {
+        if let Some(status) = self.read_st_sampled(Pid::from_raw(pid))? {
             self.forkserver.set_status(status);
+            if libc::WIFSTOPPED(status) {
+                // The stopped persistent child still has its peak for this execution
+                if let Some(resources) = self.resources.as_mut() {
+                    resources.sample(Pid::from_raw(pid));
+                }
+            }
             let exitcode_is_crash = if let Some(crash_exitcode) = self.crash_exitcode {
                 (libc::WEXITSTATUS(self.forkserver().status()) as i8) == crash_exitcode
             } else {
@@ -778,9 +865,16 @@ where
             };
             if libc::WIFSIGNALED(self.forkserver().status()) || exitcode_is_crash {
                 exit_kind = ExitKind::Crash;
+                // A child we killed for exceeding the RSS limit did not leave an ASan log behind.
                 #[cfg(feature = "regex")]
-                if let Some(asan_observer) = self.observers.get_mut(&self.asan_obs) {
-                    asan_observer.parse_asan_output_from_asan_log_file(pid)?;
+                if !self
+                    .resources
+                    .as_ref()
+                    .is_some_and(ResourceMonitor::rss_exceeded)
+                {
+                    if let Some(asan_observer) = self.observers.get_mut(&self.asan_obs) {
+                        asan_observer.parse_asan_output_from_asan_log_file(pid)?;
+                    }
                 }
             }
         } else {
@@ -800,6 +894,10 @@ where
             self.forkserver.reset_child_pid();
         }
 
+        if let Some(resources) = &self.resources {
+            exit_kind = resources.classify(exit_kind);
+        }
+
         Ok(exit_kind)
     }
 }
@@ -827,6 +925,7 @@ pub struct ForkserverExecutorBuilder<'a, TC, SP> {
     #[cfg(feature = "regex")]
     asan_obs: Option<Handle<AsanBacktraceObserver>>,
     crash_exitcode: Option<i8>,
+    resource_limits: Option<ResourceLimits>,
     target_bytes_converter: TC,
 }
 
@@ -848,7 +947,7 @@ where
         TC: TargetBytesConverter,
         SP: ShMemProvider,
     {
-        let (forkserver, input_file, map) = self.build_helper()?;
+        let (forkserver, input_file, map, resources) = self.build_helper()?;
 
         let target = self.program.take().unwrap();
         log::info!(
@@ -896,6 +995,7 @@ where
                 .clone()
                 .unwrap_or(AsanBacktraceObserver::default().handle()),
             crash_exitcode: self.crash_exitcode,
+            resources,
             target_bytes_converter: self.target_bytes_converter,
         })
     }
@@ -915,7 +1015,7 @@ where
         S::Input: Input + HasTargetBytes,
         SP: ShMemProvider,
     {
-        let (forkserver, input_file, map) = self.build_helper()?;
+        let (forkserver, input_file, map, resources) = self.build_helper()?;
 
         let target = self.program.take().unwrap();
         log::info!(
@@ -961,12 +1061,24 @@ where
                 .clone()
                 .unwrap_or(AsanBacktraceObserver::default().handle()),
             crash_exitcode: self.crash_exitcode,
+            resources,
             target_bytes_converter: self.target_bytes_converter,
         })
     }
 
     #[allow(clippy::pedantic)]
-    fn build_helper(&mut self) -> Result<(Forkserver, InputFile, Option<SP::ShMem>), Error>
+    #[allow(clippy::type_complexity)]
+    fn build_helper(
+        &mut self,
+    ) -> Result<
+        (
+            Forkserver,
+            InputFile,
+            Option<SP::ShMem>,
+            Option<ResourceMonitor>,
+        ),
+        Error,
+    >
     where
         SP: ShMemProvider,
     {
@@ -1000,7 +1112,10 @@ where
                 self.envs.clone(),
                 input_file.as_raw_fd(),
                 self.use_stdin,
-                0,
+                self.resource_limits
+                    .as_ref()
+                    .and_then(ResourceLimits::address_space_limit)
+                    .unwrap_or(0),
                 self.is_persistent,
                 self.is_deferred_frksrv,
                 self.asan_obs.is_some(),
@@ -1015,6 +1130,16 @@ where
             }
         };
 
+        // Move the forkserver into the cgroup before it forks any children, they will inherit it.
+        let resources = match self.resource_limits.clone() {
+            Some(limits) => {
+                let resources = ResourceMonitor::new(limits)?;
+                resources.attach(forkserver.pid()?)?;
+                Some(resources)
+            }
+            None => None,
+        };
+
         // Initial handshake, read 4-bytes hello message from the forkserver.
         let version_status = forkserver.read_st().map_err(|err| {
             Error::illegal_state(format!("{FAILED_TO_START_FORKSERVER_MSG}: {err:?}"))
@@ -1030,7 +1155,7 @@ where
         } else {
             self.initialize_forkserver(version_status, map.as_ref(), &mut forkserver)?;
         }
-        Ok((forkserver, input_file, map))
+        Ok((forkserver, input_file, map, resources))
     }
 
     fn is_old_forkserver(version_status: i32) -> bool {
@@ -1456,6 +1581,15 @@ where
         self.kill_signal = Some(kill_signal);
         self
     }
+
+    /// Limit the memory of each execution.
+    ///
+    /// Executions exceeding the limits are reported as [`ExitKind::Oom`].
+    #[must_use]
+    pub fn resource_limits(mut self, resource_limits: ResourceLimits) -> Self {
+        self.resource_limits = Some(resource_limits);
+        self
+    }
 }
 
 impl<'a> ForkserverExecutorBuilder<'a, NopTargetBytesConverter<BytesInput>, UnixShMemProvider> {
@@ -1487,6 +1621,7 @@ impl<'a> ForkserverExecutorBuilder<'a, NopTargetBytesConverter<BytesInput>, Unix
             timeout: None,
             asan_obs: None,
             crash_exitcode: None,
+            resource_limits: None,
             target_bytes_converter: NopTargetBytesConverter::new(),
         }
     }
@@ -1519,6 +1654,7 @@ impl<'a, TC> ForkserverExecutorBuilder<'a, TC, UnixShMemProvider> {
             timeout: self.timeout,
             asan_obs: self.asan_obs,
             crash_exitcode: self.crash_exitcode,
+            resource_limits: self.resource_limits,
             target_bytes_converter: self.target_bytes_converter,
         }
     }
@@ -1551,6 +1687,7 @@ impl<'a, TC, SP> ForkserverExecutorBuilder<'a, TC, SP> {
             timeout: self.timeout,
             asan_obs: self.asan_obs,
             crash_exitcode: self.crash_exitcode,
+            resource_limits: self.resource_limits,
             target_bytes_converter,
         }
     }
@@ -26,7 +26,8 @@ use crate::{
             inprocess_fork::{InChildProcessHooks, FORK_EXECUTOR_GLOBAL_DATA},
             ExecutorHooksTuple,
         },
-        ExitKind, HasObservers,
+        resources::rss_of,
+        ExitKind, HasObservers, ResourceLimits, ResourceMonitor,
     },
     inputs::UsesInput,
     observers::ObserversTuple,
@@ -43,6 +44,7 @@ pub struct GenericInProcessForkExecutorInner<HT, OT, S, SP, EM, Z> {
     pub(super) itimerspec: libc::itimerspec,
     #[cfg(all(unix, not(target_os = "linux")))]
     pub(super) itimerval: Itimerval,
+    pub(super) resources: Option<ResourceMonitor>,
     pub(super) phantom: PhantomData<(S, EM, Z)>,
 }
 
@@ -58,6 +60,7 @@ where
             .field("observers", &self.observers)
             .field("shmem_provider", &self.shmem_provider)
             .field("itimerspec", &self.itimerspec)
+            .field("resources", &self.resources)
             .finish_non_exhaustive()
     }
 
@@ -69,6 +72,7 @@ where
             .field("observers", &self.observers)
             .field("shmem_provider", &self.shmem_provider)
             .field("itimerval", &self.itimerval)
+            .field("resources", &self.resources)
             .finish_non_exhaustive();
     }
 }
@@ -123,6 +127,16 @@ where
     EM: EventFirer<State = S> + EventRestarter<State = S>,
     Z: UsesState<State = S>,
 {
+    /// Called in the parent right before forking the child.
+    pub(super) fn pre_fork(&mut self) -> Result<(), Error> {
+        self.shmem_provider.pre_fork()?;
+        if let Some(resources) = self.resources.as_mut() {
+            // The child starts out with our memory
+            resources.pre_exec(rss_of(Pid::this()).unwrap_or(0), None)?;
+        }
+        Ok(())
+    }
+
     pub(super) unsafe fn pre_run_target_child(
         &mut self,
         fuzzer: &mut Z,
@@ -132,6 +146,11 @@ where
     ) -> Result<(), Error> {
         self.shmem_provider.post_fork(true)?;
 
+        if let Some(resources) = &self.resources {
+            resources.attach(Pid::this())?;
+            resources.limits().apply_rlimits()?;
+        }
+
         self.enter_target(fuzzer, state, mgr, input);
         self.hooks.pre_exec_all(state, input);
 
@@ -179,14 +198,17 @@ where
         // log::trace!("from parent {} child is {}", std::process::id(), child);
         self.shmem_provider.post_fork(false)?;
 
-        let res = waitpid(child, None)?;
+        let res = match self.resources.as_mut() {
+            Some(resources) => resources.wait_for_child(child)?,
+            None => waitpid(child, None)?,
+        };
         log::trace!("{res:#?}");
-        match res {
+        let exit_kind = match res {
             WaitStatus::Signaled(_, signal, _) => match signal {
                 nix::sys::signal::Signal::SIGALRM | nix::sys::signal::Signal::SIGUSR2 => {
-                    Ok(ExitKind::Timeout)
+                    ExitKind::Timeout
                 }
-                _ => Ok(ExitKind::Crash),
+                _ => ExitKind::Crash,
             },
             WaitStatus::Exited(_, code) => {
                 if code > 128 && code < 160 {
@@ -195,16 +217,21 @@ where
                     if signal == Signal::SigAlarm as libc::c_int
                         || signal == Signal::SigUser2 as libc::c_int
                     {
-                        Ok(ExitKind::Timeout)
+                        ExitKind::Timeout
                     } else {
-                        Ok(ExitKind::Crash)
+                        ExitKind::Crash
                     }
                 } else {
-                    Ok(ExitKind::Ok)
+                    ExitKind::Ok
                 }
             }
-            _ => Ok(ExitKind::Ok),
-        }
+            _ => ExitKind::Ok,
+        };
+
+        Ok(match &self.resources {
+            Some(resources) => resources.classify(exit_kind),
+            None => exit_kind,
+        })
     }
 }
 
@@ -253,6 +280,20 @@ where
         // do nothing
     }
 
+    /// Limit the memory of each execution, executions exceeding the limits are reported as [`ExitKind::Oom`].
+    pub fn set_resource_limits(&mut self, limits: ResourceLimits) -> Result<(), Error> {
+        self.resources = Some(ResourceMonitor::new(limits)?);
+        Ok(())
+    }
+
+    /// The peak RSS of the last execution in bytes, if resource limits are set.
+    #[must_use]
+    pub fn last_peak_rss(&self) -> Option<u64> {
+        self.resources
+            .as_ref()
+            .and_then(ResourceMonitor::last_peak_rss)
+    }
+
     /// Creates a new [`GenericInProcessForkExecutorInner`] with custom hooks
     #[cfg(target_os = "linux")]
     #[allow(clippy::too_many_arguments)]
@@ -274,6 +315,7 @@ where
             observers,
             hooks,
             itimerspec,
+            resources: None,
             phantom: PhantomData,
         })
     }
@@ -301,6 +343,7 @@ where
             observers,
             hooks,
             itimerval,
+            resources: None,
             phantom: PhantomData,
         })
     }
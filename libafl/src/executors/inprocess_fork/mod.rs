@@ -18,6 +18,7 @@ use crate::{
     executors::{
         hooks::inprocess_fork::InProcessForkExecutorGlobalData,
         inprocess_fork::inner::GenericInProcessForkExecutorInner, Executor, ExitKind, HasObservers,
+        ResourceLimits,
     },
     feedbacks::Feedback,
     fuzzer::HasObjective,
@@ -163,7 +164,7 @@ where
         *state.executions_mut() += 1;
 
         unsafe {
-            self.inner.shmem_provider.pre_fork()?;
+            self.inner.pre_fork()?;
             match fork() {
                 Ok(ForkResult::Child) => {
                     // Child
@@ -231,6 +232,17 @@ where {
     pub fn harness_mut(&mut self) -> &mut H {
         self.harness_fn
     }
+
+    /// Limit the memory of each execution, executions exceeding the limits are reported as [`ExitKind::Oom`].
+    pub fn set_resource_limits(&mut self, limits: ResourceLimits) -> Result<(), Error> {
+        self.inner.set_resource_limits(limits)
+    }
+
+    /// The peak RSS of the last execution in bytes, if resource limits are set.
+    #[must_use]
+    pub fn last_peak_rss(&self) -> Option<u64> {
+        self.inner.last_peak_rss()
+    }
 }
 
 impl<H, HT, OT, S, SP, EM, Z> HasObservers
@@ -420,6 +432,7 @@ mod tests {
                 shmem_provider: provider,
                 observers: tuple_list!(),
                 itimerspec,
+                resources: None,
                 phantom: PhantomData,
             },
         };
@@ -431,6 +444,7 @@ mod tests {
                 shmem_provider: provider,
                 observers: tuple_list!(),
                 itimerval: itimerspec,
+                resources: None,
                 phantom: PhantomData,
             },
         };
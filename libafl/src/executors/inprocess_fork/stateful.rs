@@ -138,7 +138,7 @@ where
         *state.executions_mut() += 1;
 
         unsafe {
-            self.inner.shmem_provider.pre_fork()?;
+            self.inner.pre_fork()?;
             match fork() {
                 Ok(ForkResult::Child) => {
                     // Child
@@ -16,6 +16,14 @@ pub use inprocess_fork::InProcessForkExecutor;
 #[cfg(unix)]
 use libafl_bolts::os::unix_signals::Signal;
 use libafl_bolts::tuples::RefIndexable;
+pub use multi_differential::{
+    DiffOutcome, DiffTarget, DiffTargetsTuple, EqualOutcomes, MultiDiffComparator,
+    MultiDiffExecutor, MultiDiffObserver, MultiDiffReport,
+};
+#[cfg(feature = "remote_executor")]
+pub use remote::RemoteExecutor;
+#[cfg(all(feature = "std", unix))]
+pub use resources::{ResourceLimits, ResourceMonitor};
 use serde::{Deserialize, Serialize};
 pub use shadow::ShadowExecutor;
 pub use with_observers::WithObservers;
@@ -34,6 +42,16 @@ pub mod inprocess;
 #[cfg(all(feature = "std", unix))]
 pub mod inprocess_fork;
 
+pub mod multi_differential;
+
+/// Run the target on a remote device, such as a microcontroller
+#[cfg(feature = "remote_executor")]
+pub mod remote;
+
+/// Memory limits and OOM detection for executors running the target in a child process
+#[cfg(all(feature = "std", unix))]
+pub mod resources;
+
 pub mod shadow;
 
 pub mod with_observers;
@@ -0,0 +1,639 @@
+//! Executor for differential fuzzing of more than two targets.
+//!
+//! The [`MultiDiffExecutor`] runs every input on a tuple of [`DiffTarget`]s, for example several implementations of the same spec.
+//! After each run, the outcome of every target is extracted from its observers, and the outcomes are grouped by majority vote.
+//! The resulting [`MultiDiffReport`] is stored in a [`MultiDiffObserver`].
+//! Use the [`crate::feedbacks::MultiDiffFeedback`] to keep inputs on which the targets disagree, with the report as testcase metadata.
+//!
+//! In comparison to the [`crate::executors::DiffExecutor`], the observers of the wrapped executors are not exposed to the fuzzer.
+//! Pass observers that all targets write to, such as a shared coverage map, to [`MultiDiffExecutor::new`] instead.
+
+use alloc::{
+    borrow::Cow,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{fmt::Debug, marker::PhantomData};
+
+use libafl_bolts::{
+    tuples::{NamedTuple, RefIndexable},
+    Named,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    executors::{Executor, ExitKind, HasObservers},
+    inputs::UsesInput,
+    observers::{Observer, ObserversTuple},
+    state::{State, UsesState},
+    Error,
+};
+
+/// The outcome of one target of a [`MultiDiffExecutor`] for a single input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffOutcome<V> {
+    /// How the execution ended
+    pub exit_kind: ExitKind,
+    /// The value extracted from the observers of the target
+    pub value: V,
+}
+
+/// Decides whether the outcomes of two targets of a [`MultiDiffExecutor`] agree.
+pub trait MultiDiffComparator<V> {
+    /// Returns `true` if both outcomes should count as the same vote
+    fn equivalent(&mut self, first: &DiffOutcome<V>, second: &DiffOutcome<V>) -> bool;
+}
+
+impl<F, V> MultiDiffComparator<V> for F
+where
+    F: FnMut(&DiffOutcome<V>, &DiffOutcome<V>) -> bool,
+{
+    fn equivalent(&mut self, first: &DiffOutcome<V>, second: &DiffOutcome<V>) -> bool {
+        self(first, second)
+    }
+}
+
+/// A [`MultiDiffComparator`] treating outcomes as equivalent if both exit kind and value are equal
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EqualOutcomes;
+
+impl<V> MultiDiffComparator<V> for EqualOutcomes
+where
+    V: PartialEq,
+{
+    fn equivalent(&mut self, first: &DiffOutcome<V>, second: &DiffOutcome<V>) -> bool {
+        first == second
+    }
+}
+
+/// The result of a majority vote over the outcomes of all targets of a [`MultiDiffExecutor`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[allow(clippy::unsafe_derive_deserialize)] // for SerdeAny
+pub struct MultiDiffReport {
+    /// The names of the targets
+    pub names: Vec<String>,
+    /// How the execution of each target ended
+    pub exit_kinds: Vec<ExitKind>,
+    /// The extracted outcome of each target, formatted with [`Debug`]
+    pub outcomes: Vec<String>,
+    /// The vote of each target: targets with equivalent outcomes share a class
+    pub classes: Vec<usize>,
+    /// The class more than half of the targets voted for, if any
+    pub majority: Option<usize>,
+    /// The targets disagreeing with the majority, or all targets if there is no majority
+    pub outliers: Vec<usize>,
+}
+
+libafl_bolts::impl_serdeany!(MultiDiffReport);
+
+impl MultiDiffReport {
+    /// Group the `outcomes` of the targets called `names` by majority vote, using the given `comparator`.
+    pub fn vote<C, N, V>(names: &[N], outcomes: &[DiffOutcome<V>], comparator: &mut C) -> Self
+    where
+        C: MultiDiffComparator<V>,
+        N: AsRef<str>,
+        V: Debug,
+    {
+        let mut classes: Vec<usize> = Vec::with_capacity(outcomes.len());
+        let mut counts: Vec<usize> = Vec::new();
+        for (idx, outcome) in outcomes.iter().enumerate() {
+            // The first earlier target with an equivalent outcome determines the class
+            let class = (0..idx)
+                .find(|&other| comparator.equivalent(&outcomes[other], outcome))
+                .map(|other| classes[other]);
+            if let Some(class) = class {
+                classes.push(class);
+                counts[class] += 1;
+            } else {
+                classes.push(counts.len());
+                counts.push(1);
+            }
+        }
+
+        let majority = counts.iter().position(|&count| count * 2 > outcomes.len());
+        let outliers = classes
+            .iter()
+            .enumerate()
+            .filter(|&(_, &class)| Some(class) != majority)
+            .map(|(idx, _)| idx)
+            .collect();
+        // A unanimous vote has no outliers, even if there is only a single target
+        let outliers = if counts.len() <= 1 {
+            Vec::new()
+        } else {
+            outliers
+        };
+
+        Self {
+            names: names.iter().map(|name| name.as_ref().to_string()).collect(),
+            exit_kinds: outcomes.iter().map(|outcome| outcome.exit_kind).collect(),
+            outcomes: outcomes
+                .iter()
+                .map(|outcome| format!("{:?}", outcome.value))
+                .collect(),
+            classes,
+            majority,
+            outliers,
+        }
+    }
+
+    /// Returns `true` if at least one target disagrees with the others
+    #[must_use]
+    pub fn is_diff(&self) -> bool {
+        !self.outliers.is_empty()
+    }
+
+    /// The names of the targets disagreeing with the majority
+    pub fn outlier_names(&self) -> impl Iterator<Item = &str> {
+        self.outliers.iter().map(|&idx| self.names[idx].as_str())
+    }
+
+    /// The [`ExitKind`] of the whole differential run.
+    ///
+    /// If all targets exited the same way, this is their [`ExitKind`].
+    /// Else, it is an [`ExitKind::Diff`] between the majority (or the first target) and the first target exiting differently.
+    #[must_use]
+    pub fn exit_kind(&self) -> ExitKind {
+        let reference = self
+            .majority
+            .and_then(|majority| self.classes.iter().position(|&class| class == majority))
+            .unwrap_or(0);
+        let Some(&expected) = self.exit_kinds.get(reference) else {
+            return ExitKind::Ok;
+        };
+        match self.exit_kinds.iter().find(|&&kind| kind != expected) {
+            Some(&other) => ExitKind::Diff {
+                primary: expected.into(),
+                secondary: other.into(),
+            },
+            None => expected,
+        }
+    }
+}
+
+/// The observer holding the [`MultiDiffReport`] of the last run of a [`MultiDiffExecutor`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MultiDiffObserver {
+    name: Cow<'static, str>,
+    report: Option<MultiDiffReport>,
+}
+
+impl MultiDiffObserver {
+    /// Create a new [`MultiDiffObserver`] with the given name
+    #[must_use]
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name: Cow::from(name),
+            report: None,
+        }
+    }
+
+    /// The report of the last execution, if the last execution finished
+    #[must_use]
+    pub fn report(&self) -> Option<&MultiDiffReport> {
+        self.report.as_ref()
+    }
+
+    /// Set the report of the current execution
+    pub fn set_report(&mut self, report: MultiDiffReport) {
+        self.report = Some(report);
+    }
+}
+
+impl Named for MultiDiffObserver {
+    fn name(&self) -> &Cow<'static, str> {
+        &self.name
+    }
+}
+
+impl<I, S> Observer<I, S> for MultiDiffObserver {
+    fn pre_exec(&mut self, _state: &mut S, _input: &I) -> Result<(), Error> {
+        self.report = None;
+        Ok(())
+    }
+}
+
+/// One target of a [`MultiDiffExecutor`]: an executor, and a function extracting the outcome of a run from its observers
+#[derive(Debug)]
+pub struct DiffTarget<E, F> {
+    name: Cow<'static, str>,
+    executor: E,
+    extractor: F,
+}
+
+impl<E, F> DiffTarget<E, F> {
+    /// Create a new [`DiffTarget`].
+    ///
+    /// After each run, `extractor` is called with the observers of `executor` and the [`ExitKind`] of the run,
+    /// and returns the value that is compared to the other targets.
+    pub fn new(name: &'static str, executor: E, extractor: F) -> Self {
+        Self {
+            name: Cow::from(name),
+            executor,
+            extractor,
+        }
+    }
+
+    /// The wrapped executor
+    pub fn executor(&self) -> &E {
+        &self.executor
+    }
+
+    /// The wrapped executor (mutable)
+    pub fn executor_mut(&mut self) -> &mut E {
+        &mut self.executor
+    }
+}
+
+impl<E, F> Named for DiffTarget<E, F> {
+    fn name(&self) -> &Cow<'static, str> {
+        &self.name
+    }
+}
+
+/// A tuple of [`DiffTarget`]s
+pub trait DiffTargetsTuple<EM, Z, S, V>
+where
+    S: UsesInput,
+{
+    /// Run all targets on `input`, and append their outcomes to `outcomes`.
+    fn run_all(
+        &mut self,
+        fuzzer: &mut Z,
+        state: &mut S,
+        mgr: &mut EM,
+        input: &S::Input,
+        outcomes: &mut Vec<DiffOutcome<V>>,
+    ) -> Result<(), Error>;
+}
+
+impl<EM, Z, S, V> DiffTargetsTuple<EM, Z, S, V> for ()
+where
+    S: UsesInput,
+{
+    fn run_all(
+        &mut self,
+        _fuzzer: &mut Z,
+        _state: &mut S,
+        _mgr: &mut EM,
+        _input: &S::Input,
+        _outcomes: &mut Vec<DiffOutcome<V>>,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<E, F, Tail, EM, Z, S, V> DiffTargetsTuple<EM, Z, S, V> for (DiffTarget<E, F>, Tail)
+where
+    E: Executor<EM, Z, State = S> + HasObservers,
+    E::Observers: ObserversTuple<S::Input, S>,
+    EM: UsesState<State = S>,
+    F: FnMut(&E::Observers, &ExitKind) -> V,
+    S: State,
+    Tail: DiffTargetsTuple<EM, Z, S, V>,
+    Z: UsesState<State = S>,
+{
+    fn run_all(
+        &mut self,
+        fuzzer: &mut Z,
+        state: &mut S,
+        mgr: &mut EM,
+        input: &S::Input,
+        outcomes: &mut Vec<DiffOutcome<V>>,
+    ) -> Result<(), Error> {
+        let target = &mut self.0;
+        target.executor.observers_mut().pre_exec_all(state, input)?;
+        let exit_kind = target.executor.run_target(fuzzer, state, mgr, input)?;
+        target
+            .executor
+            .observers_mut()
+            .post_exec_all(state, input, &exit_kind)?;
+        let value = (target.extractor)(&*target.executor.observers(), &exit_kind);
+        outcomes.push(DiffOutcome { exit_kind, value });
+
+        self.1.run_all(fuzzer, state, mgr, input, outcomes)
+    }
+}
+
+/// A [`MultiDiffExecutor`] runs each input on a tuple of [`DiffTarget`]s and compares their outcomes by majority vote.
+#[derive(Debug)]
+pub struct MultiDiffExecutor<C, ET, OT, S, V> {
+    targets: ET,
+    names: Vec<Cow<'static, str>>,
+    comparator: C,
+    observers: (MultiDiffObserver, OT),
+    outcomes: Vec<DiffOutcome<V>>,
+    phantom: PhantomData<S>,
+}
+
+impl<C, ET, OT, S, V> MultiDiffExecutor<C, ET, OT, S, V> {
+    /// Create a new [`MultiDiffExecutor`].
+    ///
+    /// The `diff_observer` receives the [`MultiDiffReport`] of each run,
+    /// `observers` are additional observers exposed to the fuzzer, such as a coverage map shared by all targets.
+    pub fn new(targets: ET, comparator: C, diff_observer: MultiDiffObserver, observers: OT) -> Self
+    where
+        ET: NamedTuple,
+    {
+        Self {
+            names: targets.names(),
+            targets,
+            comparator,
+            observers: (diff_observer, observers),
+            outcomes: Vec::new(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// The wrapped targets
+    pub fn targets(&self) -> &ET {
+        &self.targets
+    }
+
+    /// The wrapped targets (mutable)
+    pub fn targets_mut(&mut self) -> &mut ET {
+        &mut self.targets
+    }
+
+    /// The outcomes of the last run, in the order of the targets
+    pub fn last_outcomes(&self) -> &[DiffOutcome<V>] {
+        &self.outcomes
+    }
+}
+
+impl<C, EM, ET, OT, S, V, Z> Executor<EM, Z> for MultiDiffExecutor<C, ET, OT, S, V>
+where
+    C: MultiDiffComparator<V>,
+    EM: UsesState<State = S>,
+    ET: DiffTargetsTuple<EM, Z, S, V>,
+    S: State,
+    V: Debug,
+    Z: UsesState<State = S>,
+{
+    fn run_target(
+        &mut self,
+        fuzzer: &mut Z,
+        state: &mut Self::State,
+        mgr: &mut EM,
+        input: &Self::Input,
+    ) -> Result<ExitKind, Error> {
+        self.outcomes.clear();
+        self.targets
+            .run_all(fuzzer, state, mgr, input, &mut self.outcomes)?;
+        let report = MultiDiffReport::vote(&self.names, &self.outcomes, &mut self.comparator);
+        let exit_kind = report.exit_kind();
+        self.observers.0.set_report(report);
+        Ok(exit_kind)
+    }
+}
+
+impl<C, ET, OT, S, V> UsesState for MultiDiffExecutor<C, ET, OT, S, V>
+where
+    S: State,
+{
+    type State = S;
+}
+
+impl<C, ET, OT, S, V> HasObservers for MultiDiffExecutor<C, ET, OT, S, V>
+where
+    OT: ObserversTuple<S::Input, S>,
+    S: State,
+{
+    type Observers = (MultiDiffObserver, OT);
+
+    #[inline]
+    fn observers(&self) -> RefIndexable<&Self::Observers, Self::Observers> {
+        RefIndexable::from(&self.observers)
+    }
+
+    #[inline]
+    fn observers_mut(&mut self) -> RefIndexable<&mut Self::Observers, Self::Observers> {
+        RefIndexable::from(&mut self.observers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{boxed::Box, vec, vec::Vec};
+    use core::marker::PhantomData;
+
+    use libafl_bolts::{
+        ownedref::OwnedRef,
+        tuples::{tuple_list, RefIndexable},
+        AsSlice,
+    };
+
+    use crate::{
+        corpus::Testcase,
+        events::NopEventManager,
+        executors::{
+            multi_differential::{
+                DiffOutcome, DiffTarget, EqualOutcomes, MultiDiffExecutor, MultiDiffObserver,
+                MultiDiffReport,
+            },
+            DiffExitKind, Executor, ExitKind, HasObservers,
+        },
+        feedbacks::{Feedback, MultiDiffFeedback},
+        fuzzer::NopFuzzer,
+        inputs::{BytesInput, HasTargetBytes},
+        observers::ValueObserver,
+        state::{NopState, State, UsesState},
+        Error, HasMetadata,
+    };
+
+    type LenObservers = (ValueObserver<'static, usize>, ());
+
+    /// A target reporting the length of the input to its observer.
+    /// A `buggy` one is off by one for inputs longer than 2 bytes.
+    #[derive(Debug)]
+    struct LenTarget<S> {
+        buggy: bool,
+        observers: LenObservers,
+        phantom: PhantomData<S>,
+    }
+
+    impl<S> LenTarget<S> {
+        fn new(buggy: bool) -> Self {
+            Self {
+                buggy,
+                observers: tuple_list!(ValueObserver::new("len", OwnedRef::Owned(Box::new(0)))),
+                phantom: PhantomData,
+            }
+        }
+    }
+
+    impl<S> UsesState for LenTarget<S>
+    where
+        S: State,
+    {
+        type State = S;
+    }
+
+    impl<EM, S, Z> Executor<EM, Z> for LenTarget<S>
+    where
+        EM: UsesState<State = S>,
+        S: State,
+        S::Input: HasTargetBytes,
+        Z: UsesState<State = S>,
+    {
+        fn run_target(
+            &mut self,
+            _fuzzer: &mut Z,
+            _state: &mut Self::State,
+            _mgr: &mut EM,
+            input: &Self::Input,
+        ) -> Result<ExitKind, Error> {
+            let len = input.target_bytes().as_slice().len();
+            self.observers
+                .0
+                .set(len + usize::from(self.buggy && len > 2));
+            Ok(ExitKind::Ok)
+        }
+    }
+
+    impl<S> HasObservers for LenTarget<S> {
+        type Observers = LenObservers;
+
+        fn observers(&self) -> RefIndexable<&Self::Observers, Self::Observers> {
+            RefIndexable::from(&self.observers)
+        }
+
+        fn observers_mut(&mut self) -> RefIndexable<&mut Self::Observers, Self::Observers> {
+            RefIndexable::from(&mut self.observers)
+        }
+    }
+
+    fn outcomes(values: &[(ExitKind, u8)]) -> Vec<DiffOutcome<u8>> {
+        values
+            .iter()
+            .map(|&(exit_kind, value)| DiffOutcome { exit_kind, value })
+            .collect()
+    }
+
+    #[test]
+    fn test_multi_diff_unanimous() {
+        let names = ["a", "b", "c"];
+        let outcomes = outcomes(&[(ExitKind::Ok, 1), (ExitKind::Ok, 1), (ExitKind::Ok, 1)]);
+        let report = MultiDiffReport::vote(&names, &outcomes, &mut EqualOutcomes);
+        assert!(!report.is_diff());
+        assert_eq!(report.majority, Some(0));
+        assert_eq!(report.exit_kind(), ExitKind::Ok);
+    }
+
+    #[test]
+    fn test_multi_diff_outlier() {
+        let names = ["a", "b", "c", "d"];
+        let outcomes = outcomes(&[
+            (ExitKind::Ok, 1),
+            (ExitKind::Crash, 0),
+            (ExitKind::Ok, 1),
+            (ExitKind::Ok, 1),
+        ]);
+        let report = MultiDiffReport::vote(&names, &outcomes, &mut EqualOutcomes);
+        assert!(report.is_diff());
+        assert_eq!(report.classes, vec![0, 1, 0, 0]);
+        assert_eq!(report.majority, Some(0));
+        assert_eq!(report.outlier_names().collect::<Vec<_>>(), vec!["b"]);
+        assert_eq!(
+            report.exit_kind(),
+            ExitKind::Diff {
+                primary: DiffExitKind::Ok,
+                secondary: DiffExitKind::Crash
+            }
+        );
+    }
+
+    #[test]
+    fn test_multi_diff_no_majority() {
+        let names = ["a", "b"];
+        let outcomes = outcomes(&[(ExitKind::Ok, 1), (ExitKind::Ok, 2)]);
+        let report = MultiDiffReport::vote(&names, &outcomes, &mut EqualOutcomes);
+        assert!(report.is_diff());
+        assert_eq!(report.majority, None);
+        assert_eq!(report.outliers, vec![0, 1]);
+        assert_eq!(report.exit_kind(), ExitKind::Ok);
+
+        // A custom comparator may ignore the values
+        let mut exit_kinds_only =
+            |a: &DiffOutcome<u8>, b: &DiffOutcome<u8>| a.exit_kind == b.exit_kind;
+        let report = MultiDiffReport::vote(&names, &outcomes, &mut exit_kinds_only);
+        assert!(!report.is_diff());
+    }
+
+    #[test]
+    fn test_multi_diff_executor() {
+        let extract = |observers: &LenObservers, _exit_kind: &ExitKind| *observers.0.get_ref();
+        let targets = tuple_list!(
+            DiffTarget::new("a", LenTarget::new(false), extract),
+            DiffTarget::new("b", LenTarget::new(true), extract),
+            DiffTarget::new("c", LenTarget::new(false), extract),
+        );
+        let diff_observer = MultiDiffObserver::new("multi_diff");
+        let mut feedback = MultiDiffFeedback::new(&diff_observer);
+        let mut executor = MultiDiffExecutor::new(targets, EqualOutcomes, diff_observer, ());
+        let mut fuzzer = NopFuzzer::new();
+        let mut state = NopState::<BytesInput>::new();
+        let mut mgr = NopEventManager::new();
+
+        // All targets agree
+        let input = BytesInput::new(vec![1, 2]);
+        let exit_kind = executor
+            .run_target(&mut fuzzer, &mut state, &mut mgr, &input)
+            .unwrap();
+        assert_eq!(exit_kind, ExitKind::Ok);
+        let observers = executor.observers();
+        assert!(!observers.0.report().unwrap().is_diff());
+        assert!(!feedback
+            .is_interesting(&mut state, &mut mgr, &input, &*observers, &exit_kind)
+            .unwrap());
+
+        // The buggy target is outvoted
+        let input = BytesInput::new(vec![1, 2, 3]);
+        let exit_kind = executor
+            .run_target(&mut fuzzer, &mut state, &mut mgr, &input)
+            .unwrap();
+        assert_eq!(exit_kind, ExitKind::Ok);
+        let values: Vec<usize> = executor
+            .last_outcomes()
+            .iter()
+            .map(|outcome| outcome.value)
+            .collect();
+        assert_eq!(values, vec![3, 4, 3]);
+        let observers = executor.observers();
+        assert!(feedback
+            .is_interesting(&mut state, &mut mgr, &input, &*observers, &exit_kind)
+            .unwrap());
+
+        let mut testcase = Testcase::new(input.clone());
+        feedback
+            .append_metadata(&mut state, &mut mgr, &*observers, &mut testcase)
+            .unwrap();
+        let report = testcase.metadata::<MultiDiffReport>().unwrap();
+        assert_eq!(report.outlier_names().collect::<Vec<_>>(), vec!["b"]);
+        assert_eq!(report.outcomes, vec!["3", "4", "3"]);
+
+        // Two diverging targets have no majority to tell the outlier
+        let diff_observer = MultiDiffObserver::new("multi_diff");
+        let mut strict = MultiDiffFeedback::new(&diff_observer).require_majority(true);
+        let mut executor = MultiDiffExecutor::new(
+            tuple_list!(
+                DiffTarget::new("a", LenTarget::new(false), extract),
+                DiffTarget::new("b", LenTarget::new(true), extract),
+            ),
+            EqualOutcomes,
+            diff_observer,
+            (),
+        );
+        let exit_kind = executor
+            .run_target(&mut fuzzer, &mut state, &mut mgr, &input)
+            .unwrap();
+        let observers = executor.observers();
+        assert_eq!(observers.0.report().unwrap().majority, None);
+        assert!(feedback
+            .is_interesting(&mut state, &mut mgr, &input, &*observers, &exit_kind)
+            .unwrap());
+        assert!(!strict
+            .is_interesting(&mut state, &mut mgr, &input, &*observers, &exit_kind)
+            .unwrap());
+    }
+}
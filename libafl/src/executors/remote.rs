@@ -0,0 +1,634 @@
+//! The [`RemoteExecutor`] runs inputs on a different device, such as a microcontroller,
+//! talking to a [`libafl_remote_agent::Agent`] over any byte stream.
+//!
+//! The fuzzer stays on the host: inputs are sent to the agent, which runs the harness and answers with
+//! the exit status and the coverage map, which is copied into a map observer on the host.
+//! The protocol is defined in [`libafl_remote_agent::protocol`].
+
+use alloc::{borrow::Cow, vec::Vec};
+use core::{
+    fmt::{self, Debug, Formatter},
+    marker::PhantomData,
+    time::Duration,
+};
+#[cfg(unix)]
+use std::{
+    fs::{File, OpenOptions},
+    os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+    path::Path,
+};
+use std::{
+    io::{self, BufReader, Read, Write},
+    net::TcpStream,
+    time::Instant,
+};
+
+use libafl_bolts::{
+    tuples::{Handle, MatchNameRef, RefIndexable},
+    AsSlice, AsSliceMut, Named,
+};
+use libafl_remote_agent::protocol::{
+    decode_map, encode_frame, Checksum, FrameHeader, MessageKind, ProtocolError, ReadyInfo,
+    RemoteExitKind, HEADER_LEN, MAGIC, PROTOCOL_VERSION, READY_LEN, TRAILER_LEN, UNSOLICITED_SEQ,
+};
+
+use crate::{
+    executors::{Executor, ExitKind, HasObservers, HasTimeout},
+    inputs::HasTargetBytes,
+    observers::ObserversTuple,
+    state::{HasExecutions, State, UsesState},
+    Error,
+};
+
+/// The default time the agent gets to come back after a timeout or a crash
+pub const DEFAULT_RESET_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often an input is re-sent if the result got corrupted on the way back
+const MAX_RETRIES: usize = 3;
+
+/// A byte stream to a remote agent, which can time out while reading
+pub trait RemoteTransport: Read + Write {
+    /// Set the timeout for reads, `None` blocks forever
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<(), Error>;
+}
+
+impl RemoteTransport for TcpStream {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
+        TcpStream::set_read_timeout(self, timeout)?;
+        Ok(())
+    }
+}
+
+/// A serial port, or anything else represented by a file descriptor, such as a pty
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct SerialTransport {
+    file: File,
+    timeout: Option<Duration>,
+}
+
+#[cfg(unix)]
+impl SerialTransport {
+    /// Open the serial device at the given path
+    pub fn open<P>(path: P) -> Result<Self, Error>
+    where
+        P: AsRef<Path>,
+    {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(path)?;
+        Self::from_file(file)
+    }
+
+    /// Use an already opened file.
+    ///
+    /// If it is a terminal, it is switched to raw mode, so the line discipline leaves the frames alone.
+    pub fn from_file(file: File) -> Result<Self, Error> {
+        let fd = file.as_raw_fd();
+        // # Safety
+        // Only changes the terminal attributes of a valid fd.
+        unsafe {
+            if libc::isatty(fd) == 1 {
+                let mut termios = core::mem::zeroed();
+                if libc::tcgetattr(fd, &mut termios) != 0 {
+                    return Err(Error::last_os_error(
+                        "Failed to get the terminal attributes",
+                    ));
+                }
+                libc::cfmakeraw(&mut termios);
+                if libc::tcsetattr(fd, libc::TCSANOW, &termios) != 0 {
+                    return Err(Error::last_os_error(
+                        "Failed to set the terminal to raw mode",
+                    ));
+                }
+            }
+        }
+        Ok(Self {
+            file,
+            timeout: None,
+        })
+    }
+
+    /// Set the baud rate of the serial port
+    pub fn set_baud_rate(&mut self, speed: libc::speed_t) -> Result<(), Error> {
+        let fd = self.file.as_raw_fd();
+        // # Safety
+        // Only changes the terminal attributes of a valid fd.
+        unsafe {
+            let mut termios = core::mem::zeroed();
+            if libc::tcgetattr(fd, &mut termios) != 0
+                || libc::cfsetspeed(&mut termios, speed) != 0
+                || libc::tcsetattr(fd, libc::TCSANOW, &termios) != 0
+            {
+                return Err(Error::last_os_error("Failed to set the baud rate"));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+impl Read for SerialTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(timeout) = self.timeout {
+            let mut pollfd = libc::pollfd {
+                fd: self.file.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let timeout_ms = i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX);
+            // # Safety
+            // We pass a single, valid pollfd.
+            match unsafe { libc::poll(&mut pollfd, 1, timeout_ms) } {
+                -1 => return Err(io::Error::last_os_error()),
+                0 => return Err(io::ErrorKind::TimedOut.into()),
+                _ => {}
+            }
+        }
+        self.file.read(buf)
+    }
+}
+
+#[cfg(unix)]
+impl Write for SerialTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(unix)]
+impl RemoteTransport for SerialTransport {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
+        self.timeout = timeout;
+        Ok(())
+    }
+}
+
+/// What came back from the agent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Received {
+    /// A valid frame with the given sequence number, the payload is in `RemoteExecutor::payload`
+    Frame(MessageKind, u32),
+    /// Nothing arrived in time
+    Timeout,
+    /// A frame arrived, but it was damaged
+    Corrupted,
+}
+
+fn protocol_error(err: ProtocolError) -> Error {
+    Error::illegal_state(format!("Remote agent protocol error: {err}"))
+}
+
+fn is_timeout(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+    )
+}
+
+/// Executor running the target on a remote device, through a [`libafl_remote_agent::Agent`].
+///
+/// If the agent does not answer in time, the run is a [`ExitKind::Timeout`].
+/// If the agent announces itself again while running an input, the device restarted and the run is a
+/// [`ExitKind::Crash`]. In both cases, the executor waits up to the reset timeout for the agent to come back,
+/// so the device needs some way to restart by itself, e.g., a watchdog.
+///
+/// Inputs larger than the input buffer of the agent are truncated, like the [`crate::executors::ForkserverExecutor`] does.
+/// Limit the input size of the state to [`ReadyInfo::max_input_size`] to not generate them.
+pub struct RemoteExecutor<C, OT, S, T> {
+    transport: BufReader<T>,
+    observers: OT,
+    map_observer: Handle<C>,
+    timeout: Duration,
+    reset_timeout: Duration,
+    agent: ReadyInfo,
+    /// The sequence number of the last request
+    seq: u32,
+    frame: Vec<u8>,
+    payload: Vec<u8>,
+    phantom: PhantomData<S>,
+}
+
+impl<C, OT, S, T> Debug for RemoteExecutor<C, OT, S, T>
+where
+    OT: Debug,
+    T: Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RemoteExecutor")
+            .field("transport", &self.transport)
+            .field("observers", &self.observers)
+            .field("map_observer", &self.map_observer)
+            .field("timeout", &self.timeout)
+            .field("reset_timeout", &self.reset_timeout)
+            .field("agent", &self.agent)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<C, OT, S, T> RemoteExecutor<C, OT, S, T>
+where
+    C: Named + for<'a> AsSliceMut<'a, Entry = u8>,
+    OT: ObserversTuple<S::Input, S>,
+    S: State,
+    T: RemoteTransport,
+{
+    /// Create a new [`RemoteExecutor`], waiting for the agent to announce itself.
+    ///
+    /// The coverage sent by the agent is copied into the `map_observer`, which has to be part of `observers`
+    /// and has to be exactly as large as the map of the agent.
+    pub fn new(
+        transport: T,
+        map_observer: Handle<C>,
+        observers: OT,
+        timeout: Duration,
+    ) -> Result<Self, Error> {
+        let mut executor = Self {
+            transport: BufReader::new(transport),
+            observers,
+            map_observer,
+            timeout,
+            reset_timeout: DEFAULT_RESET_TIMEOUT,
+            agent: ReadyInfo {
+                version: PROTOCOL_VERSION,
+                map_size: 0,
+                max_input_size: 0,
+            },
+            seq: UNSOLICITED_SEQ,
+            frame: Vec::new(),
+            payload: Vec::new(),
+            phantom: PhantomData,
+        };
+        executor.resync()?;
+
+        let map_size = executor
+            .observers
+            .get(&executor.map_observer)
+            .ok_or_else(|| {
+                Error::illegal_argument(format!(
+                    "RemoteExecutor: observer {} not found",
+                    executor.map_observer.name()
+                ))
+            })?
+            .as_slice()
+            .len();
+        if map_size != executor.agent.map_size as usize {
+            return Err(Error::illegal_argument(format!(
+                "The map of the remote agent has {} entries, but the map observer has {map_size}",
+                executor.agent.map_size
+            )));
+        }
+        Ok(executor)
+    }
+
+    /// Set how long the agent gets to come back after a timeout or a crash
+    #[must_use]
+    pub fn with_reset_timeout(mut self, reset_timeout: Duration) -> Self {
+        self.reset_timeout = reset_timeout;
+        self
+    }
+
+    /// What the agent told about itself the last time it announced itself
+    #[must_use]
+    pub fn agent(&self) -> &ReadyInfo {
+        &self.agent
+    }
+
+    /// The transport to the agent
+    pub fn transport_mut(&mut self) -> &mut T {
+        self.transport.get_mut()
+    }
+
+    /// Send a request with a new sequence number
+    fn send(&mut self, kind: MessageKind, payload: &[u8]) -> Result<(), Error> {
+        self.seq = self.seq.wrapping_add(1);
+        if self.seq == UNSOLICITED_SEQ {
+            self.seq += 1;
+        }
+        self.frame.clear();
+        encode_frame(kind, self.seq, payload, &mut self.frame);
+        let transport = self.transport.get_mut();
+        transport.write_all(&self.frame)?;
+        transport.flush()?;
+        Ok(())
+    }
+
+    /// Receive the next frame, skipping everything before the next frame magic
+    fn receive(&mut self, timeout: Duration) -> Result<Received, Error> {
+        self.transport.get_mut().set_read_timeout(Some(timeout))?;
+        match self.receive_frame() {
+            Ok(received) => Ok(received),
+            Err(err) if is_timeout(&err) => Ok(Received::Timeout),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn receive_frame(&mut self) -> io::Result<Received> {
+        let mut header = [0u8; HEADER_LEN];
+        loop {
+            self.transport.read_exact(&mut header[..1])?;
+            if header[0] == MAGIC {
+                break;
+            }
+        }
+        self.transport.read_exact(&mut header[1..])?;
+        let Ok(header) = FrameHeader::decode(&header) else {
+            return Ok(Received::Corrupted);
+        };
+        if header.len as usize > self.max_payload_len() {
+            // A damaged length, don't wait for (or allocate) a payload that will never arrive
+            return Ok(Received::Corrupted);
+        }
+        self.payload.resize(header.len as usize, 0);
+        self.transport.read_exact(&mut self.payload)?;
+        let mut trailer = [0u8; TRAILER_LEN];
+        self.transport.read_exact(&mut trailer)?;
+
+        let mut checksum = Checksum::new();
+        checksum.update(&header.encode()[1..]);
+        checksum.update(&self.payload);
+        if u16::from_le_bytes(trailer) == checksum.finish() {
+            Ok(Received::Frame(header.kind, header.seq))
+        } else {
+            Ok(Received::Corrupted)
+        }
+    }
+
+    /// The largest payload the agent can send: its [`ReadyInfo`], or a result with a dense map
+    fn max_payload_len(&self) -> usize {
+        READY_LEN.max(2 + self.agent.map_size as usize)
+    }
+
+    fn update_agent(&mut self) -> Result<(), Error> {
+        let agent = ReadyInfo::decode(&self.payload).map_err(protocol_error)?;
+        if agent.version != PROTOCOL_VERSION {
+            return Err(Error::illegal_state(format!(
+                "Remote agent speaks protocol version {}, we speak {PROTOCOL_VERSION}",
+                agent.version
+            )));
+        }
+        if self.agent.map_size != 0 && agent != self.agent {
+            log::warn!("Remote agent changed from {:?} to {agent:?}", self.agent);
+        }
+        self.agent = agent;
+        Ok(())
+    }
+
+    /// Wait for the agent to announce itself, asking it to do so every `timeout`
+    fn resync(&mut self) -> Result<(), Error> {
+        let start = Instant::now();
+        while start.elapsed() < self.reset_timeout {
+            self.send(MessageKind::Hello, &[])?;
+            // Drop everything the agent still had in flight, until it tells us it is ready
+            while let Received::Frame(kind, seq) = self.receive(self.timeout)? {
+                if kind == MessageKind::Ready && (seq == self.seq || seq == UNSOLICITED_SEQ) {
+                    return self.update_agent();
+                }
+            }
+        }
+        Err(Error::illegal_state(format!(
+            "Remote agent did not come back within {:?}",
+            self.reset_timeout
+        )))
+    }
+
+    fn handle_result(&mut self) -> Result<ExitKind, Error> {
+        let [exit_kind, encoding, map @ ..] = self.payload.as_slice() else {
+            return Err(protocol_error(ProtocolError::Malformed));
+        };
+        let exit_kind = match RemoteExitKind::try_from(*exit_kind).map_err(protocol_error)? {
+            RemoteExitKind::Ok => ExitKind::Ok,
+            RemoteExitKind::Crash => ExitKind::Crash,
+            RemoteExitKind::Timeout => ExitKind::Timeout,
+            RemoteExitKind::Oom => ExitKind::Oom,
+        };
+
+        let observer = self
+            .observers
+            .get_mut(&self.map_observer)
+            .ok_or_else(|| Error::illegal_state("RemoteExecutor: map observer not found"))?;
+        decode_map(*encoding, map, &mut observer.as_slice_mut()).map_err(protocol_error)?;
+        Ok(exit_kind)
+    }
+}
+
+impl<C, OT, S, T> UsesState for RemoteExecutor<C, OT, S, T>
+where
+    S: State,
+{
+    type State = S;
+}
+
+impl<C, EM, OT, S, T, Z> Executor<EM, Z> for RemoteExecutor<C, OT, S, T>
+where
+    C: Named + for<'a> AsSliceMut<'a, Entry = u8>,
+    EM: UsesState<State = S>,
+    OT: ObserversTuple<S::Input, S>,
+    S: State + HasExecutions,
+    S::Input: HasTargetBytes,
+    T: RemoteTransport,
+    Z: UsesState<State = S>,
+{
+    fn run_target(
+        &mut self,
+        _fuzzer: &mut Z,
+        state: &mut Self::State,
+        _mgr: &mut EM,
+        input: &Self::Input,
+    ) -> Result<ExitKind, Error> {
+        let bytes = input.target_bytes();
+        let mut buffer = bytes.as_slice();
+        let max_input_size = self.agent.max_input_size as usize;
+        if buffer.len() > max_input_size {
+            log::warn!(
+                "RemoteExecutor: input of {} bytes, truncated to the {max_input_size} bytes the remote agent takes",
+                buffer.len()
+            );
+            buffer = &buffer[..max_input_size];
+        }
+
+        *state.executions_mut() += 1;
+
+        for _ in 0..MAX_RETRIES {
+            self.send(MessageKind::Run, buffer)?;
+            loop {
+                match self.receive(self.timeout)? {
+                    Received::Frame(MessageKind::Result, seq) if seq == self.seq => {
+                        return self.handle_result();
+                    }
+                    Received::Frame(MessageKind::Ready, UNSOLICITED_SEQ) => {
+                        // The device restarted while running the input
+                        self.update_agent()?;
+                        return Ok(ExitKind::Crash);
+                    }
+                    // A late answer to an earlier request
+                    Received::Frame(..) => {}
+                    Received::Timeout => {
+                        self.resync()?;
+                        return Ok(ExitKind::Timeout);
+                    }
+                    Received::Corrupted => {
+                        log::warn!(
+                            "Corrupted answer from the remote agent, running the input again"
+                        );
+                        self.resync()?;
+                        break;
+                    }
+                }
+            }
+        }
+        Err(Error::illegal_state(format!(
+            "The remote agent did not answer properly {MAX_RETRIES} times in a row"
+        )))
+    }
+}
+
+impl<C, OT, S, T> HasObservers for RemoteExecutor<C, OT, S, T>
+where
+    OT: ObserversTuple<S::Input, S>,
+    S: State,
+{
+    type Observers = OT;
+
+    fn observers(&self) -> RefIndexable<&Self::Observers, Self::Observers> {
+        RefIndexable::from(&self.observers)
+    }
+
+    fn observers_mut(&mut self) -> RefIndexable<&mut Self::Observers, Self::Observers> {
+        RefIndexable::from(&mut self.observers)
+    }
+}
+
+impl<C, OT, S, T> HasTimeout for RemoteExecutor<C, OT, S, T> {
+    #[inline]
+    fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    #[inline]
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+}
+
+impl<C, OT, S, T> Named for RemoteExecutor<C, OT, S, T> {
+    fn name(&self) -> &Cow<'static, str> {
+        static NAME: Cow<'static, str> = Cow::Borrowed("RemoteExecutor");
+        &NAME
+    }
+}
+
+#[cfg(test)]
+#[cfg(unix)]
+mod tests {
+    use core::time::Duration;
+    use std::{fs::File, os::fd::FromRawFd, thread};
+
+    use libafl_bolts::{
+        tuples::{tuple_list, Handled},
+        AsSlice,
+    };
+    use libafl_remote_agent::{protocol::RemoteExitKind, Agent};
+
+    use super::{RemoteExecutor, SerialTransport};
+    use crate::{
+        events::NopEventManager,
+        executors::{Executor, ExitKind, HasObservers},
+        fuzzer::NopFuzzer,
+        inputs::BytesInput,
+        observers::StdMapObserver,
+        state::NopState,
+    };
+
+    /// Open a pty pair, the agent talks on one end, the executor on the other
+    fn pty_pair() -> (SerialTransport, SerialTransport) {
+        let (mut host, mut device) = (0, 0);
+        // # Safety
+        // openpty only writes the two fds.
+        let res = unsafe {
+            libc::openpty(
+                &mut host,
+                &mut device,
+                core::ptr::null_mut(),
+                core::ptr::null_mut(),
+                core::ptr::null_mut(),
+            )
+        };
+        assert_eq!(res, 0);
+        // # Safety
+        // The fds were just opened and are owned by the files from now on.
+        unsafe {
+            (
+                SerialTransport::from_file(File::from_raw_fd(host)).unwrap(),
+                SerialTransport::from_file(File::from_raw_fd(device)).unwrap(),
+            )
+        }
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_remote_executor() {
+        let (host, device) = pty_pair();
+
+        thread::spawn(move || {
+            let mut input = [0u8; 64];
+            let mut map = [0u8; 128];
+            let mut agent = Agent::new(device, &mut input, &mut map);
+            let _ = agent.serve(|input, map| {
+                map[input.len()] = 1;
+                match input {
+                    b"crash" => RemoteExitKind::Crash,
+                    b"hang" => {
+                        thread::sleep(Duration::from_millis(500));
+                        RemoteExitKind::Ok
+                    }
+                    _ => RemoteExitKind::Ok,
+                }
+            });
+        });
+
+        let edges = StdMapObserver::owned("edges", vec![0u8; 128]);
+        let mut executor = RemoteExecutor::new(
+            host,
+            edges.handle(),
+            tuple_list!(edges),
+            Duration::from_millis(100),
+        )
+        .unwrap()
+        .with_reset_timeout(Duration::from_secs(5));
+        assert_eq!(executor.agent().max_input_size, 64);
+
+        let run = |executor: &mut RemoteExecutor<_, _, _, _>, input: &[u8]| {
+            executor
+                .run_target(
+                    &mut NopFuzzer::new(),
+                    &mut NopState::new(),
+                    &mut NopEventManager::new(),
+                    &BytesInput::new(input.to_vec()),
+                )
+                .unwrap()
+        };
+
+        assert_eq!(run(&mut executor, b"abc"), ExitKind::Ok);
+        let map = executor.observers().0.as_slice().to_vec();
+        assert_eq!(map[3], 1);
+        assert_eq!(map.iter().filter(|&&entry| entry != 0).count(), 1);
+
+        assert_eq!(run(&mut executor, b"crash"), ExitKind::Crash);
+        assert_eq!(run(&mut executor, b"hang"), ExitKind::Timeout);
+        // The late result of the hang must not be mistaken for the result of the next runs
+        assert_eq!(run(&mut executor, b"crash"), ExitKind::Crash);
+        assert_eq!(run(&mut executor, b"ok"), ExitKind::Ok);
+
+        // Too large for the agent: truncated
+        assert_eq!(run(&mut executor, &[b'a'; 65]), ExitKind::Ok);
+        assert_eq!(executor.observers().0.as_slice()[64], 1);
+        assert_eq!(run(&mut executor, b"crash"), ExitKind::Crash);
+    }
+}
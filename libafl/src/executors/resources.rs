@@ -0,0 +1,612 @@
+//! Resource limits and out-of-memory detection for executors that run the target in a child process.
+//!
+//! A [`ResourceLimits`] describes how much memory a single execution may use.
+//! The [`ResourceMonitor`] enforces it, samples the peak memory use of each execution,
+//! and classifies executions that blew the limit as [`ExitKind::Oom`], similar to libFuzzer's `-rss_limit_mb`.
+//!
+//! A child starts out with the memory of the process it was forked from, and a persistent child keeps
+//! the memory of its earlier executions. Only the memory an execution adds on top counts against the limit.
+//!
+//! Limits are enforced in up to three ways:
+//! - an address-space `rlimit` set in the child, so huge allocations fail early,
+//! - an RSS watchdog in the parent that samples the peak RSS of the child while it runs, and kills it once it grows
+//!   too large. The kernel tracks the peak (`VmHWM`), so only the memory used after the last sample can be missed.
+//!   The children of a forkserver are also sampled after they stopped in persistent mode,
+//! - optionally (Linux only), a cgroup v2 subtree with `memory.max` set, so the kernel OOM killer catches the child
+//!   before it can take down the whole host. As the cgroup is charged for all the memory of its processes,
+//!   `memory.max` is the RSS limit on top of the memory the execution starts with.
+
+use core::time::Duration;
+#[cfg(target_os = "linux")]
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use nix::{
+    sys::{
+        signal::{kill, Signal},
+        wait::WaitStatus,
+    },
+    unistd::Pid,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{executors::ExitKind, Error};
+
+/// The default interval in which the RSS of a running child is sampled
+pub const DEFAULT_RSS_SAMPLE_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Memory limits for executions in a child process.
+///
+/// All limits are given in megabytes, a limit of `None` disables the respective check.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourceLimits {
+    rss_limit_mb: Option<u64>,
+    address_space_limit_mb: Option<u64>,
+    #[cfg(target_os = "linux")]
+    cgroup_parent: Option<PathBuf>,
+    sample_interval: Duration,
+}
+
+impl Default for ResourceLimits {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ResourceLimits {
+    /// Create new [`ResourceLimits`] that do not limit anything.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            rss_limit_mb: None,
+            address_space_limit_mb: None,
+            #[cfg(target_os = "linux")]
+            cgroup_parent: None,
+            sample_interval: DEFAULT_RSS_SAMPLE_INTERVAL,
+        }
+    }
+
+    /// Treat every execution whose resident set grows by more than `rss_limit_mb` as [`ExitKind::Oom`].
+    #[must_use]
+    pub fn rss_limit_mb(mut self, rss_limit_mb: u64) -> Self {
+        self.rss_limit_mb = Some(rss_limit_mb);
+        self
+    }
+
+    /// Limit the address space of the child via `setrlimit`, so that huge allocations fail.
+    ///
+    /// Note that sanitizers reserve large amounts of virtual memory, so don't set this for `ASan` targets.
+    #[must_use]
+    pub fn address_space_limit_mb(mut self, address_space_limit_mb: u64) -> Self {
+        self.address_space_limit_mb = Some(address_space_limit_mb);
+        self
+    }
+
+    /// Run children in a fresh cgroup v2 subtree below `parent`, with `memory.max` set to the RSS limit,
+    /// on top of the memory each execution starts with.
+    ///
+    /// `parent` needs to be writable by the fuzzer, for example a directory delegated via
+    /// `systemd-run --user --scope -p Delegate=yes`.
+    #[cfg(target_os = "linux")]
+    #[must_use]
+    pub fn cgroup<P>(mut self, parent: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        self.cgroup_parent = Some(parent.into());
+        self
+    }
+
+    /// How often the RSS of a running child is sampled, defaults to [`DEFAULT_RSS_SAMPLE_INTERVAL`].
+    #[must_use]
+    pub fn sample_interval(mut self, sample_interval: Duration) -> Self {
+        self.sample_interval = sample_interval;
+        self
+    }
+
+    /// The RSS limit in megabytes, if any
+    #[must_use]
+    pub fn rss_limit(&self) -> Option<u64> {
+        self.rss_limit_mb
+    }
+
+    /// The address space limit in megabytes, if any
+    #[must_use]
+    pub fn address_space_limit(&self) -> Option<u64> {
+        self.address_space_limit_mb
+    }
+
+    /// The RSS sampling interval
+    #[must_use]
+    pub fn rss_sample_interval(&self) -> Duration {
+        self.sample_interval
+    }
+
+    /// Applies the `rlimit`s to the current process.
+    ///
+    /// This is meant to be called in a freshly forked child, before the target runs.
+    #[allow(trivial_numeric_casts)]
+    pub fn apply_rlimits(&self) -> Result<(), Error> {
+        let Some(limit_mb) = self.address_space_limit_mb else {
+            return Ok(());
+        };
+        let limit: libc::rlim_t = (limit_mb as libc::rlim_t) << 20;
+        let r = libc::rlimit {
+            rlim_cur: limit,
+            rlim_max: limit,
+        };
+        #[cfg(target_os = "openbsd")]
+        let ret = unsafe { libc::setrlimit(libc::RLIMIT_RSS, &r) };
+        #[cfg(not(target_os = "openbsd"))]
+        let ret = unsafe { libc::setrlimit(libc::RLIMIT_AS, &r) };
+        if ret < 0 {
+            return Err(Error::last_os_error(
+                "Failed to set the address space limit",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A cgroup v2 subtree with a memory limit, used to contain the children of an executor.
+///
+/// The subtree is removed again once this struct is dropped.
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub struct CgroupV2 {
+    path: PathBuf,
+    /// The current `memory.max`, in bytes
+    memory_max: u64,
+}
+
+#[cfg(target_os = "linux")]
+impl CgroupV2 {
+    /// Create a new cgroup named `name` below `parent`, limited to `memory_max` bytes.
+    pub fn new(parent: &Path, name: &str, memory_max: u64) -> Result<Self, Error> {
+        let controllers = fs::read_to_string(parent.join("cgroup.controllers"))?;
+        if !controllers.split_whitespace().any(|c| c == "memory") {
+            return Err(Error::illegal_argument(format!(
+                "The memory controller is not available in cgroup {}",
+                parent.display()
+            )));
+        }
+        let subtree = fs::read_to_string(parent.join("cgroup.subtree_control"))?;
+        if !subtree.split_whitespace().any(|c| c == "memory") {
+            fs::write(parent.join("cgroup.subtree_control"), "+memory").map_err(|err| {
+                Error::illegal_state(format!(
+                    "Could not enable the memory controller below {} (is the cgroup delegated to us?): {err}",
+                    parent.display()
+                ))
+            })?;
+        }
+
+        let path = parent.join(name);
+        if !path.exists() {
+            fs::create_dir(&path)?;
+        }
+        let cgroup = Self { path, memory_max };
+        cgroup.write("memory.max", &format!("{memory_max}"))?;
+        // Swapping would hide the memory use from the limit; not every kernel has swap accounting, so ignore errors.
+        let _ = cgroup.write("memory.swap.max", "0");
+        Ok(cgroup)
+    }
+
+    /// The path of this cgroup
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Limit this cgroup to `memory_max` bytes, if it is not limited to that already
+    pub fn set_memory_max(&mut self, memory_max: u64) -> Result<(), Error> {
+        if memory_max != self.memory_max {
+            self.write("memory.max", &format!("{memory_max}"))?;
+            self.memory_max = memory_max;
+        }
+        Ok(())
+    }
+
+    /// Move the process `pid` (and all processes it forks later) into this cgroup.
+    pub fn attach(&self, pid: Pid) -> Result<(), Error> {
+        self.write("cgroup.procs", &format!("{pid}"))
+    }
+
+    /// The number of processes in this cgroup killed by the OOM killer so far
+    pub fn oom_kills(&self) -> Result<u64, Error> {
+        let events = fs::read_to_string(self.path.join("memory.events"))?;
+        Ok(events
+            .lines()
+            .find_map(|line| line.strip_prefix("oom_kill "))
+            .and_then(|count| count.trim().parse().ok())
+            .unwrap_or(0))
+    }
+
+    fn write(&self, file: &str, value: &str) -> Result<(), Error> {
+        fs::write(self.path.join(file), value).map_err(|err| {
+            Error::illegal_state(format!(
+                "Could not write {value} to {}: {err}",
+                self.path.join(file).display()
+            ))
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for CgroupV2 {
+    fn drop(&mut self) {
+        // This only succeeds once all processes have left the cgroup.
+        if let Err(err) = fs::remove_dir(&self.path) {
+            log::debug!("Could not remove cgroup {}: {err}", self.path.display());
+        }
+    }
+}
+
+/// Reads the current resident set size of a running process, in bytes.
+#[cfg(target_os = "linux")]
+#[must_use]
+#[allow(clippy::cast_sign_loss)]
+pub fn rss_of(pid: Pid) -> Option<u64> {
+    let statm = fs::read_to_string(format!("/proc/{pid}/statm")).ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    (page_size > 0).then(|| pages * page_size as u64)
+}
+
+/// Reads the current resident set size of a running process, in bytes.
+///
+/// Always `None` on non-Linux systems, where we have no cheap way to look into other processes.
+#[cfg(not(target_os = "linux"))]
+#[must_use]
+pub fn rss_of(_pid: Pid) -> Option<u64> {
+    None
+}
+
+/// Reads the peak resident set size of a running process as tracked by the kernel (`VmHWM`), in bytes.
+///
+/// Unlike [`rss_of`], a short spike in between two reads is not missed.
+#[cfg(target_os = "linux")]
+#[must_use]
+pub fn peak_rss_of(pid: Pid) -> Option<u64> {
+    let status = fs::read_to_string(format!("/proc/{pid}/status")).ok()?;
+    let kilobytes: u64 = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kilobytes << 10)
+}
+
+/// Reads the peak resident set size of a running process, in bytes.
+///
+/// Always `None` on non-Linux systems.
+#[cfg(not(target_os = "linux"))]
+#[must_use]
+pub fn peak_rss_of(_pid: Pid) -> Option<u64> {
+    None
+}
+
+/// Resets the peak resident set size of a process to its current one, returns `false` if it failed.
+#[cfg(target_os = "linux")]
+#[must_use]
+pub fn reset_peak_rss(pid: Pid) -> bool {
+    fs::write(format!("/proc/{pid}/clear_refs"), "5").is_ok()
+}
+
+/// Resets the peak resident set size of a process to its current one, returns `false` if it failed.
+///
+/// Always fails on non-Linux systems.
+#[cfg(not(target_os = "linux"))]
+#[must_use]
+pub fn reset_peak_rss(_pid: Pid) -> bool {
+    false
+}
+
+/// Enforces [`ResourceLimits`] for the children of an executor and keeps track of their memory use.
+#[derive(Debug)]
+pub struct ResourceMonitor {
+    limits: ResourceLimits,
+    #[cfg(target_os = "linux")]
+    cgroup: Option<CgroupV2>,
+    oom_kills_before: u64,
+    baseline_rss: u64,
+    /// If the peak RSS of the child only covers the current execution
+    sample_peak: bool,
+    last_peak_rss: Option<u64>,
+}
+
+impl ResourceMonitor {
+    /// Create a new [`ResourceMonitor`], setting up the cgroup if one was requested.
+    pub fn new(limits: ResourceLimits) -> Result<Self, Error> {
+        #[cfg(target_os = "linux")]
+        let cgroup = match (&limits.cgroup_parent, limits.rss_limit_mb) {
+            (Some(parent), Some(rss_limit_mb)) => Some(CgroupV2::new(
+                parent,
+                &format!("libafl-{}", std::process::id()),
+                rss_limit_mb << 20,
+            )?),
+            (Some(_), None) => {
+                return Err(Error::illegal_argument(
+                    "A cgroup was requested, but no RSS limit was set",
+                ))
+            }
+            _ => None,
+        };
+        Ok(Self {
+            limits,
+            #[cfg(target_os = "linux")]
+            cgroup,
+            oom_kills_before: 0,
+            baseline_rss: 0,
+            sample_peak: true,
+            last_peak_rss: None,
+        })
+    }
+
+    /// The [`ResourceLimits`] enforced by this monitor
+    #[must_use]
+    pub fn limits(&self) -> &ResourceLimits {
+        &self.limits
+    }
+
+    /// The cgroup the children run in, if any
+    #[cfg(target_os = "linux")]
+    #[must_use]
+    pub fn cgroup(&self) -> Option<&CgroupV2> {
+        self.cgroup.as_ref()
+    }
+
+    /// If the RSS of children needs to be sampled while they run
+    #[must_use]
+    pub fn needs_sampling(&self) -> bool {
+        self.limits.rss_limit_mb.is_some()
+    }
+
+    /// Move `pid` into the cgroup of this monitor, if any.
+    #[allow(unused_variables)]
+    pub fn attach(&self, pid: Pid) -> Result<(), Error> {
+        #[cfg(target_os = "linux")]
+        if let Some(cgroup) = &self.cgroup {
+            cgroup.attach(pid)?;
+        }
+        Ok(())
+    }
+
+    /// Call this before every execution.
+    ///
+    /// `baseline_rss` is the RSS (in bytes) the child starts the execution with: the RSS of the process it
+    /// is forked from, or the RSS of a persistent child after its last execution. See [`rss_of`].
+    /// It does not count against the limit, the `memory.max` of the cgroup is raised by it.
+    ///
+    /// `persistent_child` is the child resuming for this execution, if any. Its peak RSS is reset, so the
+    /// peak of its earlier executions does not count. If that fails, only its current RSS is sampled.
+    pub fn pre_exec(
+        &mut self,
+        baseline_rss: u64,
+        persistent_child: Option<Pid>,
+    ) -> Result<(), Error> {
+        self.baseline_rss = baseline_rss;
+        self.sample_peak = match persistent_child {
+            Some(child) => reset_peak_rss(child),
+            None => true,
+        };
+        self.last_peak_rss = None;
+        #[cfg(target_os = "linux")]
+        if let (Some(cgroup), Some(rss_limit_mb)) = (&mut self.cgroup, self.limits.rss_limit_mb) {
+            // Rounded to megabytes, so a baseline wobbling by a few pages does not rewrite the limit each time
+            cgroup.set_memory_max((baseline_rss.div_ceil(1 << 20) + rss_limit_mb) << 20)?;
+            self.oom_kills_before = cgroup.oom_kills()?;
+        }
+        Ok(())
+    }
+
+    /// Sample the memory use of the running, or stopped, child `pid`.
+    ///
+    /// Returns `true` if the child exceeded the RSS limit and should be killed.
+    pub fn sample(&mut self, pid: Pid) -> bool {
+        let rss = if self.sample_peak {
+            peak_rss_of(pid).or_else(|| rss_of(pid))
+        } else {
+            rss_of(pid)
+        };
+        if let Some(rss) = rss {
+            self.record_peak_rss(rss);
+        }
+        self.rss_exceeded()
+    }
+
+    /// Record a peak RSS value (in bytes) of the child for the current execution, for example from `getrusage`.
+    /// The baseline given to [`Self::pre_exec`] is subtracted.
+    pub fn record_peak_rss(&mut self, peak_rss: u64) {
+        let peak_rss = peak_rss.saturating_sub(self.baseline_rss);
+        self.last_peak_rss = Some(self.last_peak_rss.map_or(peak_rss, |old| old.max(peak_rss)));
+    }
+
+    /// The peak RSS of the last execution in bytes, on top of its baseline, if it could be measured
+    #[must_use]
+    pub fn last_peak_rss(&self) -> Option<u64> {
+        self.last_peak_rss
+    }
+
+    /// If the last execution used more memory than allowed
+    #[must_use]
+    pub fn rss_exceeded(&self) -> bool {
+        match (self.limits.rss_limit_mb, self.last_peak_rss) {
+            (Some(limit_mb), Some(peak)) => peak > limit_mb << 20,
+            _ => false,
+        }
+    }
+
+    /// Whether the kernel OOM killer hit our cgroup during the last execution
+    #[must_use]
+    pub fn oom_killed(&self) -> bool {
+        #[cfg(target_os = "linux")]
+        if let Some(cgroup) = &self.cgroup {
+            return cgroup
+                .oom_kills()
+                .is_ok_and(|kills| kills > self.oom_kills_before);
+        }
+        false
+    }
+
+    /// Wait for the forked `child` to exit, sampling its memory use while it runs.
+    ///
+    /// The child is killed once it exceeds the RSS limit.
+    /// The peak RSS reported by `wait4` is only recorded if the child could not be sampled: Linux keeps it across
+    /// `exec`, so for a program spawned from a larger process, it would be the memory of that process.
+    #[allow(clippy::cast_sign_loss)]
+    pub fn wait_for_child(&mut self, child: Pid) -> Result<WaitStatus, Error> {
+        let mut status = 0;
+        // # Safety
+        // `rusage` is a plain C struct, all zeroes is a valid value.
+        let mut rusage: libc::rusage = unsafe { core::mem::zeroed() };
+        let mut flags = if self.needs_sampling() {
+            libc::WNOHANG
+        } else {
+            0
+        };
+        // Start polling fast, most executions are short.
+        let mut backoff = Duration::from_micros(50);
+        loop {
+            // Sample before waiting, so even a short-lived child is sampled at least once
+            if flags == libc::WNOHANG && self.sample(child) {
+                log::debug!(
+                    "Child {child} exceeded the RSS limit ({:?} bytes), killing it",
+                    self.last_peak_rss
+                );
+                let _ = kill(child, Signal::SIGKILL);
+                flags = 0;
+            }
+            let ret =
+                unsafe { libc::wait4(child.as_raw(), &raw mut status, flags, &raw mut rusage) };
+            if ret == child.as_raw() {
+                break;
+            }
+            if ret < 0 {
+                let err = std::io::Error::last_os_error();
+                if err.kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(Error::os_error(err, "Failed to wait for child"));
+            }
+            // The child is still running
+            std::thread::sleep(backoff);
+            backoff = (backoff * 2).min(self.limits.sample_interval);
+        }
+
+        if self.last_peak_rss.is_none() {
+            // `ru_maxrss` is given in bytes on Apple platforms, and in kilobytes everywhere else.
+            #[cfg(target_vendor = "apple")]
+            self.record_peak_rss(rusage.ru_maxrss as u64);
+            #[cfg(not(target_vendor = "apple"))]
+            self.record_peak_rss((rusage.ru_maxrss as u64) << 10);
+        }
+
+        Ok(WaitStatus::from_raw(child, status)?)
+    }
+
+    /// Call this after every execution to turn crashes caused by memory exhaustion into [`ExitKind::Oom`].
+    #[must_use]
+    pub fn classify(&self, exit_kind: ExitKind) -> ExitKind {
+        match exit_kind {
+            ExitKind::Crash | ExitKind::Ok if self.rss_exceeded() || self.oom_killed() => {
+                ExitKind::Oom
+            }
+            exit_kind => exit_kind,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{hint::black_box, time::Duration};
+    use std::{
+        env,
+        process::{self, Command, Stdio},
+        thread,
+    };
+
+    use nix::{sys::wait::WaitStatus, unistd::Pid};
+
+    use crate::executors::{ExitKind, ResourceLimits, ResourceMonitor};
+
+    /// Set for the copy of the test binary spawned by [`test_classify_oom_child`], the number of bytes it uses
+    const OOM_HELPER_ENV: &str = "LIBAFL_TEST_OOM_HELPER_BYTES";
+
+    #[test]
+    fn test_classify_oom() {
+        let mut resources = ResourceMonitor::new(ResourceLimits::new().rss_limit_mb(1)).unwrap();
+        resources.pre_exec(0, None).unwrap();
+        resources.record_peak_rss(512 << 10);
+        assert_eq!(resources.classify(ExitKind::Crash), ExitKind::Crash);
+        assert_eq!(resources.classify(ExitKind::Ok), ExitKind::Ok);
+
+        resources.record_peak_rss(2 << 20);
+        assert_eq!(resources.last_peak_rss(), Some(2 << 20));
+        assert_eq!(resources.classify(ExitKind::Crash), ExitKind::Oom);
+        assert_eq!(resources.classify(ExitKind::Timeout), ExitKind::Timeout);
+
+        resources.pre_exec(0, None).unwrap();
+        assert_eq!(resources.last_peak_rss(), None);
+        assert_eq!(resources.classify(ExitKind::Crash), ExitKind::Crash);
+
+        // The memory the child inherited does not count
+        resources.pre_exec(64 << 20, None).unwrap();
+        resources.record_peak_rss(65 << 20);
+        assert_eq!(resources.last_peak_rss(), Some(1 << 20));
+        assert_eq!(resources.classify(ExitKind::Crash), ExitKind::Crash);
+        resources.record_peak_rss(66 << 20);
+        assert_eq!(resources.classify(ExitKind::Crash), ExitKind::Oom);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_classify_oom_child() {
+        if let Ok(bytes) = env::var(OOM_HELPER_ENV) {
+            // We are the spawned child: touch the memory, and hold it until the parent sampled it
+            let memory = vec![1u8; bytes.parse().unwrap()];
+            thread::sleep(Duration::from_millis(200));
+            drop(black_box(memory));
+            process::exit(0);
+        }
+
+        let mut resources = ResourceMonitor::new(ResourceLimits::new().rss_limit_mb(32)).unwrap();
+        let mut run = |bytes: usize| {
+            // A new program, whatever the test process uses must not count
+            resources.pre_exec(0, None).unwrap();
+            let child = Command::new(env::current_exe().unwrap())
+                .args([
+                    "--exact",
+                    "executors::resources::tests::test_classify_oom_child",
+                    "--test-threads=1",
+                ])
+                .env(OOM_HELPER_ENV, format!("{bytes}"))
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .unwrap();
+            let exit_kind = match resources
+                .wait_for_child(Pid::from_raw(child.id().try_into().unwrap()))
+                .unwrap()
+            {
+                WaitStatus::Exited(_, 0) => ExitKind::Ok,
+                _ => ExitKind::Crash,
+            };
+            (resources.classify(exit_kind), resources.last_peak_rss())
+        };
+
+        // Even with the test process using more than the limit, a small child is fine
+        let _ballast = black_box(vec![1u8; 64 << 20]);
+        let (exit_kind, peak) = run(1 << 10);
+        assert_eq!(exit_kind, ExitKind::Ok);
+        assert!(peak.is_some_and(|peak| peak < 32 << 20));
+
+        let (exit_kind, peak) = run(128 << 20);
+        assert_eq!(exit_kind, ExitKind::Oom);
+        assert!(peak.is_some_and(|peak| peak > 32 << 20));
+    }
+}
@@ -1,14 +1,16 @@
-//! Feedback that captures Timeouts for re-running
+//! Feedbacks that capture Timeouts and Crashes for re-running
 use std::{borrow::Cow, cell::RefCell, fmt::Debug, rc::Rc};
 
 use libafl_bolts::{Error, Named};
 use serde::{de::DeserializeOwned, Serialize};
 
+#[cfg(feature = "track_hit_feedbacks")]
+use crate::feedbacks::premature_last_result_err;
 use crate::{
     corpus::Testcase,
     executors::ExitKind,
     feedbacks::{Feedback, StateInitializer},
-    stages::verify_timeouts::TimeoutsToVerify,
+    stages::{verify_crashes::CrashesToVerify, verify_timeouts::TimeoutsToVerify},
     state::HasCorpus,
     HasMetadata,
 };
@@ -75,3 +77,104 @@ where
         Ok(false)
     }
 }
+
+/// A Feedback wrapping an objective, that captures all crashes and stores them in State for re-evaluation later.
+/// Use in conjunction with `VerifyCrashesStage`, which re-enables the inner objective while verifying.
+///
+/// While capturing, the inner objective is not consulted, so stateful objectives (such as `NewHashFeedback`)
+/// only ever see crashes that have been verified.
+#[derive(Debug)]
+pub struct CaptureCrashFeedback<F> {
+    inner: F,
+    enabled: Rc<RefCell<bool>>,
+    #[cfg(feature = "track_hit_feedbacks")]
+    last_result: Option<bool>,
+}
+
+impl<F> CaptureCrashFeedback<F> {
+    /// Create a new [`CaptureCrashFeedback`], capturing crashes while `enabled` and deferring to the `inner` objective otherwise.
+    pub fn new(inner: F, enabled: Rc<RefCell<bool>>) -> Self {
+        Self {
+            inner,
+            enabled,
+            #[cfg(feature = "track_hit_feedbacks")]
+            last_result: None,
+        }
+    }
+}
+
+impl<F> Named for CaptureCrashFeedback<F> {
+    fn name(&self) -> &Cow<'static, str> {
+        static NAME: Cow<'static, str> = Cow::Borrowed("CaptureCrashFeedback");
+        &NAME
+    }
+}
+
+impl<F, S> StateInitializer<S> for CaptureCrashFeedback<F>
+where
+    F: StateInitializer<S>,
+{
+    fn init_state(&mut self, state: &mut S) -> Result<(), Error> {
+        self.inner.init_state(state)
+    }
+}
+
+impl<EM, F, I, OT, S> Feedback<EM, I, OT, S> for CaptureCrashFeedback<F>
+where
+    F: Feedback<EM, I, OT, S>,
+    S: HasCorpus + HasMetadata,
+    I: Debug + Serialize + DeserializeOwned + Default + 'static + Clone,
+{
+    #[allow(clippy::wrong_self_convention)]
+    #[inline]
+    fn is_interesting(
+        &mut self,
+        state: &mut S,
+        manager: &mut EM,
+        input: &I,
+        observers: &OT,
+        exit_kind: &ExitKind,
+    ) -> Result<bool, Error> {
+        let res = if *self.enabled.borrow() && matches!(exit_kind, ExitKind::Crash) {
+            let crashes = state.metadata_or_insert_with(|| CrashesToVerify::<I>::new());
+            crashes.push(input.clone(), *exit_kind);
+            false
+        } else {
+            self.inner
+                .is_interesting(state, manager, input, observers, exit_kind)?
+        };
+        #[cfg(feature = "track_hit_feedbacks")]
+        {
+            self.last_result = Some(res);
+        }
+        Ok(res)
+    }
+
+    fn append_metadata(
+        &mut self,
+        state: &mut S,
+        manager: &mut EM,
+        observers: &OT,
+        testcase: &mut Testcase<I>,
+    ) -> Result<(), Error> {
+        if let Some(reproduction) = state
+            .metadata_map()
+            .get::<CrashesToVerify<I>>()
+            .and_then(CrashesToVerify::current)
+        {
+            testcase.add_metadata(*reproduction);
+        }
+        self.inner
+            .append_metadata(state, manager, observers, testcase)
+    }
+
+    fn discard_metadata(&mut self, state: &mut S, input: &I) -> Result<(), Error> {
+        self.inner.discard_metadata(state, input)
+    }
+
+    #[cfg(feature = "track_hit_feedbacks")]
+    #[inline]
+    fn last_result(&self) -> Result<bool, Error> {
+        self.last_result.ok_or(premature_last_result_err())
+    }
+}
@@ -1,5 +1,6 @@
 //! Diff Feedback, comparing the content of two observers of the same type.
 //!
+//! The [`MultiDiffFeedback`] evaluates the majority vote of a [`crate::executors::MultiDiffExecutor`] instead.
 
 use alloc::borrow::Cow;
 use core::fmt::{self, Debug, Formatter};
@@ -13,9 +14,10 @@ use serde::{Deserialize, Serialize};
 #[cfg(feature = "track_hit_feedbacks")]
 use crate::feedbacks::premature_last_result_err;
 use crate::{
-    executors::ExitKind,
+    corpus::Testcase,
+    executors::{ExitKind, MultiDiffObserver},
     feedbacks::{Feedback, FeedbackFactory, StateInitializer},
-    Error,
+    Error, HasMetadata,
 };
 
 /// The result of a differential test between two observers.
@@ -177,6 +179,117 @@ where
     }
 }
 
+/// A [`MultiDiffFeedback`] reports an input as interesting if the targets of a
+/// [`crate::executors::MultiDiffExecutor`] disagreed on it.
+///
+/// The [`crate::executors::MultiDiffReport`] of the run is attached to the testcase as metadata.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MultiDiffFeedback {
+    /// This feedback's name
+    name: Cow<'static, str>,
+    /// The observer holding the report
+    observer_handle: Handle<MultiDiffObserver>,
+    /// Only report inputs where a majority of the targets agreed, i.e., where the outliers are clear
+    require_majority: bool,
+    // The previous run's result of `Self::is_interesting`
+    #[cfg(feature = "track_hit_feedbacks")]
+    last_result: Option<bool>,
+}
+
+impl MultiDiffFeedback {
+    /// Create a new [`MultiDiffFeedback`] for the given observer
+    #[must_use]
+    pub fn new(observer: &MultiDiffObserver) -> Self {
+        Self {
+            name: Cow::Borrowed("MultiDiffFeedback"),
+            observer_handle: observer.handle(),
+            require_majority: false,
+            #[cfg(feature = "track_hit_feedbacks")]
+            last_result: None,
+        }
+    }
+
+    /// Only report inputs for which a majority of the targets agreed.
+    ///
+    /// This ignores inputs on which every target behaves differently, for example due to nondeterministic output.
+    #[must_use]
+    pub fn require_majority(mut self, require_majority: bool) -> Self {
+        self.require_majority = require_majority;
+        self
+    }
+}
+
+impl Named for MultiDiffFeedback {
+    fn name(&self) -> &Cow<'static, str> {
+        &self.name
+    }
+}
+
+impl<T> FeedbackFactory<MultiDiffFeedback, T> for MultiDiffFeedback {
+    fn create_feedback(&self, _ctx: &T) -> MultiDiffFeedback {
+        Self {
+            name: self.name.clone(),
+            observer_handle: self.observer_handle.clone(),
+            require_majority: self.require_majority,
+            #[cfg(feature = "track_hit_feedbacks")]
+            last_result: None,
+        }
+    }
+}
+
+impl<S> StateInitializer<S> for MultiDiffFeedback {}
+
+impl<EM, I, OT, S> Feedback<EM, I, OT, S> for MultiDiffFeedback
+where
+    OT: MatchName,
+{
+    #[allow(clippy::wrong_self_convention)]
+    fn is_interesting(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        _input: &I,
+        observers: &OT,
+        _exit_kind: &ExitKind,
+    ) -> Result<bool, Error> {
+        let observer = observers.get(&self.observer_handle).ok_or_else(|| {
+            Error::illegal_argument(format!(
+                "MultiDiffFeedback: observer {} not found",
+                self.observer_handle.name()
+            ))
+        })?;
+        let res = observer.report().is_some_and(|report| {
+            report.is_diff() && (!self.require_majority || report.majority.is_some())
+        });
+        #[cfg(feature = "track_hit_feedbacks")]
+        {
+            self.last_result = Some(res);
+        }
+        Ok(res)
+    }
+
+    #[cfg(feature = "track_hit_feedbacks")]
+    fn last_result(&self) -> Result<bool, Error> {
+        self.last_result.ok_or(premature_last_result_err())
+    }
+
+    fn append_metadata(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        observers: &OT,
+        testcase: &mut Testcase<I>,
+    ) -> Result<(), Error> {
+        if let Some(report) = observers
+            .get(&self.observer_handle)
+            .and_then(MultiDiffObserver::report)
+        {
+            testcase.add_metadata(report.clone());
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use alloc::borrow::Cow;
@@ -9,6 +9,7 @@ use core::{
     ops::{BitAnd, BitOr, Deref, DerefMut},
 };
 
+use hashbrown::HashSet;
 #[rustversion::nightly]
 use libafl_bolts::AsSlice;
 use libafl_bolts::{
@@ -302,6 +303,10 @@ pub struct MapFeedbackMetadata<T> {
     pub history_map: Vec<T>,
     /// Tells us how many non-initial entries there are in `history_map`
     pub num_covered_map_indexes: usize,
+    /// The entries the [`crate::stages::CalibrationStage`] found unstable,
+    /// ignored by a [`MapFeedback`] with [`MapFeedback::mask_unstable_entries`]
+    #[serde(default)]
+    pub unstable_entries: HashSet<usize>,
 }
 
 libafl_bolts::impl_serdeany!(
@@ -319,6 +324,7 @@ where
         Self {
             history_map: vec![T::default(); map_size],
             num_covered_map_indexes: 0,
+            unstable_entries: HashSet::new(),
         }
     }
 
@@ -333,6 +339,7 @@ where
         Self {
             history_map,
             num_covered_map_indexes,
+            unstable_entries: HashSet::new(),
         }
     }
 
@@ -370,6 +377,8 @@ pub struct MapFeedback<C, N, O, R> {
     map_ref: Handle<C>,
     /// Name of the feedback as shown in the `UserStats`
     stats_name: Cow<'static, str>,
+    /// If the entries found unstable by the [`crate::stages::CalibrationStage`] are never novel
+    mask_unstable: bool,
     // The previous run's result of [`Self::is_interesting`]
     #[cfg(feature = "track_hit_feedbacks")]
     last_result: Option<bool>,
@@ -553,6 +562,15 @@ where
         observers: &OT,
         _exit_kind: &ExitKind,
     ) -> Result<bool, Error> {
+        if self.mask_unstable {
+            // The vectorized comparison can't skip single entries
+            let res = self.is_interesting_default(state, observers);
+            #[cfg(feature = "track_hit_feedbacks")]
+            {
+                self.last_result = Some(res);
+            }
+            return Ok(res);
+        }
         Ok(self.is_interesting_u8_simd_optimized(state, observers))
     }
 }
@@ -565,7 +583,7 @@ impl<C, N, O, R> Named for MapFeedback<C, N, O, R> {
 }
 
 #[allow(clippy::ptr_arg)]
-fn create_stats_name(name: &Cow<'static, str>) -> Cow<'static, str> {
+pub(crate) fn create_stats_name(name: &Cow<'static, str>) -> Cow<'static, str> {
     if name.chars().all(char::is_lowercase) {
         name.clone()
     } else {
@@ -585,6 +603,7 @@ where
             name: map_observer.name().clone(),
             map_ref: map_observer.handle(),
             stats_name: create_stats_name(map_observer.name()),
+            mask_unstable: false,
             #[cfg(feature = "track_hit_feedbacks")]
             last_result: None,
             phantom: PhantomData,
@@ -602,11 +621,22 @@ where
             map_ref: map_observer.handle(),
             stats_name: create_stats_name(&name),
             name,
+            mask_unstable: false,
             #[cfg(feature = "track_hit_feedbacks")]
             last_result: None,
             phantom: PhantomData,
         }
     }
+
+    /// Ignore the entries the [`crate::stages::CalibrationStage`] found unstable, as listed in the
+    /// [`MapFeedbackMetadata`] of this feedback, when deciding if an input is interesting.
+    ///
+    /// Flaky entries then no longer add inputs to the corpus that only differ in nondeterministic coverage.
+    #[must_use]
+    pub fn mask_unstable_entries(mut self) -> Self {
+        self.mask_unstable = true;
+        self
+    }
 }
 
 /// Specialize for the common coverage map size, maximization of u8s
@@ -761,6 +791,12 @@ where
         }
 
         let history_map = map_state.history_map.as_slice();
+        let no_entries = HashSet::new();
+        let unstable = if self.mask_unstable {
+            &map_state.unstable_entries
+        } else {
+            &no_entries
+        };
 
         let initial = observer.initial();
 
@@ -770,7 +806,7 @@ where
                 .as_iter()
                 .map(|x| *x)
                 .enumerate()
-                .filter(|(_, item)| *item != initial)
+                .filter(|(i, item)| *item != initial && !unstable.contains(i))
             {
                 let existing = unsafe { *history_map.get_unchecked(i) };
                 let reduced = R::reduce(existing, item);
@@ -784,7 +820,7 @@ where
                 .as_iter()
                 .map(|x| *x)
                 .enumerate()
-                .filter(|(_, item)| *item != initial)
+                .filter(|(i, item)| *item != initial && !unstable.contains(i))
             {
                 let existing = unsafe { *history_map.get_unchecked(i) };
                 let reduced = R::reduce(existing, item);
@@ -801,7 +837,22 @@ where
 
 #[cfg(test)]
 mod tests {
-    use crate::feedbacks::{AllIsNovel, IsNovel, NextPow2IsNovel};
+    use libafl_bolts::{rands::StdRand, tuples::tuple_list};
+
+    use crate::{
+        corpus::InMemoryCorpus,
+        events::NopEventManager,
+        executors::ExitKind,
+        feedbacks::map::MapFeedbackMetadata,
+        feedbacks::{
+            AllIsNovel, ConstFeedback, Feedback, IsNovel, MaxMapFeedback, NextPow2IsNovel,
+            StateInitializer,
+        },
+        inputs::BytesInput,
+        observers::StdMapObserver,
+        state::StdState,
+        HasNamedMetadata,
+    };
 
     #[test]
     fn test_map_is_novel() {
@@ -822,4 +873,47 @@ mod tests {
         assert!(NextPow2IsNovel::is_novel(254_u8, 255));
         assert!(!NextPow2IsNovel::is_novel(255_u8, 255));
     }
+
+    #[test]
+    fn test_map_feedback_masks_unstable() {
+        let observer = StdMapObserver::owned("map", vec![0_u8, 1, 0, 0]);
+        let mut feedback = MaxMapFeedback::new(&observer).mask_unstable_entries();
+        let mut objective = ConstFeedback::new(false);
+        let mut state = StdState::new(
+            StdRand::with_seed(0),
+            InMemoryCorpus::<BytesInput>::new(),
+            InMemoryCorpus::new(),
+            &mut feedback,
+            &mut objective,
+        )
+        .unwrap();
+        let mut mgr = NopEventManager::new();
+        let input = BytesInput::new(vec![0]);
+
+        state
+            .named_metadata_mut::<MapFeedbackMetadata<u8>>("map")
+            .unwrap()
+            .unstable_entries
+            .insert(1);
+
+        // Only the unstable entry is hit
+        let observers = tuple_list!(observer);
+        assert!(!feedback
+            .is_interesting(&mut state, &mut mgr, &input, &observers, &ExitKind::Ok)
+            .unwrap());
+
+        // A stable entry is still novel
+        let observers = tuple_list!(StdMapObserver::owned("map", vec![0_u8, 1, 1, 0]));
+        assert!(feedback
+            .is_interesting(&mut state, &mut mgr, &input, &observers, &ExitKind::Ok)
+            .unwrap());
+
+        // Without masking, the unstable entry counts
+        let mut unmasked = MaxMapFeedback::with_name("unmasked", &observers.0);
+        unmasked.init_state(&mut state).unwrap();
+        let observers = tuple_list!(StdMapObserver::owned("map", vec![0_u8, 1, 0, 0]));
+        assert!(unmasked
+            .is_interesting(&mut state, &mut mgr, &input, &observers, &ExitKind::Ok)
+            .unwrap());
+    }
 }
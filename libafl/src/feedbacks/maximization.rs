@@ -0,0 +1,539 @@
+//! Feedbacks for resource-exhaustion fuzzing, maximizing a scalar, such as the runtime or the
+//! instructions executed, in the style of `SlowFuzz` and `PerfFuzz`.
+//!
+//! The [`MaximizationFeedback`] keeps inputs that push a [`ScalarObserver`] to a new maximum, either
+//! globally ([`GlobalBucket`]) or per covered map entry ([`MapEntryBuckets`]), so that the fuzzer
+//! climbs towards worst-case behavior of every part of the target.
+//! The [`ScalarThresholdFeedback`] turns values above a threshold into objectives.
+
+use alloc::{borrow::Cow, string::String, vec::Vec};
+
+use hashbrown::HashMap;
+use libafl_bolts::{
+    tuples::{Handle, Handled, MatchName, MatchNameRef},
+    Named,
+};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "track_hit_feedbacks")]
+use crate::feedbacks::premature_last_result_err;
+use crate::{
+    corpus::Testcase,
+    events::{Event, EventFirer},
+    executors::ExitKind,
+    feedbacks::{Feedback, HasObserverHandle, StateInitializer},
+    inputs::UsesInput,
+    monitors::{AggregatorOps, UserStats, UserStatsValue},
+    observers::{MapObserver, ScalarObserver},
+    Error, HasMetadata, HasNamedMetadata,
+};
+
+/// The prefix of the names of [`MaximizationFeedback`]s
+pub const MAXIMIZATION_FEEDBACK_PREFIX: &str = "maximize_";
+
+/// Sorts a run into the coverage buckets in which its scalar is maximized
+pub trait CoverageBuckets<OT> {
+    /// Appends the buckets of the last run to `buckets`
+    fn buckets(&self, observers: &OT, buckets: &mut Vec<usize>) -> Result<(), Error>;
+}
+
+/// A single bucket for all runs, maximizing the scalar globally, like `SlowFuzz`
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct GlobalBucket;
+
+impl<OT> CoverageBuckets<OT> for GlobalBucket {
+    fn buckets(&self, _observers: &OT, buckets: &mut Vec<usize>) -> Result<(), Error> {
+        buckets.push(0);
+        Ok(())
+    }
+}
+
+/// One bucket per map entry covered by the run, like `PerfFuzz`.
+///
+/// A run is kept if it maximizes the scalar for any of the entries it covers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MapEntryBuckets<M> {
+    map_handle: Handle<M>,
+}
+
+impl<M> MapEntryBuckets<M>
+where
+    M: Named,
+{
+    /// Creates new [`MapEntryBuckets`] for the entries of the given map observer
+    #[must_use]
+    pub fn new(map_observer: &M) -> Self {
+        Self {
+            map_handle: map_observer.handle(),
+        }
+    }
+}
+
+impl<M, OT> CoverageBuckets<OT> for MapEntryBuckets<M>
+where
+    M: MapObserver,
+    OT: MatchName,
+{
+    fn buckets(&self, observers: &OT, buckets: &mut Vec<usize>) -> Result<(), Error> {
+        let map = observers.get(&self.map_handle).ok_or_else(|| {
+            Error::key_not_found(format!(
+                "MapEntryBuckets: map observer {} not found",
+                self.map_handle.name()
+            ))
+        })?;
+        let initial = map.initial();
+        buckets.extend((0..map.usable_count()).filter(|idx| map.get(*idx) != initial));
+        Ok(())
+    }
+}
+
+/// The maxima of a [`MaximizationFeedback`] per bucket, stored in the state
+#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+#[allow(clippy::unsafe_derive_deserialize)]
+pub struct MaximizationMetadata {
+    /// The largest value seen per bucket
+    pub maxima: HashMap<usize, u64>,
+}
+
+libafl_bolts::impl_serdeany!(MaximizationMetadata);
+
+impl MaximizationMetadata {
+    /// Create a new, empty [`MaximizationMetadata`]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// If `value` is larger than the maximum of any of the `buckets`
+    #[must_use]
+    pub fn is_new_max(&self, buckets: &[usize], value: u64) -> bool {
+        buckets
+            .iter()
+            .any(|bucket| self.maxima.get(bucket).is_none_or(|max| value > *max))
+    }
+
+    /// Raise the maximum of all `buckets` to `value`
+    pub fn update(&mut self, buckets: &[usize], value: u64) {
+        for bucket in buckets {
+            let max = self.maxima.entry(*bucket).or_insert(value);
+            *max = (*max).max(value);
+        }
+    }
+
+    /// The largest value seen in any bucket
+    #[must_use]
+    pub fn global_max(&self) -> Option<u64> {
+        self.maxima.values().copied().max()
+    }
+}
+
+/// The scalars observed for a [`Testcase`], by the name of the feedback that recorded them
+#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+#[allow(clippy::unsafe_derive_deserialize)]
+pub struct ScalarsMetadata {
+    /// The observed values
+    pub values: HashMap<String, u64>,
+}
+
+libafl_bolts::impl_serdeany!(ScalarsMetadata);
+
+fn record_scalar<I>(testcase: &mut Testcase<I>, name: &str, value: u64) {
+    testcase
+        .metadata_or_insert_with(ScalarsMetadata::default)
+        .values
+        .insert(name.into(), value);
+}
+
+/// A [`MaximizationFeedback`] maximizes the value of a [`ScalarObserver`] per coverage bucket.
+///
+/// The scalar could be the runtime ([`crate::observers::TimeObserver`]), the instructions
+/// executed ([`crate::observers::ProfilingObserver`]), allocated bytes or recursion depth.
+/// Combine it with a coverage feedback using an OR, and with a
+/// [`ScalarThresholdFeedback`] as objective to report inputs that are too expensive.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MaximizationFeedback<B, O> {
+    name: Cow<'static, str>,
+    observer_handle: Handle<O>,
+    buckets: B,
+    /// The buckets of the last run
+    #[serde(skip)]
+    last_buckets: Vec<usize>,
+    /// The scalar of the last run
+    last_value: Option<u64>,
+    #[cfg(feature = "track_hit_feedbacks")]
+    // The previous run's result of `Self::is_interesting`
+    last_result: Option<bool>,
+}
+
+impl<O> MaximizationFeedback<GlobalBucket, O>
+where
+    O: Named,
+{
+    /// Creates a new [`MaximizationFeedback`], maximizing the scalar of `observer` globally
+    #[must_use]
+    pub fn new(observer: &O) -> Self {
+        Self::with_buckets(observer, GlobalBucket)
+    }
+}
+
+impl<M, O> MaximizationFeedback<MapEntryBuckets<M>, O>
+where
+    M: Named,
+    O: Named,
+{
+    /// Creates a new [`MaximizationFeedback`], maximizing the scalar of `observer` for every entry
+    /// of `map_observer` covered by a run
+    #[must_use]
+    pub fn per_map_entry(observer: &O, map_observer: &M) -> Self {
+        Self::with_buckets(observer, MapEntryBuckets::new(map_observer))
+    }
+}
+
+impl<B, O> MaximizationFeedback<B, O>
+where
+    O: Named,
+{
+    /// Creates a new [`MaximizationFeedback`] with custom [`CoverageBuckets`]
+    #[must_use]
+    pub fn with_buckets(observer: &O, buckets: B) -> Self {
+        Self {
+            name: Cow::from(format!("{MAXIMIZATION_FEEDBACK_PREFIX}{}", observer.name())),
+            observer_handle: observer.handle(),
+            buckets,
+            last_buckets: Vec::new(),
+            last_value: None,
+            #[cfg(feature = "track_hit_feedbacks")]
+            last_result: None,
+        }
+    }
+
+    /// Sets the name of this feedback, and of its metadata, e.g., to use multiple instances for the same observer
+    #[must_use]
+    pub fn with_name(mut self, name: &'static str) -> Self {
+        self.name = Cow::Borrowed(name);
+        self
+    }
+}
+
+impl<B, O, S> StateInitializer<S> for MaximizationFeedback<B, O>
+where
+    S: HasNamedMetadata,
+{
+    fn init_state(&mut self, state: &mut S) -> Result<(), Error> {
+        state.add_named_metadata(&self.name, MaximizationMetadata::new());
+        Ok(())
+    }
+}
+
+impl<B, O, EM, I, OT, S> Feedback<EM, I, OT, S> for MaximizationFeedback<B, O>
+where
+    B: CoverageBuckets<OT>,
+    EM: EventFirer<State = S>,
+    O: ScalarObserver,
+    OT: MatchName,
+    S: HasNamedMetadata + UsesInput,
+{
+    #[allow(clippy::wrong_self_convention)]
+    fn is_interesting(
+        &mut self,
+        state: &mut S,
+        _manager: &mut EM,
+        _input: &I,
+        observers: &OT,
+        _exit_kind: &ExitKind,
+    ) -> Result<bool, Error> {
+        let observer = observers.get(&self.observer_handle).ok_or_else(|| {
+            Error::illegal_state(format!(
+                "MaximizationFeedback: observer {} not found",
+                self.observer_handle.name()
+            ))
+        })?;
+        self.last_value = observer.scalar();
+        self.last_buckets.clear();
+
+        let res = match self.last_value {
+            Some(value) => {
+                self.buckets.buckets(observers, &mut self.last_buckets)?;
+                state
+                    .named_metadata::<MaximizationMetadata>(&self.name)?
+                    .is_new_max(&self.last_buckets, value)
+            }
+            None => false,
+        };
+        #[cfg(feature = "track_hit_feedbacks")]
+        {
+            self.last_result = Some(res);
+        }
+        Ok(res)
+    }
+
+    #[cfg(feature = "track_hit_feedbacks")]
+    fn last_result(&self) -> Result<bool, Error> {
+        self.last_result.ok_or(premature_last_result_err())
+    }
+
+    fn append_metadata(
+        &mut self,
+        state: &mut S,
+        manager: &mut EM,
+        _observers: &OT,
+        testcase: &mut Testcase<I>,
+    ) -> Result<(), Error> {
+        let Some(value) = self.last_value else {
+            return Ok(());
+        };
+        record_scalar(testcase, &self.name, value);
+
+        let maxima = state.named_metadata_mut::<MaximizationMetadata>(&self.name)?;
+        maxima.update(&self.last_buckets, value);
+        let max = maxima.global_max().unwrap_or(value);
+
+        manager.fire(
+            state,
+            Event::UpdateUserStats {
+                name: self.name.clone(),
+                value: UserStats::new(UserStatsValue::Number(max), AggregatorOps::Max),
+                phantom: core::marker::PhantomData,
+            },
+        )?;
+        Ok(())
+    }
+}
+
+impl<B, O> Named for MaximizationFeedback<B, O> {
+    #[inline]
+    fn name(&self) -> &Cow<'static, str> {
+        &self.name
+    }
+}
+
+impl<B, O> HasObserverHandle for MaximizationFeedback<B, O> {
+    type Observer = O;
+
+    #[inline]
+    fn observer_handle(&self) -> &Handle<O> {
+        &self.observer_handle
+    }
+}
+
+/// A [`ScalarThresholdFeedback`] is interesting if the value of a [`ScalarObserver`] exceeds a threshold.
+///
+/// Use it as objective, e.g., to report inputs executing more than a given number of instructions.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScalarThresholdFeedback<O> {
+    name: Cow<'static, str>,
+    observer_handle: Handle<O>,
+    threshold: u64,
+    last_value: Option<u64>,
+    #[cfg(feature = "track_hit_feedbacks")]
+    // The previous run's result of `Self::is_interesting`
+    last_result: Option<bool>,
+}
+
+impl<O> ScalarThresholdFeedback<O>
+where
+    O: Named,
+{
+    /// Creates a new [`ScalarThresholdFeedback`], interesting if the scalar of `observer` is above `threshold`
+    #[must_use]
+    pub fn new(observer: &O, threshold: u64) -> Self {
+        Self {
+            name: Cow::from(format!("threshold_{}", observer.name())),
+            observer_handle: observer.handle(),
+            threshold,
+            last_value: None,
+            #[cfg(feature = "track_hit_feedbacks")]
+            last_result: None,
+        }
+    }
+
+    /// The threshold
+    #[must_use]
+    pub fn threshold(&self) -> u64 {
+        self.threshold
+    }
+}
+
+impl<O, S> StateInitializer<S> for ScalarThresholdFeedback<O> {}
+
+impl<O, EM, I, OT, S> Feedback<EM, I, OT, S> for ScalarThresholdFeedback<O>
+where
+    O: ScalarObserver,
+    OT: MatchName,
+{
+    #[allow(clippy::wrong_self_convention)]
+    fn is_interesting(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        _input: &I,
+        observers: &OT,
+        _exit_kind: &ExitKind,
+    ) -> Result<bool, Error> {
+        let observer = observers.get(&self.observer_handle).ok_or_else(|| {
+            Error::illegal_state(format!(
+                "ScalarThresholdFeedback: observer {} not found",
+                self.observer_handle.name()
+            ))
+        })?;
+        self.last_value = observer.scalar();
+
+        let res = self.last_value.is_some_and(|value| value > self.threshold);
+        #[cfg(feature = "track_hit_feedbacks")]
+        {
+            self.last_result = Some(res);
+        }
+        Ok(res)
+    }
+
+    #[cfg(feature = "track_hit_feedbacks")]
+    fn last_result(&self) -> Result<bool, Error> {
+        self.last_result.ok_or(premature_last_result_err())
+    }
+
+    fn append_metadata(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        _observers: &OT,
+        testcase: &mut Testcase<I>,
+    ) -> Result<(), Error> {
+        if let Some(value) = self.last_value {
+            record_scalar(testcase, &self.name, value);
+        }
+        Ok(())
+    }
+}
+
+impl<O> Named for ScalarThresholdFeedback<O> {
+    #[inline]
+    fn name(&self) -> &Cow<'static, str> {
+        &self.name
+    }
+}
+
+impl<O> HasObserverHandle for ScalarThresholdFeedback<O> {
+    type Observer = O;
+
+    #[inline]
+    fn observer_handle(&self) -> &Handle<O> {
+        &self.observer_handle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{boxed::Box, vec, vec::Vec};
+
+    use libafl_bolts::{ownedref::OwnedRef, rands::StdRand, tuples::tuple_list};
+
+    use super::{
+        CoverageBuckets, MapEntryBuckets, MaximizationFeedback, MaximizationMetadata,
+        ScalarsMetadata,
+    };
+    use crate::{
+        corpus::{InMemoryCorpus, Testcase},
+        events::NopEventManager,
+        executors::ExitKind,
+        feedbacks::{ConstFeedback, Feedback},
+        inputs::BytesInput,
+        observers::{ScalarObserver, StdMapObserver, ValueObserver},
+        state::StdState,
+        HasMetadata, HasNamedMetadata,
+    };
+
+    #[test]
+    fn test_maximization_per_entry() {
+        let map = StdMapObserver::owned("map", vec![0u8, 3, 0, 1]);
+        let scalar = ValueObserver::new("scalar", OwnedRef::Owned(Box::new(10u32)));
+        assert_eq!(scalar.scalar(), Some(10));
+
+        let buckets = MapEntryBuckets::new(&map);
+        let observers = tuple_list!(map, scalar);
+        let mut covered = Vec::new();
+        buckets.buckets(&observers, &mut covered).unwrap();
+        assert_eq!(covered, [1, 3]);
+
+        let mut meta = MaximizationMetadata::new();
+        assert!(meta.is_new_max(&covered, 10));
+        meta.update(&covered, 10);
+        assert!(!meta.is_new_max(&covered, 10));
+        assert!(!meta.is_new_max(&[1], 5));
+        // A cheaper run is still kept if it is the most expensive one for an entry
+        assert!(meta.is_new_max(&[0, 1], 5));
+        meta.update(&[0, 1], 5);
+        assert_eq!(meta.maxima[&0], 5);
+        assert_eq!(meta.maxima[&1], 10);
+        assert_eq!(meta.global_max(), Some(10));
+    }
+
+    #[test]
+    fn test_maximization_feedback() {
+        let scalar = ValueObserver::new("scalar", OwnedRef::Owned(Box::new(10u32)));
+        let mut feedback = MaximizationFeedback::new(&scalar);
+        let mut objective = ConstFeedback::new(false);
+        let mut state = StdState::new(
+            StdRand::with_seed(0),
+            InMemoryCorpus::<BytesInput>::new(),
+            InMemoryCorpus::new(),
+            &mut feedback,
+            &mut objective,
+        )
+        .unwrap();
+        let mut mgr = NopEventManager::new();
+        let input = BytesInput::new(vec![0]);
+        let mut observers = tuple_list!(scalar);
+
+        // The first value is a new maximum, and is recorded in the testcase
+        assert!(feedback
+            .is_interesting(&mut state, &mut mgr, &input, &observers, &ExitKind::Ok)
+            .unwrap());
+        let mut testcase = Testcase::new(input.clone());
+        feedback
+            .append_metadata(&mut state, &mut mgr, &observers, &mut testcase)
+            .unwrap();
+        assert_eq!(
+            testcase.metadata::<ScalarsMetadata>().unwrap().values["maximize_scalar"],
+            10
+        );
+
+        // A larger value is a new maximum
+        observers.0.set(20);
+        assert!(feedback
+            .is_interesting(&mut state, &mut mgr, &input, &observers, &ExitKind::Ok)
+            .unwrap());
+        let mut testcase = Testcase::new(input.clone());
+        feedback
+            .append_metadata(&mut state, &mut mgr, &observers, &mut testcase)
+            .unwrap();
+        assert_eq!(
+            testcase.metadata::<ScalarsMetadata>().unwrap().values["maximize_scalar"],
+            20
+        );
+        assert_eq!(
+            state
+                .named_metadata::<MaximizationMetadata>("maximize_scalar")
+                .unwrap()
+                .global_max(),
+            Some(20)
+        );
+
+        // A smaller one is not
+        observers.0.set(15);
+        assert!(!feedback
+            .is_interesting(&mut state, &mut mgr, &input, &observers, &ExitKind::Ok)
+            .unwrap());
+
+        // Without its observer, the feedback fails instead of panicking
+        let other = ValueObserver::new("other", OwnedRef::Owned(Box::new(0u32)));
+        assert!(feedback
+            .is_interesting(
+                &mut state,
+                &mut mgr,
+                &input,
+                &tuple_list!(other),
+                &ExitKind::Ok
+            )
+            .is_err());
+    }
+}
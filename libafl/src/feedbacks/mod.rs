@@ -11,20 +11,24 @@ use core::{fmt::Debug, marker::PhantomData};
 
 #[cfg(feature = "std")]
 pub use concolic::ConcolicFeedback;
-pub use differential::DiffFeedback;
+pub use differential::{DiffFeedback, MultiDiffFeedback};
 use libafl_bolts::{
     tuples::{Handle, Handled, MatchName, MatchNameRef},
     Named,
 };
 pub use list::*;
 pub use map::*;
+pub use maximization::{MaximizationFeedback, ScalarThresholdFeedback};
 #[cfg(feature = "nautilus")]
 pub use nautilus::*;
 #[cfg(feature = "std")]
 pub use new_hash_feedback::NewHashFeedback;
 #[cfg(feature = "std")]
 pub use new_hash_feedback::NewHashFeedbackMetadata;
+#[cfg(feature = "regex")]
+pub use sanitizer_report::{SanitizerReportFeedback, SanitizerReportMetadata};
 use serde::{Deserialize, Serialize};
+pub use sparse_map::{SparseMapFeedback, SparseMapFeedbackMetadata};
 
 use crate::{corpus::Testcase, executors::ExitKind, observers::TimeObserver, Error};
 
@@ -40,16 +44,20 @@ pub mod differential;
 /// The module for list feedback
 pub mod list;
 pub mod map;
+pub mod maximization;
 #[cfg(feature = "nautilus")]
 pub mod nautilus;
 #[cfg(feature = "std")]
 pub mod new_hash_feedback;
+#[cfg(feature = "regex")]
+pub mod sanitizer_report;
+pub mod sparse_map;
 #[cfg(feature = "std")]
 pub mod stdio;
 pub mod transferred;
 
 #[cfg(feature = "std")]
-pub use capture_feedback::CaptureTimeoutFeedback;
+pub use capture_feedback::{CaptureCrashFeedback, CaptureTimeoutFeedback};
 
 #[cfg(feature = "introspection")]
 use crate::state::HasClientPerfMonitor;
@@ -811,6 +819,21 @@ impl ExitKindLogic for TimeoutLogic {
     }
 }
 
+/// Name used by `OomFeedback`
+pub const OOM_FEEDBACK_NAME: &str = "OomFeedback";
+
+/// Logic which finds all [`ExitKind::Oom`] exits interesting
+#[derive(Debug, Copy, Clone)]
+pub struct OomLogic;
+
+impl ExitKindLogic for OomLogic {
+    const NAME: Cow<'static, str> = Cow::Borrowed(OOM_FEEDBACK_NAME);
+
+    fn check_exit_kind(kind: &ExitKind) -> Result<bool, Error> {
+        Ok(matches!(kind, ExitKind::Oom))
+    }
+}
+
 /// Logic which finds all [`ExitKind::Diff`] exits interesting
 #[derive(Debug, Copy, Clone)]
 pub struct GenericDiffLogic;
@@ -823,7 +846,7 @@ impl ExitKindLogic for GenericDiffLogic {
     }
 }
 
-/// A generic exit type checking feedback. Use [`CrashFeedback`], [`TimeoutFeedback`], or
+/// A generic exit type checking feedback. Use [`CrashFeedback`], [`TimeoutFeedback`], [`OomFeedback`], or
 /// [`DiffExitKindFeedback`] directly instead.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ExitKindFeedback<L> {
@@ -908,6 +931,8 @@ where
 pub type CrashFeedback = ExitKindFeedback<CrashLogic>;
 /// A [`TimeoutFeedback`] reduces the timeout value of a run.
 pub type TimeoutFeedback = ExitKindFeedback<TimeoutLogic>;
+/// An [`OomFeedback`] reports as interesting if the target ran out of memory.
+pub type OomFeedback = ExitKindFeedback<OomLogic>;
 /// A [`DiffExitKindFeedback`] checks if there is a difference in the [`ExitKind`]s in a [`crate::executors::DiffExecutor`].
 pub type DiffExitKindFeedback = ExitKindFeedback<GenericDiffLogic>;
 
@@ -1038,6 +1063,7 @@ impl<T> FeedbackFactory<ConstFeedback, T> for ConstFeedback {
 
 #[cfg(feature = "track_hit_feedbacks")]
 /// Error if [`Feedback::last_result`] is called before the `Feedback` is actually run.
-pub(crate) fn premature_last_result_err() -> Error {
+#[must_use]
+pub fn premature_last_result_err() -> Error {
     Error::illegal_state("last_result called before Feedback was run")
 }
@@ -0,0 +1,112 @@
+//! The [`SanitizerReportFeedback`] attaches the parsed sanitizer report of a crash to the objective testcase
+
+use alloc::borrow::Cow;
+
+use libafl_bolts::{
+    tuples::{Handle, Handled, MatchName, MatchNameRef},
+    Named,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    corpus::Testcase,
+    feedbacks::{Feedback, HasObserverHandle, StateInitializer},
+    observers::{HasSanitizerReport, SanitizerReport},
+    Error, HasMetadata,
+};
+
+/// The [`SanitizerReport`] of the run that found a testcase
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SanitizerReportMetadata {
+    /// The parsed report
+    pub report: SanitizerReport,
+    /// The hash bucketing the report, see [`SanitizerReport::bucket_hash`]
+    pub bucket: u64,
+}
+
+libafl_bolts::impl_serdeany!(SanitizerReportMetadata);
+
+/// Attaches the [`SanitizerReport`] of the observer as [`SanitizerReportMetadata`] to new testcases.
+///
+/// The feedback is never interesting on its own, combine it with the objective using an OR, e.g.,
+/// `feedback_or!(feedback_and_fast!(CrashFeedback::new(), NewHashFeedback::new(&observer)), SanitizerReportFeedback::new(&observer))`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SanitizerReportFeedback<O> {
+    name: Cow<'static, str>,
+    o_ref: Handle<O>,
+    bucket_frames: usize,
+}
+
+impl<O> SanitizerReportFeedback<O>
+where
+    O: Named,
+{
+    /// Creates a new [`SanitizerReportFeedback`] for the reports of `observer`, bucketing them by the
+    /// top [`crate::observers::sanitizer_report::DEFAULT_BUCKET_FRAMES`] frames
+    #[must_use]
+    pub fn new(observer: &O) -> Self {
+        Self::with_bucket_frames(
+            observer,
+            crate::observers::sanitizer_report::DEFAULT_BUCKET_FRAMES,
+        )
+    }
+
+    /// Creates a new [`SanitizerReportFeedback`] for the reports of `observer`, bucketing them by the
+    /// top `bucket_frames` frames
+    #[must_use]
+    pub fn with_bucket_frames(observer: &O, bucket_frames: usize) -> Self {
+        Self {
+            name: Cow::from(format!("sanitizer_report_{}", observer.name())),
+            o_ref: observer.handle(),
+            bucket_frames,
+        }
+    }
+}
+
+impl<O, S> StateInitializer<S> for SanitizerReportFeedback<O> {}
+
+impl<EM, I, O, OT, S> Feedback<EM, I, OT, S> for SanitizerReportFeedback<O>
+where
+    O: HasSanitizerReport,
+    OT: MatchName,
+{
+    #[cfg(feature = "track_hit_feedbacks")]
+    fn last_result(&self) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    fn append_metadata(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        observers: &OT,
+        testcase: &mut Testcase<I>,
+    ) -> Result<(), Error> {
+        let observer = observers
+            .get(&self.o_ref)
+            .ok_or_else(|| Error::illegal_state("SanitizerReportFeedback: observer not found"))?;
+        if let Some(report) = observer.sanitizer_report() {
+            testcase.add_metadata(SanitizerReportMetadata {
+                bucket: report.bucket_hash(self.bucket_frames),
+                report: report.clone(),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl<O> Named for SanitizerReportFeedback<O> {
+    #[inline]
+    fn name(&self) -> &Cow<'static, str> {
+        &self.name
+    }
+}
+
+impl<O> HasObserverHandle for SanitizerReportFeedback<O> {
+    type Observer = O;
+
+    #[inline]
+    fn observer_handle(&self) -> &Handle<O> {
+        &self.o_ref
+    }
+}
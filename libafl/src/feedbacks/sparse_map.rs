@@ -0,0 +1,345 @@
+//! The [`SparseMapFeedback`] finds novel coverage in a [`SparseMapObserver`].
+//!
+//! Novelty is decided looking at the touched entries only, so its cost does not grow with the size of the map.
+
+use alloc::{borrow::Cow, vec::Vec};
+use core::marker::PhantomData;
+
+use libafl_bolts::{
+    tuples::{Handle, Handled, MatchName, MatchNameRef},
+    Named,
+};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "track_hit_feedbacks")]
+use crate::feedbacks::premature_last_result_err;
+use crate::{
+    corpus::Testcase,
+    events::{Event, EventFirer},
+    executors::ExitKind,
+    feedbacks::{
+        map::create_stats_name, Feedback, HasObserverHandle, MapIndexesMetadata,
+        MapNoveltiesMetadata, StateInitializer,
+    },
+    inputs::UsesInput,
+    monitors::{AggregatorOps, UserStats, UserStatsValue},
+    observers::{CanTrack, SparseMapObserver},
+    Error, HasMetadata, HasNamedMetadata,
+};
+
+/// Bits in a word of the history
+const WORD_BITS: usize = u64::BITS as usize;
+
+/// The state of a [`SparseMapFeedback`]: the entries covered so far
+#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+#[allow(clippy::unsafe_derive_deserialize)] // for SerdeAny
+pub struct SparseMapFeedbackMetadata {
+    /// One bit per entry of the map, set for the covered entries.
+    /// Grows on demand, up to the highest covered entry.
+    pub history: Vec<u64>,
+    /// The number of covered entries
+    pub num_covered: usize,
+}
+
+libafl_bolts::impl_serdeany!(SparseMapFeedbackMetadata);
+
+impl SparseMapFeedbackMetadata {
+    /// If the entry at `idx` was covered before
+    #[must_use]
+    pub fn is_covered(&self, idx: usize) -> bool {
+        self.history
+            .get(idx / WORD_BITS)
+            .is_some_and(|word| word & (1 << (idx % WORD_BITS)) != 0)
+    }
+
+    /// Marks the entry at `idx` as covered, returns `true` if it was not covered before
+    pub fn cover(&mut self, idx: usize) -> bool {
+        let word = idx / WORD_BITS;
+        if self.history.len() <= word {
+            self.history.resize(word + 1, 0);
+        }
+        let bit = 1 << (idx % WORD_BITS);
+        let new = self.history[word] & bit == 0;
+        if new {
+            self.history[word] |= bit;
+            self.num_covered += 1;
+        }
+        new
+    }
+
+    /// Forgets all covered entries
+    pub fn reset(&mut self) {
+        self.history.clear();
+        self.num_covered = 0;
+    }
+}
+
+/// A feedback for the [`SparseMapObserver`], interesting if the run touched an entry never touched before.
+///
+/// Like the [`crate::feedbacks::MapFeedback`], it records [`MapIndexesMetadata`] and [`MapNoveltiesMetadata`]
+/// in new testcases if the observer [tracks](CanTrack) them.
+#[derive(Clone, Debug)]
+pub struct SparseMapFeedback<C> {
+    /// New indexes observed in the last observation
+    novelties: Vec<usize>,
+    /// Name identifier of this instance
+    name: Cow<'static, str>,
+    /// Name identifier of the observer
+    map_ref: Handle<C>,
+    /// Name of the feedback as shown in the `UserStats`
+    stats_name: Cow<'static, str>,
+    // The previous run's result of [`Self::is_interesting`]
+    #[cfg(feature = "track_hit_feedbacks")]
+    last_result: Option<bool>,
+}
+
+impl<C> SparseMapFeedback<C>
+where
+    C: CanTrack + Named,
+{
+    /// Create new [`SparseMapFeedback`]
+    #[must_use]
+    pub fn new(map_observer: &C) -> Self {
+        Self::with_name_cow(map_observer.name().clone(), map_observer)
+    }
+
+    /// Creating a new [`SparseMapFeedback`] with a specific name, to keep a separate history for the
+    /// same observer.
+    #[must_use]
+    pub fn with_name(name: &'static str, map_observer: &C) -> Self {
+        Self::with_name_cow(Cow::from(name), map_observer)
+    }
+
+    fn with_name_cow(name: Cow<'static, str>, map_observer: &C) -> Self {
+        Self {
+            novelties: Vec::new(),
+            map_ref: map_observer.handle(),
+            stats_name: create_stats_name(&name),
+            name,
+            #[cfg(feature = "track_hit_feedbacks")]
+            last_result: None,
+        }
+    }
+}
+
+impl<C, S> StateInitializer<S> for SparseMapFeedback<C>
+where
+    S: HasNamedMetadata,
+{
+    fn init_state(&mut self, state: &mut S) -> Result<(), Error> {
+        state.add_named_metadata(&self.name, SparseMapFeedbackMetadata::default());
+        Ok(())
+    }
+}
+
+impl<'a, C, EM, I, OT, S> Feedback<EM, I, OT, S> for SparseMapFeedback<C>
+where
+    C: CanTrack + AsRef<SparseMapObserver<'a>>,
+    EM: EventFirer<State = S>,
+    OT: MatchName,
+    S: HasNamedMetadata + UsesInput,
+{
+    #[allow(clippy::wrong_self_convention)]
+    fn is_interesting(
+        &mut self,
+        state: &mut S,
+        _manager: &mut EM,
+        _input: &I,
+        observers: &OT,
+        _exit_kind: &ExitKind,
+    ) -> Result<bool, Error> {
+        let observer = observers
+            .get(&self.map_ref)
+            .ok_or_else(|| Error::illegal_state("SparseMapFeedback: observer not found"))?
+            .as_ref();
+        let map_state = state
+            .named_metadata_map()
+            .get::<SparseMapFeedbackMetadata>(&self.name)
+            .ok_or_else(|| Error::illegal_state("SparseMapFeedback: state not initialized"))?;
+
+        self.novelties.clear();
+        self.novelties.extend(
+            observer
+                .touched()
+                .iter()
+                .filter(|idx| !map_state.is_covered(**idx)),
+        );
+        let res = !self.novelties.is_empty();
+
+        #[cfg(feature = "track_hit_feedbacks")]
+        {
+            self.last_result = Some(res);
+        }
+        Ok(res)
+    }
+
+    #[cfg(feature = "track_hit_feedbacks")]
+    fn last_result(&self) -> Result<bool, Error> {
+        self.last_result.ok_or(premature_last_result_err())
+    }
+
+    fn append_metadata(
+        &mut self,
+        state: &mut S,
+        manager: &mut EM,
+        observers: &OT,
+        testcase: &mut Testcase<I>,
+    ) -> Result<(), Error> {
+        let observer = observers
+            .get(&self.map_ref)
+            .ok_or_else(|| Error::illegal_state("SparseMapFeedback: observer not found"))?
+            .as_ref();
+        if C::NOVELTIES {
+            testcase.add_metadata(MapNoveltiesMetadata::new(core::mem::take(
+                &mut self.novelties,
+            )));
+        }
+        if C::INDICES {
+            testcase.add_metadata(MapIndexesMetadata::new(observer.touched().to_vec()));
+        }
+
+        let map_state = state
+            .named_metadata_map_mut()
+            .get_mut::<SparseMapFeedbackMetadata>(&self.name)
+            .ok_or_else(|| Error::illegal_state("SparseMapFeedback: state not initialized"))?;
+        for idx in observer.touched() {
+            map_state.cover(*idx);
+        }
+
+        let covered = map_state.num_covered;
+        manager.fire(
+            state,
+            Event::UpdateUserStats {
+                name: self.stats_name.clone(),
+                value: UserStats::new(
+                    UserStatsValue::Ratio(covered as u64, observer.map_size() as u64),
+                    AggregatorOps::Avg,
+                ),
+                phantom: PhantomData,
+            },
+        )?;
+        Ok(())
+    }
+}
+
+impl<C> Named for SparseMapFeedback<C> {
+    #[inline]
+    fn name(&self) -> &Cow<'static, str> {
+        &self.name
+    }
+}
+
+impl<C> HasObserverHandle for SparseMapFeedback<C> {
+    type Observer = C;
+
+    #[inline]
+    fn observer_handle(&self) -> &Handle<C> {
+        &self.map_ref
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libafl_bolts::{rands::StdRand, tuples::tuple_list};
+
+    use super::{SparseMapFeedback, SparseMapFeedbackMetadata};
+    use crate::{
+        corpus::{InMemoryCorpus, Testcase},
+        events::NopEventManager,
+        executors::ExitKind,
+        feedbacks::{ConstFeedback, Feedback, MapIndexesMetadata, MapNoveltiesMetadata},
+        inputs::BytesInput,
+        observers::{CanTrack, Observer, SparseMapObserver},
+        state::StdState,
+        HasMetadata, HasNamedMetadata,
+    };
+
+    fn run(observer: &mut SparseMapObserver<'_>, hits: &[usize]) {
+        Observer::<(), ()>::pre_exec(observer, &mut (), &()).unwrap();
+        for idx in hits {
+            observer.hit(*idx);
+        }
+        Observer::<(), ()>::post_exec(observer, &mut (), &(), &ExitKind::Ok).unwrap();
+    }
+
+    #[test]
+    fn test_sparse_map_history() {
+        let mut history = SparseMapFeedbackMetadata::default();
+        assert!(!history.is_covered(1 << 20));
+        assert!(history.cover(1 << 20));
+        assert!(!history.cover(1 << 20));
+        assert!(history.cover(3));
+        assert!(history.is_covered(3));
+        assert!(!history.is_covered(4));
+        assert_eq!(history.num_covered, 2);
+    }
+
+    #[test]
+    fn test_sparse_map_feedback() {
+        let observer = SparseMapObserver::owned("sparse", 8, 1 << 20)
+            .track_indices()
+            .track_novelties();
+        let mut feedback = SparseMapFeedback::new(&observer);
+        let mut objective = ConstFeedback::new(false);
+        let mut state = StdState::new(
+            StdRand::with_seed(0),
+            InMemoryCorpus::<BytesInput>::new(),
+            InMemoryCorpus::new(),
+            &mut feedback,
+            &mut objective,
+        )
+        .unwrap();
+        let mut mgr = NopEventManager::new();
+        let input = BytesInput::new(vec![0]);
+        let mut observers = tuple_list!(observer);
+
+        // Everything is novel at first
+        run(observers.0.as_mut(), &[3, 70]);
+        assert!(feedback
+            .is_interesting(&mut state, &mut mgr, &input, &observers, &ExitKind::Ok)
+            .unwrap());
+        let mut testcase = Testcase::new(input.clone());
+        feedback
+            .append_metadata(&mut state, &mut mgr, &observers, &mut testcase)
+            .unwrap();
+        assert_eq!(
+            testcase.metadata::<MapIndexesMetadata>().unwrap().list,
+            [3, 70]
+        );
+        assert_eq!(
+            testcase.metadata::<MapNoveltiesMetadata>().unwrap().list,
+            [3, 70]
+        );
+        let history = state
+            .named_metadata::<SparseMapFeedbackMetadata>("sparse")
+            .unwrap();
+        assert_eq!(history.num_covered, 2);
+        assert!(history.is_covered(70));
+
+        // Covered entries are not novel anymore, only the new one is recorded
+        run(observers.0.as_mut(), &[3]);
+        assert!(!feedback
+            .is_interesting(&mut state, &mut mgr, &input, &observers, &ExitKind::Ok)
+            .unwrap());
+        run(observers.0.as_mut(), &[70, 5, 3]);
+        assert!(feedback
+            .is_interesting(&mut state, &mut mgr, &input, &observers, &ExitKind::Ok)
+            .unwrap());
+        let mut testcase = Testcase::new(input);
+        feedback
+            .append_metadata(&mut state, &mut mgr, &observers, &mut testcase)
+            .unwrap();
+        assert_eq!(
+            testcase.metadata::<MapIndexesMetadata>().unwrap().list,
+            [70, 5, 3]
+        );
+        assert_eq!(
+            testcase.metadata::<MapNoveltiesMetadata>().unwrap().list,
+            [5]
+        );
+        let history = state
+            .named_metadata::<SparseMapFeedbackMetadata>("sparse")
+            .unwrap();
+        assert_eq!(history.num_covered, 3);
+    }
+}
@@ -10,7 +10,7 @@ use serde::{de::DeserializeOwned, Serialize};
 use crate::monitors::PerfFeature;
 use crate::{
     corpus::{Corpus, CorpusId, HasCurrentCorpusId, HasTestcase, Testcase},
-    events::{Event, EventConfig, EventFirer, EventProcessor, ProgressReporter},
+    events::{delta::input_hash, Event, EventConfig, EventFirer, EventProcessor, ProgressReporter},
     executors::{Executor, ExitKind, HasObservers},
     feedbacks::Feedback,
     inputs::UsesInput,
@@ -363,6 +363,17 @@ where
     }
 }
 
+/// The content hash of the input the new testcase was mutated from: the current testcase, if any
+fn parent_hash<S>(state: &S) -> Option<u64>
+where
+    S: HasCorpus,
+    <S::Corpus as Corpus>::Input: Serialize,
+{
+    let parent_id = (*state.corpus().current())?;
+    let mut parent = state.corpus().get(parent_id).ok()?.try_borrow_mut().ok()?;
+    input_hash(parent.load_input(state.corpus()).ok()?).ok()
+}
+
 impl<CS, EM, F, OF, OT, S> ExecutionProcessor<EM, OT> for StdFuzzer<CS, F, OF, S>
 where
     CS: Scheduler<S::Input, S>,
@@ -487,6 +498,11 @@ where
         match exec_res {
             ExecuteInputResult::Corpus => {
                 if manager.should_send() {
+                    let parent_hash = if manager.sends_deltas() {
+                        parent_hash(state)
+                    } else {
+                        None
+                    };
                     manager.fire(
                         state,
                         Event::NewTestcase {
@@ -497,6 +513,7 @@ where
                             client_config: manager.configuration(),
                             time: current_time(),
                             forward_id: None,
+                            parent_hash,
                             #[cfg(all(unix, feature = "std", feature = "multi_machine"))]
                             node_id: None,
                         },
@@ -736,6 +753,7 @@ where
                 client_config: manager.configuration(),
                 time: current_time(),
                 forward_id: None,
+                parent_hash: None,
                 #[cfg(all(unix, feature = "std", feature = "multi_machine"))]
                 node_id: None,
             },
@@ -59,7 +59,7 @@ impl Aggregator {
     }
 
     /// takes the key and the ref to clients stats then aggregate them all.
-    fn aggregate(&mut self, name: &str, client_stats: &[ClientStats]) {
+    pub(crate) fn aggregate(&mut self, name: &str, client_stats: &[ClientStats]) {
         let mut gather = client_stats
             .iter()
             .filter_map(|client| client.user_monitor.get(name));
@@ -118,6 +118,12 @@ impl Aggregator {
 
         self.aggregated.insert(name.to_string(), init);
     }
+
+    /// The aggregated value of the user stats `name`, if any client reported it
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&UserStatsValue> {
+        self.aggregated.get(name)
+    }
 }
 
 /// user defined stats enum
@@ -480,6 +486,16 @@ impl ClientStats {
         self.user_monitor.get(name)
     }
 
+    /// The stability of this client, the ratio of stable to filled map entries as reported by the
+    /// [`crate::stages::CalibrationStage`], or `None` if it did not calibrate yet.
+    #[must_use]
+    pub fn stability(&self) -> Option<(u64, u64)> {
+        match self.get_user_stats("stability")?.value() {
+            UserStatsValue::Ratio(stable, filled) if *filled > 0 => Some((*stable, *filled)),
+            _ => None,
+        }
+    }
+
     /// Update the current [`ClientPerfMonitor`] with the given [`ClientPerfMonitor`]
     #[cfg(feature = "introspection")]
     pub fn update_introspection_monitor(&mut self, introspection_monitor: ClientPerfMonitor) {
@@ -778,12 +794,20 @@ where
             self.execs_per_sec_pretty()
         );
 
+        self.client_stats_insert(sender_id);
+        let client = self.client_stats_for(sender_id);
         if self.print_user_monitor {
-            self.client_stats_insert(sender_id);
-            let client = self.client_stats_mut_for(sender_id);
             for (key, val) in &client.user_monitor {
                 write!(fmt, ", {key}: {val}").unwrap();
             }
+        } else if let Some((stable, filled)) = client.stability() {
+            // Always show the stability of the client, like AFL does
+            write!(
+                fmt,
+                ", stability: {stable}/{filled} ({}%)",
+                stable * 100 / filled
+            )
+            .unwrap();
         }
 
         (self.print_fn)(&fmt);
@@ -28,7 +28,10 @@
 //! When using docker, you may need to point `prometheus.yml` to the `docker0` interface or `host.docker.internal`
 
 use alloc::{borrow::Cow, fmt::Debug, string::String, vec::Vec};
-use core::{fmt, time::Duration};
+use core::{
+    fmt::{self, Write},
+    time::Duration,
+};
 use std::{
     sync::{atomic::AtomicU64, Arc},
     thread,
@@ -156,7 +159,8 @@ where
             .set(total_clients);
 
         // display stats in a SimpleMonitor format
-        let fmt = format!(
+        self.client_stats_insert(sender_id);
+        let mut fmt = format!(
             "[Prometheus] [{} #{}] run time: {}, clients: {}, corpus: {}, objectives: {}, executions: {}, exec/sec: {}",
             event_msg,
             sender_id.0,
@@ -167,9 +171,16 @@ where
             self.total_execs(),
             self.execs_per_sec_pretty()
         );
+        if let Some((stable, filled)) = self.client_stats_for(sender_id).stability() {
+            write!(
+                fmt,
+                ", stability: {stable}/{filled} ({}%)",
+                stable * 100 / filled
+            )
+            .unwrap();
+        }
         (self.print_fn)(&fmt);
 
-        self.client_stats_insert(sender_id);
         let cur_client = self.client_stats_mut_for(sender_id);
         let cur_client_clone = cur_client.clone();
 
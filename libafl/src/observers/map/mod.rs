@@ -32,6 +32,9 @@ pub use multi_map::*;
 pub mod owned_map;
 pub use owned_map::*;
 
+pub mod sparse_map;
+pub use sparse_map::*;
+
 /// A trait indicating tracking of observed map values after testcase execution
 ///
 /// Trait marker which indicates that this [`MapObserver`] is tracked for indices or novelties.
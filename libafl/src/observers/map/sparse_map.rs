@@ -0,0 +1,286 @@
+//! A sparse map observer for targets with huge coverage maps.
+//!
+//! Instead of a dense map scanned after every run, the target appends the indices it touches to a trace
+//! buffer. The observer deduplicates them with a dense bitmap and exposes the distinct touched entries,
+//! so the cost of an execution depends on the number of touched entries, not on the size of the map.
+
+use alloc::{borrow::Cow, vec::Vec};
+use core::hash::{Hash, Hasher};
+
+use ahash::RandomState;
+use libafl_bolts::{ownedref::OwnedMutSlice, HasLen, Named};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    executors::ExitKind,
+    observers::{
+        map::{CanTrack, ExplicitTracking},
+        Observer,
+    },
+    Error,
+};
+
+/// Bits in a word of the bitmap
+const WORD_BITS: usize = u64::BITS as usize;
+
+/// A map observer recording only the touched entries of a potentially huge map.
+///
+/// The target writes to a trace buffer of `u64` words: the first word is the number of recorded indices,
+/// followed by the indices themselves, in any order and with duplicates (the layout of a `kcov` trace).
+/// Indices are taken modulo the size of the map. Indices not fitting in the buffer are dropped and the
+/// observer is marked as [`SparseMapObserver::truncated`].
+///
+/// The entries are edges covered or not, without hit counts.
+/// Use it with the [`crate::feedbacks::SparseMapFeedback`].
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[allow(clippy::unsafe_derive_deserialize)]
+pub struct SparseMapObserver<'a> {
+    name: Cow<'static, str>,
+    /// The number of entries of the map
+    map_size: usize,
+    /// The trace buffer written by the target
+    trace: OwnedMutSlice<'a, u64>,
+    /// One bit per entry of the map, set for the entries in `touched`.
+    /// Only used to deduplicate the trace, so it is not serialized.
+    #[serde(skip)]
+    bitmap: Vec<u64>,
+    /// The distinct entries touched in the last run, in the order of their first hit
+    touched: Vec<usize>,
+    /// If the trace buffer overflowed in the last run
+    truncated: bool,
+}
+
+impl<'a> SparseMapObserver<'a> {
+    /// Creates a new [`SparseMapObserver`] for a map of `map_size` entries, reading the indices from `trace`
+    ///
+    /// # Panics
+    /// Panics if `map_size` is 0 or the trace can not hold its length word
+    #[must_use]
+    pub fn from_mut_slice<S>(name: S, trace: OwnedMutSlice<'a, u64>, map_size: usize) -> Self
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        assert!(
+            map_size > 0,
+            "The map of a SparseMapObserver can not be empty"
+        );
+        assert!(
+            !trace.is_empty(),
+            "The trace of a SparseMapObserver needs at least the length word"
+        );
+        Self {
+            name: name.into(),
+            map_size,
+            trace,
+            bitmap: Vec::new(),
+            touched: Vec::new(),
+            truncated: false,
+        }
+    }
+
+    /// Creates a new [`SparseMapObserver`] for a map of `map_size` entries, with an owned trace buffer
+    /// recording up to `capacity` indices per run
+    #[must_use]
+    pub fn owned<S>(name: S, capacity: usize, map_size: usize) -> Self
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        Self::from_mut_slice(name, OwnedMutSlice::from(vec![0; capacity + 1]), map_size)
+    }
+
+    /// Creates a new [`SparseMapObserver`] for a map of `map_size` entries from a raw pointer to a trace
+    /// buffer of `len` words, e.g., in shared memory
+    ///
+    /// # Safety
+    /// Will dereference the `trace_ptr` with up to len elements.
+    #[must_use]
+    pub unsafe fn from_mut_ptr<S>(name: S, trace_ptr: *mut u64, len: usize, map_size: usize) -> Self
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        Self::from_mut_slice(
+            name,
+            OwnedMutSlice::from_raw_parts_mut(trace_ptr, len),
+            map_size,
+        )
+    }
+
+    /// Records a hit of the entry at `idx`, for targets driven by in-process hooks
+    #[inline]
+    pub fn hit(&mut self, idx: usize) {
+        let trace = &mut *self.trace;
+        let len = trace[0] as usize;
+        if len + 1 < trace.len() {
+            trace[len + 1] = idx as u64;
+        }
+        // Keep counting past the end, so the overflow is noticed
+        trace[0] += 1;
+    }
+
+    /// The number of entries of the map
+    #[must_use]
+    pub fn map_size(&self) -> usize {
+        self.map_size
+    }
+
+    /// The distinct entries touched in the last run, in the order of their first hit
+    #[must_use]
+    pub fn touched(&self) -> &[usize] {
+        &self.touched
+    }
+
+    /// If the trace buffer was too small for the last run, and some hits were lost
+    #[must_use]
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// The trace buffer written by the target
+    #[must_use]
+    pub fn trace(&self) -> &OwnedMutSlice<'a, u64> {
+        &self.trace
+    }
+
+    /// The trace buffer written by the target, mutably
+    pub fn trace_mut(&mut self) -> &mut OwnedMutSlice<'a, u64> {
+        &mut self.trace
+    }
+
+    /// Clears the recorded indices, and the bits of the touched entries in the bitmap
+    fn reset(&mut self) {
+        for idx in self.touched.drain(..) {
+            if let Some(word) = self.bitmap.get_mut(idx / WORD_BITS) {
+                *word &= !(1 << (idx % WORD_BITS));
+            }
+        }
+        self.trace[0] = 0;
+        self.truncated = false;
+    }
+
+    /// Deduplicates the indices in the trace buffer into `touched`
+    fn collect(&mut self) {
+        let words = self.map_size.div_ceil(WORD_BITS);
+        if self.bitmap.len() != words {
+            // First run, or deserialized without bitmap
+            self.bitmap = vec![0; words];
+            for &idx in &self.touched {
+                self.bitmap[idx / WORD_BITS] |= 1 << (idx % WORD_BITS);
+            }
+        }
+
+        let recorded = self.trace[0] as usize;
+        let available = self.trace.len() - 1;
+        self.truncated = recorded > available;
+        for &raw in &self.trace[1..=recorded.min(available)] {
+            let idx = (raw % self.map_size as u64) as usize;
+            let word = &mut self.bitmap[idx / WORD_BITS];
+            let bit = 1 << (idx % WORD_BITS);
+            if *word & bit == 0 {
+                *word |= bit;
+                self.touched.push(idx);
+            }
+        }
+    }
+
+    /// A hash of the set of touched entries, independent of the order of the hits
+    #[must_use]
+    pub fn hash_simple(&self) -> u64 {
+        RandomState::with_seeds(0, 0, 0, 0).hash_one(self)
+    }
+}
+
+impl<I, S> Observer<I, S> for SparseMapObserver<'_> {
+    #[inline]
+    fn pre_exec(&mut self, _state: &mut S, _input: &I) -> Result<(), Error> {
+        self.reset();
+        Ok(())
+    }
+
+    #[inline]
+    fn post_exec(
+        &mut self,
+        _state: &mut S,
+        _input: &I,
+        _exit_kind: &ExitKind,
+    ) -> Result<(), Error> {
+        self.collect();
+        Ok(())
+    }
+}
+
+impl Named for SparseMapObserver<'_> {
+    #[inline]
+    fn name(&self) -> &Cow<'static, str> {
+        &self.name
+    }
+}
+
+impl HasLen for SparseMapObserver<'_> {
+    /// The number of entries of the map
+    #[inline]
+    fn len(&self) -> usize {
+        self.map_size
+    }
+}
+
+impl Hash for SparseMapObserver<'_> {
+    fn hash<H: Hasher>(&self, hasher: &mut H) {
+        let mut touched = self.touched.clone();
+        touched.sort_unstable();
+        touched.hash(hasher);
+    }
+}
+
+impl AsRef<Self> for SparseMapObserver<'_> {
+    fn as_ref(&self) -> &Self {
+        self
+    }
+}
+
+impl AsMut<Self> for SparseMapObserver<'_> {
+    fn as_mut(&mut self) -> &mut Self {
+        self
+    }
+}
+
+impl CanTrack for SparseMapObserver<'_> {
+    type WithIndexTracking = ExplicitTracking<Self, true, false>;
+    type WithNoveltiesTracking = ExplicitTracking<Self, false, true>;
+    const INDICES: bool = false;
+    const NOVELTIES: bool = false;
+
+    fn track_indices(self) -> Self::WithIndexTracking {
+        ExplicitTracking::<Self, true, false>(self)
+    }
+
+    fn track_novelties(self) -> Self::WithNoveltiesTracking {
+        ExplicitTracking::<Self, false, true>(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SparseMapObserver;
+    use crate::{executors::ExitKind, observers::Observer};
+
+    #[test]
+    fn test_sparse_map_observer() {
+        let mut observer = SparseMapObserver::owned("sparse", 4, 1 << 24);
+        Observer::<(), ()>::pre_exec(&mut observer, &mut (), &()).unwrap();
+        for idx in [7, (1 << 23) + 3, 7, 1 << 24] {
+            observer.hit(idx);
+        }
+        Observer::<(), ()>::post_exec(&mut observer, &mut (), &(), &ExitKind::Ok).unwrap();
+        // Duplicates are dropped, indices wrap around at the map size
+        assert_eq!(observer.touched(), [7, (1 << 23) + 3, 0]);
+        assert!(!observer.truncated());
+
+        Observer::<(), ()>::pre_exec(&mut observer, &mut (), &()).unwrap();
+        for idx in 0..6 {
+            observer.hit(idx);
+        }
+        Observer::<(), ()>::post_exec(&mut observer, &mut (), &(), &ExitKind::Ok).unwrap();
+        assert_eq!(observer.touched(), [0, 1, 2, 3]);
+        assert!(observer.truncated());
+    }
+}
@@ -14,6 +14,13 @@ pub mod stacktrace;
 #[cfg(feature = "regex")]
 pub use stacktrace::*;
 
+#[cfg(feature = "regex")]
+pub mod sanitizer_report;
+#[cfg(feature = "regex")]
+pub use sanitizer_report::{
+    AccessKind, HasSanitizerReport, SanitizerKind, SanitizerReport, StackFrame,
+};
+
 /// Profiler observer
 #[cfg(feature = "std")]
 pub mod profiling;
@@ -181,6 +188,14 @@ pub trait ObserverWithHashField {
     fn hash(&self) -> Option<u64>;
 }
 
+/// A trait for [`Observer`]`s` that boil a run down to a single number, like its runtime or the
+/// instructions executed, so that it can be maximized, e.g., by a
+/// [`MaximizationFeedback`](crate::feedbacks::MaximizationFeedback).
+pub trait ScalarObserver {
+    /// get the value observed during the last run, if any
+    fn scalar(&self) -> Option<u64>;
+}
+
 /// A trait for [`Observer`]`s` which observe over differential execution.
 ///
 /// Differential observers have the following flow during a single execution:
@@ -398,6 +413,14 @@ impl Named for TimeObserver {
 
 impl<OTA, OTB, I, S> DifferentialObserver<OTA, OTB, I, S> for TimeObserver {}
 
+/// The runtime of the last run, in nanoseconds
+impl ScalarObserver for TimeObserver {
+    fn scalar(&self) -> Option<u64> {
+        self.last_runtime
+            .map(|runtime| u64::try_from(runtime.as_nanos()).unwrap_or(u64::MAX))
+    }
+}
+
 #[cfg(feature = "std")]
 #[cfg(test)]
 mod tests {
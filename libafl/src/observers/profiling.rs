@@ -5,7 +5,10 @@ use hashbrown::HashMap;
 use libafl_bolts::{ownedref::OwnedMutPtr, Named};
 use serde::{Deserialize, Serialize};
 
-use crate::{observers::Observer, Error};
+use crate::{
+    observers::{Observer, ScalarObserver},
+    Error,
+};
 #[derive(Debug, Serialize, Deserialize)]
 /// The json data
 pub struct FunctionData {
@@ -121,6 +124,25 @@ impl ProfilingObserver {
     }
 }
 
+/// The estimated number of instructions executed in the last run: the calls of every function,
+/// weighted by its static instruction count.
+impl ScalarObserver for ProfilingObserver {
+    fn scalar(&self) -> Option<u64> {
+        let insts = self
+            .map()
+            .iter()
+            .map(|(function_id, calls)| {
+                let inst_count = self
+                    .lookup(*function_id)
+                    .and_then(|data| data.inst_count)
+                    .unwrap_or(0);
+                (*calls as u64).saturating_mul(u64::from(inst_count))
+            })
+            .fold(0u64, u64::saturating_add);
+        Some(insts)
+    }
+}
+
 impl Named for ProfilingObserver {
     fn name(&self) -> &Cow<'static, str> {
         &self.name
@@ -0,0 +1,640 @@
+//! Structured parsing of the reports printed by the `ASan`, `UBSan`, `MSan`, `TSan` and `LSan` runtimes.
+//!
+//! A [`SanitizerReport`] holds the bug type, the access, the faulting address and the symbolized stacks of
+//! a report. Its [`SanitizerReport::bucket_hash`] identifies a bug by the sanitizer, the bug type and the
+//! top frames of the crashing stack, which is much more stable than a hash over raw addresses.
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt;
+use std::sync::OnceLock;
+
+use libafl_bolts::hash_std;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// The default number of frames of the crashing stack used for bucketing
+pub const DEFAULT_BUCKET_FRAMES: usize = 3;
+
+/// The sanitizer that printed a report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SanitizerKind {
+    /// `AddressSanitizer`
+    Address,
+    /// `UndefinedBehaviorSanitizer`
+    UndefinedBehavior,
+    /// `MemorySanitizer`
+    Memory,
+    /// `ThreadSanitizer`
+    Thread,
+    /// `LeakSanitizer`
+    Leak,
+}
+
+impl SanitizerKind {
+    /// The kind for the name of the sanitizer, as printed in its reports
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "AddressSanitizer" => Some(Self::Address),
+            "UndefinedBehaviorSanitizer" => Some(Self::UndefinedBehavior),
+            "MemorySanitizer" => Some(Self::Memory),
+            "ThreadSanitizer" => Some(Self::Thread),
+            "LeakSanitizer" => Some(Self::Leak),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SanitizerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Address => "AddressSanitizer",
+            Self::UndefinedBehavior => "UndefinedBehaviorSanitizer",
+            Self::Memory => "MemorySanitizer",
+            Self::Thread => "ThreadSanitizer",
+            Self::Leak => "LeakSanitizer",
+        })
+    }
+}
+
+/// The kind of the faulting memory access
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AccessKind {
+    /// A read
+    Read,
+    /// A write
+    Write,
+    /// The sanitizer could not tell
+    Unknown,
+}
+
+/// A frame of a symbolized stack
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct StackFrame {
+    /// The program counter, if printed
+    pub pc: Option<u64>,
+    /// The function
+    pub function: Option<String>,
+    /// The source file
+    pub file: Option<String>,
+    /// The line in the source file
+    pub line: Option<u32>,
+    /// The column in the source line
+    pub column: Option<u32>,
+    /// The module containing the PC
+    pub module: Option<String>,
+    /// The offset of the PC in the module
+    pub offset: Option<u64>,
+}
+
+impl StackFrame {
+    /// If the frame is part of a sanitizer runtime, rather than of the target
+    #[must_use]
+    pub fn is_sanitizer_runtime(&self) -> bool {
+        const RUNTIME_PREFIXES: [&str; 8] = [
+            "__asan",
+            "__ubsan",
+            "__msan",
+            "__tsan",
+            "__lsan",
+            "__sanitizer",
+            "__interceptor_",
+            "___interceptor_",
+        ];
+        self.function
+            .as_deref()
+            .is_some_and(|function| RUNTIME_PREFIXES.iter().any(|p| function.starts_with(p)))
+            || self.file.as_deref().is_some_and(|file| {
+                file.contains("compiler-rt/lib/") || file.contains("libsanitizer/")
+            })
+            || self
+                .module
+                .as_deref()
+                .is_some_and(|module| module.contains("libclang_rt."))
+    }
+
+    /// The key of the frame for bucketing: the function, or the module and offset if unsymbolized
+    #[must_use]
+    pub fn bucket_key(&self) -> String {
+        match (&self.function, &self.module, self.offset, self.pc) {
+            (Some(function), _, _, _) => function.clone(),
+            (None, Some(module), Some(offset), _) => format!("{module}+{offset:#x}"),
+            (None, _, _, Some(pc)) => format!("{pc:#x}"),
+            _ => String::new(),
+        }
+    }
+}
+
+/// A report printed by a sanitizer runtime
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SanitizerReport {
+    /// The sanitizer that printed the report
+    pub sanitizer: SanitizerKind,
+    /// The bug type, such as `heap-buffer-overflow`, `SEGV` or `signed integer overflow`
+    pub bug_type: String,
+    /// The kind of the faulting access
+    pub access: Option<AccessKind>,
+    /// The size of the faulting access, or the number of leaked bytes
+    pub access_size: Option<u64>,
+    /// The faulting address
+    pub address: Option<u64>,
+    /// The stack of the crash (for leaks: of the first leaked allocation)
+    pub stack: Vec<StackFrame>,
+    /// The stack allocating the memory involved, or creating the uninitialized value for `MSan`
+    pub allocation_stack: Vec<StackFrame>,
+    /// The stack freeing the memory involved
+    pub free_stack: Vec<StackFrame>,
+    /// The `SUMMARY:` line of the report
+    pub summary: Option<String>,
+}
+
+/// The stack the next frames belong to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StackSlot {
+    Crash,
+    Allocation,
+    Free,
+    Ignored,
+}
+
+/// The regexes are compiled once, on the first parsed report
+static REPORT_REGEXES: OnceLock<ReportRegexes> = OnceLock::new();
+
+struct ReportRegexes {
+    header: Regex,
+    ubsan_error: Regex,
+    access: Regex,
+    tsan_access: Regex,
+    signal_access: Regex,
+    address: Regex,
+    leak: Regex,
+    frame: Regex,
+    module: Regex,
+    location: Regex,
+}
+
+impl ReportRegexes {
+    fn get() -> &'static Self {
+        REPORT_REGEXES.get_or_init(Self::new)
+    }
+
+    fn new() -> Self {
+        Self {
+            header: Regex::new(
+                r"(?:ERROR|WARNING): (AddressSanitizer|MemorySanitizer|ThreadSanitizer|LeakSanitizer|UndefinedBehaviorSanitizer): (.*)$",
+            )
+            .unwrap(),
+            ubsan_error: Regex::new(r"^(.*?):(\d+):(\d+): runtime error: (.*)$").unwrap(),
+            access: Regex::new(r"^(READ|WRITE) of size (\d+) at 0x([0-9a-fA-F]+)").unwrap(),
+            tsan_access: Regex::new(
+                r"^\s*(Previous )?(?:[Aa]tomic )?([Rr]ead|[Ww]rite) of size (\d+) at 0x([0-9a-fA-F]+)",
+            )
+            .unwrap(),
+            signal_access: Regex::new(r"The signal is caused by a (READ|WRITE|UNKNOWN) memory access")
+                .unwrap(),
+            address: Regex::new(r" on (?:unknown )?address 0x([0-9a-fA-F]+)|double-free on 0x([0-9a-fA-F]+)")
+                .unwrap(),
+            leak: Regex::new(r"^(?:Direct|Indirect) leak of (\d+) byte").unwrap(),
+            frame: Regex::new(r"^\s*#(\d+)\s+(.*)$").unwrap(),
+            module: Regex::new(r"^(.*?)\s*\(([^()]+)\+0x([0-9a-fA-F]+)\)$").unwrap(),
+            location: Regex::new(r"^(.+?):(\d+)(?::(\d+))?$").unwrap(),
+        }
+    }
+
+    fn parse_frame(&self, rest: &str) -> StackFrame {
+        let mut frame = StackFrame::default();
+        let mut rest = rest.trim();
+        if let Some(idx) = rest.find(" (BuildId: ") {
+            rest = rest[..idx].trim_end();
+        }
+        if let Some(captures) = self.module.captures(rest) {
+            frame.module = Some(captures[2].to_string());
+            frame.offset = u64::from_str_radix(&captures[3], 16).ok();
+            rest = captures.get(1).unwrap().as_str();
+        }
+        if let Some(pc) = rest.strip_prefix("0x") {
+            let end = pc
+                .find(|c: char| !c.is_ascii_hexdigit())
+                .unwrap_or(pc.len());
+            frame.pc = u64::from_str_radix(&pc[..end], 16).ok();
+            rest = pc[end..].trim_start();
+        }
+        if let Some(function) = rest.strip_prefix("in ") {
+            rest = function.trim_start();
+        }
+        // Unsymbolized frames of `TSan`
+        rest = rest.trim_end_matches("<null>").trim_end();
+        if rest.is_empty() {
+            return frame;
+        }
+
+        let (function, location) = match rest.rsplit_once(' ') {
+            Some((function, location)) if self.location.is_match(location) => {
+                (Some(function.trim_end()), Some(location))
+            }
+            _ => (Some(rest), None),
+        };
+        frame.function = function
+            .filter(|function| !function.is_empty())
+            .map(ToString::to_string);
+        if let Some(captures) = location.and_then(|location| self.location.captures(location)) {
+            frame.file = Some(captures[1].to_string());
+            frame.line = captures[2].parse().ok();
+            frame.column = captures
+                .get(3)
+                .and_then(|column| column.as_str().parse().ok());
+        }
+        frame
+    }
+}
+
+/// Normalizes an `UBSan` runtime error message into a bug type: the part before the first colon,
+/// with numbers replaced
+fn ubsan_bug_type(message: &str) -> String {
+    let message = message.split(':').next().unwrap_or(message).trim();
+    let mut bug_type = String::with_capacity(message.len());
+    let mut in_number = false;
+    for c in message.chars() {
+        if c.is_ascii_digit() {
+            if !in_number {
+                bug_type.push('N');
+            }
+            in_number = true;
+        } else if in_number && (c.is_ascii_hexdigit() || c == 'x') {
+            // The rest of a hex number
+        } else {
+            in_number = false;
+            bug_type.push(c);
+        }
+    }
+    bug_type
+}
+
+impl SanitizerReport {
+    /// Parses the first report in the `output` of a sanitizer runtime, such as the content of its `log_path`
+    #[must_use]
+    pub fn parse(output: &str) -> Option<Self> {
+        let regexes = ReportRegexes::get();
+        let mut report: Option<Self> = None;
+        let mut slot = None;
+        let mut slot_has_frames = false;
+
+        for line in output.lines() {
+            // Strip the `==<pid>==` prefix
+            let content = line
+                .strip_prefix("==")
+                .and_then(|rest| rest.split_once("=="))
+                .filter(|(pid, _)| !pid.is_empty() && pid.bytes().all(|b| b.is_ascii_digit()))
+                .map_or(line, |(_, rest)| rest);
+
+            if report.is_none() {
+                if let Some(captures) = regexes.header.captures(content) {
+                    let sanitizer = SanitizerKind::from_name(&captures[1])?;
+                    let description = &captures[2];
+                    let end = [" on ", " (", ":"]
+                        .iter()
+                        .filter_map(|sep| description.find(sep))
+                        .min()
+                        .unwrap_or(description.len());
+                    report = Some(Self::new(sanitizer, description[..end].trim().to_string()));
+                    let report = report.as_mut().unwrap();
+                    if let Some(captures) = regexes.address.captures(description) {
+                        let address = captures.get(1).or_else(|| captures.get(2)).unwrap();
+                        report.address = u64::from_str_radix(address.as_str(), 16).ok();
+                    }
+                    slot = Some(StackSlot::Crash);
+                    slot_has_frames = false;
+                } else if let Some(captures) = regexes.ubsan_error.captures(content) {
+                    let mut new_report = Self::new(
+                        SanitizerKind::UndefinedBehavior,
+                        ubsan_bug_type(&captures[4]),
+                    );
+                    // Without `print_stacktrace=1`, the location of the error is all we get
+                    new_report.stack.push(StackFrame {
+                        file: Some(captures[1].to_string()),
+                        line: captures[2].parse().ok(),
+                        column: captures[3].parse().ok(),
+                        ..StackFrame::default()
+                    });
+                    report = Some(new_report);
+                    slot = Some(StackSlot::Crash);
+                    slot_has_frames = false;
+                }
+                continue;
+            }
+            let report = report.as_mut().unwrap();
+
+            if let Some(captures) = regexes.frame.captures(content) {
+                // A new stack starts at `#0`
+                if &captures[1] == "0" && slot_has_frames {
+                    slot = Some(StackSlot::Ignored);
+                }
+                let stack = match slot.unwrap_or(StackSlot::Ignored) {
+                    StackSlot::Crash => &mut report.stack,
+                    StackSlot::Allocation => &mut report.allocation_stack,
+                    StackSlot::Free => &mut report.free_stack,
+                    StackSlot::Ignored => {
+                        slot_has_frames = true;
+                        continue;
+                    }
+                };
+                let frame = regexes.parse_frame(&captures[2]);
+                // Replace the location-only frame of UBSan
+                if !slot_has_frames && stack.len() == 1 && stack[0].function.is_none() {
+                    stack.clear();
+                }
+                stack.push(frame);
+                slot_has_frames = true;
+                continue;
+            }
+
+            let trimmed = content.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            if let Some(summary) = trimmed.strip_prefix("SUMMARY: ") {
+                report.summary = Some(summary.to_string());
+                break;
+            }
+
+            let next_slot = if let Some(captures) = regexes.access.captures(trimmed) {
+                report.access = Some(if &captures[1] == "READ" {
+                    AccessKind::Read
+                } else {
+                    AccessKind::Write
+                });
+                report.access_size = captures[2].parse().ok();
+                report.address = u64::from_str_radix(&captures[3], 16).ok();
+                Some(StackSlot::Crash)
+            } else if let Some(captures) = regexes.tsan_access.captures(trimmed) {
+                if captures.get(1).is_some() || report.access.is_some() {
+                    Some(StackSlot::Ignored)
+                } else {
+                    report.access = Some(if captures[2].eq_ignore_ascii_case("read") {
+                        AccessKind::Read
+                    } else {
+                        AccessKind::Write
+                    });
+                    report.access_size = captures[3].parse().ok();
+                    report.address = u64::from_str_radix(&captures[4], 16).ok();
+                    Some(StackSlot::Crash)
+                }
+            } else if let Some(captures) = regexes.signal_access.captures(trimmed) {
+                report.access = Some(match &captures[1] {
+                    "READ" => AccessKind::Read,
+                    "WRITE" => AccessKind::Write,
+                    _ => AccessKind::Unknown,
+                });
+                None
+            } else if let Some(captures) = regexes.leak.captures(trimmed) {
+                if report.stack.is_empty() {
+                    report.access_size = captures[1].parse().ok();
+                    Some(StackSlot::Crash)
+                } else {
+                    Some(StackSlot::Ignored)
+                }
+            } else if trimmed.starts_with("freed by thread") {
+                Some(StackSlot::Free)
+            } else if trimmed.starts_with("allocated by thread")
+                || trimmed.starts_with("previously allocated by thread")
+                || trimmed.starts_with("Uninitialized value was created")
+            {
+                Some(StackSlot::Allocation)
+            } else {
+                None
+            };
+
+            match next_slot {
+                Some(next_slot) => {
+                    let taken = match next_slot {
+                        StackSlot::Crash => {
+                            !report.stack.is_empty() && slot != Some(StackSlot::Crash)
+                        }
+                        StackSlot::Allocation => !report.allocation_stack.is_empty(),
+                        StackSlot::Free => !report.free_stack.is_empty(),
+                        StackSlot::Ignored => false,
+                    };
+                    slot = Some(if taken { StackSlot::Ignored } else { next_slot });
+                    slot_has_frames = false;
+                }
+                None if slot_has_frames => {
+                    // Any other line ends the stack
+                    slot = None;
+                    slot_has_frames = false;
+                }
+                None => {}
+            }
+        }
+        report
+    }
+
+    fn new(sanitizer: SanitizerKind, bug_type: String) -> Self {
+        Self {
+            sanitizer,
+            bug_type,
+            access: None,
+            access_size: None,
+            address: None,
+            stack: Vec::new(),
+            allocation_stack: Vec::new(),
+            free_stack: Vec::new(),
+            summary: None,
+        }
+    }
+
+    /// The top `count` frames of the crashing stack, without the frames of the sanitizer runtime
+    pub fn top_frames(&self, count: usize) -> impl Iterator<Item = &StackFrame> {
+        self.stack
+            .iter()
+            .filter(|frame| !frame.is_sanitizer_runtime())
+            .take(count)
+    }
+
+    /// A hash bucketing the report by the sanitizer, the bug type and the top `frames` frames of the crashing stack
+    #[must_use]
+    pub fn bucket_hash(&self, frames: usize) -> u64 {
+        let mut key = format!("{}\n{}", self.sanitizer, self.bug_type);
+        for frame in self.top_frames(frames) {
+            key.push('\n');
+            key.push_str(&frame.bucket_key());
+        }
+        hash_std(key.as_bytes())
+    }
+}
+
+/// An observer holding the [`SanitizerReport`] of the last run, if the run printed one
+pub trait HasSanitizerReport {
+    /// The [`SanitizerReport`] of the last run
+    fn sanitizer_report(&self) -> Option<&SanitizerReport>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AccessKind, SanitizerKind, SanitizerReport};
+
+    const ASAN_UAF: &str = "=================================================================
+==1234==ERROR: AddressSanitizer: heap-use-after-free on address 0x602000000010 at pc 0x55555555a1b2 bp 0x7ffc sp 0x7ffc
+READ of size 4 at 0x602000000010 thread T0
+    #0 0x55555555a1b1 in parse_header /src/parser.c:42:13
+    #1 0x55555555a2c3 in LLVMFuzzerTestOneInput /src/fuzz.c:10:3
+    #2 0x7ffff7829d8f  (/lib/x86_64-linux-gnu/libc.so.6+0x29d8f) (BuildId: 490fef8403240c91833978d494d39e537409b92e)
+
+0x602000000010 is located 0 bytes inside of 4-byte region [0x602000000010,0x602000000014)
+freed by thread T0 here:
+    #0 0x4a0d2d in __interceptor_free /llvm/compiler-rt/lib/asan/asan_malloc_linux.cpp:52:3
+    #1 0x55555555a100 in release_header /src/parser.c:30:5
+
+previously allocated by thread T0 here:
+    #0 0x4a0f8d in malloc /llvm/compiler-rt/lib/asan/asan_malloc_linux.cpp:69:3
+    #1 0x55555555a0aa in new_header /src/parser.c:20:12
+
+SUMMARY: AddressSanitizer: heap-use-after-free /src/parser.c:42:13 in parse_header
+Shadow bytes around the buggy address:
+";
+
+    #[test]
+    fn test_asan_report() {
+        let report = SanitizerReport::parse(ASAN_UAF).unwrap();
+        assert_eq!(report.sanitizer, SanitizerKind::Address);
+        assert_eq!(report.bug_type, "heap-use-after-free");
+        assert_eq!(report.access, Some(AccessKind::Read));
+        assert_eq!(report.access_size, Some(4));
+        assert_eq!(report.address, Some(0x6020_0000_0010));
+        assert_eq!(report.stack.len(), 3);
+        assert_eq!(report.stack[0].function.as_deref(), Some("parse_header"));
+        assert_eq!(report.stack[0].file.as_deref(), Some("/src/parser.c"));
+        assert_eq!(report.stack[0].line, Some(42));
+        assert_eq!(report.stack[0].column, Some(13));
+        assert_eq!(
+            report.stack[2].module.as_deref(),
+            Some("/lib/x86_64-linux-gnu/libc.so.6")
+        );
+        assert_eq!(report.stack[2].offset, Some(0x29d8f));
+        assert_eq!(report.free_stack.len(), 2);
+        assert_eq!(report.allocation_stack.len(), 2);
+        assert_eq!(
+            report.allocation_stack[1].function.as_deref(),
+            Some("new_header")
+        );
+        assert!(report.free_stack[0].is_sanitizer_runtime());
+
+        // Different addresses, same bucket
+        let moved = ASAN_UAF.replace("0x55555555", "0x56565656");
+        let other = SanitizerReport::parse(&moved).unwrap();
+        assert_eq!(report.bucket_hash(3), other.bucket_hash(3));
+        let deeper = ASAN_UAF.replace("LLVMFuzzerTestOneInput", "other_caller");
+        let deeper = SanitizerReport::parse(&deeper).unwrap();
+        assert_eq!(report.bucket_hash(1), deeper.bucket_hash(1));
+        assert_ne!(report.bucket_hash(2), deeper.bucket_hash(2));
+    }
+
+    #[test]
+    fn test_asan_segv() {
+        let output = "==42==ERROR: AddressSanitizer: SEGV on unknown address 0x000000000000 (pc 0x5555 bp 0x7ffc sp 0x7ffc T0)
+==42==The signal is caused by a WRITE memory access.
+==42==Hint: address points to the zero page.
+    #0 0x5555 in store /src/a.c:3:8
+    #1 0x5556 in main /src/a.c:9:3
+
+AddressSanitizer can not provide additional info.
+SUMMARY: AddressSanitizer: SEGV /src/a.c:3:8 in store
+";
+        let report = SanitizerReport::parse(output).unwrap();
+        assert_eq!(report.bug_type, "SEGV");
+        assert_eq!(report.access, Some(AccessKind::Write));
+        assert_eq!(report.address, Some(0));
+        assert_eq!(report.stack.len(), 2);
+        assert_eq!(
+            report.summary.as_deref(),
+            Some("AddressSanitizer: SEGV /src/a.c:3:8 in store")
+        );
+    }
+
+    #[test]
+    fn test_ubsan_report() {
+        let output = "/src/a.c:5:12: runtime error: signed integer overflow: 2147483647 + 1 cannot be represented in type 'int'
+SUMMARY: UndefinedBehaviorSanitizer: undefined-behavior /src/a.c:5:12
+";
+        let report = SanitizerReport::parse(output).unwrap();
+        assert_eq!(report.sanitizer, SanitizerKind::UndefinedBehavior);
+        assert_eq!(report.bug_type, "signed integer overflow");
+        assert_eq!(report.stack.len(), 1);
+        assert_eq!(report.stack[0].line, Some(5));
+
+        let output = "/src/a.c:7:3: runtime error: index 10 out of bounds for type 'int[5]'
+    #0 0x5555 in get /src/a.c:7:3
+    #1 0x5556 in main /src/a.c:12:1
+";
+        let report = SanitizerReport::parse(output).unwrap();
+        assert_eq!(report.bug_type, "index N out of bounds for type 'int[N]'");
+        assert_eq!(report.stack.len(), 2);
+        assert_eq!(report.stack[0].function.as_deref(), Some("get"));
+    }
+
+    #[test]
+    fn test_msan_tsan_reports() {
+        let output = "==7==WARNING: MemorySanitizer: use-of-uninitialized-value
+    #0 0x5555 in check /src/m.c:8:7
+    #1 0x5556 in main /src/m.c:15:3
+
+  Uninitialized value was created by a heap allocation
+    #0 0x4444 in malloc /llvm/compiler-rt/lib/msan/msan_interceptors.cpp:1007:3
+    #1 0x5557 in main /src/m.c:13:20
+
+SUMMARY: MemorySanitizer: use-of-uninitialized-value /src/m.c:8:7 in check
+";
+        let report = SanitizerReport::parse(output).unwrap();
+        assert_eq!(report.sanitizer, SanitizerKind::Memory);
+        assert_eq!(report.bug_type, "use-of-uninitialized-value");
+        assert_eq!(report.stack.len(), 2);
+        assert_eq!(report.allocation_stack.len(), 2);
+
+        let output = "==================
+WARNING: ThreadSanitizer: data race (pid=9)
+  Write of size 4 at 0x7b0400000000 by thread T1:
+    #0 worker /src/t.c:6:10 (t+0xd1234)
+    #1 <null> <null> (libtsan.so.0+0x2d1b0)
+
+  Previous read of size 4 at 0x7b0400000000 by main thread:
+    #0 main /src/t.c:14:3 (t+0xd1300)
+
+SUMMARY: ThreadSanitizer: data race /src/t.c:6:10 in worker
+";
+        let report = SanitizerReport::parse(output).unwrap();
+        assert_eq!(report.sanitizer, SanitizerKind::Thread);
+        assert_eq!(report.bug_type, "data race");
+        assert_eq!(report.access, Some(AccessKind::Write));
+        assert_eq!(report.access_size, Some(4));
+        assert_eq!(report.stack.len(), 2);
+        assert_eq!(report.stack[0].function.as_deref(), Some("worker"));
+        assert_eq!(report.stack[0].module.as_deref(), Some("t"));
+    }
+
+    #[test]
+    fn test_leak_report() {
+        let output = "==3==ERROR: LeakSanitizer: detected memory leaks
+
+Direct leak of 7 byte(s) in 1 object(s) allocated from:
+    #0 0x4a0f8d in malloc /llvm/compiler-rt/lib/lsan/lsan_interceptors.cpp:75:3
+    #1 0x5555 in dup_name /src/l.c:4:10
+
+Direct leak of 3 byte(s) in 1 object(s) allocated from:
+    #0 0x4a0f8d in malloc /llvm/compiler-rt/lib/lsan/lsan_interceptors.cpp:75:3
+    #1 0x5556 in other /src/l.c:9:10
+
+SUMMARY: AddressSanitizer: 10 byte(s) leaked in 2 allocation(s).
+";
+        let report = SanitizerReport::parse(output).unwrap();
+        assert_eq!(report.sanitizer, SanitizerKind::Leak);
+        assert_eq!(report.bug_type, "detected memory leaks");
+        assert_eq!(report.access_size, Some(7));
+        assert_eq!(report.stack.len(), 2);
+        assert_eq!(
+            report.top_frames(1).next().unwrap().function.as_deref(),
+            Some("dup_name")
+        );
+    }
+}
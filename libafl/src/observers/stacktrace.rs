@@ -38,7 +38,10 @@ use libcasr::{
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 
-use super::ObserverWithHashField;
+use super::{
+    sanitizer_report::{HasSanitizerReport, SanitizerReport, DEFAULT_BUCKET_FRAMES},
+    ObserverWithHashField,
+};
 use crate::{executors::ExitKind, observers::Observer, Error};
 
 #[cfg(not(feature = "casr"))]
@@ -252,11 +255,31 @@ pub fn get_asan_runtime_flags() -> String {
     flags.join(":")
 }
 
+/// returns the recommended `UBSan`, `MSan` and `TSan` runtime flags to write their reports to the ASAN log path,
+/// for the [`AsanBacktraceObserver`] to parse
+#[must_use]
+pub fn get_sanitizer_runtime_flags_with_log_path() -> String {
+    let mut flags = [
+        "halt_on_error=1",
+        "abort_on_error=1",
+        "print_stacktrace=1",
+        "log_path=",
+    ]
+    .join(":");
+    flags.push_str(ASAN_LOG_PATH);
+    flags
+}
+
 /// An observer looking at the backtrace of target command using ASAN output. This observer is only compatible with a `ForkserverExecutor`.
+///
+/// Besides the hash, it keeps the parsed [`SanitizerReport`] of the last crash. Crashes with a report are
+/// bucketed by the sanitizer, the bug type and the top frames of the crashing stack.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AsanBacktraceObserver {
     observer_name: Cow<'static, str>,
     hash: Option<u64>,
+    report: Option<SanitizerReport>,
+    bucket_frames: usize,
 }
 
 impl AsanBacktraceObserver {
@@ -270,6 +293,8 @@ impl AsanBacktraceObserver {
         Self {
             observer_name: observer_name.into(),
             hash: None,
+            report: None,
+            bucket_frames: DEFAULT_BUCKET_FRAMES,
         }
     }
 
@@ -284,9 +309,18 @@ impl AsanBacktraceObserver {
         Self {
             observer_name: observer_name.into(),
             hash: None,
+            report: None,
+            bucket_frames: DEFAULT_BUCKET_FRAMES,
         }
     }
 
+    /// Sets the number of frames of the crashing stack used to bucket crashes with a [`SanitizerReport`]
+    #[must_use]
+    pub fn with_bucket_frames(mut self, bucket_frames: usize) -> Self {
+        self.bucket_frames = bucket_frames;
+        self
+    }
+
     /// read ASAN output from the child stderr and parse it.
     pub fn parse_asan_output_from_childstderr(
         &mut self,
@@ -312,8 +346,15 @@ impl AsanBacktraceObserver {
     }
 
     #[cfg(not(feature = "casr"))]
-    /// parse ASAN error output emited by the target command and compute the hash
+    /// parse ASAN error output emited by the target command and compute the hash.
+    /// The hash is the [`SanitizerReport::bucket_hash`] of the report, if the output contains one.
     pub fn parse_asan_output(&mut self, output: &str) {
+        self.report = SanitizerReport::parse(output);
+        if let Some(report) = &self.report {
+            self.update_hash(report.bucket_hash(self.bucket_frames));
+            return;
+        }
+
         let mut hash = 0;
         let matcher = Regex::new("\\s*#[0-9]*\\s0x([0-9a-f]*)\\s.*").unwrap();
         matcher.captures_iter(output).for_each(|m| {
@@ -324,8 +365,15 @@ impl AsanBacktraceObserver {
     }
 
     #[cfg(feature = "casr")]
-    /// parse ASAN error output emited by the target command and compute the hash
+    /// parse ASAN error output emited by the target command and compute the hash.
+    /// The hash is the [`SanitizerReport::bucket_hash`] of the report, if the output contains one.
     pub fn parse_asan_output(&mut self, output: &str) {
+        self.report = SanitizerReport::parse(output);
+        if let Some(report) = &self.report {
+            self.update_hash(report.bucket_hash(self.bucket_frames));
+            return;
+        }
+
         let mut hash = 0;
         if let Ok(st_vec) = AsanStacktrace::extract_stacktrace(output) {
             if let Ok(mut stacktrace) = AsanStacktrace::parse_stacktrace(&st_vec) {
@@ -352,13 +400,24 @@ impl ObserverWithHashField for AsanBacktraceObserver {
     }
 }
 
+impl HasSanitizerReport for AsanBacktraceObserver {
+    fn sanitizer_report(&self) -> Option<&SanitizerReport> {
+        self.report.as_ref()
+    }
+}
+
 impl Default for AsanBacktraceObserver {
     fn default() -> Self {
         Self::new("AsanBacktraceObserver")
     }
 }
 
-impl<I, S> Observer<I, S> for AsanBacktraceObserver {}
+impl<I, S> Observer<I, S> for AsanBacktraceObserver {
+    fn pre_exec(&mut self, _state: &mut S, _input: &I) -> Result<(), Error> {
+        self.report = None;
+        Ok(())
+    }
+}
 
 impl Named for AsanBacktraceObserver {
     fn name(&self) -> &Cow<'static, str> {
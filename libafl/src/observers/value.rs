@@ -14,7 +14,7 @@ use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 use super::Observer;
 use crate::{
-    observers::{MapObserver, ObserverWithHashField},
+    observers::{MapObserver, ObserverWithHashField, ScalarObserver},
     Error,
 };
 
@@ -69,6 +69,15 @@ impl<'a, T> ValueObserver<'a, T> {
 /// This *does not* reset the value inside the observer.
 impl<I, S, T> Observer<I, S> for ValueObserver<'_, T> {}
 
+impl<T> ScalarObserver for ValueObserver<'_, T>
+where
+    T: Copy + Into<u64>,
+{
+    fn scalar(&self) -> Option<u64> {
+        Some((*self.get_ref()).into())
+    }
+}
+
 impl<T> Named for ValueObserver<'_, T> {
     fn name(&self) -> &Cow<'static, str> {
         &self.name
@@ -236,6 +236,7 @@ where
                         map_state.num_covered_map_indexes +=
                             usize::from(*history == O::Entry::default());
                         *history = O::Entry::max_value();
+                        map_state.unstable_entries.insert(idx);
                         unstable_entries.push(idx);
                     };
                 }
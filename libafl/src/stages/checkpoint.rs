@@ -0,0 +1,251 @@
+//! The [`CheckpointStage`] periodically writes the whole state to disk, so that a campaign survives a reboot.
+//!
+//! The [`libafl_bolts::staterestore::StateRestorer`] keeps the state across restarts of a client, but only in
+//! shared memory. A checkpoint holds the same state on disk: the metadata (scheduler and feedback history
+//! included), the rng, the executions and the corpora.
+//! Testcases of on-disk corpora stay in their directories, the checkpoint only refers to them.
+//!
+//! To resume, hand the checkpoint to the restarting manager: the [`crate::events::Launcher`] takes a
+//! `checkpoint_dir` and resumes each client from its [`client_checkpoint_path`], the
+//! [`crate::events::RestartingMgr`] a `checkpoint` path. The state of the last run of the client comes
+//! first, then the checkpoint, so the client only starts a new state if both are missing.
+//! A client started by a launcher finds its checkpoint path with [`launcher_checkpoint_path`]:
+//!
+//! ```rust,ignore
+//! let mut run_client = |state: Option<_>, mut mgr, _core_id| {
+//!     let mut state = state.unwrap_or_else(|| StdState::new(/* .. */).unwrap());
+//!     // A resumed state has its corpus, it is not evaluated again
+//!     if state.must_load_initial_inputs() {
+//!         state.load_initial_inputs(/* .. */)?;
+//!     }
+//!     let checkpoint = CheckpointStage::new(launcher_checkpoint_path().unwrap(), interval);
+//!     // ..
+//! };
+//! ```
+
+use core::{
+    marker::PhantomData,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use std::{
+    env,
+    fs::{self, File},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use libafl_bolts::current_time;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{stages::Stage, state::UsesState, Error};
+
+/// The first bytes of a checkpoint
+const CHECKPOINT_MAGIC: &[u8; 8] = b"LAFLCKP1";
+
+/// The environment variable with the checkpoint path of a client started by a launcher
+pub(crate) const LIBAFL_CHECKPOINT_PATH: &str = "LIBAFL_CHECKPOINT_PATH";
+
+/// Numbers the temporary files of the checkpoints written by this process
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Writes `state` to a checkpoint at `path`.
+///
+/// The checkpoint is written next to `path` first, then moved over it, so that a crash or power loss never
+/// leaves a broken checkpoint behind. The temporary file is unique, so that writers of the same checkpoint
+/// never write into each other's file.
+pub fn save_checkpoint<S, P>(state: &S, path: P) -> Result<(), Error>
+where
+    S: Serialize,
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let mut tmp_name = path.as_os_str().to_os_string();
+    tmp_name.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let tmp_path = PathBuf::from(tmp_name);
+
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(CHECKPOINT_MAGIC)?;
+    file.write_all(&postcard::to_allocvec(state)?)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&tmp_path, path)?;
+    // The rename itself is only durable once the directory is synced
+    #[cfg(unix)]
+    {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Reads the state from the checkpoint at `path`, `None` if there is no checkpoint yet
+pub fn load_checkpoint<S, P>(path: P) -> Result<Option<S>, Error>
+where
+    S: DeserializeOwned,
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let state = bytes
+        .strip_prefix(CHECKPOINT_MAGIC)
+        .ok_or_else(|| Error::illegal_argument(format!("{} is no checkpoint", path.display())))?;
+    log::info!("Resuming from the checkpoint {}", path.display());
+    Ok(Some(postcard::from_bytes(state)?))
+}
+
+/// The path of the checkpoint of the client with the given index in `dir`, where the [`crate::events::Launcher`]
+/// resumes it from. The launchers number their clients from 1, in the order they spawn them, so that clients
+/// sharing a core (with `overcommit`) get their own checkpoint.
+#[must_use]
+pub fn client_checkpoint_path<P>(dir: P, client_index: u64) -> PathBuf
+where
+    P: AsRef<Path>,
+{
+    dir.as_ref()
+        .join(format!("checkpoint_client_{client_index}"))
+}
+
+/// The checkpoint path the launcher assigned to this client, see [`client_checkpoint_path`].
+/// `None` if the client was not started by a launcher with a `checkpoint_dir`.
+#[must_use]
+pub fn launcher_checkpoint_path() -> Option<PathBuf> {
+    env::var_os(LIBAFL_CHECKPOINT_PATH).map(PathBuf::from)
+}
+
+/// The [`CheckpointStage`] writes the whole state to a checkpoint, at most once per interval.
+/// Resume from it with [`load_checkpoint`].
+///
+/// Each client needs its own checkpoint, e.g., at its [`launcher_checkpoint_path`].
+#[derive(Debug)]
+pub struct CheckpointStage<EM, Z> {
+    path: PathBuf,
+    interval: Duration,
+    /// The time of the last checkpoint, or of the start of this client
+    last_checkpoint: Duration,
+    phantom: PhantomData<(EM, Z)>,
+}
+
+impl<EM, Z> UsesState for CheckpointStage<EM, Z>
+where
+    EM: UsesState,
+{
+    type State = EM::State;
+}
+
+impl<E, EM, Z> Stage<E, EM, Z> for CheckpointStage<EM, Z>
+where
+    EM: UsesState,
+    E: UsesState<State = Self::State>,
+    Z: UsesState<State = Self::State>,
+{
+    #[inline]
+    fn perform(
+        &mut self,
+        _fuzzer: &mut Z,
+        _executor: &mut E,
+        state: &mut Self::State,
+        _manager: &mut EM,
+    ) -> Result<(), Error> {
+        let now = current_time();
+        if now.saturating_sub(self.last_checkpoint) < self.interval {
+            return Ok(());
+        }
+        save_checkpoint(state, &self.path)?;
+        log::debug!("Wrote the checkpoint {}", self.path.display());
+        self.last_checkpoint = now;
+        Ok(())
+    }
+
+    #[inline]
+    fn should_restart(&mut self, _state: &mut Self::State) -> Result<bool, Error> {
+        // Not executing the target, so restart safety is not needed
+        Ok(true)
+    }
+
+    #[inline]
+    fn clear_progress(&mut self, _state: &mut Self::State) -> Result<(), Error> {
+        // Not executing the target, so restart safety is not needed
+        Ok(())
+    }
+}
+
+impl<EM, Z> CheckpointStage<EM, Z> {
+    /// Create a new [`CheckpointStage`], writing the checkpoint at `path` at most every `interval`
+    #[must_use]
+    pub fn new<P>(path: P, interval: Duration) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        Self {
+            path: path.into(),
+            interval,
+            last_checkpoint: current_time(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// The path of the checkpoint
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use libafl_bolts::rands::StdRand;
+
+    use super::{load_checkpoint, save_checkpoint};
+    use crate::{
+        corpus::{Corpus, InMemoryCorpus, Testcase},
+        feedbacks::ConstFeedback,
+        inputs::BytesInput,
+        state::{HasCorpus, HasExecutions, StdState},
+    };
+
+    type TestState =
+        StdState<BytesInput, InMemoryCorpus<BytesInput>, StdRand, InMemoryCorpus<BytesInput>>;
+
+    #[test]
+    fn test_checkpoint() {
+        let path = env::temp_dir().join(format!("libafl_checkpoint_{}", std::process::id()));
+        let mut feedback = ConstFeedback::new(false);
+        let mut objective = ConstFeedback::new(false);
+        let mut state: TestState = StdState::new(
+            StdRand::with_seed(4),
+            InMemoryCorpus::new(),
+            InMemoryCorpus::new(),
+            &mut feedback,
+            &mut objective,
+        )
+        .unwrap();
+        state
+            .corpus_mut()
+            .add(Testcase::new(BytesInput::new(vec![1, 2, 3])))
+            .unwrap();
+        *state.executions_mut() = 1337;
+
+        assert!(load_checkpoint::<TestState, _>(&path).unwrap().is_none());
+        save_checkpoint(&state, &path).unwrap();
+        let resumed: TestState = load_checkpoint(&path).unwrap().unwrap();
+        assert_eq!(*resumed.executions(), 1337);
+        assert_eq!(resumed.corpus().count(), 1);
+        assert!(!resumed.must_load_initial_inputs());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -15,6 +15,11 @@ use core::{fmt, marker::PhantomData};
 #[cfg(feature = "std")]
 pub use afl_stats::{AflStatsStage, CalibrationTime, FuzzTime, SyncTime};
 pub use calibrate::CalibrationStage;
+#[cfg(feature = "std")]
+pub use checkpoint::{
+    client_checkpoint_path, launcher_checkpoint_path, load_checkpoint, save_checkpoint,
+    CheckpointStage,
+};
 pub use colorization::*;
 #[cfg(all(feature = "std", unix))]
 pub use concolic::ConcolicTracingStage;
@@ -47,6 +52,10 @@ use tuple_list::NonEmptyTuple;
 #[cfg(feature = "unicode")]
 pub use unicode::*;
 #[cfg(feature = "std")]
+pub use verify_crashes::{
+    CrashReproductionMetadata, CrashesToVerify, FlakyCrashPolicy, VerifyCrashesStage,
+};
+#[cfg(feature = "std")]
 pub use verify_timeouts::{TimeoutsToVerify, VerifyTimeoutsStage};
 
 use crate::{
@@ -70,6 +79,8 @@ pub mod tmin;
 #[cfg(feature = "std")]
 pub mod afl_stats;
 pub mod calibrate;
+#[cfg(feature = "std")]
+pub mod checkpoint;
 pub mod colorization;
 #[cfg(all(feature = "std", unix))]
 pub mod concolic;
@@ -89,6 +100,8 @@ pub mod tuneable;
 #[cfg(feature = "unicode")]
 pub mod unicode;
 #[cfg(feature = "std")]
+pub mod verify_crashes;
+#[cfg(feature = "std")]
 pub mod verify_timeouts;
 
 /// A stage is one step in the fuzzing process.
@@ -1,26 +1,34 @@
-//! The [`SyncFromDiskStage`] is a stage that imports inputs from disk for e.g. sync with AFL
+//! The [`SyncFromDiskStage`] is a stage that imports inputs from disk for e.g. sync with AFL,
+//! the [`SyncToDiskStage`] exports the corpus to an AFL++ queue for AFL++ to sync from us.
 
 use alloc::{
     borrow::{Cow, ToOwned},
+    string::String,
     vec::Vec,
 };
-use core::{marker::PhantomData, time::Duration};
-use std::path::{Path, PathBuf};
+use core::{fmt::Write as _, marker::PhantomData, time::Duration};
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
 
+use hashbrown::{HashMap, HashSet};
 use libafl_bolts::{current_time, fs::find_new_files_rec, shmem::ShMemProvider, Named};
 use serde::{Deserialize, Serialize};
 
 #[cfg(feature = "introspection")]
 use crate::state::HasClientPerfMonitor;
 use crate::{
-    corpus::{Corpus, CorpusId},
+    corpus::{Corpus, CorpusId, Testcase},
     events::{llmp::LlmpEventConverter, Event, EventConfig, EventFirer},
     executors::{Executor, ExitKind, HasObservers},
     fuzzer::{Evaluator, EvaluatorObservers, ExecutionProcessor},
     inputs::{Input, InputConverter, UsesInput},
-    stages::{RetryCountRestartHelper, Stage},
+    mutators::LogMutationMetadata,
+    stages::Stage,
     state::{HasCorpus, HasExecutions, HasRand, State, UsesState},
-    Error, HasMetadata, HasNamedMetadata,
+    Error, HasMetadata,
 };
 
 /// Default name for `SyncFromDiskStage`; derived from AFL++
@@ -35,12 +43,25 @@ pub const SYNC_FROM_DISK_STAGE_NAME: &str = "sync";
 pub struct SyncFromDiskMetadata {
     /// The last time the sync was done
     pub last_time: Duration,
-    /// The paths that are left to sync
+    /// The paths that are left to sync, the last one is synced next
     pub left_to_sync: Vec<PathBuf>,
 }
 
 libafl_bolts::impl_serdeany!(SyncFromDiskMetadata);
 
+/// Marks a testcase imported by a [`SyncFromDiskStage`]. The [`SyncToDiskStage`] does not export it back.
+#[cfg_attr(
+    any(not(feature = "serdeany_autoreg"), miri),
+    allow(clippy::unsafe_derive_deserialize)
+)] // for SerdeAny
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SyncedFromDiskMetadata {
+    /// The file the testcase was imported from
+    pub path: PathBuf,
+}
+
+libafl_bolts::impl_serdeany!(SyncedFromDiskMetadata);
+
 impl SyncFromDiskMetadata {
     /// Create a new [`struct@SyncFromDiskMetadata`]
     #[must_use]
@@ -53,6 +74,10 @@ impl SyncFromDiskMetadata {
 }
 
 /// A stage that loads testcases from disk to sync with other fuzzers such as AFL++
+///
+/// Hidden files and directories are skipped, such as the files other fuzzers are still writing,
+/// and files removed before they are loaded are ignored.
+/// The imported testcases are marked with [`SyncedFromDiskMetadata`].
 #[derive(Debug)]
 pub struct SyncFromDiskStage<CB, E, EM, Z> {
     name: Cow<'static, str>,
@@ -81,7 +106,7 @@ where
     E: UsesState<State = Self::State>,
     EM: UsesState<State = Self::State>,
     Z: Evaluator<E, EM>,
-    Self::State: HasCorpus + HasRand + HasMetadata + HasNamedMetadata,
+    Self::State: HasCorpus + HasRand + HasMetadata,
 {
     #[inline]
     fn perform(
@@ -91,25 +116,44 @@ where
         state: &mut Self::State,
         manager: &mut EM,
     ) -> Result<(), Error> {
-        let last = state
+        let (last, left_to_sync) = state
             .metadata_map()
             .get::<SyncFromDiskMetadata>()
-            .map(|m| m.last_time);
+            .map_or((None, Vec::new()), |m| {
+                (Some(m.last_time), m.left_to_sync.clone())
+            });
 
+        // Files left from the last sync, after an imported input crashed the target, do not wait for the interval
         if let Some(last) = last {
-            if current_time().saturating_sub(last) < self.interval {
+            if left_to_sync.is_empty() && current_time().saturating_sub(last) < self.interval {
                 return Ok(());
             }
         }
 
         let new_max_time = current_time();
 
-        let mut new_files = vec![];
+        let mut known_files: HashSet<PathBuf> = left_to_sync.iter().cloned().collect();
+        let mut found_files = Vec::new();
         for dir in &self.sync_dirs {
             log::debug!("Syncing from dir: {:?}", dir);
-            let new_dir_files = find_new_files_rec(dir, &last)?;
-            new_files.extend(new_dir_files);
+            let new_dir_files = match find_new_files_rec(dir, &last) {
+                Ok(new_dir_files) => new_dir_files,
+                Err(e) => {
+                    // E.g. the other fuzzer did not create it yet
+                    log::warn!("Could not sync from dir {dir:?}: {e}");
+                    continue;
+                }
+            };
+            for file in new_dir_files {
+                if !is_hidden(dir, &file) && known_files.insert(file.clone()) {
+                    found_files.push(file);
+                }
+            }
         }
+        // The files are synced from the back: the ones left from the last sync first, then the new ones in
+        // the order they were found
+        let mut new_files: Vec<PathBuf> = found_files.into_iter().rev().collect();
+        new_files.extend(left_to_sync);
 
         let sync_from_disk_metadata = state
             .metadata_or_insert_with(|| SyncFromDiskMetadata::new(new_max_time, new_files.clone()));
@@ -121,20 +165,34 @@ where
         // Iterate over the paths of files left to sync.
         // By keeping track of these files, we ensure that no file is missed during synchronization,
         // even in the event of a target restart.
-        let to_sync = sync_from_disk_metadata.left_to_sync.clone();
-        log::debug!("Number of files to sync: {:?}", to_sync.len());
-        for path in to_sync {
-            let input = (self.load_callback)(fuzzer, state, &path)?;
-            // Removing each path from the `left_to_sync` Vec before evaluating
-            // prevents duplicate processing and ensures that each file is evaluated only once. This approach helps
-            // avoid potential infinite loops that may occur if a file is an objective.
-            state
-                .metadata_mut::<SyncFromDiskMetadata>()
-                .unwrap()
-                .left_to_sync
-                .retain(|p| p != &path);
+        log::debug!(
+            "Number of files to sync: {:?}",
+            sync_from_disk_metadata.left_to_sync.len()
+        );
+        // Removing each path from the `left_to_sync` Vec before evaluating
+        // prevents duplicate processing and ensures that each file is evaluated only once. This approach helps
+        // avoid potential infinite loops that may occur if a file is an objective.
+        while let Some(path) = state
+            .metadata_mut::<SyncFromDiskMetadata>()?
+            .left_to_sync
+            .pop()
+        {
+            let input = match (self.load_callback)(fuzzer, state, &path) {
+                Ok(input) => input,
+                Err(Error::OsError(e, _, _)) if e.kind() == ErrorKind::NotFound => {
+                    log::debug!("Skipping {path:?}, it was removed before syncing");
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
             log::debug!("Syncing and evaluating {:?}", path);
-            fuzzer.evaluate_input(state, executor, manager, input)?;
+            if let (_, Some(id)) = fuzzer.evaluate_input(state, executor, manager, input)? {
+                state
+                    .corpus()
+                    .get(id)?
+                    .borrow_mut()
+                    .add_metadata(SyncedFromDiskMetadata { path });
+            }
         }
 
         #[cfg(feature = "introspection")]
@@ -144,18 +202,28 @@ where
     }
 
     #[inline]
-    fn should_restart(&mut self, state: &mut Self::State) -> Result<bool, Error> {
-        // TODO: Needs proper crash handling for when an imported testcase crashes
-        // For now, Make sure we don't get stuck crashing on this testcase
-        RetryCountRestartHelper::no_retry(state, &self.name)
+    fn should_restart(&mut self, _state: &mut Self::State) -> Result<bool, Error> {
+        // An imported input crashing the target is an objective like any other: the crash handler of the
+        // executor stores it. It was removed from the files left to sync before running it, so the stage
+        // resumes with the next file.
+        Ok(true)
     }
 
     #[inline]
-    fn clear_progress(&mut self, state: &mut Self::State) -> Result<(), Error> {
-        RetryCountRestartHelper::clear_progress(state, &self.name)
+    fn clear_progress(&mut self, _state: &mut Self::State) -> Result<(), Error> {
+        // The files left to sync are the progress
+        Ok(())
     }
 }
 
+/// Whether the file, or one of its parent directories below `dir`, is hidden
+fn is_hidden(dir: &Path, file: &Path) -> bool {
+    file.strip_prefix(dir)
+        .unwrap_or(file)
+        .components()
+        .any(|c| c.as_os_str().to_string_lossy().starts_with('.'))
+}
+
 impl<CB, E, EM, Z> SyncFromDiskStage<CB, E, EM, Z> {
     /// Creates a new [`SyncFromDiskStage`]
     #[must_use]
@@ -200,6 +268,227 @@ where
     }
 }
 
+/// The directory of an AFL++ instance with its interesting testcases, the other instances sync from it
+pub const AFL_QUEUE_DIR: &str = "queue";
+
+/// The longest original filename or mutation kept in the name of an exported testcase
+const MAX_NAME_PART_LEN: usize = 64;
+
+/// Metadata used to store the testcases already exported by the [`SyncToDiskStage`]
+#[cfg_attr(
+    any(not(feature = "serdeany_autoreg"), miri),
+    allow(clippy::unsafe_derive_deserialize)
+)] // for SerdeAny
+#[derive(Default, Serialize, Deserialize, Debug)]
+pub struct SyncToDiskMetadata {
+    /// The last exported testcase
+    pub last_id: Option<CorpusId>,
+    /// The ids in the queue of the exported testcases, to name them as source of their children
+    pub queue_ids: HashMap<CorpusId, usize>,
+}
+
+libafl_bolts::impl_serdeany!(SyncToDiskMetadata);
+
+/// A stage that writes the new testcases of the corpus to the `queue/` directory of `out_dir`, named like
+/// AFL++ does: `id:000042,src:000007,op:...`.
+///
+/// AFL++ syncs from every `<sync_dir>/<instance>/queue`: with `out_dir` at `<sync_dir>/libafl`, the AFL++
+/// instances started with `-o <sync_dir>` import our testcases. In turn, a [`SyncFromDiskStage`] on their
+/// `<sync_dir>/<instance>/queue` directories imports their testcases and, on their
+/// `<sync_dir>/<instance>/crashes` directories, their crashes, which become our objectives.
+/// honggfuzz imports any directory, e.g., with `--input <out_dir>/queue`.
+///
+/// The testcases imported by a [`SyncFromDiskStage`] are not exported back.
+#[derive(Debug)]
+pub struct SyncToDiskStage<CB, EM, Z> {
+    queue_dir: PathBuf,
+    /// The id in the queue of the next exported testcase
+    next_queue_id: usize,
+    to_bytes: CB,
+    phantom: PhantomData<(EM, Z)>,
+}
+
+impl<CB, EM, Z> UsesState for SyncToDiskStage<CB, EM, Z>
+where
+    EM: UsesState,
+{
+    type State = EM::State;
+}
+
+impl<CB, E, EM, Z> Stage<E, EM, Z> for SyncToDiskStage<CB, EM, Z>
+where
+    CB: FnMut(&Self::Input, &Self::State) -> Vec<u8>,
+    EM: UsesState,
+    E: UsesState<State = Self::State>,
+    Z: UsesState<State = Self::State>,
+    EM::State: HasCorpus + HasMetadata,
+    <<EM as UsesState>::State as HasCorpus>::Corpus: Corpus<Input = Self::Input>, //delete me
+{
+    #[inline]
+    fn perform(
+        &mut self,
+        _fuzzer: &mut Z,
+        _executor: &mut E,
+        state: &mut Self::State,
+        _manager: &mut EM,
+    ) -> Result<(), Error> {
+        let mut meta = state
+            .metadata_map_mut()
+            .remove::<SyncToDiskMetadata>()
+            .map_or_else(SyncToDiskMetadata::default, |meta| *meta);
+        // Keep track of the testcases written so far, even if writing the next one fails
+        let exported = self.export_new_testcases(state, &mut meta);
+        state.add_metadata(meta);
+        exported
+    }
+
+    #[inline]
+    fn should_restart(&mut self, _state: &mut Self::State) -> Result<bool, Error> {
+        // Not executing the target, so restart safety is not needed
+        Ok(true)
+    }
+
+    #[inline]
+    fn clear_progress(&mut self, _state: &mut Self::State) -> Result<(), Error> {
+        // Not executing the target, so restart safety is not needed
+        Ok(())
+    }
+}
+
+impl<CB, EM, Z> SyncToDiskStage<CB, EM, Z>
+where
+    EM: UsesState,
+    <EM as UsesState>::State: HasCorpus + HasMetadata,
+    <<EM as UsesState>::State as HasCorpus>::Corpus: Corpus<Input = EM::Input>,
+{
+    /// Create a new [`SyncToDiskStage`], writing the testcases, turned to bytes by `to_bytes`, to
+    /// `out_dir/queue`. The ids continue after the highest one already in the queue, e.g., after a restart.
+    pub fn new<P>(to_bytes: CB, out_dir: P) -> Result<Self, Error>
+    where
+        P: Into<PathBuf>,
+    {
+        let queue_dir = out_dir.into().join(AFL_QUEUE_DIR);
+        if let Err(e) = fs::create_dir_all(&queue_dir) {
+            return Err(Error::os_error(
+                e,
+                format!("Error creating directory {queue_dir:?}"),
+            ));
+        }
+        let next_queue_id = next_afl_queue_id(&queue_dir)?;
+        Ok(Self {
+            queue_dir,
+            next_queue_id,
+            to_bytes,
+            phantom: PhantomData,
+        })
+    }
+
+    /// The queue directory the testcases go to
+    #[must_use]
+    pub fn queue_dir(&self) -> &Path {
+        &self.queue_dir
+    }
+
+    fn export_new_testcases(
+        &mut self,
+        state: &<EM as UsesState>::State,
+        meta: &mut SyncToDiskMetadata,
+    ) -> Result<(), Error>
+    where
+        CB: FnMut(&EM::Input, &<EM as UsesState>::State) -> Vec<u8>,
+    {
+        let mut cur_id = match meta.last_id {
+            None => state.corpus().first(),
+            Some(last_id) if state.corpus().get(last_id).is_ok() => state.corpus().next(last_id),
+            // The last exported testcase was removed from the corpus since, ids only grow
+            Some(last_id) => state.corpus().ids().find(|id| *id > last_id),
+        };
+
+        while let Some(id) = cur_id {
+            let mut testcase = state.corpus().get(id)?.borrow_mut();
+            if testcase.has_metadata::<SyncedFromDiskMetadata>() {
+                // The other fuzzers have it already
+                drop(testcase);
+                meta.last_id = Some(id);
+                cur_id = state.corpus().next(id);
+                continue;
+            }
+            state.corpus().load_input_into(&mut testcase)?;
+            let bytes = (self.to_bytes)(testcase.input().as_ref().unwrap(), state);
+
+            let queue_id = self.next_queue_id;
+            let name = afl_queue_name(queue_id, &testcase, &meta.queue_ids);
+            // AFL++ skips hidden files, so it never reads a testcase half written
+            let tmp_path = self.queue_dir.join(format!(".{name}"));
+            fs::write(&tmp_path, bytes)?;
+            fs::rename(&tmp_path, self.queue_dir.join(&name))?;
+            drop(testcase);
+
+            self.next_queue_id += 1;
+            meta.queue_ids.insert(id, queue_id);
+            meta.last_id = Some(id);
+            cur_id = state.corpus().next(id);
+        }
+        Ok(())
+    }
+}
+
+/// The id after the highest one of the testcases in the AFL++ `queue_dir`, 0 if it is empty
+fn next_afl_queue_id(queue_dir: &Path) -> Result<usize, Error> {
+    let mut next_queue_id = 0;
+    for entry in fs::read_dir(queue_dir)? {
+        let name = entry?.file_name();
+        let queue_id = name
+            .to_str()
+            .and_then(|name| name.strip_prefix("id:"))
+            .and_then(|name| name.split(',').next())
+            .and_then(|queue_id| queue_id.parse::<usize>().ok());
+        if let Some(queue_id) = queue_id {
+            next_queue_id = next_queue_id.max(queue_id + 1);
+        }
+    }
+    Ok(next_queue_id)
+}
+
+/// Replaces the characters AFL++ or the file system could trip over
+fn afl_name_part(part: &str) -> String {
+    part.chars()
+        .take(MAX_NAME_PART_LEN)
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// The AFL++ name of a testcase in the queue: its id, then its parent in the queue, or its original
+/// filename for a seed, and the mutations leading to it, if logged by a
+/// [`crate::mutators::LoggerScheduledMutator`]
+fn afl_queue_name<I>(
+    queue_id: usize,
+    testcase: &Testcase<I>,
+    queue_ids: &HashMap<CorpusId, usize>,
+) -> String {
+    let mut name = format!("id:{queue_id:06}");
+    if let Some(src) = testcase
+        .parent_id()
+        .and_then(|parent_id| queue_ids.get(&parent_id))
+    {
+        write!(name, ",src:{src:06}").unwrap();
+    } else if let Some(filename) = testcase.filename() {
+        write!(name, ",orig:{}", afl_name_part(filename)).unwrap();
+    }
+    if let Ok(log) = testcase.metadata::<LogMutationMetadata>() {
+        if let Some(op) = log.first() {
+            write!(name, ",op:{},rep:{}", afl_name_part(op), log.len()).unwrap();
+        }
+    }
+    name
+}
+
 /// Metadata used to store information about the last sent testcase with `SyncFromBrokerStage`
 #[cfg_attr(
     any(not(feature = "serdeany_autoreg"), miri),
@@ -290,6 +579,7 @@ where
                         client_config: EventConfig::AlwaysUnique,
                         time: current_time(),
                         forward_id: None,
+                        parent_hash: None,
                         #[cfg(all(unix, feature = "std", feature = "multi_machine"))]
                         node_id: None,
                     },
@@ -345,3 +635,139 @@ where
         Self { client }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use alloc::{borrow::Cow, string::String, vec::Vec};
+    use std::{env, fs, path::Path};
+
+    use hashbrown::HashMap;
+    use libafl_bolts::rands::StdRand;
+
+    use super::{afl_queue_name, is_hidden, SyncToDiskStage, SyncedFromDiskMetadata};
+    use crate::{
+        corpus::{Corpus, CorpusId, InMemoryCorpus, Testcase},
+        events::NopEventManager,
+        feedbacks::ConstFeedback,
+        fuzzer::NopFuzzer,
+        inputs::{BytesInput, HasMutatorBytes},
+        mutators::LogMutationMetadata,
+        stages::Stage,
+        state::{HasCorpus, StdState},
+        HasMetadata,
+    };
+
+    #[test]
+    fn test_afl_queue_name() {
+        let mut queue_ids = HashMap::new();
+        let seed = Testcase::with_filename(BytesInput::new(vec![0]), "seed #1".into());
+        assert_eq!(
+            afl_queue_name(0, &seed, &queue_ids),
+            "id:000000,orig:seed__1"
+        );
+
+        queue_ids.insert(CorpusId(3), 7);
+        let mut child = Testcase::with_parent_id(BytesInput::new(vec![1]), CorpusId(3));
+        child.add_metadata(LogMutationMetadata::new(vec![
+            Cow::Borrowed("BitFlipMutator"),
+            Cow::Borrowed("ByteAddMutator"),
+        ]));
+        assert_eq!(
+            afl_queue_name(8, &child, &queue_ids),
+            "id:000008,src:000007,op:BitFlipMutator,rep:2"
+        );
+
+        let orphan = Testcase::with_parent_id(BytesInput::new(vec![2]), CorpusId(4));
+        assert_eq!(afl_queue_name(9, &orphan, &queue_ids), "id:000009");
+    }
+
+    #[test]
+    fn test_is_hidden() {
+        let dir = Path::new("/sync/.afl");
+        assert!(!is_hidden(dir, &dir.join("queue/id:000000")));
+        assert!(is_hidden(dir, &dir.join("queue/.id:000000")));
+        assert!(is_hidden(dir, &dir.join(".state/auto_extras/auto_000000")));
+    }
+
+    #[test]
+    fn test_sync_to_disk() {
+        let out_dir = env::temp_dir().join(format!("libafl_sync_to_disk_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&out_dir);
+
+        let mut feedback = ConstFeedback::new(false);
+        let mut objective = ConstFeedback::new(false);
+        let mut state = StdState::new(
+            StdRand::with_seed(0),
+            InMemoryCorpus::<BytesInput>::new(),
+            InMemoryCorpus::new(),
+            &mut feedback,
+            &mut objective,
+        )
+        .unwrap();
+        let mut fuzzer = NopFuzzer::new();
+        let mut executor = NopFuzzer::new();
+        let mut mgr = NopEventManager::new();
+        let to_bytes = |input: &BytesInput, _state: &_| input.bytes().to_vec();
+
+        let queue = |stage: &SyncToDiskStage<_, _, _>| {
+            let mut names: Vec<String> = fs::read_dir(stage.queue_dir())
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .collect();
+            names.sort();
+            names
+        };
+
+        let seed = state
+            .corpus_mut()
+            .add(Testcase::with_filename(
+                BytesInput::new(vec![0]),
+                "seed".into(),
+            ))
+            .unwrap();
+        let mut imported = Testcase::new(BytesInput::new(vec![1]));
+        imported.add_metadata(SyncedFromDiskMetadata {
+            path: "afl/queue/id:000000".into(),
+        });
+        state.corpus_mut().add(imported).unwrap();
+        let child = state
+            .corpus_mut()
+            .add(Testcase::with_parent_id(BytesInput::new(vec![2]), seed))
+            .unwrap();
+
+        let mut stage = SyncToDiskStage::new(to_bytes, &out_dir).unwrap();
+        stage
+            .perform(&mut fuzzer, &mut executor, &mut state, &mut mgr)
+            .unwrap();
+        // The imported testcase is not exported back
+        assert_eq!(
+            queue(&stage),
+            ["id:000000,orig:seed", "id:000001,src:000000"]
+        );
+        assert_eq!(
+            fs::read(stage.queue_dir().join("id:000001,src:000000")).unwrap(),
+            [2]
+        );
+
+        // The last exported testcase was removed, and the stage restarted
+        state.corpus_mut().remove(child).unwrap();
+        state
+            .corpus_mut()
+            .add(Testcase::with_parent_id(BytesInput::new(vec![3]), seed))
+            .unwrap();
+        let mut stage = SyncToDiskStage::new(to_bytes, &out_dir).unwrap();
+        stage
+            .perform(&mut fuzzer, &mut executor, &mut state, &mut mgr)
+            .unwrap();
+        assert_eq!(
+            queue(&stage),
+            [
+                "id:000000,orig:seed",
+                "id:000001,src:000000",
+                "id:000002,src:000000"
+            ]
+        );
+
+        fs::remove_dir_all(&out_dir).unwrap();
+    }
+}
@@ -0,0 +1,457 @@
+//! Stage that re-runs captured crashes multiple times, to only keep the ones that reproduce.
+//!
+//! Note: To capture the crashes, wrap the objective in a `CaptureCrashFeedback`.
+//! Note: Will NOT work with in process executors, since a crash during verification will restart the client.
+//! Use it with executors that run the target in a fresh process, such as the forkserver, fork, or command executors,
+//! or hand such an executor to [`VerifyCrashesStage::with_executor`] for the re-runs.
+use alloc::borrow::Cow;
+use core::fmt::Debug;
+use std::{cell::RefCell, collections::VecDeque, fs, marker::PhantomData, path::PathBuf, rc::Rc};
+
+use libafl_bolts::{Error, Named};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+#[cfg(not(miri))]
+use crate::inputs::BytesInput;
+use crate::{
+    corpus::Corpus,
+    events::EventFirer,
+    executors::{Executor, ExitKind, HasObservers},
+    inputs::{Input, UsesInput},
+    observers::ObserversTuple,
+    stages::Stage,
+    state::{HasCorpus, HasSolutions, State, UsesState},
+    ExecuteInputResult, ExecutesInput, ExecutionProcessor, HasMetadata,
+};
+
+/// Crashes that [`VerifyCrashesStage`] will re-run, together with the [`ExitKind`] they were found with
+#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+#[serde(bound = "I: for<'a> Deserialize<'a> + Serialize")]
+pub struct CrashesToVerify<I> {
+    inputs: VecDeque<(I, ExitKind)>,
+    /// The result of the verification currently in progress, attached to the solution once it is stored
+    current: Option<CrashReproductionMetadata>,
+}
+
+libafl_bolts::impl_serdeany!(
+    CrashesToVerify<I: Debug + 'static + Serialize + DeserializeOwned + Clone>,
+    <BytesInput>
+);
+
+impl<I> CrashesToVerify<I> {
+    /// Create a new `CrashesToVerify`
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            inputs: VecDeque::new(),
+            current: None,
+        }
+    }
+
+    /// Add a crash to the queue
+    pub fn push(&mut self, input: I, exit_kind: ExitKind) {
+        self.inputs.push_back((input, exit_kind));
+    }
+
+    /// Pop a crash from the queue
+    pub fn pop(&mut self) -> Option<(I, ExitKind)> {
+        self.inputs.pop_front()
+    }
+
+    /// Count the crashes in the queue
+    #[must_use]
+    pub fn count(&self) -> usize {
+        self.inputs.len()
+    }
+
+    /// The result of the verification currently in progress, if any
+    #[must_use]
+    pub fn current(&self) -> Option<&CrashReproductionMetadata> {
+        self.current.as_ref()
+    }
+}
+
+/// How often a solution reproduced when it was re-run by the [`VerifyCrashesStage`]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(clippy::unsafe_derive_deserialize)] // for SerdeAny
+pub struct CrashReproductionMetadata {
+    /// How often the input was re-run
+    pub runs: usize,
+    /// How often the re-run ended with the same [`ExitKind`] as the original run
+    pub reproductions: usize,
+}
+
+libafl_bolts::impl_serdeany!(CrashReproductionMetadata);
+
+impl CrashReproductionMetadata {
+    /// The reproduction rate, between `0.0` and `1.0`
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn rate(&self) -> f64 {
+        if self.runs == 0 {
+            0.0
+        } else {
+            self.reproductions as f64 / self.runs as f64
+        }
+    }
+}
+
+/// What to do with crashes that do not reproduce often enough
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FlakyCrashPolicy {
+    /// Forget about them
+    Drop,
+    /// Write them to the given directory, outside of the solutions
+    Quarantine(PathBuf),
+    /// Hand them to the objective anyway, the [`CrashReproductionMetadata`] of the solutions tells them apart
+    Keep,
+}
+
+/// Stage that re-runs inputs deemed as crashes multiple times, to weed out flaky crashes.
+///
+/// The re-runs only compare the [`ExitKind`] with the one of the original run. Crashes reproducing at least
+/// `min_reproductions` out of `runs` times are then evaluated once more by the (inner) objective and stored as
+/// solutions, with a [`CrashReproductionMetadata`] attached. The others are handled according to the [`FlakyCrashPolicy`].
+/// Note: Will NOT work with in process executors due to the potential for restarts/crashes when
+/// running inputs.
+#[derive(Debug)]
+pub struct VerifyCrashesStage<E, S, V = E> {
+    name: Cow<'static, str>,
+    capture_crashes: Rc<RefCell<bool>>,
+    runs: usize,
+    min_reproductions: usize,
+    policy: FlakyCrashPolicy,
+    executor: Option<V>,
+    phantom: PhantomData<(E, S)>,
+}
+
+impl<E, S> VerifyCrashesStage<E, S> {
+    /// Create a `VerifyCrashesStage` re-running each crash `runs` times,
+    /// keeping it if it reproduced at least `min_reproductions` times.
+    pub fn new(capture_crashes: Rc<RefCell<bool>>, runs: usize, min_reproductions: usize) -> Self {
+        Self {
+            name: Cow::Borrowed("VerifyCrashesStage"),
+            capture_crashes,
+            runs,
+            min_reproductions: min_reproductions.min(runs),
+            policy: FlakyCrashPolicy::Keep,
+            executor: None,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Re-run the crashes with the given executor instead of the fuzzing one,
+    /// for example a fork or command executor, to run each of them in a fresh process.
+    ///
+    /// Only the [`ExitKind`] of these runs is looked at, their observers are not run.
+    pub fn with_executor<V>(self, executor: V) -> VerifyCrashesStage<E, S, V> {
+        VerifyCrashesStage {
+            name: self.name,
+            capture_crashes: self.capture_crashes,
+            runs: self.runs,
+            min_reproductions: self.min_reproductions,
+            policy: self.policy,
+            executor: Some(executor),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<E, S, V> VerifyCrashesStage<E, S, V> {
+    /// Set what happens to crashes that do not reproduce often enough, defaults to [`FlakyCrashPolicy::Keep`]
+    #[must_use]
+    pub fn with_policy(mut self, policy: FlakyCrashPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+}
+
+impl<E, S, V> UsesState for VerifyCrashesStage<E, S, V>
+where
+    S: State,
+{
+    type State = S;
+}
+
+impl<E, S, V> Named for VerifyCrashesStage<E, S, V> {
+    fn name(&self) -> &Cow<'static, str> {
+        &self.name
+    }
+}
+
+impl<E, S, V> VerifyCrashesStage<E, S, V>
+where
+    S: UsesInput,
+    S::Input: Input,
+{
+    /// Handles a crash that did not reproduce often enough, returns if it should be stored as a solution anyway
+    fn handle_flaky(
+        &self,
+        input: &S::Input,
+        reproduction: CrashReproductionMetadata,
+    ) -> Result<bool, Error> {
+        log::info!(
+            "Crash reproduced only {} out of {} times, handling it as flaky ({:?})",
+            reproduction.reproductions,
+            reproduction.runs,
+            self.policy
+        );
+        match &self.policy {
+            FlakyCrashPolicy::Drop => Ok(false),
+            FlakyCrashPolicy::Quarantine(dir) => {
+                fs::create_dir_all(dir)?;
+                input.to_file(dir.join(input.generate_name(None)))?;
+                Ok(false)
+            }
+            FlakyCrashPolicy::Keep => Ok(true),
+        }
+    }
+}
+
+impl<E, EM, Z, S, V> Stage<E, EM, Z> for VerifyCrashesStage<E, S, V>
+where
+    E::Observers:
+        ObserversTuple<<Self as UsesInput>::Input, <Self as UsesState>::State> + Serialize,
+    E: Executor<EM, Z, State = S> + HasObservers,
+    V: Executor<EM, Z, State = S>,
+    EM: EventFirer<State = S>,
+    Z: UsesState<State = S> + ExecutesInput<E, EM> + ExecutionProcessor<EM, E::Observers>,
+    S: HasCorpus + HasSolutions + State + HasMetadata,
+    Self::Input: Input + Debug + Serialize + DeserializeOwned + Default + 'static + Clone,
+    S::Solutions: Corpus<Input = Self::Input>,
+{
+    fn perform(
+        &mut self,
+        fuzzer: &mut Z,
+        executor: &mut E,
+        state: &mut Self::State,
+        manager: &mut EM,
+    ) -> Result<(), Error> {
+        let mut crashes = state
+            .metadata_or_insert_with(CrashesToVerify::<Self::Input>::new)
+            .clone();
+        if crashes.count() == 0 {
+            return Ok(());
+        }
+        *self.capture_crashes.borrow_mut() = false;
+        while let Some((input, exit_kind)) = crashes.pop() {
+            let mut reproduction = CrashReproductionMetadata {
+                runs: self.runs,
+                reproductions: 0,
+            };
+            for _ in 0..self.runs {
+                let run_exit_kind = match &mut self.executor {
+                    Some(verifier) => verifier.run_target(fuzzer, state, manager, &input)?,
+                    None => fuzzer.execute_input(state, executor, manager, &input)?,
+                };
+                if run_exit_kind == exit_kind {
+                    reproduction.reproductions += 1;
+                }
+            }
+
+            if reproduction.reproductions < self.min_reproductions
+                && !self.handle_flaky(&input, reproduction)?
+            {
+                continue;
+            }
+
+            // Hand the crash to the objective, so that it can deduplicate it and attach its metadata.
+            // Prefer the observers of a reproducing run, but judge it with the original exit kind either way:
+            // a verified crash stays verified.
+            state
+                .metadata_mut::<CrashesToVerify<Self::Input>>()?
+                .current = Some(reproduction);
+            for _ in 0..self.runs.max(1) {
+                if fuzzer.execute_input(state, executor, manager, &input)? == exit_kind {
+                    break;
+                }
+            }
+            let observers = executor.observers();
+            let (result, _) =
+                fuzzer.evaluate_execution(state, manager, input, &*observers, &exit_kind, true)?;
+            state
+                .metadata_mut::<CrashesToVerify<Self::Input>>()?
+                .current = None;
+            if result != ExecuteInputResult::Solution {
+                log::debug!("Crash was not deemed a new solution by the objective");
+            }
+        }
+        *self.capture_crashes.borrow_mut() = true;
+        let res = state.metadata_mut::<CrashesToVerify<Self::Input>>()?;
+        *res = CrashesToVerify::<Self::Input>::new();
+        Ok(())
+    }
+
+    fn should_restart(&mut self, _state: &mut Self::State) -> Result<bool, Error> {
+        Ok(true)
+    }
+
+    fn clear_progress(&mut self, _state: &mut Self::State) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{borrow::Cow, vec::Vec};
+    use core::cell::Cell;
+    use std::{cell::RefCell, rc::Rc};
+
+    use libafl_bolts::{rands::StdRand, tuples::tuple_list, Error, Named};
+
+    use super::{CrashReproductionMetadata, CrashesToVerify, FlakyCrashPolicy, VerifyCrashesStage};
+    use crate::{
+        corpus::{Corpus, InMemoryCorpus},
+        events::NopEventManager,
+        executors::{ExitKind, InProcessExecutor},
+        feedbacks::{CaptureCrashFeedback, ConstFeedback, Feedback, StateInitializer},
+        inputs::BytesInput,
+        schedulers::QueueScheduler,
+        stages::Stage,
+        state::{HasSolutions, StdState},
+        Evaluator, HasMetadata, StdFuzzer,
+    };
+
+    /// An objective that only reports the first crash it sees, like a deduplicating objective would
+    #[derive(Debug, Default)]
+    struct FirstCrashFeedback {
+        seen: bool,
+    }
+
+    impl Named for FirstCrashFeedback {
+        fn name(&self) -> &Cow<'static, str> {
+            static NAME: Cow<'static, str> = Cow::Borrowed("FirstCrashFeedback");
+            &NAME
+        }
+    }
+
+    impl<S> StateInitializer<S> for FirstCrashFeedback {}
+
+    impl<EM, I, OT, S> Feedback<EM, I, OT, S> for FirstCrashFeedback {
+        fn is_interesting(
+            &mut self,
+            _state: &mut S,
+            _manager: &mut EM,
+            _input: &I,
+            _observers: &OT,
+            exit_kind: &ExitKind,
+        ) -> Result<bool, Error> {
+            let res = *exit_kind == ExitKind::Crash && !self.seen;
+            self.seen |= res;
+            Ok(res)
+        }
+
+        #[cfg(feature = "track_hit_feedbacks")]
+        fn last_result(&self) -> Result<bool, Error> {
+            Ok(self.seen)
+        }
+    }
+
+    /// Runs a harness crashing on the executions `crashes_at` returns `true` for (counting from 1)
+    /// through capture and verification, returning the solutions found
+    fn verify(
+        crashes_at: fn(usize) -> bool,
+        policy: FlakyCrashPolicy,
+    ) -> Vec<Option<CrashReproductionMetadata>> {
+        let capture = Rc::new(RefCell::new(true));
+        let mut feedback = ConstFeedback::new(false);
+        let mut objective =
+            CaptureCrashFeedback::new(FirstCrashFeedback::default(), capture.clone());
+        let mut state = StdState::new(
+            StdRand::with_seed(0),
+            InMemoryCorpus::<BytesInput>::new(),
+            InMemoryCorpus::new(),
+            &mut feedback,
+            &mut objective,
+        )
+        .unwrap();
+        let mut fuzzer = StdFuzzer::new(QueueScheduler::new(), feedback, objective);
+        let mut mgr = NopEventManager::new();
+
+        let executions = Cell::new(0);
+        let mut harness = |_input: &BytesInput| {
+            executions.set(executions.get() + 1);
+            if crashes_at(executions.get()) {
+                ExitKind::Crash
+            } else {
+                ExitKind::Ok
+            }
+        };
+        let mut executor = InProcessExecutor::new(
+            &mut harness,
+            tuple_list!(),
+            &mut fuzzer,
+            &mut state,
+            &mut mgr,
+        )
+        .unwrap();
+
+        // Find the crash, it is only captured
+        fuzzer
+            .evaluate_input(
+                &mut state,
+                &mut executor,
+                &mut mgr,
+                BytesInput::new(vec![0]),
+            )
+            .unwrap();
+        assert_eq!(state.solutions().count(), 0);
+        assert_eq!(
+            state
+                .metadata::<CrashesToVerify<BytesInput>>()
+                .unwrap()
+                .count(),
+            1
+        );
+
+        let mut stage = VerifyCrashesStage::new(capture.clone(), 4, 4).with_policy(policy);
+        stage
+            .perform(&mut fuzzer, &mut executor, &mut state, &mut mgr)
+            .unwrap();
+        assert!(*capture.borrow());
+
+        state
+            .solutions()
+            .ids()
+            .map(|id| {
+                state
+                    .solutions()
+                    .get(id)
+                    .unwrap()
+                    .borrow()
+                    .metadata::<CrashReproductionMetadata>()
+                    .ok()
+                    .copied()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_verify_crashes() {
+        let reproducing = CrashReproductionMetadata {
+            runs: 4,
+            reproductions: 4,
+        };
+        assert_eq!(
+            verify(|_| true, FlakyCrashPolicy::Drop),
+            vec![Some(reproducing)],
+            "a reproducing crash must reach the deduplicating objective unseen"
+        );
+
+        let flaky = CrashReproductionMetadata {
+            runs: 4,
+            reproductions: 2,
+        };
+        assert_eq!(
+            verify(|n| n % 2 == 1, FlakyCrashPolicy::Keep),
+            vec![Some(flaky)]
+        );
+        assert!(verify(|n| n % 2 == 1, FlakyCrashPolicy::Drop).is_empty());
+
+        // The original run and the 4 re-runs crash, the crash stays verified even if it stops reproducing afterwards
+        assert_eq!(
+            verify(|n| n <= 5, FlakyCrashPolicy::Drop),
+            vec![Some(reproducing)]
+        );
+    }
+}
@@ -12,7 +12,7 @@ use std::os::unix::prelude::{AsRawFd, RawFd};
 use std::time::SystemTime;
 use std::{
     fs::{self, remove_file, File, OpenOptions},
-    io::{Seek, Write},
+    io::{self, Seek, Write},
     path::{Path, PathBuf},
     string::String,
 };
@@ -176,8 +176,12 @@ pub fn find_new_files_rec<P: AsRef<Path>>(
                 new_files.push(path.clone());
             }
         } else if attr.is_dir() {
-            let dir_left_to_sync = find_new_files_rec(entry.path(), last_check)?;
-            new_files.extend(dir_left_to_sync);
+            match find_new_files_rec(entry.path(), last_check) {
+                Ok(dir_left_to_sync) => new_files.extend(dir_left_to_sync),
+                // The directory was removed in the meantime, skip it like the files above
+                Err(Error::OsError(e, _, _)) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
         }
     }
 
@@ -99,6 +99,8 @@ pub mod llmp;
 pub mod math;
 #[cfg(feature = "std")]
 pub mod minibsod;
+#[cfg(feature = "noise")]
+pub mod noise;
 pub mod os;
 #[cfg(feature = "alloc")]
 pub mod ownedref;
@@ -81,7 +81,7 @@ use std::{
     env,
     io::{ErrorKind, Read, Write},
     net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
-    sync::mpsc::channel,
+    sync::mpsc::{channel, Sender},
     thread,
 };
 
@@ -94,6 +94,11 @@ use serde::{Deserialize, Serialize};
 #[cfg(feature = "std")]
 use tuple_list::tuple_list;
 
+#[cfg(feature = "noise")]
+use std::sync::{Arc, RwLock};
+
+#[cfg(feature = "noise")]
+use crate::noise::{NoiseChannel, PreSharedKey};
 #[cfg(all(unix, not(miri)))]
 use crate::os::unix_signals::setup_signal_handler;
 #[cfg(unix)]
@@ -150,6 +155,14 @@ pub const LLMP_FLAG_FROM_MM: Flags = Flags(0x4);
 /// before checking for own data to forward again.
 const _LLMP_B2B_BLOCK_TIME: Duration = Duration::from_millis(3_000);
 
+/// Time a broker 2 broker connection waits for the peer during the `Noise` handshake
+#[cfg(feature = "noise")]
+const LLMP_B2B_HANDSHAKE_TIMEOUT: Duration = Duration::from_millis(10_000);
+
+/// The largest message taken from a remote broker or node.
+/// Lengths read off the wire are checked against it before allocating, so peers can not exhaust the memory.
+pub const LLMP_MAX_REMOTE_MSG_LEN: usize = 1 << 28;
+
 /// If broker2broker is enabled, bind to public IP
 #[cfg(feature = "llmp_bind_public")]
 const _LLMP_BIND_ADDR: &str = "0.0.0.0";
@@ -512,6 +525,126 @@ pub fn recv_tcp_msg(stream: &mut TcpStream) -> Result<Vec<u8>, Error> {
     Ok(bytes)
 }
 
+/// The key authenticating and encrypting broker 2 broker connections, if any.
+/// Shared with the listener thread, so it also applies to listeners launched before it was set.
+#[cfg(feature = "noise")]
+type B2bPsk = Arc<RwLock<Option<PreSharedKey>>>;
+
+/// Broker 2 broker connections are in cleartext without the `noise` feature
+#[cfg(all(feature = "std", not(feature = "noise")))]
+#[derive(Debug, Clone, Default)]
+struct B2bPsk;
+
+/// What the tcp listener asks the broker to do, through the map of the listener
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy)]
+enum ListenerAnnouncement {
+    /// A new client, local or a remote broker, reachable on the given map
+    NewClient(ShMemDescription),
+    /// The client with the given id left
+    ClientExit(ClientId),
+}
+
+/// A broker 2 broker connection, encrypted if the brokers share a `PreSharedKey`
+#[cfg(feature = "std")]
+#[derive(Debug)]
+struct B2bStream {
+    stream: TcpStream,
+    #[cfg(feature = "noise")]
+    channel: Option<NoiseChannel>,
+}
+
+#[cfg(feature = "std")]
+impl B2bStream {
+    /// Authenticates the connection as the broker that opened it, if there is a key.
+    #[cfg_attr(not(feature = "noise"), allow(clippy::unnecessary_wraps))]
+    fn connect(stream: TcpStream, psk: &B2bPsk) -> Result<Self, Error> {
+        #[cfg(feature = "noise")]
+        if let Some(psk) = psk.read().unwrap().clone() {
+            return Self::handshake(stream, &psk, true);
+        }
+        #[cfg(not(feature = "noise"))]
+        let _ = psk;
+        Ok(Self {
+            stream,
+            #[cfg(feature = "noise")]
+            channel: None,
+        })
+    }
+
+    /// Authenticates the connection as the broker that accepted it, if there is a key.
+    /// Fails for brokers without the key.
+    #[cfg_attr(not(feature = "noise"), allow(clippy::unnecessary_wraps))]
+    fn accept(stream: TcpStream, psk: &B2bPsk) -> Result<Self, Error> {
+        #[cfg(feature = "noise")]
+        if let Some(psk) = psk.read().unwrap().clone() {
+            return Self::handshake(stream, &psk, false);
+        }
+        #[cfg(not(feature = "noise"))]
+        let _ = psk;
+        Ok(Self {
+            stream,
+            #[cfg(feature = "noise")]
+            channel: None,
+        })
+    }
+
+    #[cfg(feature = "noise")]
+    fn handshake(
+        mut stream: TcpStream,
+        psk: &PreSharedKey,
+        initiator: bool,
+    ) -> Result<Self, Error> {
+        // Don't wait forever for peers not talking `Noise`
+        let old_timeout = stream.read_timeout()?;
+        stream.set_read_timeout(Some(LLMP_B2B_HANDSHAKE_TIMEOUT))?;
+        let channel = if initiator {
+            NoiseChannel::connect(&mut stream, psk)?
+        } else {
+            NoiseChannel::accept(&mut stream, psk)?
+        };
+        stream.set_read_timeout(old_timeout)?;
+        Ok(Self {
+            stream,
+            channel: Some(channel),
+        })
+    }
+
+    /// Send one message, see [`send_tcp_msg`]
+    fn send<T>(&mut self, msg: &T) -> Result<(), Error>
+    where
+        T: Serialize,
+    {
+        #[cfg(feature = "noise")]
+        if let Some(channel) = &mut self.channel {
+            return channel.send_msg(&mut self.stream, &postcard::to_allocvec(msg)?);
+        }
+        send_tcp_msg(&mut self.stream, msg)
+    }
+
+    /// Receive one message, see [`recv_tcp_msg`]
+    fn recv(&mut self) -> Result<Vec<u8>, Error> {
+        #[cfg(feature = "noise")]
+        if let Some(channel) = &mut self.channel {
+            return channel.recv_msg(&mut self.stream, LLMP_MAX_REMOTE_MSG_LEN);
+        }
+        recv_tcp_msg(&mut self.stream)
+    }
+
+    /// If the connection is encrypted
+    #[cfg(feature = "noise")]
+    fn is_encrypted(&self) -> bool {
+        self.channel.is_some()
+    }
+
+    /// If the connection is encrypted
+    #[cfg(not(feature = "noise"))]
+    #[allow(clippy::unused_self)]
+    fn is_encrypted(&self) -> bool {
+        false
+    }
+}
+
 /// In case we don't have enough space, make sure the next page will be large
 /// enough. For now, we want to have at least enough space to store 2 of the
 /// largest messages we encountered (plus message one `new_page` message).
@@ -2075,6 +2208,9 @@ where
     clients_to_remove: Vec<ClientId>,
     /// The `ShMemProvider` to use
     shmem_provider: SP,
+    /// The key required from remote brokers, see [`LlmpBrokerInner::set_b2b_psk`]
+    #[cfg(feature = "std")]
+    b2b_psk: B2bPsk,
 }
 
 /// The broker (node 0)
@@ -2860,9 +2996,21 @@ where
             exit_cleanly_after: None,
             num_clients_seen: 0,
             shmem_provider,
+            #[cfg(feature = "std")]
+            #[allow(clippy::default_constructed_unit_structs)] // Without the `noise` feature
+            b2b_psk: B2bPsk::default(),
         })
     }
 
+    /// Authenticates and encrypts all new broker 2 broker connections with the given [`PreSharedKey`].
+    /// Remote brokers without the same key are rejected, in both directions.
+    /// Set it before calling [`LlmpBrokerInner::connect_b2b`].
+    /// It also applies to the listeners launched before, e.g. by [`LlmpBrokerInner::create_attach_to_tcp`].
+    #[cfg(feature = "noise")]
+    pub fn set_b2b_psk(&mut self, psk: PreSharedKey) {
+        *self.b2b_psk.write().unwrap() = Some(psk);
+    }
+
     /// Gets the [`ClientId`] the next client attaching to this broker will get.
     /// In its current implementation, the inner value of the next [`ClientId`]
     /// is equal to `self.num_clients_seen`.
@@ -2977,7 +3125,12 @@ where
 
         send_tcp_msg(&mut stream, &TcpRequest::RemoteBrokerHello { hostname })?;
 
-        let broker_id = match recv_tcp_msg(&mut stream)?.try_into()? {
+        let mut stream = B2bStream::connect(stream, &self.b2b_psk)?;
+        if stream.is_encrypted() {
+            log::info!("B2B: Authenticated, the connection is encrypted");
+        }
+
+        let broker_id = match stream.recv()?.try_into()? {
             TcpResponse::RemoteBrokerAccepted { broker_id } => {
                 log::info!("B2B: Got Connection Ack, broker_id {broker_id:?}");
                 broker_id
@@ -3129,7 +3282,7 @@ where
     #[cfg(feature = "std")]
     #[allow(clippy::let_and_return, clippy::too_many_lines)]
     fn b2b_thread_on(
-        mut stream: TcpStream,
+        mut stream: B2bStream,
         b2b_client_id: ClientId,
         broker_shmem_description: &ShMemDescription,
     ) -> Result<ShMemDescription, Error> {
@@ -3148,6 +3301,7 @@ where
 
             // The background thread blocks on the incoming connection for 15 seconds (if no data is available), then checks if it should forward own messages, then blocks some more.
             stream
+                .stream
                 .set_read_timeout(Some(_LLMP_B2B_BLOCK_TIME))
                 .expect("Failed to set tcp stream timeout");
 
@@ -3175,7 +3329,7 @@ where
             #[cfg(feature = "llmp_debug")]
             log::info!("B2B: Starting proxy loop :)");
 
-            let peer_address = stream.peer_addr().unwrap();
+            let peer_address = stream.stream.peer_addr().unwrap();
 
             loop {
                 // first, forward all data we have.
@@ -3196,15 +3350,12 @@ where
                                 payload.len()
                             );
                             // We got a new message! Forward...
-                            if let Err(e) = send_tcp_msg(
-                                &mut stream,
-                                &TcpRemoteNewMessage {
-                                    client_id,
-                                    tag,
-                                    flags,
-                                    payload: payload.to_vec(),
-                                },
-                            ) {
+                            if let Err(e) = stream.send(&TcpRemoteNewMessage {
+                                client_id,
+                                tag,
+                                flags,
+                                payload: payload.to_vec(),
+                            }) {
                                 log::info!("Got error {e} while trying to forward a message to broker {peer_address}, exiting thread");
                                 return;
                             }
@@ -3223,7 +3374,7 @@ where
                 // Forwarding happens between each recv, too, as simplification.
                 // We ignore errors completely as they may be timeout, or stream closings.
                 // Instead, we catch stream close when/if we next try to send.
-                match recv_tcp_msg(&mut stream) {
+                match stream.recv() {
                     Ok(val) => {
                         let msg: TcpRemoteNewMessage = val.try_into().expect(
                             "Illegal message received from broker 2 broker connection - shutting down.",
@@ -3253,6 +3404,10 @@ where
                                 );
                                 return;
                             }
+                        } else if stream.is_encrypted() {
+                            // Not a timeout: the message failed authentication, and the channel is out of sync
+                            log::error!("Dropping the connection to broker {peer_address}: {e}");
+                            return;
                         }
 
                         #[cfg(feature = "llmp_debug")]
@@ -3272,28 +3427,61 @@ where
         ret
     }
 
+    /// Requests of local clients are not authenticated.
+    /// With a `PreSharedKey` set, only take them from the loopback interface, so remote peers without the key
+    /// can neither make the broker map their shared memory nor drop other clients.
+    #[cfg(feature = "std")]
+    fn accepts_local_request(stream: &TcpStream, b2b_psk: &B2bPsk) -> bool {
+        #[cfg(feature = "noise")]
+        if b2b_psk.read().unwrap().is_some() {
+            return stream
+                .peer_addr()
+                .is_ok_and(|peer_address| peer_address.ip().is_loopback());
+        }
+        #[cfg(not(feature = "noise"))]
+        let _ = (stream, b2b_psk);
+        true
+    }
+
     /// handles a single tcp request in the current context.
     #[cfg(feature = "std")]
     fn handle_tcp_request(
         mut stream: TcpStream,
         request: &TcpRequest,
         current_client_id: &mut ClientId,
-        sender: &mut LlmpSender<SP>,
+        announcer: &Sender<ListenerAnnouncement>,
         broker_shmem_description: &ShMemDescription,
+        b2b_psk: &B2bPsk,
     ) {
+        if matches!(
+            request,
+            TcpRequest::ClientQuit { .. } | TcpRequest::LocalClientHello { .. }
+        ) && !Self::accepts_local_request(&stream, b2b_psk)
+        {
+            log::warn!(
+                "Ignoring an unauthenticated local client request from remote peer {:?}",
+                stream.peer_addr()
+            );
+            return;
+        }
+
         match request {
             TcpRequest::ClientQuit { client_id } => {
                 // todo search the ancestor_id and remove it.
-                match Self::announce_client_exit(sender, client_id.0) {
-                    Ok(()) => (),
-                    Err(e) => log::info!("Error announcing client exit: {e:?}"),
+                if announcer
+                    .send(ListenerAnnouncement::ClientExit(*client_id))
+                    .is_err()
+                {
+                    log::info!("Error announcing client exit: the announcer is gone");
                 }
             }
             TcpRequest::LocalClientHello { shmem_description } => {
-                match Self::announce_new_client(sender, shmem_description) {
-                    Ok(()) => (),
-                    Err(e) => log::info!("Error forwarding client on map: {e:?}"),
-                };
+                if announcer
+                    .send(ListenerAnnouncement::NewClient(*shmem_description))
+                    .is_err()
+                {
+                    log::info!("Error forwarding client on map: the announcer is gone");
+                }
 
                 if let Err(e) = send_tcp_msg(
                     &mut stream,
@@ -3309,26 +3497,46 @@ where
                 log::info!("B2B new client: {hostname}");
 
                 // TODO: Clean up broker ids.
-                if send_tcp_msg(
-                    &mut stream,
-                    &TcpResponse::RemoteBrokerAccepted {
-                        broker_id: BrokerId(current_client_id.0),
-                    },
-                )
-                .is_err()
-                {
-                    log::info!("Error accepting broker, ignoring.");
-                    return;
-                }
+                let b2b_client_id = *current_client_id;
+                current_client_id.0 += 1;
 
-                if let Ok(shmem_description) =
-                    Self::b2b_thread_on(stream, *current_client_id, broker_shmem_description)
-                {
-                    if Self::announce_new_client(sender, &shmem_description).is_err() {
-                        log::info!("B2B: Error announcing client {shmem_description:?}");
+                // The handshake waits for the remote broker, don't keep other clients from connecting meanwhile.
+                let hostname = hostname.clone();
+                let announcer = announcer.clone();
+                let broker_shmem_description = *broker_shmem_description;
+                let b2b_psk = b2b_psk.clone();
+                thread::spawn(move || {
+                    let mut stream = match B2bStream::accept(stream, &b2b_psk) {
+                        Ok(stream) => stream,
+                        Err(e) => {
+                            log::warn!(
+                                "B2B: Rejecting broker {hostname}, authentication failed: {e}"
+                            );
+                            return;
+                        }
                     };
-                    current_client_id.0 += 1;
-                }
+
+                    if stream
+                        .send(&TcpResponse::RemoteBrokerAccepted {
+                            broker_id: BrokerId(b2b_client_id.0),
+                        })
+                        .is_err()
+                    {
+                        log::info!("Error accepting broker, ignoring.");
+                        return;
+                    }
+
+                    if let Ok(shmem_description) =
+                        Self::b2b_thread_on(stream, b2b_client_id, &broker_shmem_description)
+                    {
+                        if announcer
+                            .send(ListenerAnnouncement::NewClient(shmem_description))
+                            .is_err()
+                        {
+                            log::info!("B2B: Error announcing client {shmem_description:?}");
+                        }
+                    }
+                });
             }
         };
     }
@@ -3353,6 +3561,7 @@ where
         };
 
         let llmp_tcp_id = self.peek_next_client_id();
+        let b2b_psk = self.b2b_psk.clone();
 
         // Tcp out map sends messages from background thread tcp server to foreground client
         let tcp_out_shmem = LlmpSharedMap::new(
@@ -3363,25 +3572,43 @@ where
         let listener_id = self.register_client(tcp_out_shmem);
 
         let ret = thread::spawn(move || {
-            // Create a new ShMemProvider for this background thread.
-            let mut shmem_provider_bg = SP::new().unwrap();
-
             let mut current_client_id = ClientId(llmp_tcp_id.0 + 1);
 
-            let mut tcp_incoming_sender = LlmpSender {
-                id: llmp_tcp_id,
-                last_msg_sent: ptr::null_mut(),
-                out_shmems: vec![LlmpSharedMap::existing(
-                    shmem_provider_bg
-                        .shmem_from_description(tcp_out_shmem_description)
-                        .unwrap(),
-                )],
-                // drop pages to the broker, if it already read them.
-                keep_pages_forever: false,
-                has_unsent_message: false,
-                shmem_provider: shmem_provider_bg.clone(),
-                unused_shmem_cache: vec![],
-            };
+            // Only the announcer thread writes to the tcp out map, b2b handshakes finish on their own threads.
+            let (announcer, announcements) = channel();
+            thread::spawn(move || {
+                // Create a new ShMemProvider for this background thread.
+                let mut shmem_provider_bg = SP::new().unwrap();
+
+                let mut tcp_incoming_sender = LlmpSender {
+                    id: llmp_tcp_id,
+                    last_msg_sent: ptr::null_mut(),
+                    out_shmems: vec![LlmpSharedMap::existing(
+                        shmem_provider_bg
+                            .shmem_from_description(tcp_out_shmem_description)
+                            .unwrap(),
+                    )],
+                    // drop pages to the broker, if it already read them.
+                    keep_pages_forever: false,
+                    has_unsent_message: false,
+                    shmem_provider: shmem_provider_bg.clone(),
+                    unused_shmem_cache: vec![],
+                };
+
+                for announcement in announcements {
+                    let res = match announcement {
+                        ListenerAnnouncement::NewClient(shmem_description) => {
+                            Self::announce_new_client(&mut tcp_incoming_sender, &shmem_description)
+                        }
+                        ListenerAnnouncement::ClientExit(client_id) => {
+                            Self::announce_client_exit(&mut tcp_incoming_sender, client_id.0)
+                        }
+                    };
+                    if let Err(e) = res {
+                        log::info!("Error announcing {announcement:?}: {e:?}");
+                    }
+                }
+            });
 
             loop {
                 match listener.accept() {
@@ -3423,8 +3650,9 @@ where
                             stream,
                             &req,
                             &mut current_client_id,
-                            &mut tcp_incoming_sender,
+                            &announcer,
                             &broker_shmem_description,
+                            &b2b_psk,
                         );
                     }
                     ListenerStream::Empty() => {
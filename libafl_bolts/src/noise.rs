@@ -0,0 +1,402 @@
+//! Encrypted and authenticated links between machines, using the [`Noise`](https://noiseprotocol.org) protocol.
+//!
+//! Both ends of a link know the same secret [`PreSharedKey`]. The handshake mixes it into the session keys,
+//! so a peer without the key fails the handshake and gets rejected. After the handshake, every message is
+//! encrypted and authenticated with `ChaCha20-Poly1305`. Tampered, replayed or reordered messages fail to decrypt.
+//!
+//! A key can be generated with `head -c 32 /dev/urandom | xxd -p -c 64`.
+//!
+//! [`NoiseChannel::connect`] and [`NoiseChannel::accept`] run the handshake over blocking streams.
+//! For other transports, drive a [`NoiseHandshake`] by hand.
+
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+use std::{
+    fs,
+    io::{Read, Write},
+    path::Path,
+};
+
+use snow::{Builder, HandshakeState, TransportState};
+
+use crate::Error;
+
+/// The `Noise` protocol of the links. Peers are authenticated by the pre-shared key only.
+pub const NOISE_PARAMS: &str = "Noise_NNpsk0_25519_ChaChaPoly_BLAKE2s";
+
+/// The maximum length of a single `Noise` message
+pub const NOISE_MAX_MSG_LEN: usize = 65535;
+
+/// The length of the authentication tag of each encrypted chunk
+const TAG_LEN: usize = 16;
+
+/// The maximum length of the plaintext in a single `Noise` message
+const MAX_CHUNK_LEN: usize = NOISE_MAX_MSG_LEN - TAG_LEN;
+
+/// A secret key shared by all the machines allowed to connect to each other
+#[derive(Clone, PartialEq, Eq)]
+pub struct PreSharedKey([u8; PreSharedKey::LEN]);
+
+impl PreSharedKey {
+    /// The length of a key, in bytes
+    pub const LEN: usize = 32;
+
+    /// Creates a [`PreSharedKey`] from raw bytes
+    #[must_use]
+    pub fn new(key: [u8; Self::LEN]) -> Self {
+        Self(key)
+    }
+
+    /// Parses a [`PreSharedKey`] from 64 hex digits
+    pub fn from_hex(hex: &str) -> Result<Self, Error> {
+        let hex = hex.trim();
+        if hex.len() != 2 * Self::LEN || !hex.is_ascii() {
+            return Err(Error::illegal_argument(format!(
+                "A pre-shared key needs exactly {} hex digits",
+                2 * Self::LEN
+            )));
+        }
+        let mut key = [0; Self::LEN];
+        for (byte, digits) in key.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            let digits = core::str::from_utf8(digits).unwrap();
+            *byte = u8::from_str_radix(digits, 16).map_err(|_| {
+                Error::illegal_argument(format!("Invalid hex digits {digits} in pre-shared key"))
+            })?;
+        }
+        Ok(Self(key))
+    }
+
+    /// Loads a [`PreSharedKey`] from a file, holding either the 32 raw bytes or 64 hex digits
+    pub fn from_file<P>(path: P) -> Result<Self, Error>
+    where
+        P: AsRef<Path>,
+    {
+        let content = fs::read(path.as_ref())?;
+        match <[u8; Self::LEN]>::try_from(content.as_slice()) {
+            Ok(key) => Ok(Self(key)),
+            Err(_) => Self::from_hex(&String::from_utf8_lossy(&content)),
+        }
+    }
+
+    /// The raw bytes of the key
+    #[must_use]
+    pub fn as_bytes(&self) -> &[u8; Self::LEN] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for PreSharedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never leak the key to the logs
+        f.write_str("PreSharedKey(..)")
+    }
+}
+
+/// An ongoing `Noise` handshake.
+///
+/// The side opening the connection is the initiator. Send the messages of [`Self::write_message`] to the
+/// peer while [`Self::is_my_turn`], else pass the messages of the peer to [`Self::read_message`], until
+/// [`Self::is_finished`].
+pub struct NoiseHandshake {
+    state: HandshakeState,
+}
+
+impl fmt::Debug for NoiseHandshake {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NoiseHandshake")
+            .field("initiator", &self.state.is_initiator())
+            .field("finished", &self.state.is_handshake_finished())
+            .finish_non_exhaustive()
+    }
+}
+
+impl NoiseHandshake {
+    /// Starts the handshake of the side opening the connection
+    pub fn initiator(psk: &PreSharedKey) -> Result<Self, Error> {
+        Self::builder(psk)?
+            .build_initiator()
+            .map(|state| Self { state })
+            .map_err(|e| Error::illegal_state(format!("Failed to start the Noise handshake: {e}")))
+    }
+
+    /// Starts the handshake of the side accepting the connection
+    pub fn responder(psk: &PreSharedKey) -> Result<Self, Error> {
+        Self::builder(psk)?
+            .build_responder()
+            .map(|state| Self { state })
+            .map_err(|e| Error::illegal_state(format!("Failed to start the Noise handshake: {e}")))
+    }
+
+    fn builder(psk: &PreSharedKey) -> Result<Builder<'_>, Error> {
+        let params = NOISE_PARAMS
+            .parse()
+            .map_err(|e| Error::illegal_state(format!("Invalid Noise parameters: {e}")))?;
+        Ok(Builder::new(params).psk(0, psk.as_bytes()))
+    }
+
+    /// If the handshake is done, and the [`NoiseChannel`] can be created
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        self.state.is_handshake_finished()
+    }
+
+    /// If the next handshake message has to be sent by this side
+    #[must_use]
+    pub fn is_my_turn(&self) -> bool {
+        self.state.is_my_turn()
+    }
+
+    /// The next handshake message to send to the peer
+    pub fn write_message(&mut self) -> Result<Vec<u8>, Error> {
+        let mut msg = vec![0; NOISE_MAX_MSG_LEN];
+        let len = self
+            .state
+            .write_message(&[], &mut msg)
+            .map_err(|e| Error::illegal_state(format!("Noise handshake failed: {e}")))?;
+        msg.truncate(len);
+        Ok(msg)
+    }
+
+    /// Processes a handshake message of the peer.
+    /// Fails if the peer does not know the [`PreSharedKey`].
+    pub fn read_message(&mut self, msg: &[u8]) -> Result<(), Error> {
+        let mut payload = vec![0; NOISE_MAX_MSG_LEN];
+        self.state.read_message(msg, &mut payload).map_err(|e| {
+            Error::illegal_state(format!(
+                "Noise handshake failed, the peer is not authenticated: {e}"
+            ))
+        })?;
+        Ok(())
+    }
+
+    /// Finishes the handshake
+    pub fn into_channel(self) -> Result<NoiseChannel, Error> {
+        self.state
+            .into_transport_mode()
+            .map(|transport| NoiseChannel { transport })
+            .map_err(|e| Error::illegal_state(format!("Noise handshake not finished: {e}")))
+    }
+}
+
+/// An authenticated and encrypted channel to a peer, after a [`NoiseHandshake`].
+///
+/// Messages of any size are encrypted in chunks of up to [`NOISE_MAX_MSG_LEN`] bytes.
+/// Both sides have to process the messages in the order they were sent.
+pub struct NoiseChannel {
+    transport: TransportState,
+}
+
+impl fmt::Debug for NoiseChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NoiseChannel")
+            .field("initiator", &self.transport.is_initiator())
+            .finish_non_exhaustive()
+    }
+}
+
+impl NoiseChannel {
+    /// Runs the handshake as the side that opened the connection on `stream`.
+    ///
+    /// Set a read timeout on the stream, so a peer not answering can not block forever.
+    pub fn connect<S>(stream: &mut S, psk: &PreSharedKey) -> Result<Self, Error>
+    where
+        S: Read + Write,
+    {
+        Self::handshake(stream, NoiseHandshake::initiator(psk)?)
+    }
+
+    /// Runs the handshake as the side that accepted the connection on `stream`.
+    /// Fails for peers without the [`PreSharedKey`].
+    ///
+    /// Set a read timeout on the stream, so a peer not answering can not block forever.
+    pub fn accept<S>(stream: &mut S, psk: &PreSharedKey) -> Result<Self, Error>
+    where
+        S: Read + Write,
+    {
+        Self::handshake(stream, NoiseHandshake::responder(psk)?)
+    }
+
+    /// Exchanges the handshake messages, each prefixed by its `u16` big endian length
+    fn handshake<S>(stream: &mut S, mut handshake: NoiseHandshake) -> Result<Self, Error>
+    where
+        S: Read + Write,
+    {
+        while !handshake.is_finished() {
+            if handshake.is_my_turn() {
+                let msg = handshake.write_message()?;
+                stream.write_all(&(msg.len() as u16).to_be_bytes())?;
+                stream.write_all(&msg)?;
+            } else {
+                let mut len = [0; 2];
+                stream.read_exact(&mut len)?;
+                let mut msg = vec![0; u16::from_be_bytes(len).into()];
+                stream.read_exact(&mut msg)?;
+                handshake.read_message(&msg)?;
+            }
+        }
+        handshake.into_channel()
+    }
+
+    /// The length of a message of `msg_len` bytes, once encrypted
+    #[must_use]
+    pub const fn ciphertext_len(msg_len: usize) -> usize {
+        msg_len + (msg_len / MAX_CHUNK_LEN + 1) * TAG_LEN
+    }
+
+    /// Encrypts a message for the peer
+    pub fn encrypt(&mut self, msg: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut ciphertext = Vec::with_capacity(Self::ciphertext_len(msg.len()));
+        let mut start = 0;
+        // Even an empty message gets a chunk, to authenticate it
+        loop {
+            let end = (start + MAX_CHUNK_LEN).min(msg.len());
+            let offset = ciphertext.len();
+            ciphertext.resize(offset + end - start + TAG_LEN, 0);
+            let len = self
+                .transport
+                .write_message(&msg[start..end], &mut ciphertext[offset..])
+                .map_err(|e| Error::illegal_state(format!("Noise encryption failed: {e}")))?;
+            ciphertext.truncate(offset + len);
+
+            start = end;
+            if start == msg.len() {
+                return Ok(ciphertext);
+            }
+        }
+    }
+
+    /// Decrypts a message of the peer.
+    /// Fails if the message was not sent by the peer, or was altered on the way.
+    pub fn decrypt(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>, Error> {
+        if ciphertext.is_empty() {
+            return Err(Error::illegal_argument("Empty Noise message"));
+        }
+        let mut msg = Vec::with_capacity(ciphertext.len());
+        for chunk in ciphertext.chunks(NOISE_MAX_MSG_LEN) {
+            let offset = msg.len();
+            msg.resize(offset + chunk.len(), 0);
+            let len = self
+                .transport
+                .read_message(chunk, &mut msg[offset..])
+                .map_err(|e| {
+                    Error::illegal_state(format!(
+                        "Noise decryption failed, the message is not authentic: {e}"
+                    ))
+                })?;
+            msg.truncate(offset + len);
+        }
+        Ok(msg)
+    }
+
+    /// Encrypts and sends a message to a blocking stream, prefixed by its `u32` big endian length.
+    /// Read it with [`Self::recv_msg`].
+    pub fn send_msg<W>(&mut self, stream: &mut W, msg: &[u8]) -> Result<(), Error>
+    where
+        W: Write,
+    {
+        let ciphertext = self.encrypt(msg)?;
+        let len = u32::try_from(ciphertext.len()).map_err(|_| {
+            Error::illegal_argument(format!(
+                "Trying to send an encrypted message > u32! (size: {})",
+                ciphertext.len()
+            ))
+        })?;
+        stream.write_all(&len.to_be_bytes())?;
+        stream.write_all(&ciphertext)?;
+        Ok(())
+    }
+
+    /// Receives and decrypts a message sent with [`Self::send_msg`] from a blocking stream.
+    ///
+    /// The length is not authenticated before the message is read:
+    /// messages longer than `max_len` bytes are rejected without allocating room for them.
+    pub fn recv_msg<R>(&mut self, stream: &mut R, max_len: usize) -> Result<Vec<u8>, Error>
+    where
+        R: Read,
+    {
+        let mut len = [0; 4];
+        stream.read_exact(&mut len)?;
+        let len = u32::from_be_bytes(len) as usize;
+        if len > Self::ciphertext_len(max_len) {
+            return Err(Error::illegal_argument(format!(
+                "Refusing to receive an encrypted message of {len} bytes, the limit is {max_len} bytes"
+            )));
+        }
+        let mut ciphertext = vec![0; len];
+        stream.read_exact(&mut ciphertext)?;
+        self.decrypt(&ciphertext)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::{NoiseChannel, NoiseHandshake, PreSharedKey, MAX_CHUNK_LEN};
+
+    fn handshake(
+        initiator_psk: &PreSharedKey,
+        responder_psk: &PreSharedKey,
+    ) -> Result<(NoiseChannel, NoiseChannel), crate::Error> {
+        let mut initiator = NoiseHandshake::initiator(initiator_psk)?;
+        let mut responder = NoiseHandshake::responder(responder_psk)?;
+        while !initiator.is_finished() || !responder.is_finished() {
+            if initiator.is_my_turn() {
+                responder.read_message(&initiator.write_message()?)?;
+            } else {
+                initiator.read_message(&responder.write_message()?)?;
+            }
+        }
+        Ok((initiator.into_channel()?, responder.into_channel()?))
+    }
+
+    #[test]
+    fn test_noise_channel() {
+        let psk = PreSharedKey::from_hex(&"2a".repeat(PreSharedKey::LEN)).unwrap();
+        let (mut initiator, mut responder) = handshake(&psk, &psk).unwrap();
+
+        let msg: Vec<u8> = (0..3 * MAX_CHUNK_LEN + 7).map(|i| i as u8).collect();
+        let ciphertext = initiator.encrypt(&msg).unwrap();
+        assert_ne!(&ciphertext[..msg.len()], msg.as_slice());
+        assert_eq!(responder.decrypt(&ciphertext).unwrap(), msg);
+
+        let empty = responder.encrypt(&[]).unwrap();
+        assert_eq!(initiator.decrypt(&empty).unwrap(), Vec::<u8>::new());
+
+        // Tampered and replayed messages are rejected
+        let mut ciphertext = initiator.encrypt(b"corpus").unwrap();
+        ciphertext[0] ^= 1;
+        assert!(responder.decrypt(&ciphertext).is_err());
+        assert!(initiator.decrypt(&empty).is_err());
+    }
+
+    #[test]
+    fn test_noise_max_len() {
+        let psk = PreSharedKey::new([3; PreSharedKey::LEN]);
+        let (mut initiator, mut responder) = handshake(&psk, &psk).unwrap();
+
+        let mut stream = Vec::new();
+        initiator.send_msg(&mut stream, &[0x41; 64]).unwrap();
+        assert_eq!(
+            responder.recv_msg(&mut stream.as_slice(), 64).unwrap(),
+            [0x41; 64]
+        );
+
+        // A forged length is rejected before allocating
+        let mut forged = u32::MAX.to_be_bytes().to_vec();
+        forged.extend_from_slice(&[0; 32]);
+        assert!(responder.recv_msg(&mut forged.as_slice(), 64).is_err());
+
+        let mut stream = Vec::new();
+        initiator.send_msg(&mut stream, &[0x41; 65]).unwrap();
+        assert!(responder.recv_msg(&mut stream.as_slice(), 64).is_err());
+    }
+
+    #[test]
+    fn test_noise_wrong_psk() {
+        let psk = PreSharedKey::new([1; PreSharedKey::LEN]);
+        let other = PreSharedKey::new([2; PreSharedKey::LEN]);
+        assert!(handshake(&psk, &other).is_err());
+        assert!(PreSharedKey::from_hex("2a2a").is_err());
+    }
+}
@@ -167,6 +167,10 @@ bool DumpCfgPass::runOnModule(Module &M) {
 
   nlohmann::json cfg;
 
+  // The source file, to tell apart static functions of the same name in
+  // different modules
+  cfg["module"] = M.getSourceFileName();
+
   // Dump CFG for this module
   for (auto record = bb_to_cur_loc.begin(); record != bb_to_cur_loc.end();
        record++) {
@@ -186,6 +190,19 @@ bool DumpCfgPass::runOnModule(Module &M) {
       outgoing.push_back(bb_to_cur_loc[*bb_successor]);
     }
     cfg["edges"][func_name][loc] = outgoing;
+
+    // The source location of the first instruction with debug info, to map
+    // the block to the coverage of the binary. [0, 0] without debug info.
+    std::vector<uint32_t> source_loc = {0, 0};
+    for (auto &IN : *current_bb) {
+      if (const DebugLoc &DL = IN.getDebugLoc()) {
+        if (DL.getLine()) {
+          source_loc = {DL.getLine(), DL.getCol()};
+          break;
+        }
+      }
+    }
+    cfg["locs"][func_name][loc] = source_loc;
   }
 
   for (auto record = calls_in_bb.begin(); record != calls_in_bb.end();
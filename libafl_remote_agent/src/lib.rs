@@ -0,0 +1,434 @@
+//! A `no_std` agent running harnesses on a remote device, such as a microcontroller.
+//!
+//! The fuzzer runs on the host and drives the agent with the `RemoteExecutor` from `libafl`,
+//! speaking the framed protocol defined in [`protocol`] over any byte stream.
+//! The agent neither allocates nor depends on anything but `core`, so it fits on small targets:
+//!
+//! ```rust,ignore
+//! let mut input = [0u8; 1024];
+//! let mut map = [0u8; 4096];
+//! let mut agent = Agent::new(uart, &mut input, &mut map);
+//! agent.serve(|input, map| {
+//!     run_target(input, map);
+//!     RemoteExitKind::Ok
+//! });
+//! ```
+
+#![no_std]
+
+#[cfg(any(test, feature = "std"))]
+extern crate std;
+
+pub mod protocol;
+
+use core::{convert::Infallible, fmt};
+
+use protocol::{
+    Checksum, FrameHeader, MapEncoding, MessageKind, ProtocolError, ReadyInfo, RemoteExitKind,
+    HEADER_LEN, MAGIC, PROTOCOL_VERSION, TRAILER_LEN, UNSOLICITED_SEQ,
+};
+
+/// A byte stream between the agent and the fuzzer, e.g., a UART
+pub trait Transport {
+    /// The error of this transport
+    type Error;
+
+    /// Read exactly `buf.len()` bytes, blocking until they arrive
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Write all of `buf`
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Self::Error>;
+
+    /// Make sure everything written so far is sent
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> Transport for T
+where
+    T: std::io::Read + std::io::Write,
+{
+    type Error = std::io::Error;
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Self::Error> {
+        std::io::Read::read_exact(self, buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+        std::io::Write::write_all(self, buf)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        std::io::Write::flush(self)
+    }
+}
+
+/// An error while handling a message
+#[derive(Debug)]
+pub enum AgentError<E> {
+    /// The transport failed
+    Transport(E),
+    /// The fuzzer sent something we did not understand
+    Protocol(ProtocolError),
+}
+
+impl<E> From<ProtocolError> for AgentError<E> {
+    fn from(err: ProtocolError) -> Self {
+        Self::Protocol(err)
+    }
+}
+
+impl<E> fmt::Display for AgentError<E>
+where
+    E: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Transport(err) => write!(f, "transport error: {err:?}"),
+            Self::Protocol(err) => write!(f, "protocol error: {err}"),
+        }
+    }
+}
+
+/// The agent, receiving inputs from the fuzzer and running them in the harness
+#[derive(Debug)]
+pub struct Agent<'a, T> {
+    transport: T,
+    input: &'a mut [u8],
+    map: &'a mut [u8],
+    /// The sequence number of the last request
+    seq: u32,
+}
+
+impl<'a, T> Agent<'a, T>
+where
+    T: Transport,
+{
+    /// Create a new agent.
+    ///
+    /// The inputs are received into `input`, which also sets the maximum input size.
+    /// The harness is expected to record its coverage in `map`, which is sent back after each run.
+    pub fn new(transport: T, input: &'a mut [u8], map: &'a mut [u8]) -> Self {
+        Self {
+            transport,
+            input,
+            map,
+            seq: UNSOLICITED_SEQ,
+        }
+    }
+
+    /// The transport used by this agent
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Tell the fuzzer that the agent is (again) ready to run inputs.
+    ///
+    /// Call this once when the device starts, so that the fuzzer notices a restart.
+    pub fn announce(&mut self) -> Result<(), T::Error> {
+        self.seq = UNSOLICITED_SEQ;
+        self.send_ready()
+    }
+
+    fn send_ready(&mut self) -> Result<(), T::Error> {
+        let payload = ReadyInfo {
+            version: PROTOCOL_VERSION,
+            map_size: len_u32(self.map.len()),
+            max_input_size: len_u32(self.input.len()),
+        }
+        .encode();
+        self.send_frame(MessageKind::Ready, &[&payload])
+    }
+
+    /// Announce the agent, then handle messages forever.
+    ///
+    /// Malformed messages are skipped, the fuzzer will notice the missing answer and resynchronize.
+    /// Only returns if the transport fails.
+    pub fn serve<H>(&mut self, mut harness: H) -> Result<Infallible, T::Error>
+    where
+        H: FnMut(&[u8], &mut [u8]) -> RemoteExitKind,
+    {
+        self.announce()?;
+        loop {
+            match self.handle_next(&mut harness) {
+                Ok(()) | Err(AgentError::Protocol(_)) => {}
+                Err(AgentError::Transport(err)) => return Err(err),
+            }
+        }
+    }
+
+    /// Receive and handle a single message, running the harness if it was an input
+    pub fn handle_next<H>(&mut self, harness: &mut H) -> Result<(), AgentError<T::Error>>
+    where
+        H: FnMut(&[u8], &mut [u8]) -> RemoteExitKind,
+    {
+        let header = self.read_header()?;
+        let len = header.len as usize;
+        self.seq = header.seq;
+        let mut checksum = Checksum::new();
+        checksum.update(&header.encode()[1..]);
+
+        match header.kind {
+            MessageKind::Hello => {
+                self.read_trailer(checksum)?;
+                self.send_ready().map_err(AgentError::Transport)
+            }
+            MessageKind::Run => {
+                // The header checksum was verified, so this is the length the fuzzer sent
+                if len > self.input.len() {
+                    self.skip(len + TRAILER_LEN)?;
+                    return Err(ProtocolError::TooLarge {
+                        len,
+                        max: self.input.len(),
+                    }
+                    .into());
+                }
+                self.transport
+                    .read_exact(&mut self.input[..len])
+                    .map_err(AgentError::Transport)?;
+                checksum.update(&self.input[..len]);
+                self.read_trailer(checksum)?;
+
+                self.map.fill(0);
+                let exit_kind = harness(&self.input[..len], &mut *self.map);
+                self.send_result(exit_kind).map_err(AgentError::Transport)
+            }
+            MessageKind::Ready | MessageKind::Result => {
+                self.skip(len + TRAILER_LEN)?;
+                Err(ProtocolError::UnexpectedKind(header.kind as u8).into())
+            }
+        }
+    }
+
+    /// Send the result of the current run, together with the coverage map.
+    ///
+    /// Apart from [`Self::handle_next`], this is useful in fault or panic handlers,
+    /// to report a [`RemoteExitKind::Crash`] before resetting the device.
+    pub fn send_result(&mut self, exit_kind: RemoteExitKind) -> Result<(), T::Error> {
+        let nonzero = self.map.iter().filter(|&&entry| entry != 0).count();
+        let encoding = MapEncoding::for_map(self.map.len(), nonzero);
+        let status = [exit_kind as u8, encoding as u8];
+
+        match encoding {
+            MapEncoding::Dense => {
+                let map: &[u8] = self.map;
+                let len = status.len() + map.len();
+                let mut checksum = Checksum::new();
+                let header = FrameHeader::new(MessageKind::Result, self.seq, len_u32(len)).encode();
+                checksum.update(&header[1..]);
+                checksum.update(&status);
+                checksum.update(map);
+                self.transport.write_all(&header)?;
+                self.transport.write_all(&status)?;
+                self.transport.write_all(map)?;
+                self.transport.write_all(&checksum.finish().to_le_bytes())?;
+            }
+            MapEncoding::Sparse => {
+                let len = status.len() + nonzero * protocol::SPARSE_ENTRY_LEN;
+                let header = FrameHeader::new(MessageKind::Result, self.seq, len_u32(len)).encode();
+                let mut checksum = Checksum::new();
+                checksum.update(&header[1..]);
+                checksum.update(&status);
+                self.transport.write_all(&header)?;
+                self.transport.write_all(&status)?;
+                for (idx, &value) in self.map.iter().enumerate() {
+                    if value != 0 {
+                        let entry = protocol::encode_sparse_entry(len_u32(idx), value);
+                        checksum.update(&entry);
+                        self.transport.write_all(&entry)?;
+                    }
+                }
+                self.transport.write_all(&checksum.finish().to_le_bytes())?;
+            }
+        }
+        self.transport.flush()
+    }
+
+    fn send_frame(&mut self, kind: MessageKind, payload: &[&[u8]]) -> Result<(), T::Error> {
+        let len: usize = payload.iter().map(|part| part.len()).sum();
+        let header = FrameHeader::new(kind, self.seq, len_u32(len)).encode();
+        let mut checksum = Checksum::new();
+        checksum.update(&header[1..]);
+        self.transport.write_all(&header)?;
+        for part in payload {
+            checksum.update(part);
+            self.transport.write_all(part)?;
+        }
+        self.transport.write_all(&checksum.finish().to_le_bytes())?;
+        self.transport.flush()
+    }
+
+    /// Read the next valid frame header, skipping everything before it.
+    ///
+    /// If a header turns out to be damaged, the search for the next [`MAGIC`] byte goes on right after the
+    /// magic of the damaged one, so that a frame following garbage is never missed.
+    fn read_header(&mut self) -> Result<FrameHeader, AgentError<T::Error>> {
+        let mut header = [0u8; HEADER_LEN];
+        // The bytes of `header` read already
+        let mut filled = 0;
+        loop {
+            while filled == 0 {
+                self.transport
+                    .read_exact(&mut header[..1])
+                    .map_err(AgentError::Transport)?;
+                if header[0] == MAGIC {
+                    filled = 1;
+                }
+            }
+            self.transport
+                .read_exact(&mut header[filled..])
+                .map_err(AgentError::Transport)?;
+            if let Ok(header) = FrameHeader::decode(&header) {
+                return Ok(header);
+            }
+            filled = match header[1..].iter().position(|&byte| byte == MAGIC) {
+                Some(pos) => {
+                    header.copy_within(pos + 1.., 0);
+                    HEADER_LEN - pos - 1
+                }
+                None => 0,
+            };
+        }
+    }
+
+    fn read_trailer(&mut self, checksum: Checksum) -> Result<(), AgentError<T::Error>> {
+        let mut trailer = [0u8; TRAILER_LEN];
+        self.transport
+            .read_exact(&mut trailer)
+            .map_err(AgentError::Transport)?;
+        if u16::from_le_bytes(trailer) == checksum.finish() {
+            Ok(())
+        } else {
+            Err(ProtocolError::BadChecksum.into())
+        }
+    }
+
+    fn skip(&mut self, mut len: usize) -> Result<(), AgentError<T::Error>> {
+        let mut scratch = [0u8; 16];
+        while len > 0 {
+            let chunk = len.min(scratch.len());
+            self.transport
+                .read_exact(&mut scratch[..chunk])
+                .map_err(AgentError::Transport)?;
+            len -= chunk;
+        }
+        Ok(())
+    }
+}
+
+/// Lengths on the wire are `u32`, which is plenty for the buffers of any device
+fn len_u32(len: usize) -> u32 {
+    u32::try_from(len).expect("Buffers larger than 4GB are not supported")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, vec::Vec};
+
+    use crate::{
+        protocol::{decode_map, FrameHeader, MessageKind, RemoteExitKind, HEADER_LEN},
+        Agent, Transport,
+    };
+
+    /// Bytes from the fuzzer in `rx`, bytes to the fuzzer in `tx`
+    #[derive(Default)]
+    struct Loopback {
+        rx: VecDeque<u8>,
+        tx: Vec<u8>,
+    }
+
+    impl Transport for Loopback {
+        type Error = ();
+
+        fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), ()> {
+            for byte in buf {
+                *byte = self.rx.pop_front().ok_or(())?;
+            }
+            Ok(())
+        }
+
+        fn write_all(&mut self, buf: &[u8]) -> Result<(), ()> {
+            self.tx.extend_from_slice(buf);
+            Ok(())
+        }
+    }
+
+    fn push_frame(line: &mut Loopback, kind: MessageKind, payload: &[u8]) {
+        let mut frame = Vec::new();
+        crate::protocol::encode_frame(kind, 42, payload, &mut frame);
+        line.rx.extend(frame);
+    }
+
+    #[test]
+    fn test_run() {
+        let mut line = Loopback::default();
+        // garbage before the frame must be skipped
+        line.rx.extend([0x00, 0x42]);
+        push_frame(&mut line, MessageKind::Run, b"crash");
+
+        let mut input = [0u8; 16];
+        let mut map = [0u8; 64];
+        let mut agent = Agent::new(line, &mut input, &mut map);
+        agent
+            .handle_next(&mut |input: &[u8], map: &mut [u8]| {
+                map[3] = 1;
+                if input == b"crash" {
+                    RemoteExitKind::Crash
+                } else {
+                    RemoteExitKind::Ok
+                }
+            })
+            .unwrap();
+
+        let line = agent.transport();
+        let header = FrameHeader::decode(line.tx[..HEADER_LEN].try_into().unwrap()).unwrap();
+        assert_eq!(header.kind, MessageKind::Result);
+        assert_eq!(header.seq, 42);
+        let payload = &line.tx[HEADER_LEN..HEADER_LEN + header.len as usize];
+        assert_eq!(payload[0], RemoteExitKind::Crash as u8);
+
+        let mut received = [0u8; 64];
+        decode_map(payload[1], &payload[2..], &mut received).unwrap();
+        assert_eq!(received[3], 1);
+        assert_eq!(received.iter().filter(|&&entry| entry != 0).count(), 1);
+    }
+
+    #[test]
+    fn test_corrupted_input() {
+        let mut line = Loopback::default();
+        push_frame(&mut line, MessageKind::Run, b"input");
+        let checksum_at = line.rx.len() - 1;
+        line.rx[checksum_at] ^= 0xff;
+
+        let mut input = [0u8; 16];
+        let mut map = [0u8; 8];
+        let mut agent = Agent::new(line, &mut input, &mut map);
+        assert!(agent
+            .handle_next(&mut |_: &[u8], _: &mut [u8]| RemoteExitKind::Ok)
+            .is_err());
+        assert!(agent.transport().tx.is_empty());
+    }
+
+    #[test]
+    fn test_corrupted_header() {
+        let mut line = Loopback::default();
+        push_frame(&mut line, MessageKind::Run, b"input");
+        // A damaged length must not make the agent skip the frames after it
+        line.rx[9] = 0xff;
+        push_frame(&mut line, MessageKind::Hello, b"");
+
+        let mut input = [0u8; 16];
+        let mut map = [0u8; 8];
+        let mut agent = Agent::new(line, &mut input, &mut map);
+        agent
+            .handle_next(&mut |_: &[u8], _: &mut [u8]| RemoteExitKind::Ok)
+            .unwrap();
+
+        let line = agent.transport();
+        let header = FrameHeader::decode(line.tx[..HEADER_LEN].try_into().unwrap()).unwrap();
+        assert_eq!(header.kind, MessageKind::Ready);
+        assert!(line.rx.is_empty());
+    }
+}
@@ -0,0 +1,395 @@
+//! The framed protocol spoken between the `RemoteExecutor` and the [`crate::Agent`].
+//!
+//! Every frame is `MAGIC | kind | seq (u32 le) | length (u32 le) | header checksum (u16 le) | payload | checksum (u16 le)`.
+//! The header checksum is a CRC-16 over kind, sequence number and length, so that a damaged length is never
+//! trusted. The checksum after the payload is a Fletcher-16 over everything but the magic.
+//! Replies carry the sequence number of the request they answer, so late replies can be told apart.
+//! See the README of this crate for the messages.
+
+use core::fmt;
+
+/// The first byte of every frame
+pub const MAGIC: u8 = 0xAF;
+/// The version of the protocol, sent in [`MessageKind::Ready`]
+pub const PROTOCOL_VERSION: u8 = 2;
+/// The length of a frame header: magic, kind, sequence number, payload length and header checksum
+pub const HEADER_LEN: usize = 12;
+/// The sequence number of frames the agent sends on its own, i.e., when it (re)starts
+pub const UNSOLICITED_SEQ: u32 = 0;
+/// The length of the checksum after the payload
+pub const TRAILER_LEN: usize = 2;
+/// The length of a single entry in a [`MapEncoding::Sparse`] map: `u32` index and `u8` value
+pub const SPARSE_ENTRY_LEN: usize = 5;
+/// The length of the [`ReadyInfo`] payload
+pub const READY_LEN: usize = 9;
+
+/// An error in a received frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolError {
+    /// The frame did not start with [`MAGIC`]
+    BadMagic(u8),
+    /// The frame kind is unknown, or not expected in this direction
+    UnexpectedKind(u8),
+    /// The payload does not fit into the receive buffer
+    TooLarge {
+        /// The length of the payload
+        len: usize,
+        /// The size of the buffer
+        max: usize,
+    },
+    /// The checksum did not match
+    BadChecksum,
+    /// The checksum of the frame header did not match
+    BadHeaderChecksum,
+    /// The payload could not be decoded
+    Malformed,
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic(byte) => write!(f, "expected frame magic, got {byte:#x}"),
+            Self::UnexpectedKind(kind) => write!(f, "unexpected frame kind {kind:#x}"),
+            Self::TooLarge { len, max } => {
+                write!(
+                    f,
+                    "payload of {len} bytes is larger than the buffer ({max})"
+                )
+            }
+            Self::BadChecksum => write!(f, "bad frame checksum"),
+            Self::BadHeaderChecksum => write!(f, "bad frame header checksum"),
+            Self::Malformed => write!(f, "malformed payload"),
+        }
+    }
+}
+
+/// The kind of a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MessageKind {
+    /// Host to agent: ask the agent to announce itself
+    Hello = 0x01,
+    /// Host to agent: run the input in the payload
+    Run = 0x02,
+    /// Agent to host: the agent is ready, payload is a [`ReadyInfo`]
+    Ready = 0x81,
+    /// Agent to host: the result of a run
+    Result = 0x82,
+}
+
+impl TryFrom<u8> for MessageKind {
+    type Error = ProtocolError;
+
+    fn try_from(kind: u8) -> Result<Self, Self::Error> {
+        match kind {
+            0x01 => Ok(Self::Hello),
+            0x02 => Ok(Self::Run),
+            0x81 => Ok(Self::Ready),
+            0x82 => Ok(Self::Result),
+            _ => Err(ProtocolError::UnexpectedKind(kind)),
+        }
+    }
+}
+
+/// How a run on the device ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum RemoteExitKind {
+    /// The harness returned normally
+    Ok = 0,
+    /// The harness detected a crash, e.g., in a fault handler
+    Crash = 1,
+    /// The harness ran for too long, e.g., detected by a watchdog
+    Timeout = 2,
+    /// The harness ran out of memory
+    Oom = 3,
+}
+
+impl TryFrom<u8> for RemoteExitKind {
+    type Error = ProtocolError;
+
+    fn try_from(kind: u8) -> Result<Self, Self::Error> {
+        match kind {
+            0 => Ok(Self::Ok),
+            1 => Ok(Self::Crash),
+            2 => Ok(Self::Timeout),
+            3 => Ok(Self::Oom),
+            _ => Err(ProtocolError::Malformed),
+        }
+    }
+}
+
+/// How the coverage map is encoded in a [`MessageKind::Result`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MapEncoding {
+    /// Every byte of the map
+    Dense = 0,
+    /// Index and value of the non-zero entries
+    Sparse = 1,
+}
+
+impl MapEncoding {
+    /// The shorter encoding for a map of `len` entries, `nonzero` of which are set
+    #[must_use]
+    pub fn for_map(len: usize, nonzero: usize) -> Self {
+        if nonzero * SPARSE_ENTRY_LEN < len {
+            Self::Sparse
+        } else {
+            Self::Dense
+        }
+    }
+}
+
+impl TryFrom<u8> for MapEncoding {
+    type Error = ProtocolError;
+
+    fn try_from(encoding: u8) -> Result<Self, Self::Error> {
+        match encoding {
+            0 => Ok(Self::Dense),
+            1 => Ok(Self::Sparse),
+            _ => Err(ProtocolError::Malformed),
+        }
+    }
+}
+
+/// The header of a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    /// The kind of the frame
+    pub kind: MessageKind,
+    /// The sequence number of the request, or of the request answered
+    pub seq: u32,
+    /// The length of the payload
+    pub len: u32,
+}
+
+impl FrameHeader {
+    /// Create a new header
+    #[must_use]
+    pub fn new(kind: MessageKind, seq: u32, len: u32) -> Self {
+        Self { kind, seq, len }
+    }
+
+    /// Encode this header, including the [`MAGIC`] and the header checksum
+    #[must_use]
+    pub fn encode(&self) -> [u8; HEADER_LEN] {
+        let mut header = [0u8; HEADER_LEN];
+        header[0] = MAGIC;
+        header[1] = self.kind as u8;
+        header[2..6].copy_from_slice(&self.seq.to_le_bytes());
+        header[6..10].copy_from_slice(&self.len.to_le_bytes());
+        let crc = crc16(&header[1..10]);
+        header[10..].copy_from_slice(&crc.to_le_bytes());
+        header
+    }
+
+    /// Decode a header, verifying its checksum before anything else
+    pub fn decode(header: &[u8; HEADER_LEN]) -> Result<Self, ProtocolError> {
+        if header[0] != MAGIC {
+            return Err(ProtocolError::BadMagic(header[0]));
+        }
+        if u16::from_le_bytes([header[10], header[11]]) != crc16(&header[1..10]) {
+            return Err(ProtocolError::BadHeaderChecksum);
+        }
+        Ok(Self {
+            kind: MessageKind::try_from(header[1])?,
+            seq: u32::from_le_bytes([header[2], header[3], header[4], header[5]]),
+            len: u32::from_le_bytes([header[6], header[7], header[8], header[9]]),
+        })
+    }
+}
+
+/// What the agent tells about itself in a [`MessageKind::Ready`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadyInfo {
+    /// The [`PROTOCOL_VERSION`] of the agent
+    pub version: u8,
+    /// The size of the coverage map
+    pub map_size: u32,
+    /// The size of the input buffer
+    pub max_input_size: u32,
+}
+
+impl ReadyInfo {
+    /// Encode this info as payload
+    #[must_use]
+    pub fn encode(&self) -> [u8; READY_LEN] {
+        let mut payload = [0u8; READY_LEN];
+        payload[0] = self.version;
+        payload[1..5].copy_from_slice(&self.map_size.to_le_bytes());
+        payload[5..9].copy_from_slice(&self.max_input_size.to_le_bytes());
+        payload
+    }
+
+    /// Decode this info from a payload
+    pub fn decode(payload: &[u8]) -> Result<Self, ProtocolError> {
+        if payload.len() != READY_LEN {
+            return Err(ProtocolError::Malformed);
+        }
+        Ok(Self {
+            version: payload[0],
+            map_size: u32::from_le_bytes([payload[1], payload[2], payload[3], payload[4]]),
+            max_input_size: u32::from_le_bytes([payload[5], payload[6], payload[7], payload[8]]),
+        })
+    }
+}
+
+/// Fletcher-16 checksum, updated incrementally so that frames can be streamed
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Checksum {
+    sum1: u16,
+    sum2: u16,
+}
+
+impl Checksum {
+    /// Start a new checksum
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `data` to the checksum
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.sum1 = (self.sum1 + u16::from(byte)) % 255;
+            self.sum2 = (self.sum2 + self.sum1) % 255;
+        }
+    }
+
+    /// The checksum of everything added so far
+    #[must_use]
+    pub fn finish(&self) -> u16 {
+        (self.sum2 << 8) | self.sum1
+    }
+}
+
+/// CRC-16/CCITT of `data`, used for the frame header.
+///
+/// Unlike Fletcher-16, it notices a byte flipping between `0x00` and `0xff`, a likely damage to a length.
+#[must_use]
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xffff_u16;
+    for &byte in data {
+        crc ^= u16::from(byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 == 0 {
+                crc << 1
+            } else {
+                (crc << 1) ^ 0x1021
+            };
+        }
+    }
+    crc
+}
+
+/// Encode a single entry of a [`MapEncoding::Sparse`] map
+#[must_use]
+pub fn encode_sparse_entry(idx: u32, value: u8) -> [u8; SPARSE_ENTRY_LEN] {
+    let idx = idx.to_le_bytes();
+    [idx[0], idx[1], idx[2], idx[3], value]
+}
+
+/// Decode the coverage map of a [`MessageKind::Result`] into `map`.
+///
+/// Entries not present in a sparse map are set to zero.
+pub fn decode_map(encoding: u8, data: &[u8], map: &mut [u8]) -> Result<(), ProtocolError> {
+    match MapEncoding::try_from(encoding)? {
+        MapEncoding::Dense => {
+            if data.len() != map.len() {
+                return Err(ProtocolError::Malformed);
+            }
+            map.copy_from_slice(data);
+        }
+        MapEncoding::Sparse => {
+            let entries = data.chunks_exact(SPARSE_ENTRY_LEN);
+            if !entries.remainder().is_empty() {
+                return Err(ProtocolError::Malformed);
+            }
+            map.fill(0);
+            for entry in entries {
+                let idx = u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]) as usize;
+                *map.get_mut(idx).ok_or(ProtocolError::Malformed)? = entry[4];
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Encode a complete frame into `out`
+#[cfg(feature = "std")]
+pub fn encode_frame(kind: MessageKind, seq: u32, payload: &[u8], out: &mut std::vec::Vec<u8>) {
+    let len = u32::try_from(payload.len()).expect("Payloads larger than 4GB are not supported");
+    let header = FrameHeader::new(kind, seq, len).encode();
+    let mut checksum = Checksum::new();
+    checksum.update(&header[1..]);
+    checksum.update(payload);
+
+    out.extend_from_slice(&header);
+    out.extend_from_slice(payload);
+    out.extend_from_slice(&checksum.finish().to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_map, Checksum, FrameHeader, MessageKind, ProtocolError, ReadyInfo};
+
+    #[test]
+    fn test_header_roundtrip() {
+        let header = FrameHeader::new(MessageKind::Run, 7, 0x1234_5678);
+        assert_eq!(FrameHeader::decode(&header.encode()), Ok(header));
+
+        // A damaged length is caught by the header checksum
+        let mut bad = header.encode();
+        bad[9] = 0xff;
+        assert_eq!(
+            FrameHeader::decode(&bad),
+            Err(ProtocolError::BadHeaderChecksum)
+        );
+
+        let mut bad = header.encode();
+        bad[1] = 0x7f;
+        let crc = super::crc16(&bad[1..10]);
+        bad[10..].copy_from_slice(&crc.to_le_bytes());
+        assert_eq!(
+            FrameHeader::decode(&bad),
+            Err(ProtocolError::UnexpectedKind(0x7f))
+        );
+    }
+
+    #[test]
+    fn test_ready_roundtrip() {
+        let info = ReadyInfo {
+            version: 1,
+            map_size: 65536,
+            max_input_size: 1024,
+        };
+        assert_eq!(ReadyInfo::decode(&info.encode()), Ok(info));
+    }
+
+    #[test]
+    fn test_checksum() {
+        // Known Fletcher-16 value
+        let mut checksum = Checksum::new();
+        checksum.update(b"abcde");
+        assert_eq!(checksum.finish(), 0xC8F0);
+        // Known CRC-16/CCITT value
+        assert_eq!(super::crc16(b"123456789"), 0x29B1);
+    }
+
+    #[test]
+    fn test_sparse_map() {
+        let mut data = [0u8; 10];
+        data[..5].copy_from_slice(&super::encode_sparse_entry(2, 7));
+        data[5..].copy_from_slice(&super::encode_sparse_entry(9, 1));
+        let mut map = [0xffu8; 12];
+        decode_map(1, &data, &mut map).unwrap();
+        assert_eq!(map, [0, 0, 7, 0, 0, 0, 0, 0, 0, 1, 0, 0]);
+
+        let mut small = [0u8; 4];
+        assert_eq!(
+            decode_map(1, &data, &mut small),
+            Err(ProtocolError::Malformed)
+        );
+    }
+}
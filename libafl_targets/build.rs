@@ -56,6 +56,14 @@ fn main() {
         .map_or(Ok(SIXTY_FIVE_KB), str::parse)
         .expect("Could not parse LIBAFL_DDG_MAP_SIZE");
 
+    let ijon_map_size: usize = option_env!("LIBAFL_IJON_MAP_SIZE")
+        .map_or(Ok(SIXTY_FIVE_KB), str::parse)
+        .expect("Could not parse LIBAFL_IJON_MAP_SIZE");
+
+    let ijon_max_slots: usize = option_env!("LIBAFL_IJON_MAX_SLOTS")
+        .map_or(Ok(512), str::parse)
+        .expect("Could not parse LIBAFL_IJON_MAX_SLOTS");
+
     assert!(edges_map_default_size <= edges_map_allocated_size);
     assert!(edges_map_default_size.is_power_of_two());
 
@@ -77,6 +85,10 @@ fn main() {
         pub const ACCOUNTING_MAP_SIZE: usize = {acc_map_size};
         /// The size of the accounting maps
         pub const DDG_MAP_SIZE: usize = {ddg_map_size};        
+        /// The size of the IJON map
+        pub const IJON_MAP_SIZE: usize = {ijon_map_size};
+        /// The number of slots for IJON max and min annotations
+        pub const IJON_MAX_SLOTS: usize = {ijon_max_slots};
 "
     )
     .expect("Could not write file");
@@ -90,6 +102,8 @@ fn main() {
     println!("cargo:rerun-if-env-changed=LIBAFL_CMPLOG_MAP_H");
     println!("cargo:rerun-if-env-changed=LIBAFL_ACCOUNTING_MAP_SIZE");
     println!("cargo:rerun-if-env-changed=LIBAFL_DDG_MAP_SIZE");
+    println!("cargo:rerun-if-env-changed=LIBAFL_IJON_MAP_SIZE");
+    println!("cargo:rerun-if-env-changed=LIBAFL_IJON_MAX_SLOTS");
 
     #[cfg(feature = "common")]
     {
@@ -103,9 +117,42 @@ fn main() {
             common.define("DEFAULT_SANITIZERS_OPTIONS", "1");
         }
 
+        #[cfg(feature = "lsan")]
+        {
+            common.define("LIBAFL_LSAN", "1");
+        }
+
         common.file(src_dir.join("common.c")).compile("common");
     }
 
+    #[cfg(feature = "lsan")]
+    if std::env::var("CARGO_CFG_TARGET_FAMILY").unwrap() == "unix" {
+        println!("cargo:rerun-if-changed=src/lsan.c");
+
+        cc::Build::new()
+            .file(src_dir.join("lsan.c"))
+            .compile("lsan");
+    }
+
+    #[cfg(feature = "memlock")]
+    if std::env::var("CARGO_CFG_TARGET_FAMILY").unwrap() == "unix" {
+        println!("cargo:rerun-if-changed=src/memlock.c");
+
+        let mut memlock = cc::Build::new();
+
+        #[cfg(feature = "libfuzzer")]
+        {
+            memlock.define("LIBAFL_LIBFUZZER", "1");
+        }
+
+        #[cfg(feature = "memlock_malloc")]
+        {
+            memlock.define("LIBAFL_MEMLOCK_MALLOC", "1");
+        }
+
+        memlock.file(src_dir.join("memlock.c")).compile("memlock");
+    }
+
     #[cfg(any(feature = "sancov_value_profile", feature = "sancov_cmplog"))]
     {
         println!("cargo:rerun-if-changed=src/sancov_cmp.c");
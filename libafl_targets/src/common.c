@@ -1,11 +1,20 @@
 #include "common.h"
 
 #ifdef DEFAULT_SANITIZERS_OPTIONS
-// TODO MSan and LSan. however they don't support abort_on_error
+// TODO MSan. however it does not support abort_on_error
+
+  #ifdef LIBAFL_LSAN
+// Leaks are checked by the fuzzer after each run, not when the process exits.
+// Keep the allocation stacks around, they are used to deduplicate the leaks.
+    #define LIBAFL_LEAK_OPTIONS \
+      "detect_leaks=1:leak_check_at_exit=0:malloc_context_size=30:"
+  #else
+    #define LIBAFL_LEAK_OPTIONS "detect_leaks=0:malloc_context_size=0:"
+  #endif
 
 const char *__asan_default_options() {
-  return "abort_on_error=1:detect_leaks=0:"
-         "malloc_context_size=0:symbolize=0:"
+  return "abort_on_error=1:" LIBAFL_LEAK_OPTIONS
+         "symbolize=0:"
          "allocator_may_return_null=1:"
          "detect_odr_violation=0:handle_segv=0:"
          "handle_sigbus=0:handle_abort=0:"
@@ -20,4 +29,9 @@ const char *__ubsan_default_options() {
          "handle_sigill=0:print_stacktrace=0:"
          "symbolize=0:symbolize_inline_frames=0";
 }
+  #ifdef LIBAFL_LSAN
+const char *__lsan_default_options() {
+  return "detect_leaks=1:leak_check_at_exit=0:symbolize=0";
+}
+  #endif
 #endif  // DEFAULT_SANITIZERS_OPTIONS
@@ -0,0 +1,583 @@
+//! Source-level coverage reports of the corpus, from the `sancov` PC tables.
+//!
+//! Build the target with `-fsanitize-coverage=trace-pc-guard,pc-table` and debug info (`-g`).
+//! [`replay_corpus_coverage`] runs every corpus entry and counts the entries covering each edge,
+//! a [`CoverageReport`] maps the PC of every edge to its function and source line via `DWARF`
+//! and writes `lcov` and HTML reports, and a per-function summary listing the least covered functions first,
+//! i.e., the likely fuzz blockers. The [`CoverageReportStage`] does all of it periodically while fuzzing.
+//!
+//! The edges map only tells whether an edge was hit by a run, not how often, so the counts of the reports are
+//! corpus entries, not executions: a line or function counts the corpus entries covering it.
+
+use alloc::{
+    borrow::Cow,
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{ffi::CStr, fmt, marker::PhantomData, time::Duration};
+use std::{
+    fs,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use hashbrown::HashMap;
+use libafl::{
+    corpus::{Corpus, CorpusId},
+    fuzzer::ExecutesInput,
+    inputs::UsesInput,
+    stages::Stage,
+    state::{HasCorpus, UsesState},
+    Error,
+};
+use libafl_bolts::Named;
+
+use crate::{coverage::MAX_EDGES_FOUND, edges_map_mut_ptr, sanitizer_cov_pc_table};
+
+/// The source location of a PC
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    /// The source file
+    pub file: String,
+    /// The line in the source file, `0` if unknown
+    pub line: u32,
+    /// The column in the source line, `0` if unknown
+    pub column: u32,
+    /// The (demangled) name of the function, with inlined functions resolved to their caller
+    pub function: String,
+    /// The linkage name of the symbol containing the PC, as named in the LLVM IR
+    pub symbol: Option<String>,
+}
+
+/// Resolves PCs of this process to [`SourceLocation`]s using the `DWARF` debug info of the loaded modules
+#[derive(Default)]
+pub struct Symbolizer {
+    modules: HashMap<PathBuf, Option<addr2line::Loader>>,
+}
+
+impl fmt::Debug for Symbolizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Symbolizer")
+            .field("modules", &self.modules.keys().collect::<Vec<_>>())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Symbolizer {
+    /// Creates a new [`Symbolizer`]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resolves the `pc`, if the module containing it has debug info
+    pub fn symbolize(&mut self, pc: usize) -> Option<SourceLocation> {
+        let mut info: libc::Dl_info = unsafe { core::mem::zeroed() };
+        if unsafe { libc::dladdr(pc as *const libc::c_void, &mut info) } == 0
+            || info.dli_fname.is_null()
+        {
+            return None;
+        }
+        let module = PathBuf::from(
+            unsafe { CStr::from_ptr(info.dli_fname) }
+                .to_string_lossy()
+                .into_owned(),
+        );
+        let base = info.dli_fbase as usize;
+
+        let loader = self
+            .modules
+            .entry(module)
+            .or_insert_with_key(|module| match addr2line::Loader::new(module) {
+                Ok(loader) => Some(loader),
+                Err(err) => {
+                    log::warn!("Cannot load debug info of {}: {err}", module.display());
+                    None
+                }
+            })
+            .as_ref()?;
+
+        let probe = (pc - base) as u64 + loader.relative_address_base();
+        let location = loader.find_location(probe).ok().flatten();
+
+        // The outermost frame is the function the code was inlined into
+        let mut function = None;
+        if let Ok(mut frames) = loader.find_frames(probe) {
+            while let Ok(Some(frame)) = frames.next() {
+                if let Some(name) = frame
+                    .function
+                    .as_ref()
+                    .and_then(|name| name.demangle().ok())
+                {
+                    function = Some(name.into_owned());
+                }
+            }
+        }
+        let symbol = loader.find_symbol(probe).map(ToString::to_string);
+        let function = function
+            .or_else(|| symbol.clone())
+            .unwrap_or_else(|| format!("{pc:#x}"));
+
+        Some(SourceLocation {
+            file: location
+                .as_ref()
+                .and_then(|location| location.file)
+                .unwrap_or("<unknown>")
+                .to_string(),
+            line: location
+                .as_ref()
+                .and_then(|location| location.line)
+                .unwrap_or(0),
+            column: location
+                .as_ref()
+                .and_then(|location| location.column)
+                .unwrap_or(0),
+            function,
+            symbol,
+        })
+    }
+}
+
+/// The coverage of a single function
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FunctionCoverage {
+    /// The first line of the function
+    pub line: u32,
+    /// The number of instrumented PCs
+    pub total_pcs: usize,
+    /// The number of covered PCs
+    pub covered_pcs: usize,
+    /// How many corpus entries reach the function entry
+    pub entries: u64,
+}
+
+/// The coverage of a single source file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileCoverage {
+    /// The instrumented lines and how many corpus entries cover them
+    pub lines: BTreeMap<u32, u64>,
+    /// The functions in this file
+    pub functions: BTreeMap<String, FunctionCoverage>,
+}
+
+impl FileCoverage {
+    /// The number of covered lines
+    #[must_use]
+    pub fn covered_lines(&self) -> usize {
+        self.lines.values().filter(|entries| **entries > 0).count()
+    }
+}
+
+/// A source-level coverage report
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CoverageReport {
+    /// The coverage per source file
+    pub files: BTreeMap<String, FileCoverage>,
+}
+
+impl CoverageReport {
+    /// Creates a new, empty [`CoverageReport`]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a report from the corpus entries covering each edge, as returned by [`replay_corpus_coverage`],
+    /// resolving the PCs of the `sancov` PC tables of this process
+    #[must_use]
+    pub fn from_covering_entries(covering_entries: &[u64], symbolizer: &mut Symbolizer) -> Self {
+        let mut report = Self::new();
+        let pcs = sanitizer_cov_pc_table().flat_map(<[_]>::iter);
+        for (pc, entries) in pcs.zip(covering_entries.iter()) {
+            if let Some(location) = symbolizer.symbolize(pc.addr()) {
+                report.add_pc(&location, pc.is_function_entry(), *entries);
+            }
+        }
+        report
+    }
+
+    /// Adds an instrumented PC at `location`, covered by `entries` corpus entries.
+    ///
+    /// A line with several PCs counts the entries of its most covered PC, a lower bound of the entries covering the line.
+    pub fn add_pc(&mut self, location: &SourceLocation, is_function_entry: bool, entries: u64) {
+        let file = self.files.entry(location.file.clone()).or_default();
+        if location.line != 0 {
+            let line = file.lines.entry(location.line).or_default();
+            *line = (*line).max(entries);
+        }
+
+        let function = file
+            .functions
+            .entry(location.function.clone())
+            .or_insert_with(|| FunctionCoverage {
+                line: location.line,
+                ..FunctionCoverage::default()
+            });
+        function.total_pcs += 1;
+        if entries > 0 {
+            function.covered_pcs += 1;
+        }
+        if is_function_entry {
+            function.line = location.line;
+            function.entries += entries;
+        }
+    }
+
+    /// Writes the report in the `lcov` tracefile format, for `genhtml` and friends.
+    ///
+    /// The `DA` and `FNDA` counts are the corpus entries covering the line or function, not execution counts.
+    pub fn write_lcov<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "TN:libafl")?;
+        for (path, file) in &self.files {
+            writeln!(out, "SF:{path}")?;
+            for (name, function) in &file.functions {
+                writeln!(out, "FN:{},{name}", function.line)?;
+            }
+            for (name, function) in &file.functions {
+                writeln!(out, "FNDA:{},{name}", function.entries)?;
+            }
+            let functions_hit = file.functions.values().filter(|f| f.entries > 0).count();
+            writeln!(out, "FNF:{}", file.functions.len())?;
+            writeln!(out, "FNH:{functions_hit}")?;
+            for (line, entries) in &file.lines {
+                writeln!(out, "DA:{line},{entries}")?;
+            }
+            writeln!(out, "LF:{}", file.lines.len())?;
+            writeln!(out, "LH:{}", file.covered_lines())?;
+            writeln!(out, "end_of_record")?;
+        }
+        Ok(())
+    }
+
+    /// Writes one line per function, the functions with the most uncovered PCs first
+    pub fn write_function_summary<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut functions: Vec<(&String, &String, &FunctionCoverage)> = self
+            .files
+            .iter()
+            .flat_map(|(path, file)| {
+                file.functions
+                    .iter()
+                    .map(move |(name, function)| (path, name, function))
+            })
+            .collect();
+        functions.sort_by_key(|(path, name, function)| {
+            (
+                core::cmp::Reverse(function.total_pcs - function.covered_pcs),
+                *path,
+                *name,
+            )
+        });
+
+        writeln!(
+            out,
+            "uncovered\tcovered\ttotal\tcoverage\tfunction\tlocation"
+        )?;
+        for (path, name, function) in functions {
+            #[allow(clippy::cast_precision_loss)]
+            let percent = 100.0 * function.covered_pcs as f64 / function.total_pcs as f64;
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{percent:.1}%\t{name}\t{path}:{}",
+                function.total_pcs - function.covered_pcs,
+                function.covered_pcs,
+                function.total_pcs,
+                function.line
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes an HTML report to `dir`: an index of all files, and one page per file showing
+    /// the source (if it can be read) with covered and uncovered lines highlighted,
+    /// next to the number of corpus entries covering them
+    pub fn write_html(&self, dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(dir)?;
+        let mut index = BufWriter::new(fs::File::create(dir.join("index.html"))?);
+        write_html_header(&mut index, "Coverage")?;
+        writeln!(
+            index,
+            "<table><tr><th>File</th><th>Lines</th><th>Functions</th></tr>"
+        )?;
+
+        for (id, (path, file)) in self.files.iter().enumerate() {
+            let page = format!("file{id}.html");
+            let functions_hit = file.functions.values().filter(|f| f.entries > 0).count();
+            writeln!(
+                index,
+                "<tr><td><a href=\"{page}\">{}</a></td><td>{}</td><td>{}</td></tr>",
+                html_escape(path),
+                ratio(file.covered_lines(), file.lines.len()),
+                ratio(functions_hit, file.functions.len()),
+            )?;
+
+            let mut out = BufWriter::new(fs::File::create(dir.join(&page))?);
+            write_html_header(&mut out, path)?;
+            writeln!(out, "<p><a href=\"index.html\">Index</a></p><pre>")?;
+            match fs::read_to_string(path) {
+                Ok(source) => {
+                    for (line, text) in (1..).zip(source.lines()) {
+                        let class = match file.lines.get(&line) {
+                            Some(0) => "uncovered",
+                            Some(_) => "covered",
+                            None => "",
+                        };
+                        let entries = file.lines.get(&line).map(u64::to_string);
+                        writeln!(
+                            out,
+                            "<span class=\"{class}\">{line:>6} {:>8} {}</span>",
+                            entries.unwrap_or_default(),
+                            html_escape(text)
+                        )?;
+                    }
+                }
+                Err(_) => {
+                    for (line, entries) in &file.lines {
+                        let class = if *entries > 0 { "covered" } else { "uncovered" };
+                        writeln!(out, "<span class=\"{class}\">{line:>6} {entries:>8}</span>")?;
+                    }
+                }
+            }
+            writeln!(out, "</pre></body></html>")?;
+            out.flush()?;
+        }
+
+        writeln!(index, "</table></body></html>")?;
+        index.flush()
+    }
+}
+
+fn ratio(covered: usize, total: usize) -> String {
+    if total == 0 {
+        return "-".to_string();
+    }
+    #[allow(clippy::cast_precision_loss)]
+    let percent = 100.0 * covered as f64 / total as f64;
+    format!("{covered}/{total} ({percent:.1}%)")
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn write_html_header<W: Write>(out: &mut W, title: &str) -> io::Result<()> {
+    writeln!(
+        out,
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{}</title><style>\
+         .covered {{ background: #c8f0c8; }} .uncovered {{ background: #f0c8c8; }} \
+         td, th {{ padding: 0 1em; text-align: left; }}</style></head><body><h1>{}</h1>",
+        html_escape(title),
+        html_escape(title)
+    )
+}
+
+/// Runs every corpus entry and calls `on_entry` with its id and the `sancov` edges map of its run
+pub fn replay_corpus_with<E, EM, Z, F>(
+    fuzzer: &mut Z,
+    executor: &mut E,
+    state: &mut Z::State,
+    manager: &mut EM,
+    mut on_entry: F,
+) -> Result<(), Error>
+where
+    E: UsesState<State = Z::State>,
+    EM: UsesState<State = Z::State>,
+    Z: ExecutesInput<E, EM>,
+    Z::State: HasCorpus,
+    <Z::State as HasCorpus>::Corpus: Corpus<Input = <Z::State as UsesInput>::Input>,
+    F: FnMut(&mut Z::State, CorpusId, &[u8]) -> Result<(), Error>,
+{
+    let edges = unsafe { MAX_EDGES_FOUND };
+
+    let mut corpus_id = state.corpus().first();
+    while let Some(id) = corpus_id {
+        let input = state.corpus().cloned_input_for_id(id)?;
+        fuzzer.execute_input(state, executor, manager, &input)?;
+
+        // # Safety
+        // The target is done, nobody writes the map.
+        let map = unsafe { core::slice::from_raw_parts(edges_map_mut_ptr(), edges) };
+        on_entry(state, id, map)?;
+        corpus_id = state.corpus().next(id);
+    }
+    Ok(())
+}
+
+/// Runs every corpus entry and returns, per edge of the `sancov` edges map, how many entries cover it
+pub fn replay_corpus_coverage<E, EM, Z>(
+    fuzzer: &mut Z,
+    executor: &mut E,
+    state: &mut Z::State,
+    manager: &mut EM,
+) -> Result<Vec<u64>, Error>
+where
+    E: UsesState<State = Z::State>,
+    EM: UsesState<State = Z::State>,
+    Z: ExecutesInput<E, EM>,
+    Z::State: HasCorpus,
+    <Z::State as HasCorpus>::Corpus: Corpus<Input = <Z::State as UsesInput>::Input>,
+{
+    let mut covering_entries = vec![0u64; unsafe { MAX_EDGES_FOUND }];
+    replay_corpus_with(fuzzer, executor, state, manager, |_state, _id, map| {
+        for (entries, edge) in covering_entries.iter_mut().zip(map) {
+            if *edge != 0 {
+                *entries += 1;
+            }
+        }
+        Ok(())
+    })?;
+    Ok(covering_entries)
+}
+
+/// The name of the [`CoverageReportStage`]
+pub const COVERAGE_REPORT_STAGE_NAME: &str = "coverage_report";
+
+/// A stage replaying the corpus to write a source-level [`CoverageReport`] to a directory, periodically.
+///
+/// Writes `coverage.lcov`, `functions.tsv` and `html/index.html`.
+#[derive(Debug)]
+pub struct CoverageReportStage<EM, Z> {
+    name: Cow<'static, str>,
+    report_dir: PathBuf,
+    interval: Duration,
+    last_report: Option<Instant>,
+    symbolizer: Symbolizer,
+    phantom: PhantomData<(EM, Z)>,
+}
+
+impl<EM, Z> CoverageReportStage<EM, Z> {
+    /// Creates a new [`CoverageReportStage`], writing a report to `report_dir` at most every `interval`
+    pub fn new<P>(report_dir: P, interval: Duration) -> Result<Self, Error>
+    where
+        P: Into<PathBuf>,
+    {
+        let report_dir = report_dir.into();
+        fs::create_dir_all(&report_dir)?;
+        Ok(Self {
+            name: Cow::Borrowed(COVERAGE_REPORT_STAGE_NAME),
+            report_dir,
+            interval,
+            last_report: None,
+            symbolizer: Symbolizer::new(),
+            phantom: PhantomData,
+        })
+    }
+
+    /// Writes the `report` to the report directory
+    pub fn write_report(&self, report: &CoverageReport) -> Result<(), Error> {
+        let mut lcov = BufWriter::new(fs::File::create(self.report_dir.join("coverage.lcov"))?);
+        report.write_lcov(&mut lcov)?;
+        lcov.flush()?;
+
+        let mut summary = BufWriter::new(fs::File::create(self.report_dir.join("functions.tsv"))?);
+        report.write_function_summary(&mut summary)?;
+        summary.flush()?;
+
+        report.write_html(&self.report_dir.join("html"))?;
+        Ok(())
+    }
+}
+
+impl<EM, Z> UsesState for CoverageReportStage<EM, Z>
+where
+    EM: UsesState,
+{
+    type State = EM::State;
+}
+
+impl<EM, Z> Named for CoverageReportStage<EM, Z> {
+    fn name(&self) -> &Cow<'static, str> {
+        &self.name
+    }
+}
+
+impl<E, EM, Z> Stage<E, EM, Z> for CoverageReportStage<EM, Z>
+where
+    E: UsesState<State = Self::State>,
+    EM: UsesState,
+    Z: ExecutesInput<E, EM, State = Self::State>,
+    EM::State: HasCorpus,
+    <EM::State as HasCorpus>::Corpus: Corpus<Input = EM::Input>,
+{
+    fn perform(
+        &mut self,
+        fuzzer: &mut Z,
+        executor: &mut E,
+        state: &mut Self::State,
+        manager: &mut EM,
+    ) -> Result<(), Error> {
+        if self
+            .last_report
+            .is_some_and(|last| last.elapsed() < self.interval)
+        {
+            return Ok(());
+        }
+
+        let covering_entries = replay_corpus_coverage(fuzzer, executor, state, manager)?;
+        let report = CoverageReport::from_covering_entries(&covering_entries, &mut self.symbolizer);
+        self.write_report(&report)?;
+        self.last_report = Some(Instant::now());
+        Ok(())
+    }
+
+    #[inline]
+    fn should_restart(&mut self, _state: &mut Self::State) -> Result<bool, Error> {
+        // Only replays inputs already in the corpus, restarting is fine
+        Ok(true)
+    }
+
+    #[inline]
+    fn clear_progress(&mut self, _state: &mut Self::State) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{
+        string::{String, ToString},
+        vec::Vec,
+    };
+
+    use super::{CoverageReport, SourceLocation};
+
+    #[test]
+    fn test_coverage_report() {
+        let location = |line, function: &str| SourceLocation {
+            file: "a.c".to_string(),
+            line,
+            column: 0,
+            function: function.to_string(),
+            symbol: None,
+        };
+        let mut report = CoverageReport::new();
+        report.add_pc(&location(1, "main"), true, 3);
+        report.add_pc(&location(2, "main"), false, 1);
+        // The same line, the most covered PC counts
+        report.add_pc(&location(2, "main"), false, 2);
+        report.add_pc(&location(3, "main"), false, 0);
+        report.add_pc(&location(10, "parse"), true, 0);
+        report.add_pc(&location(11, "parse"), false, 0);
+
+        let mut lcov = Vec::new();
+        report.write_lcov(&mut lcov).unwrap();
+        let lcov = String::from_utf8(lcov).unwrap();
+        assert!(lcov.contains("SF:a.c\n"));
+        assert!(lcov.contains("FN:10,parse\n"));
+        assert!(lcov.contains("FNDA:3,main\n"));
+        assert!(lcov.contains("DA:2,2\n"));
+        assert!(lcov.contains("DA:3,0\n"));
+        assert!(lcov.contains("LF:5\nLH:2\n"));
+
+        let mut summary = Vec::new();
+        report.write_function_summary(&mut summary).unwrap();
+        let summary = String::from_utf8(summary).unwrap();
+        // The completely uncovered function comes first
+        let first = summary.lines().nth(1).unwrap();
+        assert!(first.starts_with("2\t0\t2\t0.0%\tparse\ta.c:10"));
+    }
+}
@@ -0,0 +1,750 @@
+//! Fuzz-blocker frontier analysis, from the control flow graphs dumped by `libafl_cc` and the corpus coverage.
+//!
+//! Build the target with the `dump-cfg` pass of `libafl_cc` (writing a `.cfg` file per module to
+//! `CFG_OUTPUT_PATH`), `-fsanitize-coverage=trace-pc-guard,pc-table,no-prune` and debug info (`-g`).
+//! The basic blocks of the [`ProgramCfg`] are matched to the covered PCs through their source locations,
+//! and every uncovered successor of a covered block becomes a [`FrontierEdge`]: a branch the fuzzer
+//! never managed to flip. The edges are ranked by the number of uncovered blocks hiding behind them,
+//! then by how often the guarding block was covered, i.e., how hard the fuzzer already tried.
+//!
+//! The [`FrontierStage`] writes the ranked frontier to `frontier.tsv` periodically and tags the corpus
+//! entries covering the guard of a top frontier edge with [`FrontierTestcaseMetadata`].
+//! Use [`reaches_frontier`] to spend the comparison solving stages on these entries only:
+//!
+//! ```rust,ignore
+//! let stages = tuple_list!(
+//!     FrontierStage::new(cfg_dir, report_dir, Duration::from_secs(600), 32)?,
+//!     IfStage::new(
+//!         |_fuzzer, _executor, state, _manager| reaches_frontier(state),
+//!         tuple_list!(tracing, StdMutationalStage::new(StdScheduledMutator::new(tuple_list!(I2SRandReplace::new()))))
+//!     ),
+//!     mutational
+//! );
+//! ```
+
+use alloc::{
+    borrow::Cow,
+    collections::{BTreeMap, VecDeque},
+    string::String,
+    vec::Vec,
+};
+use core::{marker::PhantomData, time::Duration};
+use std::{
+    fs,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use hashbrown::{HashMap, HashSet};
+use libafl::{
+    corpus::{Corpus, CorpusId},
+    fuzzer::ExecutesInput,
+    stages::Stage,
+    state::{HasCorpus, HasCurrentTestcase, UsesState},
+    Error, HasMetadata,
+};
+use libafl_bolts::Named;
+use serde::{Deserialize, Serialize};
+
+use crate::{replay_corpus_with, sanitizer_cov_pc_table, SourceLocation, Symbolizer};
+
+/// A `.cfg` file, as written by the `dump-cfg` pass
+#[derive(Debug, Deserialize)]
+struct ModuleCfgFile {
+    #[serde(default)]
+    module: String,
+    #[serde(default)]
+    edges: BTreeMap<String, Vec<Option<Vec<usize>>>>,
+    #[serde(default)]
+    calls: BTreeMap<String, BTreeMap<String, Vec<String>>>,
+    #[serde(default)]
+    entries: BTreeMap<String, usize>,
+    #[serde(default)]
+    locs: BTreeMap<String, Vec<Option<(u32, u32)>>>,
+}
+
+/// The control flow graph of a single function
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FunctionCfg {
+    /// The successors of each basic block
+    pub successors: Vec<Vec<usize>>,
+    /// The functions called by each basic block
+    pub calls: BTreeMap<usize, Vec<String>>,
+    /// The source line and column of each basic block, `(0, 0)` if unknown
+    pub locations: Vec<(u32, u32)>,
+    /// The entry block
+    pub entry: usize,
+}
+
+impl FunctionCfg {
+    /// The number of basic blocks
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.successors.len()
+    }
+
+    /// If the function has no basic blocks, i.e., is only declared
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.successors.is_empty()
+    }
+}
+
+/// A function, identified by the source file of its module and its linkage name.
+///
+/// Functions with internal linkage (`static`) of different modules may share the same name.
+pub type FunctionId = (String, String);
+
+/// The control flow graphs of all functions of the target, merged from the modules dumped by `libafl_cc`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProgramCfg {
+    /// The functions, by their module and linkage name. Added through [`Self::add_module`].
+    pub functions: HashMap<FunctionId, FunctionCfg>,
+    /// The modules defining a function of each linkage name
+    modules: HashMap<String, Vec<String>>,
+}
+
+impl ProgramCfg {
+    /// Creates a new, empty [`ProgramCfg`]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads all `.cfg` files in `dir`, usually the `CFG_OUTPUT_PATH` of the build
+    pub fn from_dir<P>(dir: P) -> Result<Self, Error>
+    where
+        P: AsRef<Path>,
+    {
+        let mut cfg = Self::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "cfg") {
+                cfg.add_module(&fs::read_to_string(&path)?)?;
+            }
+        }
+        Ok(cfg)
+    }
+
+    /// Adds the functions of a module, from the content of its `.cfg` file
+    pub fn add_module(&mut self, content: &str) -> Result<(), Error> {
+        let module: ModuleCfgFile = serde_json::from_str(content)
+            .map_err(|err| Error::illegal_argument(format!("Invalid CFG file: {err}")))?;
+
+        for (name, edges) in module.edges {
+            let modules = self.modules.entry(name.clone()).or_default();
+            if !modules.contains(&module.module) {
+                modules.push(module.module.clone());
+            }
+            let successors: Vec<Vec<usize>> =
+                edges.into_iter().map(Option::unwrap_or_default).collect();
+            let len = successors.len();
+            let mut locations: Vec<(u32, u32)> = module
+                .locs
+                .get(&name)
+                .map(|locs| locs.iter().map(|loc| loc.unwrap_or_default()).collect())
+                .unwrap_or_default();
+            locations.resize(len, (0, 0));
+            let calls = module
+                .calls
+                .get(&name)
+                .map(|calls| {
+                    calls
+                        .iter()
+                        .filter_map(|(block, callees)| Some((block.parse().ok()?, callees.clone())))
+                        .collect()
+                })
+                .unwrap_or_default();
+            let entry = module.entries.get(&name).copied().unwrap_or(0);
+
+            self.functions.insert(
+                (module.module.clone(), name),
+                FunctionCfg {
+                    successors,
+                    calls,
+                    locations,
+                    entry,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// The function called `name` as seen from `module`: the one defined in `module` itself if any,
+    /// e.g., a `static` function, else the one of another module.
+    #[must_use]
+    pub fn resolve(&self, module: &str, name: &str) -> Option<(&FunctionId, &FunctionCfg)> {
+        let modules = self.modules.get(name)?;
+        let module = modules
+            .iter()
+            .find(|other| *other == module)
+            .unwrap_or(&modules[0]);
+        self.functions
+            .get_key_value(&(module.clone(), String::from(name)))
+    }
+
+    /// The function called `name` containing code of the source `file`.
+    /// If several modules define `name`, only the one compiled from `file` matches.
+    fn function_at(&self, name: &str, file: &str) -> Option<(&FunctionId, &FunctionCfg)> {
+        let modules = self.modules.get(name)?;
+        let module = match modules.as_slice() {
+            [module] => module,
+            _ => modules.iter().find(|module| {
+                Path::new(file).ends_with(module.as_str())
+                    || Path::new(module.as_str()).ends_with(file)
+            })?,
+        };
+        self.functions
+            .get_key_value(&(module.clone(), String::from(name)))
+    }
+
+    /// The predecessors of each block of `function`
+    fn predecessors(function: &FunctionCfg) -> Vec<Vec<usize>> {
+        let mut predecessors = vec![Vec::new(); function.len()];
+        for (block, successors) in function.successors.iter().enumerate() {
+            for successor in successors {
+                if let Some(preds) = predecessors.get_mut(*successor) {
+                    preds.push(block);
+                }
+            }
+        }
+        predecessors
+    }
+}
+
+/// A basic block, identified by its function and its index in the function
+pub type BlockId = (FunctionId, usize);
+
+/// The coverage of a basic block, from the `sancov` PCs matched to it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockCoverage {
+    /// How often the block was covered
+    pub hits: u64,
+    /// The source file of the block
+    pub file: String,
+    /// The indices of the `sancov` edges map matched to the block
+    pub edges: Vec<usize>,
+}
+
+/// The coverage of the basic blocks of a [`ProgramCfg`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CfgCoverage {
+    /// The coverage of the blocks any PC was matched to
+    pub blocks: HashMap<BlockId, BlockCoverage>,
+}
+
+impl CfgCoverage {
+    /// Creates a new, empty [`CfgCoverage`]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Matches the `hits` per edge, as returned by [`crate::replay_corpus_coverage`], to the blocks of `cfg`,
+    /// resolving the PCs of the `sancov` PC tables of this process
+    #[must_use]
+    pub fn from_edge_hits(cfg: &ProgramCfg, hits: &[u64], symbolizer: &mut Symbolizer) -> Self {
+        let mut coverage = Self::new();
+        let entries = sanitizer_cov_pc_table().flat_map(<[_]>::iter);
+        for (edge, (entry, hits)) in entries.zip(hits.iter()).enumerate() {
+            if let Some(location) = symbolizer.symbolize(entry.addr()) {
+                coverage.add_pc(cfg, &location, edge, *hits);
+            }
+        }
+        coverage
+    }
+
+    /// Adds the PC of the `edge` at `location`, covered `hits` times, to the blocks of its function starting
+    /// at the same line and column, or, if there are none, at the same line.
+    ///
+    /// If several modules define a function of that name, the PC is matched to the one of the module
+    /// compiled from the source file of `location`.
+    pub fn add_pc(&mut self, cfg: &ProgramCfg, location: &SourceLocation, edge: usize, hits: u64) {
+        if location.line == 0 {
+            return;
+        }
+        let name = location.symbol.as_ref().unwrap_or(&location.function);
+        let Some((id, function)) = cfg.function_at(name, &location.file) else {
+            return;
+        };
+
+        let mut blocks: Vec<usize> = function
+            .locations
+            .iter()
+            .enumerate()
+            .filter(|(_, loc)| **loc == (location.line, location.column))
+            .map(|(block, _)| block)
+            .collect();
+        if blocks.is_empty() {
+            blocks = function
+                .locations
+                .iter()
+                .enumerate()
+                .filter(|(_, (line, _))| *line == location.line)
+                .map(|(block, _)| block)
+                .collect();
+        }
+
+        for block in blocks {
+            let coverage = self.blocks.entry((id.clone(), block)).or_default();
+            coverage.hits = coverage.hits.max(hits);
+            if coverage.file.is_empty() {
+                coverage.file.clone_from(&location.file);
+            }
+            coverage.edges.push(edge);
+        }
+    }
+
+    /// How often the block was covered, `0` if no PC was matched to it
+    #[must_use]
+    pub fn hits(&self, function: &FunctionId, block: usize) -> u64 {
+        self.blocks
+            .get(&(function.clone(), block))
+            .map_or(0, |coverage| coverage.hits)
+    }
+}
+
+/// An uncovered successor of a covered basic block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrontierEdge {
+    /// The source file of the module of the function
+    pub module: String,
+    /// The linkage name of the function
+    pub function: String,
+    /// The source file of the guarding block
+    pub file: String,
+    /// The covered block guarding the edge
+    pub guard: usize,
+    /// The source line of the guarding block
+    pub guard_line: u32,
+    /// The uncovered successor
+    pub target: usize,
+    /// The source line of the uncovered successor
+    pub target_line: u32,
+    /// How often the guarding block was covered
+    pub guard_hits: u64,
+    /// The number of uncovered blocks reachable from the successor through uncovered code, including called functions
+    pub hidden_blocks: usize,
+    /// The indices of the `sancov` edges map matched to the guarding block
+    pub guard_edges: Vec<usize>,
+}
+
+/// Computes the frontier of the `coverage` in `cfg`, ranked by [`FrontierEdge::hidden_blocks`],
+/// then by [`FrontierEdge::guard_hits`]
+#[must_use]
+pub fn compute_frontier(cfg: &ProgramCfg, coverage: &CfgCoverage) -> Vec<FrontierEdge> {
+    let covered = covered_blocks(cfg, coverage);
+    let is_covered = |function: &FunctionId, block: usize| -> bool {
+        covered
+            .get(function)
+            .and_then(|blocks| blocks.get(block))
+            .copied()
+            .unwrap_or(false)
+    };
+
+    let mut frontier = Vec::new();
+    let mut hidden_cache: HashMap<BlockId, usize> = HashMap::new();
+    for (id, function) in &cfg.functions {
+        for (guard, successors) in function.successors.iter().enumerate() {
+            if !is_covered(id, guard) {
+                continue;
+            }
+            let mut targets = HashSet::new();
+            for target in successors {
+                if *target >= function.len() || is_covered(id, *target) || !targets.insert(*target)
+                {
+                    continue;
+                }
+                let hidden_blocks = *hidden_cache
+                    .entry((id.clone(), *target))
+                    .or_insert_with(|| hidden_blocks(cfg, &is_covered, id, *target));
+                let guard_coverage = coverage.blocks.get(&(id.clone(), guard));
+                frontier.push(FrontierEdge {
+                    module: id.0.clone(),
+                    function: id.1.clone(),
+                    file: guard_coverage
+                        .map(|coverage| coverage.file.clone())
+                        .unwrap_or_default(),
+                    guard,
+                    guard_line: function.locations[guard].0,
+                    target: *target,
+                    target_line: function.locations[*target].0,
+                    guard_hits: guard_coverage.map_or(0, |coverage| coverage.hits),
+                    hidden_blocks,
+                    guard_edges: guard_coverage
+                        .map(|coverage| coverage.edges.clone())
+                        .unwrap_or_default(),
+                });
+            }
+        }
+    }
+
+    frontier.sort_by(|a, b| {
+        b.hidden_blocks
+            .cmp(&a.hidden_blocks)
+            .then(b.guard_hits.cmp(&a.guard_hits))
+            .then_with(|| a.function.cmp(&b.function))
+            .then_with(|| a.module.cmp(&b.module))
+            .then(a.guard.cmp(&b.guard))
+            .then(a.target.cmp(&b.target))
+    });
+    frontier
+}
+
+/// The covered blocks of each function.
+///
+/// `sancov` does not instrument every block, so a block no PC was matched to counts as covered
+/// if it is the only successor of a covered block, or the only predecessor of one.
+fn covered_blocks<'a>(
+    cfg: &'a ProgramCfg,
+    coverage: &CfgCoverage,
+) -> HashMap<&'a FunctionId, Vec<bool>> {
+    let mut covered = HashMap::new();
+    for (id, function) in &cfg.functions {
+        let mut blocks: Vec<bool> = (0..function.len())
+            .map(|block| coverage.hits(id, block) > 0)
+            .collect();
+        let matched: Vec<bool> = (0..function.len())
+            .map(|block| coverage.blocks.contains_key(&(id.clone(), block)))
+            .collect();
+        let predecessors = ProgramCfg::predecessors(function);
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in 0..function.len() {
+                if !blocks[block] {
+                    continue;
+                }
+                if let [successor] = function.successors[block][..] {
+                    if successor < blocks.len() && !matched[successor] && !blocks[successor] {
+                        blocks[successor] = true;
+                        changed = true;
+                    }
+                }
+                if let [predecessor] = predecessors[block][..] {
+                    if !matched[predecessor] && !blocks[predecessor] {
+                        blocks[predecessor] = true;
+                        changed = true;
+                    }
+                }
+            }
+        }
+        covered.insert(id, blocks);
+    }
+    covered
+}
+
+/// The number of uncovered blocks reachable from `start` through uncovered blocks and the uncovered
+/// functions they call
+fn hidden_blocks<F>(cfg: &ProgramCfg, is_covered: &F, function: &FunctionId, start: usize) -> usize
+where
+    F: Fn(&FunctionId, usize) -> bool,
+{
+    let mut seen: HashSet<(&FunctionId, usize)> = HashSet::new();
+    let mut queue = VecDeque::new();
+    let Some((id, _)) = cfg.functions.get_key_value(function) else {
+        return 0;
+    };
+    seen.insert((id, start));
+    queue.push_back((id, start));
+
+    while let Some((id, block)) = queue.pop_front() {
+        let function = &cfg.functions[id];
+        let mut next: Vec<(&FunctionId, usize)> = function.successors[block]
+            .iter()
+            .filter(|successor| **successor < function.len())
+            .map(|successor| (id, *successor))
+            .collect();
+        if let Some(callees) = function.calls.get(&block) {
+            for callee in callees {
+                if let Some((callee, callee_cfg)) = cfg.resolve(&id.0, callee) {
+                    if callee_cfg.entry < callee_cfg.len() {
+                        next.push((callee, callee_cfg.entry));
+                    }
+                }
+            }
+        }
+        for (id, block) in next {
+            if !is_covered(id, block) && seen.insert((id, block)) {
+                queue.push_back((id, block));
+            }
+        }
+    }
+    seen.len()
+}
+
+/// Writes the `frontier` as tab-separated values, one edge per line
+pub fn write_frontier<W: Write>(frontier: &[FrontierEdge], out: &mut W) -> io::Result<()> {
+    writeln!(
+        out,
+        "rank\thidden_blocks\tguard_hits\tfunction\tguard\ttarget\tlocation"
+    )?;
+    for (rank, edge) in frontier.iter().enumerate() {
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}:{}->{}",
+            rank,
+            edge.hidden_blocks,
+            edge.guard_hits,
+            edge.function,
+            edge.guard,
+            edge.target,
+            edge.file,
+            edge.guard_line,
+            edge.target_line
+        )?;
+    }
+    Ok(())
+}
+
+/// Marks a corpus entry covering the guarding block of a top frontier edge
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FrontierTestcaseMetadata {
+    /// The best rank of the frontier edges the entry reaches
+    pub rank: usize,
+}
+
+libafl_bolts::impl_serdeany!(FrontierTestcaseMetadata);
+
+/// If the current corpus entry reaches a top frontier edge, as tagged by the [`FrontierStage`].
+/// Use it as the condition of an [`libafl::stages::IfStage`].
+pub fn reaches_frontier<S>(state: &S) -> Result<bool, Error>
+where
+    S: HasCurrentTestcase,
+{
+    Ok(state
+        .current_testcase()?
+        .has_metadata::<FrontierTestcaseMetadata>())
+}
+
+/// The name of the [`FrontierStage`]
+pub const FRONTIER_STAGE_NAME: &str = "frontier";
+
+/// A stage replaying the corpus to compute and write the frontier periodically, and to tag the
+/// corpus entries reaching its top edges with [`FrontierTestcaseMetadata`].
+///
+/// Writes `frontier.tsv` to the report directory.
+#[derive(Debug)]
+pub struct FrontierStage<EM, Z> {
+    name: Cow<'static, str>,
+    cfg: ProgramCfg,
+    report_dir: PathBuf,
+    interval: Duration,
+    top: usize,
+    last_report: Option<Instant>,
+    symbolizer: Symbolizer,
+    phantom: PhantomData<(EM, Z)>,
+}
+
+impl<EM, Z> FrontierStage<EM, Z> {
+    /// Creates a new [`FrontierStage`], loading the `.cfg` files in `cfg_dir` and writing the frontier to
+    /// `report_dir` at most every `interval`. The corpus entries reaching one of the `top` edges get tagged.
+    pub fn new<P, Q>(
+        cfg_dir: P,
+        report_dir: Q,
+        interval: Duration,
+        top: usize,
+    ) -> Result<Self, Error>
+    where
+        P: AsRef<Path>,
+        Q: Into<PathBuf>,
+    {
+        let cfg = ProgramCfg::from_dir(cfg_dir)?;
+        let report_dir = report_dir.into();
+        fs::create_dir_all(&report_dir)?;
+        Ok(Self {
+            name: Cow::Borrowed(FRONTIER_STAGE_NAME),
+            cfg,
+            report_dir,
+            interval,
+            top,
+            last_report: None,
+            symbolizer: Symbolizer::new(),
+            phantom: PhantomData,
+        })
+    }
+
+    /// The control flow graph of the target
+    #[must_use]
+    pub fn cfg(&self) -> &ProgramCfg {
+        &self.cfg
+    }
+}
+
+impl<EM, Z> UsesState for FrontierStage<EM, Z>
+where
+    EM: UsesState,
+{
+    type State = EM::State;
+}
+
+impl<EM, Z> Named for FrontierStage<EM, Z> {
+    fn name(&self) -> &Cow<'static, str> {
+        &self.name
+    }
+}
+
+impl<E, EM, Z> Stage<E, EM, Z> for FrontierStage<EM, Z>
+where
+    E: UsesState<State = Self::State>,
+    EM: UsesState,
+    Z: ExecutesInput<E, EM, State = Self::State>,
+    EM::State: HasCorpus,
+    <EM::State as HasCorpus>::Corpus: Corpus<Input = EM::Input>,
+{
+    fn perform(
+        &mut self,
+        fuzzer: &mut Z,
+        executor: &mut E,
+        state: &mut Self::State,
+        manager: &mut EM,
+    ) -> Result<(), Error> {
+        if self
+            .last_report
+            .is_some_and(|last| last.elapsed() < self.interval)
+        {
+            return Ok(());
+        }
+
+        let mut hits = Vec::new();
+        let mut entry_edges: Vec<(CorpusId, Vec<usize>)> = Vec::new();
+        replay_corpus_with(fuzzer, executor, state, manager, |_state, id, map| {
+            hits.resize(hits.len().max(map.len()), 0u64);
+            let mut edges = Vec::new();
+            for (edge, entry) in map.iter().enumerate() {
+                if *entry != 0 {
+                    hits[edge] += 1;
+                    edges.push(edge);
+                }
+            }
+            entry_edges.push((id, edges));
+            Ok(())
+        })?;
+
+        let coverage = CfgCoverage::from_edge_hits(&self.cfg, &hits, &mut self.symbolizer);
+        let frontier = compute_frontier(&self.cfg, &coverage);
+
+        let mut out = BufWriter::new(fs::File::create(self.report_dir.join("frontier.tsv"))?);
+        write_frontier(&frontier, &mut out)?;
+        out.flush()?;
+
+        let mut guard_ranks: HashMap<usize, usize> = HashMap::new();
+        for (rank, edge) in frontier.iter().take(self.top).enumerate() {
+            for guard_edge in &edge.guard_edges {
+                guard_ranks.entry(*guard_edge).or_insert(rank);
+            }
+        }
+        for (id, edges) in entry_edges {
+            let rank = edges
+                .iter()
+                .filter_map(|edge| guard_ranks.get(edge))
+                .min()
+                .copied();
+            let mut testcase = state.corpus().get(id)?.borrow_mut();
+            match rank {
+                Some(rank) => testcase.add_metadata(FrontierTestcaseMetadata { rank }),
+                None => {
+                    testcase
+                        .metadata_map_mut()
+                        .remove::<FrontierTestcaseMetadata>();
+                }
+            }
+        }
+
+        self.last_report = Some(Instant::now());
+        Ok(())
+    }
+
+    #[inline]
+    fn should_restart(&mut self, _state: &mut Self::State) -> Result<bool, Error> {
+        // Only replays inputs already in the corpus, restarting is fine
+        Ok(true)
+    }
+
+    #[inline]
+    fn clear_progress(&mut self, _state: &mut Self::State) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::{String, ToString};
+
+    use super::{compute_frontier, CfgCoverage, FunctionId, ProgramCfg};
+    use crate::SourceLocation;
+
+    // main: 0 -> {1, 2}, 1 -> 3, 2 -> 3; block 2 calls parse
+    // parse: 0 -> {1, 2}
+    const MODULE: &str = r#"{
+        "module": "a.c",
+        "edges": {"main": [[1, 2], [3], [3], []], "parse": [[1, 2], [], []]},
+        "calls": {"main": {"2": ["parse"]}},
+        "entries": {"main": 0, "parse": 0},
+        "locs": {"main": [[1, 1], [2, 5], [3, 5], [4, 1]], "parse": [[10, 1], [11, 5], [12, 5]]}
+    }"#;
+
+    // Another static parse, with a single block
+    const OTHER_MODULE: &str = r#"{
+        "module": "b.c",
+        "edges": {"parse": [[]]},
+        "entries": {"parse": 0},
+        "locs": {"parse": [[20, 1]]}
+    }"#;
+
+    fn id(module: &str, function: &str) -> FunctionId {
+        (module.to_string(), function.to_string())
+    }
+
+    #[test]
+    fn test_frontier() {
+        let mut cfg = ProgramCfg::new();
+        cfg.add_module(MODULE).unwrap();
+        cfg.add_module(OTHER_MODULE).unwrap();
+        assert_eq!(cfg.functions.len(), 3);
+        assert_eq!(cfg.functions[&id("a.c", "main")].len(), 4);
+        assert_eq!(cfg.functions[&id("a.c", "parse")].len(), 3);
+        assert_eq!(cfg.functions[&id("b.c", "parse")].len(), 1);
+        assert_eq!(cfg.resolve("a.c", "parse").unwrap().0, &id("a.c", "parse"));
+
+        let location = |line, column, function: &str| SourceLocation {
+            file: "a.c".to_string(),
+            line,
+            column,
+            function: function.to_string(),
+            symbol: Some(String::from(function)),
+        };
+        let mut coverage = CfgCoverage::new();
+        coverage.add_pc(&cfg, &location(1, 1, "main"), 0, 7);
+        coverage.add_pc(&cfg, &location(2, 5, "main"), 1, 7);
+        coverage.add_pc(&cfg, &location(3, 5, "main"), 2, 0);
+        coverage.add_pc(&cfg, &location(10, 1, "parse"), 3, 0);
+        let other = SourceLocation {
+            file: "b.c".to_string(),
+            ..location(20, 1, "parse")
+        };
+        coverage.add_pc(&cfg, &other, 4, 5);
+        assert_eq!(coverage.hits(&id("a.c", "main"), 1), 7);
+        assert_eq!(coverage.hits(&id("a.c", "parse"), 0), 0);
+        assert_eq!(coverage.hits(&id("b.c", "parse"), 0), 5);
+
+        let frontier = compute_frontier(&cfg, &coverage);
+        // Block 3 is implied by block 1, only the branch to block 2 (and the parse of a.c) is missing
+        assert_eq!(frontier.len(), 1);
+        let edge = &frontier[0];
+        assert_eq!(
+            (
+                edge.module.as_str(),
+                edge.function.as_str(),
+                edge.guard,
+                edge.target
+            ),
+            ("a.c", "main", 0, 2)
+        );
+        assert_eq!(edge.guard_hits, 7);
+        assert_eq!(edge.hidden_blocks, 4);
+        assert_eq!(edge.guard_edges, [0]);
+    }
+}
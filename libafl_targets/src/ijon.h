@@ -0,0 +1,44 @@
+#ifndef __LIBAFL_TARGETS_IJON__
+#define __LIBAFL_TARGETS_IJON__
+
+// IJON-style annotations for C and C++ targets.
+// The functions are implemented in `libafl_targets` (feature `ijon`), see `ijon.rs`.
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+uint32_t ijon_hashint(uint32_t old, uint32_t val);
+uint32_t ijon_hashstr(uint32_t old, const char *val);
+uint32_t ijon_hashmem(uint32_t old, const uint8_t *val, size_t len);
+
+void ijon_map_set(uint32_t addr);
+void ijon_map_inc(uint32_t addr);
+void ijon_max(uint32_t addr, uint64_t val);
+void ijon_min(uint32_t addr, uint64_t val);
+void ijon_xor_state(uint32_t val);
+void ijon_reset_state(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#define IJON_SITE ijon_hashstr(__LINE__, __FILE__)
+
+// Mark the value as seen
+#define IJON_SET(x) ijon_map_set(ijon_hashint(IJON_SITE, (uint32_t)(x)))
+// Count how often the value was seen
+#define IJON_INC(x) ijon_map_inc(ijon_hashint(IJON_SITE, (uint32_t)(x)))
+// Maximize the value
+#define IJON_MAX(x) ijon_max(IJON_SITE, (uint64_t)(x))
+// Minimize the value
+#define IJON_MIN(x) ijon_min(IJON_SITE, (uint64_t)(x))
+// Mix the value into the state, making all following IJON_SET and IJON_INC new again
+#define IJON_STATE(x) ijon_xor_state((uint32_t)(x))
+// Mark the contents of a buffer as seen
+#define IJON_SET_MEM(x, len) ijon_map_set(ijon_hashmem(IJON_SITE, (const uint8_t *)(x), (len)))
+
+#endif  // __LIBAFL_TARGETS_IJON__
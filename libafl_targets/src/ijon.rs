@@ -0,0 +1,349 @@
+//! [IJON](https://github.com/RUB-SysSec/ijon)-style annotations to steer the fuzzer through the state space of the target.
+//!
+//! The annotations write into dedicated maps, next to the regular coverage:
+//! - [`ijon_set!`] and [`ijon_inc!`] mark (or count) a value in the [`IJON_MAP`], observe it with [`ijon_map_observer`].
+//! - [`ijon_max!`] and [`ijon_min!`] keep the largest (smallest) value per annotation in the [`IJON_MAX_MAP`],
+//!   observe it with [`ijon_max_map_observer`] and reward every improvement with [`ijon_max_feedback`].
+//! - [`ijon_state!`] mixes a value into all following [`ijon_set!`] and [`ijon_inc!`] annotations,
+//!   so that the same location is new again in a different state.
+//!
+//! C and C++ targets can use the same annotations through `ijon.h`, which calls the functions exported here.
+//! Add the [`IjonHook`] to the executor to reset the state before each run.
+//!
+//! ```rust,ignore
+//! fn walk(maze: &Maze, x: usize, y: usize) {
+//!     // Every position in the maze is interesting on its own.
+//!     ijon_set!(y * maze.width + x);
+//!     // The closer to the exit, the better.
+//!     ijon_max!(x + y);
+//! }
+//! ```
+
+use core::{ffi::c_char, marker::PhantomData};
+
+use libafl::{
+    executors::{hooks::ExecutorHook, HasObservers},
+    feedbacks::MaxMapFeedback,
+    inputs::UsesInput,
+    observers::StdMapObserver,
+};
+
+use crate::{IJON_MAP_SIZE, IJON_MAX_SLOTS};
+
+/// The map written by [`ijon_set!`] and [`ijon_inc!`]
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+pub static mut __libafl_ijon_map: [u8; IJON_MAP_SIZE] = [0; IJON_MAP_SIZE];
+pub use __libafl_ijon_map as IJON_MAP;
+
+/// The map written by [`ijon_max!`] and [`ijon_min!`], one slot per annotation.
+///
+/// Minimized values are stored as `u64::MAX - value`, so that a larger entry is always better.
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+pub static mut __libafl_ijon_max_map: [u64; IJON_MAX_SLOTS] = [0; IJON_MAX_SLOTS];
+pub use __libafl_ijon_max_map as IJON_MAX_MAP;
+
+/// The current state set by [`ijon_state!`], mixed into the [`IJON_MAP`] indices
+static mut IJON_STATE: u32 = 0;
+
+/// The FNV-1a hash used to derive the map index of an annotation from its location in the source
+#[must_use]
+pub const fn ijon_site(file: &str, line: u32) -> u32 {
+    let file = file.as_bytes();
+    let mut hash: u32 = 0x811c_9dc5;
+    let mut i = 0;
+    while i < file.len() {
+        #[allow(clippy::cast_lossless)]
+        {
+            hash ^= file[i] as u32;
+        }
+        hash = hash.wrapping_mul(0x0100_0193);
+        i += 1;
+    }
+    ijon_hashint(hash, line)
+}
+
+/// Combine a hash with an integer
+#[no_mangle]
+#[must_use]
+pub const extern "C" fn ijon_hashint(old: u32, val: u32) -> u32 {
+    let mut hash = old ^ val;
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x85eb_ca6b);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0xc2b2_ae35);
+    hash ^ (hash >> 16)
+}
+
+/// Combine a hash with a buffer
+#[must_use]
+pub fn ijon_hash_bytes(old: u32, val: &[u8]) -> u32 {
+    val.iter()
+        .fold(old, |hash, &byte| ijon_hashint(hash, u32::from(byte)))
+}
+
+/// Combine a hash with a nul-terminated string
+///
+/// # Safety
+/// `val` has to point to a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn ijon_hashstr(old: u32, val: *const c_char) -> u32 {
+    ijon_hash_bytes(old, core::ffi::CStr::from_ptr(val).to_bytes())
+}
+
+/// Combine a hash with `len` bytes of memory
+///
+/// # Safety
+/// `val` has to point to at least `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn ijon_hashmem(old: u32, val: *const u8, len: usize) -> u32 {
+    ijon_hash_bytes(old, core::slice::from_raw_parts(val, len))
+}
+
+/// Mark the given value as seen in the [`IJON_MAP`]
+///
+/// # Safety
+/// Writes to the [`IJON_MAP`], may not be called concurrently with the fuzzer reading it.
+#[no_mangle]
+pub unsafe extern "C" fn ijon_map_set(addr: u32) {
+    let idx = (addr ^ IJON_STATE) as usize % IJON_MAP_SIZE;
+    let map = &raw mut IJON_MAP;
+    (*map)[idx] |= 1;
+}
+
+/// Count how often the given value was seen in the [`IJON_MAP`]
+///
+/// # Safety
+/// Writes to the [`IJON_MAP`], may not be called concurrently with the fuzzer reading it.
+#[no_mangle]
+pub unsafe extern "C" fn ijon_map_inc(addr: u32) {
+    let idx = (addr ^ IJON_STATE) as usize % IJON_MAP_SIZE;
+    let map = &raw mut IJON_MAP;
+    (*map)[idx] = (*map)[idx].wrapping_add(1);
+}
+
+/// Keep the largest value seen for the given annotation in the [`IJON_MAX_MAP`]
+///
+/// # Safety
+/// Writes to the [`IJON_MAX_MAP`], may not be called concurrently with the fuzzer reading it.
+#[no_mangle]
+pub unsafe extern "C" fn ijon_max(addr: u32, val: u64) {
+    let slot = addr as usize % IJON_MAX_SLOTS;
+    let map = &raw mut IJON_MAX_MAP;
+    if (*map)[slot] < val {
+        (*map)[slot] = val;
+    }
+}
+
+/// Keep the smallest value seen for the given annotation in the [`IJON_MAX_MAP`]
+///
+/// # Safety
+/// Writes to the [`IJON_MAX_MAP`], may not be called concurrently with the fuzzer reading it.
+#[no_mangle]
+pub unsafe extern "C" fn ijon_min(addr: u32, val: u64) {
+    ijon_max(addr, u64::MAX - val);
+}
+
+/// Mix the given value into the current state
+///
+/// # Safety
+/// Changes the global state, may not be called concurrently.
+#[no_mangle]
+pub unsafe extern "C" fn ijon_xor_state(val: u32) {
+    IJON_STATE ^= val;
+}
+
+/// Reset the current state, as done before each run by the [`IjonHook`]
+///
+/// # Safety
+/// Changes the global state, may not be called concurrently.
+#[no_mangle]
+pub unsafe extern "C" fn ijon_reset_state() {
+    IJON_STATE = 0;
+}
+
+/// Mark a value as seen, see [`ijon_map_set`]
+#[macro_export]
+macro_rules! ijon_set {
+    ($val:expr) => {{
+        const SITE: u32 = $crate::ijon::ijon_site(file!(), line!());
+        #[allow(
+            clippy::cast_possible_truncation,
+            clippy::cast_sign_loss,
+            clippy::cast_lossless,
+            clippy::unnecessary_cast
+        )]
+        unsafe {
+            $crate::ijon::ijon_map_set($crate::ijon::ijon_hashint(SITE, ($val) as u32));
+        };
+    }};
+}
+
+/// Count how often a value was seen, see [`ijon_map_inc`]
+#[macro_export]
+macro_rules! ijon_inc {
+    ($val:expr) => {{
+        const SITE: u32 = $crate::ijon::ijon_site(file!(), line!());
+        #[allow(
+            clippy::cast_possible_truncation,
+            clippy::cast_sign_loss,
+            clippy::cast_lossless,
+            clippy::unnecessary_cast
+        )]
+        unsafe {
+            $crate::ijon::ijon_map_inc($crate::ijon::ijon_hashint(SITE, ($val) as u32));
+        };
+    }};
+}
+
+/// Maximize a value, see [`ijon_max`]
+#[macro_export]
+macro_rules! ijon_max {
+    ($val:expr) => {{
+        const SITE: u32 = $crate::ijon::ijon_site(file!(), line!());
+        #[allow(
+            clippy::cast_sign_loss,
+            clippy::cast_lossless,
+            clippy::unnecessary_cast
+        )]
+        unsafe {
+            $crate::ijon::ijon_max(SITE, ($val) as u64);
+        };
+    }};
+}
+
+/// Minimize a value, see [`ijon_min`]
+#[macro_export]
+macro_rules! ijon_min {
+    ($val:expr) => {{
+        const SITE: u32 = $crate::ijon::ijon_site(file!(), line!());
+        #[allow(
+            clippy::cast_sign_loss,
+            clippy::cast_lossless,
+            clippy::unnecessary_cast
+        )]
+        unsafe {
+            $crate::ijon::ijon_min(SITE, ($val) as u64);
+        };
+    }};
+}
+
+/// Mix a value into the current state, see [`ijon_xor_state`]
+#[macro_export]
+macro_rules! ijon_state {
+    ($val:expr) => {{
+        #[allow(
+            clippy::cast_possible_truncation,
+            clippy::cast_sign_loss,
+            clippy::cast_lossless,
+            clippy::unnecessary_cast
+        )]
+        unsafe {
+            $crate::ijon::ijon_xor_state(($val) as u32);
+        };
+    }};
+}
+
+/// Gets a new [`StdMapObserver`] for the [`IJON_MAP`].
+///
+/// Wrap it in a [`libafl::observers::HitcountsMapObserver`] when using [`ijon_inc!`].
+///
+/// # Safety
+/// The observer has mutable access to the [`IJON_MAP`], there may only be one at a time.
+#[must_use]
+pub unsafe fn ijon_map_observer(name: &'static str) -> StdMapObserver<'static, u8, false> {
+    StdMapObserver::from_mut_ptr(name, &raw mut IJON_MAP as *mut u8, IJON_MAP_SIZE)
+}
+
+/// Gets a new [`StdMapObserver`] for the [`IJON_MAX_MAP`]
+///
+/// # Safety
+/// The observer has mutable access to the [`IJON_MAX_MAP`], there may only be one at a time.
+#[must_use]
+pub unsafe fn ijon_max_map_observer(name: &'static str) -> StdMapObserver<'static, u64, false> {
+    StdMapObserver::from_mut_ptr(name, &raw mut IJON_MAX_MAP as *mut u64, IJON_MAX_SLOTS)
+}
+
+/// A [`MaxMapFeedback`] reporting an input as interesting whenever it improves the value of any [`ijon_max!`]
+/// or [`ijon_min!`] annotation
+#[must_use]
+pub fn ijon_max_feedback(
+    observer: &StdMapObserver<'static, u64, false>,
+) -> MaxMapFeedback<StdMapObserver<'static, u64, false>, StdMapObserver<'static, u64, false>> {
+    MaxMapFeedback::new(observer)
+}
+
+/// The hook resetting the [`ijon_state!`] before each run
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IjonHook<S> {
+    phantom: PhantomData<S>,
+}
+
+impl<S> IjonHook<S> {
+    /// The constructor
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<S> ExecutorHook<S> for IjonHook<S>
+where
+    S: UsesInput,
+{
+    fn init<E: HasObservers>(&mut self, _state: &mut S) {}
+
+    fn pre_exec(&mut self, _state: &mut S, _input: &S::Input) {
+        // # Safety
+        // The target is not running yet.
+        unsafe {
+            ijon_reset_state();
+        }
+    }
+
+    fn post_exec(&mut self, _state: &mut S, _input: &S::Input) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::{ijon_reset_state, ijon_site, IJON_MAP, IJON_MAX_MAP};
+
+    #[test]
+    fn test_ijon_annotations() {
+        // Different lines are different annotations
+        assert_ne!(ijon_site("a.rs", 1), ijon_site("a.rs", 2));
+
+        unsafe {
+            ijon_reset_state();
+            (*(&raw mut IJON_MAX_MAP)).fill(0);
+            (*(&raw mut IJON_MAP)).fill(0);
+        }
+
+        for value in [3u64, 7, 5] {
+            ijon_max!(value);
+        }
+        for value in [9u64, 2, 4] {
+            ijon_min!(value);
+        }
+        let max_map = unsafe { &*(&raw const IJON_MAX_MAP) };
+        assert!(max_map.contains(&7));
+        assert!(max_map.contains(&(u64::MAX - 2)));
+        assert_eq!(max_map.iter().filter(|&&value| value != 0).count(), 2);
+
+        for _ in 0..3 {
+            ijon_inc!(1);
+        }
+        ijon_state!(42);
+        ijon_inc!(1);
+        let map = unsafe { &*(&raw const IJON_MAP) };
+        let mut counts: Vec<u8> = map.iter().copied().filter(|&count| count != 0).collect();
+        counts.sort_unstable();
+        // The annotation after the state change is a different entry
+        assert_eq!(counts, [1, 3]);
+    }
+}
@@ -115,6 +115,58 @@ pub mod call;
 #[cfg(feature = "function-logging")]
 pub use call::*;
 
+/// IJON-style annotations of the target state
+#[cfg(feature = "ijon")]
+pub mod ijon;
+#[cfg(feature = "ijon")]
+pub use ijon::*;
+
+/// `LeakSanitizer` integration for in-process executors
+#[cfg(all(unix, feature = "lsan"))]
+pub mod lsan;
+#[cfg(all(unix, feature = "lsan"))]
+pub use lsan::*;
+
+/// Heap consumption and stack depth of the target
+#[cfg(all(unix, feature = "memlock"))]
+pub mod memlock;
+#[cfg(all(unix, feature = "memlock"))]
+pub use memlock::*;
+
+/// Coverage from LLVM source-based coverage counters
+#[cfg(feature = "llvm_profile")]
+pub mod llvm_profile;
+#[cfg(feature = "llvm_profile")]
+pub use llvm_profile::*;
+
+/// Source-level coverage reports from the `sancov` PC tables
+#[cfg(all(
+    unix,
+    feature = "coverage_report",
+    any(feature = "sancov_pcguard_edges", feature = "sancov_pcguard_hitcounts")
+))]
+pub mod coverage_report;
+#[cfg(all(
+    unix,
+    feature = "coverage_report",
+    any(feature = "sancov_pcguard_edges", feature = "sancov_pcguard_hitcounts")
+))]
+pub use coverage_report::*;
+
+/// Fuzz-blocker frontier analysis from the CFGs dumped by `libafl_cc`
+#[cfg(all(
+    unix,
+    feature = "frontier",
+    any(feature = "sancov_pcguard_edges", feature = "sancov_pcguard_hitcounts")
+))]
+pub mod frontier;
+#[cfg(all(
+    unix,
+    feature = "frontier",
+    any(feature = "sancov_pcguard_edges", feature = "sancov_pcguard_hitcounts")
+))]
+pub use frontier::*;
+
 /// runtime related to comparisons
 pub mod cmps;
 pub use cmps::*;
@@ -0,0 +1,387 @@
+//! Coverage from the region counters of LLVM source-based coverage.
+//!
+//! Targets built with `-Cinstrument-coverage` (Rust) or `-fprofile-instr-generate -fcoverage-mapping` (C/C++)
+//! count how often every code region runs in the `__llvm_prf_cnts` section.
+//! The [`LlvmProfileObserver`] exposes these counters as hitcount-bucketed [`MapObserver`], to fuzz with
+//! region coverage instead of (or next to) sancov edges.
+//! The [`ProfrawFeedback`] writes an `llvm-cov` compatible `.profraw` file for every new corpus entry, which
+//! can be merged with `llvm-profdata merge` into a coverage report of the whole corpus.
+//!
+//! Only instrument the target, not the fuzzer: the counters of all instrumented code end up in the map.
+//! The counters have to be 64 bit wide, i.e., the target must not be built with `-enable-single-byte-coverage`.
+
+use alloc::{borrow::Cow, string::String, vec, vec::Vec};
+use core::{
+    ops::{Deref, DerefMut},
+    slice,
+};
+use std::{fs, path::PathBuf};
+
+use libafl::{
+    corpus::Testcase,
+    executors::ExitKind,
+    feedbacks::{Feedback, StateInitializer},
+    inputs::Input,
+    observers::{HitcountsMapObserver, MapObserver, Observer, StdMapObserver},
+    Error,
+};
+use libafl_bolts::{AsSlice, AsSliceMut, HasLen, Named};
+use serde::{Deserialize, Serialize};
+
+#[cfg(not(test))]
+extern "C" {
+    fn __llvm_profile_begin_counters() -> *mut u8;
+    fn __llvm_profile_end_counters() -> *mut u8;
+    fn __llvm_profile_reset_counters();
+    fn __llvm_profile_get_size_for_buffer() -> u64;
+    fn __llvm_profile_write_buffer(buffer: *mut u8) -> i32;
+}
+
+/// The functions of the profile runtime used here.
+///
+/// The tests use a fake runtime instead of defining the symbols of the real one, which would clash with
+/// compiler-rt in instrumented builds.
+struct ProfileRuntime {
+    begin_counters: unsafe extern "C" fn() -> *mut u8,
+    end_counters: unsafe extern "C" fn() -> *mut u8,
+    reset_counters: unsafe extern "C" fn(),
+    get_size_for_buffer: unsafe extern "C" fn() -> u64,
+    write_buffer: unsafe extern "C" fn(buffer: *mut u8) -> i32,
+}
+
+/// The profile runtime linked into this process
+#[cfg(not(test))]
+static RUNTIME: ProfileRuntime = ProfileRuntime {
+    begin_counters: __llvm_profile_begin_counters,
+    end_counters: __llvm_profile_end_counters,
+    reset_counters: __llvm_profile_reset_counters,
+    get_size_for_buffer: __llvm_profile_get_size_for_buffer,
+    write_buffer: __llvm_profile_write_buffer,
+};
+
+#[cfg(test)]
+use tests::RUNTIME;
+
+/// The region counters of this process, in the `__llvm_prf_cnts` section
+///
+/// # Safety
+/// The instrumented code writes to the counters while the slice is alive.
+#[must_use]
+#[allow(clippy::cast_ptr_alignment)] // The runtime aligns the 64 bit counters
+pub unsafe fn llvm_profile_counters() -> &'static mut [u64] {
+    let begin = (RUNTIME.begin_counters)();
+    let end = (RUNTIME.end_counters)();
+    let len = (end as usize - begin as usize) / size_of::<u64>();
+    if len == 0 {
+        return &mut [];
+    }
+    slice::from_raw_parts_mut(begin.cast::<u64>(), len)
+}
+
+/// Reset all region counters to zero
+pub fn reset_llvm_profile_counters() {
+    unsafe { (RUNTIME.reset_counters)() }
+}
+
+/// The `.profraw` data of the current counters, as written by the profile runtime on exit
+pub fn llvm_profile_profraw() -> Result<Vec<u8>, Error> {
+    let size = unsafe { (RUNTIME.get_size_for_buffer)() };
+    let mut buffer = vec![0; usize::try_from(size)?];
+    if unsafe { (RUNTIME.write_buffer)(buffer.as_mut_ptr()) } != 0 {
+        return Err(Error::illegal_state(
+            "The profile runtime failed to write the profraw data",
+        ));
+    }
+    Ok(buffer)
+}
+
+/// A [`MapObserver`] over the LLVM region counters.
+///
+/// The counters are reset before each run and saturated into a `u8` map, bucketed like AFL hitcounts, after it.
+#[derive(Serialize, Deserialize, Clone, Debug, Hash)]
+pub struct LlvmProfileObserver {
+    base: HitcountsMapObserver<StdMapObserver<'static, u8, false>>,
+}
+
+impl LlvmProfileObserver {
+    /// Creates a new [`LlvmProfileObserver`], with one map entry per region counter of this process
+    #[must_use]
+    pub fn new(name: &'static str) -> Self {
+        let len = unsafe { llvm_profile_counters() }.len();
+        Self {
+            base: HitcountsMapObserver::new(StdMapObserver::owned(name, vec![0; len])),
+        }
+    }
+}
+
+impl<I, S> Observer<I, S> for LlvmProfileObserver {
+    fn pre_exec(&mut self, state: &mut S, input: &I) -> Result<(), Error> {
+        reset_llvm_profile_counters();
+        self.base.pre_exec(state, input)
+    }
+
+    fn post_exec(&mut self, state: &mut S, input: &I, exit_kind: &ExitKind) -> Result<(), Error> {
+        let counters = unsafe { llvm_profile_counters() };
+        for (entry, counter) in self.base.as_slice_mut().iter_mut().zip(counters.iter()) {
+            #[allow(clippy::cast_possible_truncation)]
+            {
+                *entry = (*counter).min(u64::from(u8::MAX)) as u8;
+            }
+        }
+        self.base.post_exec(state, input, exit_kind)
+    }
+}
+
+impl Named for LlvmProfileObserver {
+    fn name(&self) -> &Cow<'static, str> {
+        self.base.name()
+    }
+}
+
+impl HasLen for LlvmProfileObserver {
+    fn len(&self) -> usize {
+        self.base.len()
+    }
+}
+
+impl AsRef<Self> for LlvmProfileObserver {
+    fn as_ref(&self) -> &Self {
+        self
+    }
+}
+
+impl AsMut<Self> for LlvmProfileObserver {
+    fn as_mut(&mut self) -> &mut Self {
+        self
+    }
+}
+
+impl Deref for LlvmProfileObserver {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.base.as_slice()
+    }
+}
+
+impl DerefMut for LlvmProfileObserver {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.base.as_slice_mut()
+    }
+}
+
+impl MapObserver for LlvmProfileObserver {
+    type Entry = u8;
+
+    #[inline]
+    fn initial(&self) -> u8 {
+        self.base.initial()
+    }
+
+    #[inline]
+    fn usable_count(&self) -> usize {
+        self.base.usable_count()
+    }
+
+    #[inline]
+    fn get(&self, idx: usize) -> u8 {
+        self.base.get(idx)
+    }
+
+    #[inline]
+    fn set(&mut self, idx: usize, val: u8) {
+        self.base.set(idx, val);
+    }
+
+    fn count_bytes(&self) -> u64 {
+        self.base.count_bytes()
+    }
+
+    fn reset_map(&mut self) -> Result<(), Error> {
+        self.base.reset_map()
+    }
+
+    fn hash_simple(&self) -> u64 {
+        self.base.hash_simple()
+    }
+
+    fn to_vec(&self) -> Vec<u8> {
+        self.base.to_vec()
+    }
+
+    fn how_many_set(&self, indexes: &[usize]) -> usize {
+        self.base.how_many_set(indexes)
+    }
+}
+
+/// Writes the `.profraw` data of every new corpus entry to a directory, named like the entry.
+///
+/// Merge them with `llvm-profdata merge -sparse <dir>/*.profraw -o corpus.profdata` and show the report with
+/// `llvm-cov show <target> -instr-profile=corpus.profdata`.
+/// The feedback is never interesting on its own, combine it with an OR.
+#[derive(Debug, Clone)]
+pub struct ProfrawFeedback {
+    name: Cow<'static, str>,
+    profraw_dir: PathBuf,
+}
+
+impl ProfrawFeedback {
+    /// Creates a new [`ProfrawFeedback`], writing to `profraw_dir`
+    pub fn new<P>(profraw_dir: P) -> Result<Self, Error>
+    where
+        P: Into<PathBuf>,
+    {
+        let profraw_dir = profraw_dir.into();
+        fs::create_dir_all(&profraw_dir)?;
+        Ok(Self {
+            name: Cow::Borrowed("ProfrawFeedback"),
+            profraw_dir,
+        })
+    }
+}
+
+impl<S> StateInitializer<S> for ProfrawFeedback {}
+
+impl<EM, I, OT, S> Feedback<EM, I, OT, S> for ProfrawFeedback
+where
+    I: Input,
+{
+    #[cfg(feature = "track_hit_feedbacks")]
+    fn last_result(&self) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    fn append_metadata(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        _observers: &OT,
+        testcase: &mut Testcase<I>,
+    ) -> Result<(), Error> {
+        let Some(input) = testcase.input() else {
+            return Ok(());
+        };
+        let file_name: String = input.generate_name(None);
+        fs::write(
+            self.profraw_dir.join(format!("{file_name}.profraw")),
+            llvm_profile_profraw()?,
+        )?;
+        Ok(())
+    }
+}
+
+impl Named for ProfrawFeedback {
+    fn name(&self) -> &Cow<'static, str> {
+        &self.name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use core::slice;
+    use std::{fs, sync::Mutex};
+
+    use libafl::{
+        corpus::Testcase,
+        feedbacks::Feedback,
+        inputs::{BytesInput, Input},
+        observers::Observer,
+    };
+
+    use super::{
+        llvm_profile_counters, ExitKind, LlvmProfileObserver, ProfileRuntime, ProfrawFeedback,
+    };
+
+    /// The magic of 64 bit `.profraw` files, `\xfflprofr\x81`
+    const PROFRAW_MAGIC: u64 = 0xff6c_7072_6f66_7281;
+
+    /// Stand-in for the `__llvm_prf_cnts` section of an instrumented target
+    static mut COUNTERS: [u64; 4] = [0; 4];
+    /// The tests share the counters
+    static COUNTERS_LOCK: Mutex<()> = Mutex::new(());
+
+    /// A minimal profile runtime: the `.profraw` data is the magic followed by the counters
+    pub(super) static RUNTIME: ProfileRuntime = ProfileRuntime {
+        begin_counters,
+        end_counters,
+        reset_counters,
+        get_size_for_buffer,
+        write_buffer,
+    };
+
+    extern "C" fn begin_counters() -> *mut u8 {
+        (&raw mut COUNTERS).cast()
+    }
+
+    extern "C" fn end_counters() -> *mut u8 {
+        (&raw mut COUNTERS).wrapping_add(1).cast()
+    }
+
+    extern "C" fn reset_counters() {
+        unsafe { (&raw mut COUNTERS).write([0; 4]) };
+    }
+
+    extern "C" fn get_size_for_buffer() -> u64 {
+        size_of::<u64>() as u64 * 5
+    }
+
+    extern "C" fn write_buffer(buffer: *mut u8) -> i32 {
+        let words = [PROFRAW_MAGIC].into_iter().chain(unsafe { COUNTERS });
+        let buffer = unsafe { slice::from_raw_parts_mut(buffer, size_of::<u64>() * 5) };
+        for (chunk, word) in buffer.chunks_exact_mut(size_of::<u64>()).zip(words) {
+            chunk.copy_from_slice(&word.to_ne_bytes());
+        }
+        0
+    }
+
+    fn parse_profraw(data: &[u8]) -> Option<Vec<u64>> {
+        let mut words = data
+            .chunks_exact(size_of::<u64>())
+            .map(|word| u64::from_ne_bytes(word.try_into().unwrap()));
+        (words.next()? == PROFRAW_MAGIC).then(|| words.collect())
+    }
+
+    #[test]
+    fn test_llvm_profile_observer() {
+        let _lock = COUNTERS_LOCK.lock().unwrap();
+        let input = BytesInput::new(vec![]);
+        let mut observer = LlvmProfileObserver::new("llvm_profile");
+        assert_eq!(observer.len(), 4);
+
+        unsafe { llvm_profile_counters() }.copy_from_slice(&[7, 1, 0, 1000]);
+        observer.pre_exec(&mut (), &input).unwrap();
+        assert_eq!(unsafe { llvm_profile_counters() }, &[0; 4]);
+
+        // The target runs
+        unsafe { llvm_profile_counters() }.copy_from_slice(&[0, 1, 3, 1000]);
+        observer.post_exec(&mut (), &input, &ExitKind::Ok).unwrap();
+        // Saturated at 255 and bucketed like AFL hitcounts
+        assert_eq!(&*observer, &[0, 1, 4, 128]);
+    }
+
+    #[test]
+    fn test_profraw_feedback() {
+        let _lock = COUNTERS_LOCK.lock().unwrap();
+        let dir = std::env::temp_dir().join(format!("libafl_profraw_{}", std::process::id()));
+        let mut feedback = ProfrawFeedback::new(&dir).unwrap();
+
+        unsafe { llvm_profile_counters() }.copy_from_slice(&[2, 0, 5, 1]);
+        let input = BytesInput::new(b"abc".to_vec());
+        let name = input.generate_name(None);
+        let mut testcase = Testcase::new(input);
+        Feedback::<(), BytesInput, (), ()>::append_metadata(
+            &mut feedback,
+            &mut (),
+            &mut (),
+            &(),
+            &mut testcase,
+        )
+        .unwrap();
+
+        let profraw = fs::read(dir.join(format!("{name}.profraw"))).unwrap();
+        assert_eq!(parse_profraw(&profraw), Some(vec![2, 0, 5, 1]));
+        assert_eq!(parse_profraw(&[0; 8]), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
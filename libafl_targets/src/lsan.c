@@ -0,0 +1,54 @@
+#include "common.h"
+
+#include <stddef.h>
+
+// LeakSanitizer interface, present if the target was built with
+// -fsanitize=address or -fsanitize=leak
+int  __lsan_do_recoverable_leak_check(void) __attribute__((weak));
+int  __sanitizer_install_malloc_and_free_hooks(
+     void (*malloc_hook)(const volatile void *, size_t),
+     void (*free_hook)(const volatile void *)) __attribute__((weak));
+
+static volatile size_t libafl_lsan_mallocs;
+static volatile size_t libafl_lsan_frees;
+static volatile int    libafl_lsan_tracking;
+
+static void libafl_lsan_malloc_hook(const volatile void *ptr, size_t size) {
+  (void)ptr;
+  (void)size;
+  if (libafl_lsan_tracking) { libafl_lsan_mallocs++; }
+}
+
+static void libafl_lsan_free_hook(const volatile void *ptr) {
+  (void)ptr;
+  if (libafl_lsan_tracking) { libafl_lsan_frees++; }
+}
+
+int libafl_lsan_available(void) {
+  return __lsan_do_recoverable_leak_check != NULL;
+}
+
+int libafl_lsan_install_hooks(void) {
+  if (!__sanitizer_install_malloc_and_free_hooks) { return 0; }
+  return __sanitizer_install_malloc_and_free_hooks(libafl_lsan_malloc_hook,
+                                                   libafl_lsan_free_hook);
+}
+
+void libafl_lsan_start_tracking(void) {
+  libafl_lsan_mallocs = 0;
+  libafl_lsan_frees = 0;
+  libafl_lsan_tracking = 1;
+}
+
+void libafl_lsan_stop_tracking(void) {
+  libafl_lsan_tracking = 0;
+}
+
+int libafl_lsan_maybe_leaked(void) {
+  return libafl_lsan_mallocs > libafl_lsan_frees;
+}
+
+int libafl_lsan_check(void) {
+  if (!__lsan_do_recoverable_leak_check) { return 0; }
+  return __lsan_do_recoverable_leak_check();
+}
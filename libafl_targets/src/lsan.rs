@@ -0,0 +1,569 @@
+//! Leak detection for in-process targets built with `-fsanitize=address` or `-fsanitize=leak`.
+//!
+//! The [`LsanHook`] runs `__lsan_do_recoverable_leak_check` after the harness returned,
+//! the [`LeakObserver`] picks up the leaks not reported before and hashes their allocation stacks,
+//! and the [`LeakFeedback`] turns leaking runs into objectives.
+//! To only keep one input per leak, combine the [`LeakFeedback`] with a
+//! [`libafl::feedbacks::NewHashFeedback`] on the same observer, e.g.
+//! `feedback_and_fast!(LeakFeedback::new(&leak_observer), NewHashFeedback::new(&leak_observer))`.
+//!
+//! Requires the `lsan` feature, which also turns on `detect_leaks` in the default sanitizer options.
+
+use alloc::{
+    borrow::Cow,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::marker::PhantomData;
+use std::{
+    fs::{self, File},
+    io::{self, Read, Seek, Write},
+    os::fd::AsRawFd,
+    sync::Mutex,
+};
+
+use hashbrown::HashSet;
+#[cfg(feature = "track_hit_feedbacks")]
+use libafl::feedbacks::premature_last_result_err;
+use libafl::{
+    corpus::Testcase,
+    executors::{hooks::ExecutorHook, ExitKind, HasObservers},
+    feedbacks::{Feedback, StateInitializer},
+    inputs::UsesInput,
+    observers::{Observer, ObserverWithHashField},
+    Error, HasMetadata,
+};
+use libafl_bolts::{
+    hash_std,
+    tuples::{Handle, Handled, MatchName, MatchNameRef},
+    Named,
+};
+use serde::{Deserialize, Serialize};
+
+extern "C" {
+    fn libafl_lsan_available() -> i32;
+    fn libafl_lsan_install_hooks() -> i32;
+    fn libafl_lsan_start_tracking();
+    fn libafl_lsan_stop_tracking();
+    fn libafl_lsan_maybe_leaked() -> i32;
+    fn libafl_lsan_check() -> i32;
+}
+
+/// The report of the last leak check that found leaks, until a [`LeakObserver`] takes it
+static LEAK_REPORT: Mutex<Option<String>> = Mutex::new(None);
+
+/// Run the leak check, capturing what `LeakSanitizer` writes to `stderr`.
+///
+/// Returns the report, if leaks were found.
+fn capture_leak_check() -> Result<Option<String>, Error> {
+    let path = std::env::temp_dir().join(format!("libafl_lsan_{}.log", std::process::id()));
+    let mut file = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)?;
+
+    io::stderr().flush()?;
+    let leaked = check_with_stderr_to(&file);
+
+    let mut report = String::new();
+    if let Ok(true) = leaked {
+        // `LeakSanitizer` moved the offset, shared with the redirected stderr, to the end
+        file.rewind()?;
+        file.read_to_string(&mut report)?;
+    }
+    drop(file);
+    fs::remove_file(&path)?;
+
+    Ok(leaked?.then_some(report))
+}
+
+/// Run the leak check with `stderr` redirected to `file`, returns if leaks were found
+fn check_with_stderr_to(file: &File) -> Result<bool, Error> {
+    // # Safety
+    // We only swap the stderr file descriptor for the duration of the check, and restore it right after.
+    unsafe {
+        let saved_stderr = libc::dup(libc::STDERR_FILENO);
+        if saved_stderr < 0 {
+            return Err(Error::last_os_error("Failed to duplicate stderr"));
+        }
+        if libc::dup2(file.as_raw_fd(), libc::STDERR_FILENO) < 0 {
+            // stderr is left as it was
+            let err = Error::last_os_error("Failed to redirect stderr");
+            libc::close(saved_stderr);
+            return Err(err);
+        }
+        let leaked = libafl_lsan_check() != 0;
+        let mut restored = libc::dup2(saved_stderr, libc::STDERR_FILENO);
+        while restored < 0 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+            restored = libc::dup2(saved_stderr, libc::STDERR_FILENO);
+        }
+        let err = (restored < 0).then(|| Error::last_os_error("Failed to restore stderr"));
+        libc::close(saved_stderr);
+        match err {
+            Some(err) => Err(err),
+            None => Ok(leaked),
+        }
+    }
+}
+
+/// The hook running the leak check after the harness returned.
+///
+/// Add it to the user hooks of an [`libafl::executors::InProcessExecutor`],
+/// e.g. using [`libafl::executors::InProcessExecutor::with_timeout_generic`].
+/// The (expensive) leak check is only performed if the target did more allocations than frees.
+///
+/// Leaked objects stay unreachable, so every later check would report them again. Each leak is only
+/// reported by the first run it is found in.
+#[derive(Debug, Clone)]
+pub struct LsanHook<S> {
+    period: u64,
+    runs: u64,
+    enabled: bool,
+    count_allocations: bool,
+    /// The hashes of the allocation stacks of the leaks reported so far
+    reported: HashSet<u64>,
+    phantom: PhantomData<S>,
+}
+
+impl<S> LsanHook<S> {
+    /// Create a new [`LsanHook`], checking for leaks after every run
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            period: 1,
+            runs: 0,
+            enabled: false,
+            count_allocations: false,
+            reported: HashSet::new(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Only check for leaks every `period` runs.
+    ///
+    /// This is faster, but a leak will be attributed to the last input of the period.
+    #[must_use]
+    pub fn with_period(mut self, period: u64) -> Self {
+        self.period = period.max(1);
+        self
+    }
+
+    /// Hands the leaks of `report` not reported before to the [`LeakObserver`]
+    fn record_leaks(&mut self, report: &str) {
+        if let Some(report) = new_leaks(report, &mut self.reported) {
+            *LEAK_REPORT.lock().unwrap() = Some(report);
+        }
+    }
+}
+
+impl<S> Default for LsanHook<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> ExecutorHook<S> for LsanHook<S>
+where
+    S: UsesInput,
+{
+    fn init<E: HasObservers>(&mut self, _state: &mut S) {
+        // # Safety
+        // Only queries and registers sanitizer callbacks.
+        unsafe {
+            if libafl_lsan_available() == 0 {
+                log::warn!(
+                    "LeakSanitizer is not linked into the target, leaks will not be detected"
+                );
+                return;
+            }
+            self.count_allocations = libafl_lsan_install_hooks() != 0;
+        }
+        if !self.count_allocations {
+            log::warn!("Failed to install the malloc hooks, checking for leaks after every run");
+        }
+        self.enabled = true;
+    }
+
+    fn pre_exec(&mut self, _state: &mut S, _input: &S::Input) {
+        if self.enabled && self.runs % self.period == 0 {
+            // # Safety
+            // Only resets the allocation counters.
+            unsafe {
+                libafl_lsan_start_tracking();
+            }
+        }
+    }
+
+    fn post_exec(&mut self, _state: &mut S, _input: &S::Input) {
+        if !self.enabled {
+            return;
+        }
+        self.runs += 1;
+        if self.runs % self.period != 0 {
+            return;
+        }
+        // # Safety
+        // Only reads the allocation counters.
+        let maybe_leaked = unsafe {
+            libafl_lsan_stop_tracking();
+            libafl_lsan_maybe_leaked() != 0
+        };
+        // If the hooks could not be installed, the counters stay zero and we always check.
+        if !maybe_leaked && self.count_allocations {
+            return;
+        }
+        match capture_leak_check() {
+            Ok(Some(report)) => self.record_leaks(&report),
+            Ok(None) => {}
+            Err(err) => log::error!("Leak check failed: {err}"),
+        }
+    }
+}
+
+/// Parse the `SUMMARY: ... N byte(s) leaked in M allocation(s).` line of a leak report
+fn parse_leaked_bytes(report: &str) -> Option<usize> {
+    let summary = report
+        .lines()
+        .find(|line| line.starts_with("SUMMARY:") && line.contains("leaked in"))?;
+    let before = summary.split(" byte(s) leaked").next()?;
+    before.rsplit(' ').next()?.parse().ok()
+}
+
+/// Parse the size and the number of objects of a `Direct leak of 16 byte(s) in 1 object(s) allocated from:` line
+fn parse_leak_size(line: &str) -> Option<(usize, usize)> {
+    let (_, rest) = line.split_once(" leak of ")?;
+    let (bytes, rest) = rest.split_once(" byte(s) in ")?;
+    let (objects, _) = rest.split_once(' ')?;
+    Some((bytes.parse().ok()?, objects.parse().ok()?))
+}
+
+/// The part of a leak report with the leaks whose allocation stacks are not in `reported` yet,
+/// with the `SUMMARY:` line recomputed for these leaks. Adds their hashes to `reported`.
+///
+/// Returns `None` if all leaks were reported before.
+fn new_leaks(report: &str, reported: &mut HashSet<u64>) -> Option<String> {
+    let mut header: Vec<&str> = Vec::new();
+    let mut leaks: Vec<Vec<&str>> = Vec::new();
+    for line in report.lines() {
+        if line.starts_with("Direct leak of") || line.starts_with("Indirect leak of") {
+            leaks.push(vec![line]);
+        } else if line.starts_with("SUMMARY:") {
+            // Recomputed for the new leaks below
+        } else if let Some(leak) = leaks.last_mut() {
+            leak.push(line);
+        } else {
+            header.push(line);
+        }
+    }
+    if leaks.is_empty() {
+        // Not a report we can split, take it as a whole
+        return reported
+            .insert(hash_leak_stacks(report))
+            .then(|| report.to_string());
+    }
+
+    let mut new = header.join("\n").trim_end().to_string();
+    let (mut found, mut leaked_bytes, mut allocations) = (false, 0, 0);
+    for leak in leaks {
+        let leak = leak.join("\n");
+        if !reported.insert(hash_leak_stacks(&leak)) {
+            continue;
+        }
+        let (bytes, objects) = parse_leak_size(&leak).unwrap_or_default();
+        found = true;
+        leaked_bytes += bytes;
+        allocations += objects;
+        new.push_str("\n\n");
+        new.push_str(leak.trim_end());
+    }
+    found.then(|| {
+        format!(
+            "{new}\n\nSUMMARY: LeakSanitizer: {leaked_bytes} byte(s) leaked in {allocations} allocation(s).\n"
+        )
+    })
+}
+
+/// Hash the frames of the allocation stacks in a leak report.
+///
+/// ASLR moves the frame addresses from run to run, so a frame is identified by what follows its address:
+/// the function and source location, `in parse_header src/parse.c:42:7`, or, unsymbolized, the module
+/// and offset, `(libfoo.so+0x1234)`. Only frames without either fall back to the address.
+/// The frames are sorted, so the order in which `LeakSanitizer` lists the leaks does not matter.
+fn hash_leak_stacks(report: &str) -> u64 {
+    let mut frames: Vec<&str> = report
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim_start().strip_prefix('#')?;
+            let (_idx, line) = line.split_once(char::is_whitespace)?;
+            let line = line.trim_start();
+            let (addr, location) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            if !addr.starts_with("0x") {
+                return None;
+            }
+            let location = location.trim();
+            Some(if location.is_empty() { addr } else { location })
+        })
+        .collect();
+    frames.sort_unstable();
+    frames.dedup();
+    hash_std(frames.join("\n").as_bytes())
+}
+
+/// Observer picking up the leak reports of the [`LsanHook`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeakObserver {
+    name: Cow<'static, str>,
+    report: Option<String>,
+    leaked_bytes: usize,
+    hash: Option<u64>,
+}
+
+impl LeakObserver {
+    /// Create a new [`LeakObserver`]
+    #[must_use]
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name: Cow::from(name),
+            report: None,
+            leaked_bytes: 0,
+            hash: None,
+        }
+    }
+
+    /// Whether the last run leaked memory
+    #[must_use]
+    pub fn leaked(&self) -> bool {
+        self.report.is_some()
+    }
+
+    /// The leak report of the last run, if it leaked memory
+    #[must_use]
+    pub fn report(&self) -> Option<&str> {
+        self.report.as_deref()
+    }
+
+    /// The number of bytes leaked by the last run
+    #[must_use]
+    pub fn leaked_bytes(&self) -> usize {
+        self.leaked_bytes
+    }
+}
+
+impl Named for LeakObserver {
+    fn name(&self) -> &Cow<'static, str> {
+        &self.name
+    }
+}
+
+impl ObserverWithHashField for LeakObserver {
+    fn hash(&self) -> Option<u64> {
+        self.hash
+    }
+}
+
+impl<I, S> Observer<I, S> for LeakObserver {
+    fn pre_exec(&mut self, _state: &mut S, _input: &I) -> Result<(), Error> {
+        self.report = None;
+        self.leaked_bytes = 0;
+        self.hash = None;
+        Ok(())
+    }
+
+    fn post_exec(
+        &mut self,
+        _state: &mut S,
+        _input: &I,
+        _exit_kind: &ExitKind,
+    ) -> Result<(), Error> {
+        if let Some(report) = LEAK_REPORT.lock().unwrap().take() {
+            self.leaked_bytes = parse_leaked_bytes(&report).unwrap_or(0);
+            self.hash = Some(hash_leak_stacks(&report));
+            self.report = Some(report);
+        }
+        Ok(())
+    }
+}
+
+/// The leak report attached to the solutions found by the [`LeakFeedback`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeakReportMetadata {
+    /// The report printed by `LeakSanitizer`
+    pub report: String,
+    /// The number of bytes leaked
+    pub leaked_bytes: usize,
+}
+
+libafl_bolts::impl_serdeany!(LeakReportMetadata);
+
+/// Feedback reporting the runs in which the [`LeakObserver`] saw a leak
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeakFeedback {
+    name: Cow<'static, str>,
+    observer_handle: Handle<LeakObserver>,
+    // The previous run's result of `Self::is_interesting`
+    #[cfg(feature = "track_hit_feedbacks")]
+    last_result: Option<bool>,
+}
+
+impl LeakFeedback {
+    /// Create a new [`LeakFeedback`] for the given observer
+    #[must_use]
+    pub fn new(observer: &LeakObserver) -> Self {
+        Self {
+            name: Cow::Borrowed("LeakFeedback"),
+            observer_handle: observer.handle(),
+            #[cfg(feature = "track_hit_feedbacks")]
+            last_result: None,
+        }
+    }
+
+    fn observer<'a, OT>(&self, observers: &'a OT) -> Result<&'a LeakObserver, Error>
+    where
+        OT: MatchName,
+    {
+        observers.get(&self.observer_handle).ok_or_else(|| {
+            Error::illegal_argument(format!(
+                "LeakFeedback: observer {} not found",
+                self.observer_handle.name()
+            ))
+        })
+    }
+}
+
+impl Named for LeakFeedback {
+    fn name(&self) -> &Cow<'static, str> {
+        &self.name
+    }
+}
+
+impl<S> StateInitializer<S> for LeakFeedback {}
+
+impl<EM, I, OT, S> Feedback<EM, I, OT, S> for LeakFeedback
+where
+    OT: MatchName,
+{
+    fn is_interesting(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        _input: &I,
+        observers: &OT,
+        _exit_kind: &ExitKind,
+    ) -> Result<bool, Error> {
+        let res = self.observer(observers)?.leaked();
+        #[cfg(feature = "track_hit_feedbacks")]
+        {
+            self.last_result = Some(res);
+        }
+        Ok(res)
+    }
+
+    #[cfg(feature = "track_hit_feedbacks")]
+    fn last_result(&self) -> Result<bool, Error> {
+        self.last_result.ok_or(premature_last_result_err())
+    }
+
+    fn append_metadata(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        observers: &OT,
+        testcase: &mut Testcase<I>,
+    ) -> Result<(), Error> {
+        let observer = self.observer(observers)?;
+        if let Some(report) = observer.report() {
+            testcase.add_metadata(LeakReportMetadata {
+                report: report.to_string(),
+                leaked_bytes: observer.leaked_bytes(),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libafl::{
+        executors::ExitKind,
+        observers::{Observer, ObserverWithHashField},
+    };
+
+    use super::{hash_leak_stacks, parse_leaked_bytes, LeakObserver, LsanHook};
+
+    const REPORT: &str = "
+=================================================================
+==1234==ERROR: LeakSanitizer: detected memory leaks
+
+Direct leak of 16 byte(s) in 1 object(s) allocated from:
+    #0 0x4a1b2c in malloc
+    #1 0x4c3d4e in parse_header
+
+Direct leak of 8 byte(s) in 1 object(s) allocated from:
+    #0 0x4a1b2c in malloc
+    #1 0x4c5f60 in parse_body
+
+SUMMARY: AddressSanitizer: 24 byte(s) leaked in 2 allocation(s).
+";
+
+    #[test]
+    fn test_parse_leak_report() {
+        assert_eq!(parse_leaked_bytes(REPORT), Some(24));
+        assert_eq!(parse_leaked_bytes("no leaks here"), None);
+
+        // The order of the leaks does not change the hash
+        let (header, body) = REPORT.split_at(REPORT.find("Direct leak of 8").unwrap());
+        let reordered = format!("{body}\n{header}");
+        assert_eq!(hash_leak_stacks(REPORT), hash_leak_stacks(&reordered));
+
+        // ASLR moves the frames, the functions stay the same
+        let moved = REPORT
+            .replace("0x4a1b2c", "0x7f4a1b2c")
+            .replace("0x4c", "0x7f4c");
+        assert_eq!(hash_leak_stacks(REPORT), hash_leak_stacks(&moved));
+
+        let other = REPORT.replace("parse_body", "parse_footer");
+        assert_ne!(hash_leak_stacks(REPORT), hash_leak_stacks(&other));
+
+        let unsymbolized = "    #0 0x4a1b2c  (/usr/lib/libfoo.so+0x1b2c)\n";
+        let moved = "    #0 0x7f4a1b2c  (/usr/lib/libfoo.so+0x1b2c)\n";
+        assert_eq!(hash_leak_stacks(unsymbolized), hash_leak_stacks(moved));
+    }
+
+    #[test]
+    fn test_leaks_reported_once() {
+        let mut hook = LsanHook::<()>::new();
+        let mut observer = LeakObserver::new("leaks");
+
+        // The first run leaks
+        Observer::<(), ()>::pre_exec(&mut observer, &mut (), &()).unwrap();
+        hook.record_leaks(REPORT);
+        Observer::<(), ()>::post_exec(&mut observer, &mut (), &(), &ExitKind::Ok).unwrap();
+        assert!(observer.leaked());
+        assert_eq!(observer.leaked_bytes(), 24);
+        assert_eq!(observer.hash(), Some(hash_leak_stacks(REPORT)));
+
+        // The second run does not, but the leaked objects are still reported by the leak check
+        Observer::<(), ()>::pre_exec(&mut observer, &mut (), &()).unwrap();
+        hook.record_leaks(REPORT);
+        Observer::<(), ()>::post_exec(&mut observer, &mut (), &(), &ExitKind::Ok).unwrap();
+        assert!(!observer.leaked());
+        assert_eq!(observer.hash(), None);
+
+        // A third run leaks something else, only the new leak is reported
+        let (header, body) = REPORT.split_at(REPORT.find("Direct leak of 8").unwrap());
+        let new_leak = "Direct leak of 4 byte(s) in 1 object(s) allocated from:
+    #0 0x4a1b2c in malloc
+    #1 0x4c7182 in parse_footer
+
+";
+        Observer::<(), ()>::pre_exec(&mut observer, &mut (), &()).unwrap();
+        hook.record_leaks(&format!("{header}{new_leak}{body}"));
+        Observer::<(), ()>::post_exec(&mut observer, &mut (), &(), &ExitKind::Ok).unwrap();
+        assert_eq!(observer.leaked_bytes(), 4);
+        assert!(observer.report().unwrap().contains("parse_footer"));
+        assert!(!observer.report().unwrap().contains("parse_body"));
+        assert_eq!(observer.hash(), Some(hash_leak_stacks(new_leak)));
+    }
+}
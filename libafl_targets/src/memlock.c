@@ -0,0 +1,227 @@
+#include "common.h"
+
+#include "android-ashmem.h"
+#include <signal.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <pthread.h>
+#ifndef USEMMAP
+  #include <sys/shm.h>
+#else
+  #include <sys/mman.h>
+  #include <sys/stat.h>
+  #include <fcntl.h>
+  #include <unistd.h>
+#endif
+#ifdef LIBAFL_MEMLOCK_MALLOC
+  #include <errno.h>
+  #include <malloc.h>
+#endif
+
+#define MEMLOCK_SHM_ENV_VAR "__LIBAFL_MEMLOCK_SHM_ID"
+#define DEFAULT_PERMISSION 0600
+
+// Keep in sync with `MemlockStats` in memlock.rs
+struct libafl_memlock_stats {
+  uint64_t peak_heap;
+  uint64_t max_stack_depth;
+};
+
+static struct libafl_memlock_stats libafl_memlock_local_stats;
+// Points to shared memory if mapped with `libafl_memlock_map_shm`
+struct libafl_memlock_stats *libafl_memlock_stats = &libafl_memlock_local_stats;
+
+// The bytes currently allocated, and at the start of the run
+static int64_t   libafl_memlock_heap;
+static int64_t   libafl_memlock_heap_base;
+static uintptr_t libafl_memlock_stack_base;
+
+// Updated by -fsanitize-coverage=stack-depth on every function entry.
+// libfuzzer.c already defines it.
+#ifdef LIBAFL_LIBFUZZER
+extern MAYBE_THREAD_LOCAL uintptr_t __sancov_lowest_stack;
+#else
+MAYBE_THREAD_LOCAL uintptr_t __sancov_lowest_stack;
+#endif
+
+// Writes the stack depth down to `lowest`, if it is the deepest of the run.
+// Written as it grows, so that a run killed by a crash or a timeout reports
+// it as well.
+static void libafl_memlock_update_stack(uintptr_t lowest) {
+  if (lowest < libafl_memlock_stack_base) {
+    uint64_t depth = libafl_memlock_stack_base - lowest;
+    if (depth > libafl_memlock_stats->max_stack_depth) {
+      libafl_memlock_stats->max_stack_depth = depth;
+    }
+  }
+}
+
+// With -fsanitize-coverage-stack-depth-callback-min=N (LLVM 20+), called on
+// the entry of functions with large frames, instead of updating
+// __sancov_lowest_stack
+void __sanitizer_cov_stack_depth(void) {
+  uintptr_t sp = (uintptr_t)__builtin_frame_address(0);
+  if (sp < __sancov_lowest_stack) {
+    __sancov_lowest_stack = sp;
+    libafl_memlock_update_stack(sp);
+  }
+}
+
+static void libafl_memlock_alloc(size_t size) {
+  // The plain stack-depth instrumentation has no callback, check it here
+  libafl_memlock_update_stack(__sancov_lowest_stack);
+  int64_t now = __atomic_add_fetch(&libafl_memlock_heap, (int64_t)size,
+                                   __ATOMIC_RELAXED);
+  int64_t used = now - libafl_memlock_heap_base;
+  if (used > 0 && (uint64_t)used > libafl_memlock_stats->peak_heap) {
+    libafl_memlock_stats->peak_heap = (uint64_t)used;
+  }
+}
+
+static void libafl_memlock_free(size_t size) {
+  __atomic_sub_fetch(&libafl_memlock_heap, (int64_t)size, __ATOMIC_RELAXED);
+}
+
+// Sanitizer allocator interface, present if the target was built with
+// -fsanitize=address, -fsanitize=leak or -fsanitize=memory
+size_t __sanitizer_get_allocated_size(const volatile void *ptr)
+    __attribute__((weak));
+int __sanitizer_install_malloc_and_free_hooks(
+    void (*malloc_hook)(const volatile void *, size_t),
+    void (*free_hook)(const volatile void *)) __attribute__((weak));
+
+#ifndef LIBAFL_MEMLOCK_MALLOC
+static void libafl_memlock_malloc_hook(const volatile void *ptr, size_t size) {
+  (void)ptr;
+  libafl_memlock_alloc(size);
+}
+
+static void libafl_memlock_free_hook(const volatile void *ptr) {
+  if (ptr) { libafl_memlock_free(__sanitizer_get_allocated_size(ptr)); }
+}
+#else
+// Interpose the glibc allocator, for targets built without sanitizers
+extern void *__libc_malloc(size_t size);
+extern void *__libc_calloc(size_t nmemb, size_t size);
+extern void *__libc_realloc(void *ptr, size_t size);
+extern void *__libc_memalign(size_t alignment, size_t size);
+extern void  __libc_free(void *ptr);
+
+void *malloc(size_t size) {
+  void *ptr = __libc_malloc(size);
+  if (ptr) { libafl_memlock_alloc(malloc_usable_size(ptr)); }
+  return ptr;
+}
+
+void *calloc(size_t nmemb, size_t size) {
+  void *ptr = __libc_calloc(nmemb, size);
+  if (ptr) { libafl_memlock_alloc(malloc_usable_size(ptr)); }
+  return ptr;
+}
+
+void *realloc(void *ptr, size_t size) {
+  size_t old_size = ptr ? malloc_usable_size(ptr) : 0;
+  void  *new_ptr = __libc_realloc(ptr, size);
+  // realloc(ptr, 0) frees ptr, a failed realloc leaves it alone
+  if (new_ptr || !size) { libafl_memlock_free(old_size); }
+  if (new_ptr) { libafl_memlock_alloc(malloc_usable_size(new_ptr)); }
+  return new_ptr;
+}
+
+void *memalign(size_t alignment, size_t size) {
+  void *ptr = __libc_memalign(alignment, size);
+  if (ptr) { libafl_memlock_alloc(malloc_usable_size(ptr)); }
+  return ptr;
+}
+
+void *aligned_alloc(size_t alignment, size_t size) {
+  return memalign(alignment, size);
+}
+
+int posix_memalign(void **memptr, size_t alignment, size_t size) {
+  if (!alignment || (alignment & (alignment - 1)) ||
+      alignment % sizeof(void *)) {
+    return EINVAL;
+  }
+  void *ptr = memalign(alignment, size);
+  if (!ptr) { return ENOMEM; }
+  *memptr = ptr;
+  return 0;
+}
+
+void free(void *ptr) {
+  if (ptr) { libafl_memlock_free(malloc_usable_size(ptr)); }
+  __libc_free(ptr);
+}
+#endif
+
+int libafl_memlock_install_hooks(void) {
+#ifdef LIBAFL_MEMLOCK_MALLOC
+  return 1;
+#else
+  if (!__sanitizer_install_malloc_and_free_hooks ||
+      !__sanitizer_get_allocated_size) {
+    return 0;
+  }
+  return __sanitizer_install_malloc_and_free_hooks(libafl_memlock_malloc_hook,
+                                                   libafl_memlock_free_hook);
+#endif
+}
+
+// Stack depths are measured from (right below) the frame of the caller.
+// In-process targets call it once, from the entry of the harness.
+__attribute__((noinline)) void libafl_memlock_set_stack_base(void) {
+  libafl_memlock_stack_base = (uintptr_t)__builtin_frame_address(0);
+}
+
+void libafl_memlock_reset(void) {
+  libafl_memlock_heap_base =
+      __atomic_load_n(&libafl_memlock_heap, __ATOMIC_RELAXED);
+  libafl_memlock_stats->peak_heap = 0;
+  libafl_memlock_stats->max_stack_depth = 0;
+  __sancov_lowest_stack = UINTPTR_MAX;
+}
+
+void libafl_memlock_flush(void) {
+  libafl_memlock_update_stack(__sancov_lowest_stack);
+}
+
+// In persistent mode, the child stops itself after each run, and the
+// forkserver continues it for the next one
+static void libafl_memlock_on_continue(int sig) {
+  (void)sig;
+  libafl_memlock_reset();
+}
+
+// For forkserver targets: report to the fuzzer through shared memory.
+// Every forked child, and every iteration of a persistent child, starts a
+// new run. The stack depth is reported as it grows, and on exit.
+int libafl_memlock_map_shm(void) {
+  char *id_str = getenv(MEMLOCK_SHM_ENV_VAR);
+  if (!id_str) { return 0; }
+
+#ifdef USEMMAP
+  int shm_fd = shm_open(id_str, O_RDWR, DEFAULT_PERMISSION);
+  if (shm_fd == -1) { return -1; }
+  void *shm_base = mmap(0, sizeof(struct libafl_memlock_stats),
+                        PROT_READ | PROT_WRITE, MAP_SHARED, shm_fd, 0);
+  close(shm_fd);
+  if (shm_base == MAP_FAILED) { return -1; }
+#else
+  void *shm_base = shmat(atoi(id_str), NULL, 0);
+  if (!shm_base || shm_base == (void *)-1) { return -1; }
+#endif
+
+  libafl_memlock_stats = (struct libafl_memlock_stats *)shm_base;
+  libafl_memlock_install_hooks();
+  libafl_memlock_set_stack_base();
+  libafl_memlock_reset();
+  pthread_atfork(NULL, NULL, libafl_memlock_reset);
+  struct sigaction sa = {0};
+  sa.sa_handler = libafl_memlock_on_continue;
+  sa.sa_flags = SA_RESTART;
+  sigaction(SIGCONT, &sa, NULL);
+  atexit(libafl_memlock_flush);
+  return 1;
+}
@@ -0,0 +1,204 @@
+//! [MemLock](https://github.com/wcventure/MemLock-Fuzz)-style observation of the memory consumption of the target.
+//!
+//! Per run, the target records the peak of heap bytes allocated and the maximum stack depth reached
+//! into a [`MemlockStats`], exposed as [`ValueObserver`]s by [`peak_heap_observer`] and [`stack_depth_observer`].
+//! Maximize them with a [`libafl::feedbacks::MaximizationFeedback`], or turn excessive consumption into
+//! objectives with a [`libafl::feedbacks::ScalarThresholdFeedback`].
+//!
+//! - The heap is tracked with the sanitizer allocator hooks if the target is built with `-fsanitize=address`
+//!   (or `leak`, `memory`). Without sanitizers, the `memlock_malloc` feature interposes the glibc allocator.
+//! - The stack depth is tracked by the `-fsanitize-coverage=stack-depth` instrumentation, which has to be
+//!   added to the compiler flags of the target.
+//!
+//! In-process targets add the [`MemlockHook`] to the executor, wrap the harness with [`memlock_harness`],
+//! and observe [`memlock_stats_ptr`].
+//! Forkserver targets call [`map_memlock_shared_memory`] before starting the forkserver, and the fuzzer
+//! observes a shared memory of the size of [`MemlockStats`], passed in [`MEMLOCK_SHM_ENV_VAR`]:
+//!
+//! ```rust,ignore
+//! let mut memlock_shmem = shmem_provider.new_shmem(core::mem::size_of::<MemlockStats>())?;
+//! memlock_shmem.write_to_env(MEMLOCK_SHM_ENV_VAR)?;
+//! let stats = memlock_shmem.as_mut_ptr_of::<MemlockStats>().unwrap();
+//! let heap_observer = unsafe { peak_heap_observer("peak_heap", stats) };
+//! ```
+
+use core::marker::PhantomData;
+
+use libafl::{
+    executors::{hooks::ExecutorHook, ExitKind, HasObservers},
+    inputs::UsesInput,
+    observers::ValueObserver,
+    Error,
+};
+use libafl_bolts::ownedref::OwnedRef;
+
+/// The environment variable holding the id of the shared memory for the [`MemlockStats`] of forkserver targets
+pub const MEMLOCK_SHM_ENV_VAR: &str = "__LIBAFL_MEMLOCK_SHM_ID";
+
+/// The memory consumption of the last run, written by the target
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MemlockStats {
+    /// The peak of heap bytes allocated during the run, on top of what was allocated before
+    pub peak_heap: u64,
+    /// The maximum stack depth in bytes, needs `-fsanitize-coverage=stack-depth`
+    pub max_stack_depth: u64,
+}
+
+extern "C" {
+    static mut libafl_memlock_stats: *mut MemlockStats;
+
+    fn libafl_memlock_install_hooks() -> i32;
+    fn libafl_memlock_set_stack_base();
+    fn libafl_memlock_reset();
+    fn libafl_memlock_flush();
+    fn libafl_memlock_map_shm() -> i32;
+}
+
+/// The [`MemlockStats`] this process writes to
+#[must_use]
+pub fn memlock_stats_ptr() -> *mut MemlockStats {
+    unsafe { libafl_memlock_stats }
+}
+
+/// Gets a new [`ValueObserver`] for the [`MemlockStats::peak_heap`] of `stats`
+///
+/// # Safety
+/// `stats` has to stay valid for as long as the observer lives.
+#[must_use]
+pub unsafe fn peak_heap_observer(
+    name: &'static str,
+    stats: *const MemlockStats,
+) -> ValueObserver<'static, u64> {
+    ValueObserver::new(name, OwnedRef::from_ptr(&raw const (*stats).peak_heap))
+}
+
+/// Gets a new [`ValueObserver`] for the [`MemlockStats::max_stack_depth`] of `stats`
+///
+/// # Safety
+/// `stats` has to stay valid for as long as the observer lives.
+#[must_use]
+pub unsafe fn stack_depth_observer(
+    name: &'static str,
+    stats: *const MemlockStats,
+) -> ValueObserver<'static, u64> {
+    ValueObserver::new(
+        name,
+        OwnedRef::from_ptr(&raw const (*stats).max_stack_depth),
+    )
+}
+
+/// Wraps the harness of an in-process target, so that stack depths are measured from its entry.
+/// The base of the stack is taken at the first run, the harness is entered with the same stack each run.
+pub fn memlock_harness<I, H>(mut harness: H) -> impl FnMut(&I) -> ExitKind
+where
+    H: FnMut(&I) -> ExitKind,
+{
+    let mut has_stack_base = false;
+    move |input| {
+        if !has_stack_base {
+            // # Safety
+            // Only sets the base of the stack depths.
+            unsafe {
+                libafl_memlock_set_stack_base();
+            }
+            has_stack_base = true;
+        }
+        harness(input)
+    }
+}
+
+/// Report the memory consumption of a forkserver target to the fuzzer, through the shared memory in
+/// [`MEMLOCK_SHM_ENV_VAR`]. Call it from the target, before starting the forkserver.
+///
+/// Stack depths are measured from the frame calling this function.
+/// In persistent mode, the stats are reset when the forkserver continues the target for the next run.
+/// Returns `false` if the fuzzer did not pass any shared memory.
+///
+/// # Note
+///
+/// The function's logic is written in C and this code is a wrapper.
+pub fn map_memlock_shared_memory() -> Result<bool, Error> {
+    match unsafe { libafl_memlock_map_shm() } {
+        0 => Ok(false),
+        1 => Ok(true),
+        _ => Err(Error::illegal_state(format!(
+            "Failed to map the shared memory in {MEMLOCK_SHM_ENV_VAR}"
+        ))),
+    }
+}
+
+/// The hook tracking the memory consumption of in-process targets during each run.
+/// The stack depth needs the harness to be wrapped with [`memlock_harness`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MemlockHook<S> {
+    phantom: PhantomData<S>,
+}
+
+impl<S> MemlockHook<S> {
+    /// The constructor
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<S> ExecutorHook<S> for MemlockHook<S>
+where
+    S: UsesInput,
+{
+    fn init<E: HasObservers>(&mut self, _state: &mut S) {
+        if unsafe { libafl_memlock_install_hooks() } == 0 {
+            log::warn!(
+                "No allocator hooks available, build the target with sanitizers or enable the memlock_malloc feature to track the heap"
+            );
+        }
+    }
+
+    fn pre_exec(&mut self, _state: &mut S, _input: &S::Input) {
+        // # Safety
+        // The target is not running yet.
+        unsafe {
+            libafl_memlock_reset();
+        }
+    }
+
+    fn post_exec(&mut self, _state: &mut S, _input: &S::Input) {
+        // # Safety
+        // The target is done.
+        unsafe {
+            libafl_memlock_flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libafl::observers::ScalarObserver;
+
+    use super::{
+        libafl_memlock_reset, memlock_stats_ptr, peak_heap_observer, stack_depth_observer,
+    };
+
+    #[test]
+    fn test_memlock_observers() {
+        let stats = memlock_stats_ptr();
+        let heap = unsafe { peak_heap_observer("peak_heap", stats) };
+        let stack = unsafe { stack_depth_observer("stack_depth", stats) };
+
+        unsafe {
+            (*stats).peak_heap = 1234;
+            (*stats).max_stack_depth = 42;
+        }
+        assert_eq!(heap.scalar(), Some(1234));
+        assert_eq!(stack.scalar(), Some(42));
+
+        unsafe {
+            libafl_memlock_reset();
+        }
+        assert_eq!(heap.scalar(), Some(0));
+        assert_eq!(stack.scalar(), Some(0));
+    }
+}
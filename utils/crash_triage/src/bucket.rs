@@ -0,0 +1,180 @@
+//! Crash signatures and the buckets of crashes sharing one
+
+use std::path::PathBuf;
+
+use clap::ValueEnum;
+use libafl::observers::{SanitizerReport, StackFrame};
+use libafl_bolts::hash_std;
+use serde::Serialize;
+
+use crate::runner::{RunOutcome, RunResult};
+
+/// What identifies a bug
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SignatureKind {
+    /// The sanitizer, the bug type and the top frames
+    BugTypeFrames,
+    /// The top frames only
+    Frames,
+    /// The sanitizer and the bug type only
+    BugType,
+    /// The `SUMMARY:` line of the sanitizer report
+    Summary,
+}
+
+/// Computes the signature of crashes
+#[derive(Debug, Clone, Copy)]
+pub struct Signer {
+    kind: SignatureKind,
+    frames: usize,
+}
+
+impl Signer {
+    /// Creates a new [`Signer`], using the top `frames` frames
+    #[must_use]
+    pub fn new(kind: SignatureKind, frames: usize) -> Self {
+        Self { kind, frames }
+    }
+
+    /// The signature of a crashing run: a readable description of the bug, identifying its bucket
+    #[must_use]
+    pub fn signature(&self, result: &RunResult) -> String {
+        let bug_type = match (&result.report, &result.outcome) {
+            (Some(report), _) => format!("{} {}", report.sanitizer, report.bug_type),
+            (None, RunOutcome::Signaled(signal)) => format!("signal {signal}"),
+            (None, RunOutcome::Exited(code)) => format!("exit code {code}"),
+            (None, RunOutcome::Timeout) => "timeout".to_string(),
+        };
+        let frames = self.top_frames(result);
+
+        match self.kind {
+            SignatureKind::BugTypeFrames if frames.is_empty() => bug_type,
+            SignatureKind::BugTypeFrames => format!("{bug_type} in {}", frames.join(" < ")),
+            SignatureKind::Frames => frames.join(" < "),
+            SignatureKind::BugType => bug_type,
+            SignatureKind::Summary => result
+                .report
+                .as_ref()
+                .and_then(|report| report.summary.clone())
+                .unwrap_or(bug_type),
+        }
+    }
+
+    fn top_frames(&self, result: &RunResult) -> Vec<String> {
+        match &result.report {
+            Some(report) => report
+                .top_frames(self.frames)
+                .map(StackFrame::bucket_key)
+                .collect(),
+            None => result
+                .backtrace
+                .iter()
+                .filter(|frame| !is_signal_frame(frame))
+                .take(self.frames)
+                .map(StackFrame::bucket_key)
+                .collect(),
+        }
+    }
+}
+
+/// Frames of libc raising the signal, on top of `gdb` backtraces of aborts
+fn is_signal_frame(frame: &StackFrame) -> bool {
+    frame.is_sanitizer_runtime()
+        || frame.function.as_deref().is_some_and(|function| {
+            ["raise", "abort", "__pthread_kill", "pthread_kill", "__GI_"]
+                .iter()
+                .any(|prefix| function.starts_with(prefix))
+        })
+}
+
+/// The crashes sharing a signature
+#[derive(Debug, Clone, Serialize)]
+pub struct Bucket {
+    /// The hash of the signature
+    pub id: String,
+    /// The signature
+    pub signature: String,
+    /// The crashing inputs
+    pub crashes: Vec<PathBuf>,
+    /// The smallest crashing input
+    pub representative: PathBuf,
+    /// The size of the representative
+    pub representative_size: usize,
+    /// The minimized representative, written to the output directory
+    pub minimized: Option<PathBuf>,
+    /// The size of the minimized representative
+    pub minimized_size: Option<usize>,
+    /// The sanitizer report of the representative
+    pub report: Option<SanitizerReport>,
+    /// The `gdb` backtrace of the representative, for crashes without sanitizer report
+    pub backtrace: Vec<StackFrame>,
+}
+
+impl Bucket {
+    /// Creates a new [`Bucket`] for the first crash with this `signature`
+    #[must_use]
+    pub fn new(signature: String, crash: PathBuf, size: usize, result: RunResult) -> Self {
+        Self {
+            id: format!("{:016x}", hash_std(signature.as_bytes())),
+            signature,
+            crashes: vec![crash.clone()],
+            representative: crash,
+            representative_size: size,
+            minimized: None,
+            minimized_size: None,
+            report: result.report,
+            backtrace: result.backtrace,
+        }
+    }
+
+    /// Adds a crash, which becomes the representative if it is smaller
+    pub fn add(&mut self, crash: PathBuf, size: usize, result: RunResult) {
+        self.crashes.push(crash.clone());
+        if size < self.representative_size {
+            self.representative = crash;
+            self.representative_size = size;
+            self.report = result.report;
+            self.backtrace = result.backtrace;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libafl::observers::SanitizerReport;
+
+    use super::{SignatureKind, Signer};
+    use crate::runner::{RunOutcome, RunResult};
+
+    #[test]
+    fn test_signatures() {
+        let report = SanitizerReport::parse(
+            "==1==ERROR: AddressSanitizer: heap-buffer-overflow on address 0x602000000011 at pc 0x1 bp 0x2 sp 0x3
+READ of size 1 at 0x602000000011 thread T0
+    #0 0x1 in parse /src/p.c:3:5
+    #1 0x2 in LLVMFuzzerTestOneInput /src/p.c:10:3
+",
+        );
+        let result = RunResult {
+            outcome: RunOutcome::Signaled(6),
+            report,
+            backtrace: Vec::new(),
+        };
+
+        let signer = Signer::new(SignatureKind::BugTypeFrames, 2);
+        assert_eq!(
+            signer.signature(&result),
+            "AddressSanitizer heap-buffer-overflow in parse < LLVMFuzzerTestOneInput"
+        );
+        let signer = Signer::new(SignatureKind::Frames, 1);
+        assert_eq!(signer.signature(&result), "parse");
+
+        let plain = RunResult {
+            outcome: RunOutcome::Signaled(11),
+            report: None,
+            backtrace: Vec::new(),
+        };
+        let signer = Signer::new(SignatureKind::BugTypeFrames, 2);
+        assert_eq!(signer.signature(&plain), "signal 11");
+    }
+}
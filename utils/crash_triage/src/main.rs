@@ -0,0 +1,287 @@
+//! Replays a directory of crashes against a harness, buckets them by signature and writes a summary
+//! with one minimized representative per bucket.
+
+mod bucket;
+mod minimize;
+mod runner;
+
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use clap::Parser;
+use serde::Serialize;
+
+use crate::{
+    bucket::{Bucket, SignatureKind, Signer},
+    minimize::minimize,
+    runner::Runner,
+};
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+#[command(
+    name = "crash_triage",
+    about,
+    long_about = "Replays crashes against a harness, buckets them by signature and minimizes one representative per bucket"
+)]
+pub struct Opt {
+    #[arg(short, long, help = "The directory of crashing inputs")]
+    pub crashes: PathBuf,
+    #[arg(
+        short,
+        long,
+        help = "The output directory for the summary and the representatives"
+    )]
+    pub output: PathBuf,
+    #[arg(
+        long,
+        help = "Pass the input on stdin, if the command has no @@. By default, the input file is the last argument"
+    )]
+    pub stdin: bool,
+    #[arg(
+        short,
+        long,
+        default_value_t = 5000,
+        help = "The timeout of a run in milliseconds"
+    )]
+    pub timeout: u64,
+    #[arg(
+        short,
+        long,
+        value_enum,
+        default_value = "bug-type-frames",
+        help = "What identifies a bug"
+    )]
+    pub signature: SignatureKind,
+    #[arg(
+        short,
+        long,
+        default_value_t = 3,
+        help = "The number of top frames in the signature"
+    )]
+    pub frames: usize,
+    #[arg(
+        long,
+        default_value_t = 256,
+        help = "The runs spent to minimize each representative, 0 to disable"
+    )]
+    pub minimize_runs: usize,
+    #[arg(
+        long,
+        help = "Collect backtraces with gdb for crashes without sanitizer report"
+    )]
+    pub gdb: bool,
+    #[arg(
+        help = "The harness, followed by its arguments, run in a new process for each input. @@ is replaced by the input file",
+        required = true,
+        last = true
+    )]
+    pub command: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Summary {
+    target: String,
+    crashes: usize,
+    reproduced: usize,
+    buckets: Vec<Bucket>,
+    not_reproduced: Vec<PathBuf>,
+}
+
+fn crash_files(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut files = Vec::new();
+    for entry in
+        fs::read_dir(dir).map_err(|err| format!("Failed to read {}: {err}", dir.display()))?
+    {
+        let path = entry.map_err(|err| err.to_string())?.path();
+        let hidden = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        if path.is_file() && !hidden {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn write_markdown(summary: &Summary) -> String {
+    let mut md = String::new();
+    let _ = writeln!(md, "# Crash triage of `{}`\n", summary.target);
+    let _ = writeln!(
+        md,
+        "{} crashes, {} reproduced, {} buckets\n",
+        summary.crashes,
+        summary.reproduced,
+        summary.buckets.len()
+    );
+    let _ = writeln!(md, "| Bucket | Crashes | Representative | Signature |");
+    let _ = writeln!(md, "|---|---|---|---|");
+    for bucket in &summary.buckets {
+        let size = bucket.minimized_size.unwrap_or(bucket.representative_size);
+        let _ = writeln!(
+            md,
+            "| `{}` | {} | {} bytes | {} |",
+            bucket.id,
+            bucket.crashes.len(),
+            size,
+            bucket.signature.replace('|', "\\|")
+        );
+    }
+
+    for bucket in &summary.buckets {
+        let _ = writeln!(md, "\n## `{}`\n\n{}\n", bucket.id, bucket.signature);
+        let _ = writeln!(
+            md,
+            "- Representative: `{}`",
+            bucket.representative.display()
+        );
+        if let Some(minimized) = &bucket.minimized {
+            let _ = writeln!(md, "- Minimized: `{}`", minimized.display());
+        }
+        if let Some(report) = &bucket.report {
+            match (report.access, report.access_size) {
+                (Some(access), Some(size)) => {
+                    let _ = writeln!(md, "- Access: {access:?} of size {size}");
+                }
+                (Some(access), None) => {
+                    let _ = writeln!(md, "- Access: {access:?}");
+                }
+                _ => {}
+            }
+            if let Some(address) = report.address {
+                let _ = writeln!(md, "- Address: `{address:#x}`");
+            }
+        }
+        let stack = bucket
+            .report
+            .as_ref()
+            .map_or(&bucket.backtrace, |report| &report.stack);
+        if !stack.is_empty() {
+            let _ = writeln!(md, "\n```");
+            for (idx, frame) in stack.iter().enumerate() {
+                let location = match (&frame.file, frame.line) {
+                    (Some(file), Some(line)) => format!(" {file}:{line}"),
+                    _ => String::new(),
+                };
+                let _ = writeln!(md, "#{idx} {}{location}", frame.bucket_key());
+            }
+            let _ = writeln!(md, "```");
+        }
+    }
+
+    if !summary.not_reproduced.is_empty() {
+        let _ = writeln!(md, "\n## Not reproduced\n");
+        for crash in &summary.not_reproduced {
+            let _ = writeln!(md, "- `{}`", crash.display());
+        }
+    }
+    md
+}
+
+fn run(opts: &Opt) -> Result<(), String> {
+    fs::create_dir_all(opts.output.join("buckets"))
+        .map_err(|err| format!("Failed to create {}: {err}", opts.output.display()))?;
+
+    let runner = Runner::new(
+        PathBuf::from(&opts.command[0]),
+        opts.command[1..].to_vec(),
+        opts.stdin,
+        Duration::from_millis(opts.timeout),
+        opts.gdb,
+        opts.output.join(".cur_input"),
+    );
+    let signer = Signer::new(opts.signature, opts.frames);
+
+    let crashes = crash_files(&opts.crashes)?;
+    let mut buckets: BTreeMap<String, Bucket> = BTreeMap::new();
+    let mut not_reproduced = Vec::new();
+    for (idx, crash) in crashes.iter().enumerate() {
+        let input =
+            fs::read(crash).map_err(|err| format!("Failed to read {}: {err}", crash.display()))?;
+        let result = runner.run(&input)?;
+        if !result.crashed() {
+            println!(
+                "[{}/{}] {}: no crash",
+                idx + 1,
+                crashes.len(),
+                crash.display()
+            );
+            not_reproduced.push(crash.clone());
+            continue;
+        }
+        let signature = signer.signature(&result);
+        println!(
+            "[{}/{}] {}: {signature}",
+            idx + 1,
+            crashes.len(),
+            crash.display()
+        );
+        match buckets.get_mut(&signature) {
+            Some(bucket) => bucket.add(crash.clone(), input.len(), result),
+            None => {
+                buckets.insert(
+                    signature.clone(),
+                    Bucket::new(signature, crash.clone(), input.len(), result),
+                );
+            }
+        }
+    }
+
+    let mut buckets: Vec<Bucket> = buckets.into_values().collect();
+    buckets.sort_by_key(|bucket| Reverse(bucket.crashes.len()));
+    for bucket in &mut buckets {
+        let dir = opts.output.join("buckets").join(&bucket.id);
+        fs::create_dir_all(&dir).map_err(|err| err.to_string())?;
+        let input = fs::read(&bucket.representative).map_err(|err| err.to_string())?;
+        fs::write(dir.join("representative"), &input).map_err(|err| err.to_string())?;
+
+        if opts.minimize_runs > 0 {
+            println!("Minimizing {} ({} bytes)", bucket.id, input.len());
+            let minimized = minimize(&input, opts.minimize_runs, |candidate| {
+                let result = runner.run(candidate)?;
+                Ok(result.crashed() && signer.signature(&result) == bucket.signature)
+            })?;
+            let path = dir.join("minimized");
+            fs::write(&path, &minimized).map_err(|err| err.to_string())?;
+            bucket.minimized = Some(path);
+            bucket.minimized_size = Some(minimized.len());
+        }
+    }
+    let _ = fs::remove_file(opts.output.join(".cur_input"));
+
+    let summary = Summary {
+        target: opts.command.join(" "),
+        crashes: crashes.len(),
+        reproduced: crashes.len() - not_reproduced.len(),
+        buckets,
+        not_reproduced,
+    };
+    let json = serde_json::to_string_pretty(&summary).map_err(|err| err.to_string())?;
+    fs::write(opts.output.join("summary.json"), json).map_err(|err| err.to_string())?;
+    fs::write(opts.output.join("summary.md"), write_markdown(&summary))
+        .map_err(|err| err.to_string())?;
+
+    println!(
+        "{} of {} crashes reproduced, {} buckets, summary in {}",
+        summary.reproduced,
+        summary.crashes,
+        summary.buckets.len(),
+        opts.output.display()
+    );
+    Ok(())
+}
+
+fn main() {
+    let opts = Opt::parse();
+    if let Err(err) = run(&opts) {
+        eprintln!("Error: {err}");
+        std::process::exit(1);
+    }
+}
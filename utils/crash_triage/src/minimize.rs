@@ -0,0 +1,58 @@
+//! Minimization of crashing inputs, keeping their signature
+
+/// Removes chunks of halving size from `input` as long as `still_crashes` holds, with at most `budget` tries.
+pub fn minimize<F>(input: &[u8], budget: usize, mut still_crashes: F) -> Result<Vec<u8>, String>
+where
+    F: FnMut(&[u8]) -> Result<bool, String>,
+{
+    let mut current = input.to_vec();
+    let mut tries = 0;
+    let mut chunk = current.len().div_ceil(2);
+
+    while chunk > 0 && tries < budget {
+        let mut removed_any = false;
+        let mut start = 0;
+        while start < current.len() && tries < budget {
+            let end = (start + chunk).min(current.len());
+            let mut candidate = Vec::with_capacity(current.len() - (end - start));
+            candidate.extend_from_slice(&current[..start]);
+            candidate.extend_from_slice(&current[end..]);
+
+            tries += 1;
+            if still_crashes(&candidate)? {
+                current = candidate;
+                removed_any = true;
+            } else {
+                start = end;
+            }
+        }
+        // Retry the same size while it helps
+        if !removed_any {
+            if chunk == 1 {
+                break;
+            }
+            chunk /= 2;
+        }
+        chunk = chunk.min(current.len());
+    }
+    Ok(current)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::minimize;
+
+    #[test]
+    fn test_minimize() {
+        let input = b"aaaaaaaaBUGbbbbbbbbbbbbb";
+        let minimized = minimize(input, 1000, |candidate| {
+            Ok(candidate.windows(3).any(|window| window == b"BUG"))
+        })
+        .unwrap();
+        assert_eq!(minimized, b"BUG");
+
+        // Out of budget, the input stays as is
+        let minimized = minimize(input, 0, |_| Ok(true)).unwrap();
+        assert_eq!(minimized, input);
+    }
+}
@@ -0,0 +1,323 @@
+//! Runs the harness as a subprocess on a single input and collects what it left behind
+
+use std::{
+    env, fs,
+    io::{Read, Write},
+    os::unix::process::ExitStatusExt,
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use libafl::observers::{get_asan_runtime_flags, SanitizerReport, StackFrame};
+use regex::Regex;
+
+/// The outcome of a single run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunOutcome {
+    /// The target exited with the given code
+    Exited(i32),
+    /// The target was killed by the given signal
+    Signaled(i32),
+    /// The target did not finish in time
+    Timeout,
+}
+
+/// The result of a single run
+#[derive(Debug, Clone)]
+pub struct RunResult {
+    /// How the run ended
+    pub outcome: RunOutcome,
+    /// The sanitizer report printed by the target, if any
+    pub report: Option<SanitizerReport>,
+    /// The backtrace collected with `gdb`, for crashes without a sanitizer report
+    pub backtrace: Vec<StackFrame>,
+}
+
+impl RunResult {
+    /// If the run crashed
+    #[must_use]
+    pub fn crashed(&self) -> bool {
+        self.report.is_some() || matches!(self.outcome, RunOutcome::Signaled(_))
+    }
+}
+
+/// Runs the harness on inputs, in a new process for each run.
+///
+/// The input goes to the file replacing `@@` in the arguments, else to stdin if requested, else its file is
+/// passed as last argument, as libFuzzer-style harnesses expect.
+#[derive(Debug)]
+pub struct Runner {
+    target: PathBuf,
+    args: Vec<String>,
+    stdin: bool,
+    timeout: Duration,
+    gdb: bool,
+    input_file: PathBuf,
+    gdb_frame: Regex,
+}
+
+impl Runner {
+    /// Creates a new [`Runner`], writing the inputs to `input_file`
+    #[must_use]
+    pub fn new(
+        target: PathBuf,
+        args: Vec<String>,
+        stdin: bool,
+        timeout: Duration,
+        gdb: bool,
+        input_file: PathBuf,
+    ) -> Self {
+        Self {
+            target,
+            args,
+            stdin,
+            timeout,
+            gdb,
+            input_file,
+            gdb_frame: Regex::new(
+                r"^#(\d+)\s+(?:0x([0-9a-fA-F]+) in )?(\S+) \(.*?\)(?: at (.+):(\d+))?(?: from (.+))?$",
+            )
+            .unwrap(),
+        }
+    }
+
+    /// The command line running the target on the current input, and if the input goes to stdin
+    fn command_line(&self) -> (Vec<String>, bool) {
+        let input = self.input_file.to_string_lossy().into_owned();
+        let mut cmdline = vec![self.target.to_string_lossy().into_owned()];
+
+        let has_file_arg = self.args.iter().any(|arg| arg == "@@");
+        cmdline.extend(self.args.iter().map(|arg| {
+            if arg == "@@" {
+                input.clone()
+            } else {
+                arg.clone()
+            }
+        }));
+        if has_file_arg {
+            (cmdline, false)
+        } else if self.stdin {
+            (cmdline, true)
+        } else {
+            // libFuzzer harnesses run the files passed on the command line
+            cmdline.push(input);
+            (cmdline, false)
+        }
+    }
+
+    fn command(cmdline: &[String], use_stdin: bool) -> Command {
+        let mut command = Command::new(&cmdline[0]);
+        command
+            .args(&cmdline[1..])
+            .stdin(if use_stdin {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            // Run forkserver targets standalone
+            .env_remove("__AFL_SHM_ID")
+            .env_remove("__AFL_SHM_FUZZ_ID");
+
+        if env::var_os("ASAN_OPTIONS").is_none() {
+            command.env("ASAN_OPTIONS", get_asan_runtime_flags() + ":symbolize=1");
+        }
+        for options in ["UBSAN_OPTIONS", "MSAN_OPTIONS", "TSAN_OPTIONS"] {
+            if env::var_os(options).is_none() {
+                command.env(
+                    options,
+                    "halt_on_error=1:abort_on_error=1:print_stacktrace=1:symbolize=1",
+                );
+            }
+        }
+        command
+    }
+
+    /// Runs the target on `input`
+    pub fn run(&self, input: &[u8]) -> Result<RunResult, String> {
+        fs::write(&self.input_file, input)
+            .map_err(|err| format!("Failed to write {}: {err}", self.input_file.display()))?;
+
+        let (cmdline, use_stdin) = self.command_line();
+        let (outcome, stderr) =
+            self.spawn_and_wait(Self::command(&cmdline, use_stdin), input, use_stdin)?;
+
+        let report = SanitizerReport::parse(&stderr);
+        let mut backtrace = Vec::new();
+        if report.is_none() && matches!(outcome, RunOutcome::Signaled(_)) && self.gdb {
+            backtrace = self.gdb_backtrace(&cmdline, input, use_stdin)?;
+        }
+
+        Ok(RunResult {
+            outcome,
+            report,
+            backtrace,
+        })
+    }
+
+    fn spawn_and_wait(
+        &self,
+        mut command: Command,
+        input: &[u8],
+        use_stdin: bool,
+    ) -> Result<(RunOutcome, String), String> {
+        let mut child = command.spawn().map_err(|err| {
+            format!(
+                "Failed to run {}: {err}",
+                command.get_program().to_string_lossy()
+            )
+        })?;
+
+        // Drain stderr while waiting, the report can be larger than the pipe
+        let reader = drain(child.stderr.take().unwrap());
+        let writer = use_stdin.then(|| feed(child.stdin.take().unwrap(), input));
+
+        let outcome = wait_timeout(&mut child, self.timeout)?;
+        if let Some(writer) = writer {
+            let _ = writer.join();
+        }
+        let stderr = reader.join().unwrap_or_default();
+        Ok((outcome, String::from_utf8_lossy(&stderr).into_owned()))
+    }
+
+    /// Reruns a crash under `gdb` to get a backtrace
+    fn gdb_backtrace(
+        &self,
+        cmdline: &[String],
+        input: &[u8],
+        use_stdin: bool,
+    ) -> Result<Vec<StackFrame>, String> {
+        let mut gdb_cmdline: Vec<String> = ["gdb", "-q", "-nx", "-batch"]
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        gdb_cmdline.extend(
+            [
+                "-ex",
+                if use_stdin { "run < /dev/stdin" } else { "run" },
+                "-ex",
+                "bt",
+                "--args",
+            ]
+            .iter()
+            .map(ToString::to_string),
+        );
+        gdb_cmdline.extend(cmdline.iter().cloned());
+
+        let mut command = Self::command(&gdb_cmdline, use_stdin);
+        // gdb prints the backtrace to stdout
+        command.stdout(Stdio::piped()).stderr(Stdio::null());
+        let mut child = command
+            .spawn()
+            .map_err(|err| format!("Failed to run gdb: {err}"))?;
+        let reader = drain(child.stdout.take().unwrap());
+        let writer = use_stdin.then(|| feed(child.stdin.take().unwrap(), input));
+
+        wait_timeout(&mut child, self.timeout * 4)?;
+        if let Some(writer) = writer {
+            let _ = writer.join();
+        }
+        let stdout = reader.join().unwrap_or_default();
+
+        Ok(self.parse_gdb_backtrace(&String::from_utf8_lossy(&stdout)))
+    }
+
+    /// Parses the frames printed by the `bt` command of `gdb`
+    #[must_use]
+    pub fn parse_gdb_backtrace(&self, output: &str) -> Vec<StackFrame> {
+        output
+            .lines()
+            .filter_map(|line| self.gdb_frame.captures(line.trim()))
+            .map(|captures| StackFrame {
+                pc: captures
+                    .get(2)
+                    .and_then(|pc| u64::from_str_radix(pc.as_str(), 16).ok()),
+                function: Some(captures[3].to_string()).filter(|function| function != "??"),
+                file: captures.get(4).map(|file| file.as_str().to_string()),
+                line: captures.get(5).and_then(|line| line.as_str().parse().ok()),
+                column: None,
+                module: captures.get(6).map(|module| module.as_str().to_string()),
+                offset: None,
+            })
+            .collect()
+    }
+}
+
+/// Reads a pipe of the target to the end, in its own thread
+fn drain<R>(mut pipe: R) -> thread::JoinHandle<Vec<u8>>
+where
+    R: Read + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = pipe.read_to_end(&mut buf);
+        buf
+    })
+}
+
+/// Writes the input to the stdin of the target, in its own thread, so a target writing its output before
+/// reading all of the input can not block the run
+fn feed<W>(mut stdin: W, input: &[u8]) -> thread::JoinHandle<()>
+where
+    W: Write + Send + 'static,
+{
+    let input = input.to_vec();
+    thread::spawn(move || {
+        // The target may exit without reading all of it
+        let _ = stdin.write_all(&input);
+    })
+}
+
+fn wait_timeout(child: &mut Child, timeout: Duration) -> Result<RunOutcome, String> {
+    let start = Instant::now();
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => {
+                return Ok(match (status.code(), status.signal()) {
+                    (_, Some(signal)) => RunOutcome::Signaled(signal),
+                    (Some(code), None) => RunOutcome::Exited(code),
+                    (None, None) => RunOutcome::Exited(-1),
+                });
+            }
+            Ok(None) if start.elapsed() > timeout => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Ok(RunOutcome::Timeout);
+            }
+            Ok(None) => thread::sleep(Duration::from_millis(5)),
+            Err(err) => return Err(format!("Failed to wait for the target: {err}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf, process, time::Duration};
+
+    use super::{RunOutcome, Runner};
+
+    #[test]
+    fn test_stdin_and_stderr_do_not_block() {
+        let input_file = env::temp_dir().join(format!("crash_triage_runner_{}", process::id()));
+        // Fills the stderr pipe before reading any of the input
+        let runner = Runner::new(
+            PathBuf::from("sh"),
+            vec![
+                "-c".to_string(),
+                "head -c 1000000 /dev/zero >&2; cat > /dev/null; exit 3".to_string(),
+            ],
+            true,
+            Duration::from_secs(10),
+            false,
+            input_file.clone(),
+        );
+        let input = vec![0x41; 1_000_000];
+        let result = runner.run(&input).unwrap();
+        assert_eq!(result.outcome, RunOutcome::Exited(3));
+        assert!(!result.crashed());
+        let _ = fs::remove_file(input_file);
+    }
+}